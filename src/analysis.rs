@@ -0,0 +1,134 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use crate::model::{LinkGraph, LinkId, NodeKind};
+
+const PAGERANK_DAMPING: f64 = 0.85;
+const PAGERANK_ITERATIONS: usize = 30;
+
+/// Computes the PageRank of every link in the graph. The
+/// ranks add up to 1, pages with no outlinks spread their
+/// rank evenly over every page.
+pub fn pagerank(links: &LinkGraph) -> HashMap<LinkId, f64> {
+    let n = links.len();
+    if n == 0 {
+        return HashMap::new();
+    }
+
+    let initial_rank = 1.0 / n as f64;
+    let mut ranks: HashMap<LinkId, f64> = links
+        .into_iter()
+        .map(|(id, _)| (*id, initial_rank))
+        .collect();
+
+    for _ in 0..PAGERANK_ITERATIONS {
+        let dangling_rank: f64 = links
+            .into_iter()
+            .filter(|(_, link)| link.children.is_empty())
+            .map(|(id, _)| ranks[id])
+            .sum();
+
+        let base_rank =
+            (1.0 - PAGERANK_DAMPING) / n as f64 + PAGERANK_DAMPING * dangling_rank / n as f64;
+        let mut next_ranks: HashMap<LinkId, f64> =
+            ranks.keys().map(|id| (*id, base_rank)).collect();
+
+        for (id, link) in links {
+            if link.children.is_empty() {
+                continue;
+            }
+
+            let share = PAGERANK_DAMPING * ranks[id] / link.children.len() as f64;
+            for child in &link.children {
+                if let Some(rank) = next_ranks.get_mut(child) {
+                    *rank += share;
+                }
+            }
+        }
+
+        ranks = next_ranks;
+    }
+
+    ranks
+}
+
+/// The fewest clicks it takes to reach every page from `seed`.
+/// Pages that can't be reached by following links are left out
+pub fn click_depths(links: &LinkGraph, seed: LinkId) -> HashMap<LinkId, usize> {
+    let mut depths = HashMap::from([(seed, 0)]);
+    let mut to_visit = VecDeque::from([seed]);
+
+    while let Some(id) = to_visit.pop_front() {
+        let Some(link) = links.get_by_id(&id) else {
+            continue;
+        };
+        let child_depth = depths[&id] + 1;
+        for child in &link.children {
+            if !depths.contains_key(child) {
+                depths.insert(*child, child_depth);
+                to_visit.push_back(*child);
+            }
+        }
+    }
+
+    depths
+}
+
+/// How many pages sit at each click depth, and how many can't
+/// be reached. External links aren't counted
+pub fn depth_histogram(links: &LinkGraph) -> (BTreeMap<usize, usize>, usize) {
+    let mut histogram = BTreeMap::new();
+    let mut unreachable = 0;
+    for (_, link) in links.into_iter().filter(|(_, link)| link.kind != NodeKind::External) {
+        match link.depth {
+            Some(depth) => *histogram.entry(depth).or_default() += 1,
+            None => unreachable += 1,
+        }
+    }
+    (histogram, unreachable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pagerank_favours_linked_pages() {
+        let mut links = LinkGraph::default();
+        let page = |p: &str| format!("https://example.com/{}", p);
+
+        links.update(&page(""), "", &[page("a"), page("b")], &[], &[]).unwrap();
+        links.update(&page("a"), &page(""), &[page("b")], &[], &[]).unwrap();
+        links.update(&page("b"), &page(""), &[], &[], &[]).unwrap();
+
+        let ranks = pagerank(&links);
+        let rank = |p: &str| ranks[&links.get(&page(p)).unwrap().id];
+
+        assert!((ranks.values().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!(rank("b") > rank("a"));
+        assert!(rank("a") > rank(""));
+    }
+
+    #[test]
+    fn test_click_depths_take_the_shortest_path() {
+        let mut links = LinkGraph::default();
+        let page = |p: &str| format!("https://example.com/{}", p);
+
+        links.update(&page(""), "", &[page("a"), page("c")], &[], &[]).unwrap();
+        links.update(&page("a"), &page(""), &[page("b")], &[], &[]).unwrap();
+        links.update(&page("b"), &page("a"), &[page("c")], &[], &[]).unwrap();
+        links.update(&page("c"), &page("b"), &[], &[], &[]).unwrap();
+        // Only found through the sitemap
+        links.update(&page("orphan"), "", &[], &[], &[]).unwrap();
+
+        let id = |p: &str| links.get(&page(p)).unwrap().id;
+        let depths = click_depths(&links, id(""));
+        assert_eq!(depths[&id("b")], 2);
+        assert_eq!(depths[&id("c")], 1);
+        assert!(!depths.contains_key(&id("orphan")));
+
+        links.set_click_depths(&depths);
+        let (histogram, unreachable) = depth_histogram(&links);
+        assert_eq!(histogram, BTreeMap::from([(0, 1), (1, 2), (2, 1)]));
+        assert_eq!(unreachable, 1);
+    }
+}
@@ -1,17 +1,34 @@
+use anyhow::{bail, Result};
 use axum::{
     extract::{Path, State},
-    http::StatusCode,
-    response::Json,
+    http::{header, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Json,
+    },
     routing::{get, post},
     Router,
 };
+use futures::Stream;
+use log2::*;
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::sync::{broadcast, RwLock};
+use tokio::time::Interval;
 use uuid::Uuid;
 use std::collections::HashMap;
 
+use crate::crawler::{Crawler, CrawlerState, CrawlerStateRef, PageEvent};
+use crate::image_utils::{extension_media_type, read_download_log, DOWNLOAD_LOG};
+use crate::job_store::JobStore;
 use crate::model::LinkGraph;
+use crate::stats::CrawlStats;
+
+/// How often the counts of running jobs are updated
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
 
 #[derive(Debug, Deserialize)]
 pub struct CrawlRequest {
@@ -42,7 +59,10 @@ pub struct JobStatus {
     pub job_id: String,
     pub url: String,
     pub status: JobState,
+    /// Pages fetched successfully
     pub pages_crawled: usize,
+    /// Pages we tried to fetch, including failures
+    pub pages_attempted: usize,
     pub images_downloaded: usize,
     pub started_at: String,
     pub completed_at: Option<String>,
@@ -53,21 +73,81 @@ pub struct JobStatus {
 pub enum JobState {
     Pending,
     Running,
+    Paused,
     Completed,
+    Cancelled,
     Failed,
 }
 
+impl JobState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobState::Pending => "pending",
+            JobState::Running => "running",
+            JobState::Paused => "paused",
+            JobState::Completed => "completed",
+            JobState::Cancelled => "cancelled",
+            JobState::Failed => "failed",
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "pending" => JobState::Pending,
+            "running" => JobState::Running,
+            "paused" => JobState::Paused,
+            "completed" => JobState::Completed,
+            "cancelled" => JobState::Cancelled,
+            "failed" => JobState::Failed,
+            _ => bail!("unknown job state '{}'", name),
+        })
+    }
+}
+
+impl JobStatus {
+    /// Copies the counts of a running crawl
+    fn update_counts(&mut self, crawler_state: &CrawlerState) {
+        self.pages_crawled = crawler_state.crawled_count.load(Ordering::Relaxed);
+        self.pages_attempted = crawler_state.attempted_count.load(Ordering::Relaxed);
+        if let Some(image_downloader) = &crawler_state.image_downloader {
+            self.images_downloaded = image_downloader.downloaded_count();
+        }
+    }
+}
+
 /// App state shared across handlers
 #[derive(Clone)]
 pub struct AppState {
     pub jobs: Arc<RwLock<HashMap<String, JobStatus>>>,
+    /// The crawls of the jobs that are running or paused
+    pub crawls: Arc<RwLock<HashMap<String, CrawlerStateRef>>>,
+    /// Every job and the results of the finished ones, `jobs`
+    /// has the counts of running jobs as they change
+    pub store: JobStore,
+    /// Each job downloads its images to a directory in here named after it
+    pub images_dir: PathBuf,
 }
 
 impl AppState {
-    pub fn new() -> Self {
-        Self {
-            jobs: Arc::new(RwLock::new(HashMap::new())),
+    /// Loads the jobs in the database at `database`. Jobs that were still
+    /// running when the server stopped can't be resumed and are failed
+    pub async fn open(images_dir: PathBuf, database: &str) -> Result<Self> {
+        let store = JobStore::open(database).await?;
+        let mut jobs = HashMap::new();
+        for mut job in store.jobs().await? {
+            if matches!(job.status, JobState::Pending | JobState::Running | JobState::Paused) {
+                job.status = JobState::Failed;
+                store.save_job(&job).await?;
+            }
+            jobs.insert(job.job_id.clone(), job);
         }
+
+        Ok(Self {
+            jobs: Arc::new(RwLock::new(jobs)),
+            crawls: Arc::new(RwLock::new(HashMap::new())),
+            store,
+            images_dir,
+        })
     }
 }
 
@@ -77,43 +157,46 @@ async fn start_crawl(
     Json(req): Json<CrawlRequest>,
 ) -> Result<Json<CrawlResponse>, StatusCode> {
     let job_id = Uuid::new_v4().to_string();
-    
-    // Validate URL
-    if req.url.is_empty() {
-        return Err(StatusCode::BAD_REQUEST);
+
+    let images_dir = state.images_dir.join(&job_id);
+    let crawler = Crawler::builder()
+        .start_url(&req.url)
+        .max_links(req.max_links as usize)
+        .workers(req.workers as usize)
+        .download_images(&images_dir.to_string_lossy(), req.max_images as usize);
+    // The url is checked before the images directory is created
+    let crawler = crawler.build().map_err(|_| StatusCode::BAD_REQUEST)?;
+    if let Err(e) = tokio::fs::create_dir_all(&images_dir).await {
+        error!("could not create {}: {}", images_dir.display(), e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
     }
-    
+
     // Create job status
     let job = JobStatus {
         job_id: job_id.clone(),
         url: req.url.clone(),
         status: JobState::Running,
         pages_crawled: 0,
+        pages_attempted: 0,
         images_downloaded: 0,
         started_at: chrono::Utc::now().to_rfc3339(),
         completed_at: None,
     };
     
     // Store job
+    if let Err(e) = state.store.save_job(&job).await {
+        error!("could not save crawl job {}: {}", job_id, e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
     state.jobs.write().await.insert(job_id.clone(), job);
-    
-    // TODO: Actually start the crawl in background
-    // For now, we'll simulate it
-    let state_clone = state.clone();
-    let job_id_clone = job_id.clone();
-    tokio::spawn(async move {
-        // Simulate crawling
-        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-        
-        // Update job status
-        if let Some(job) = state_clone.jobs.write().await.get_mut(&job_id_clone) {
-            job.status = JobState::Completed;
-            job.pages_crawled = req.max_links as usize;
-            job.images_downloaded = req.max_images as usize;
-            job.completed_at = Some(chrono::Utc::now().to_rfc3339());
-        }
-    });
-    
+    state
+        .crawls
+        .write()
+        .await
+        .insert(job_id.clone(), crawler.state().clone());
+
+    tokio::spawn(run_job(state.clone(), job_id.clone(), crawler));
+
     Ok(Json(CrawlResponse {
         job_id,
         status: "started".to_string(),
@@ -121,6 +204,230 @@ async fn start_crawl(
     }))
 }
 
+/// Runs a job's crawl, keeping its counts up to date
+/// while it runs and storing its link graph when it's done
+async fn run_job(state: AppState, job_id: String, crawler: Crawler) {
+    let crawler_state = crawler.state().clone();
+    let progress = {
+        let state = state.clone();
+        let job_id = job_id.clone();
+        let crawler_state = crawler_state.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(PROGRESS_INTERVAL).await;
+                if let Some(job) = state.jobs.write().await.get_mut(&job_id) {
+                    job.update_counts(&crawler_state);
+                }
+            }
+        })
+    };
+
+    let result = crawler.run().await;
+    progress.abort();
+    state.crawls.write().await.remove(&job_id);
+
+    // The result is stored before the job is marked as done
+    if let Ok(link_graph) = &result {
+        let images = downloaded_images(&state.images_dir.join(&job_id)).await;
+        if let Err(e) = state.store.save_result(&job_id, link_graph, &images).await {
+            error!("could not save the result of crawl job {}: {}", job_id, e);
+        }
+    }
+
+    let mut jobs = state.jobs.write().await;
+    if let Some(job) = jobs.get_mut(&job_id) {
+        job.update_counts(&crawler_state);
+        job.completed_at = Some(chrono::Utc::now().to_rfc3339());
+        job.status = match &result {
+            Ok(_) if crawler_state.control.is_cancelled() => JobState::Cancelled,
+            Ok(_) => JobState::Completed,
+            Err(e) => {
+                error!("crawl job {} failed: {}", job_id, e);
+                JobState::Failed
+            }
+        };
+        if let Err(e) = state.store.save_job(job).await {
+            error!("could not save crawl job {}: {}", job_id, e);
+        }
+    }
+}
+
+/// The records of the images a job downloaded and kept,
+/// from the download log in its images directory
+async fn downloaded_images(directory: &std::path::Path) -> Vec<serde_json::Value> {
+    let Ok(log) = tokio::fs::read_to_string(directory.join(DOWNLOAD_LOG)).await else {
+        return Vec::new();
+    };
+    read_download_log(&log)
+}
+
+/// What can be done to a running job
+#[derive(Clone, Copy)]
+enum JobAction {
+    Cancel,
+    Pause,
+    Resume,
+}
+
+/// Cancels, pauses or resumes a job. Jobs that aren't running or
+/// paused can't be changed any more
+async fn control_job(
+    state: &AppState,
+    job_id: &str,
+    action: JobAction,
+) -> Result<Json<JobStatus>, StatusCode> {
+    let job = {
+        let crawls = state.crawls.read().await;
+        let mut jobs = state.jobs.write().await;
+        let job = jobs.get_mut(job_id).ok_or(StatusCode::NOT_FOUND)?;
+        let crawler_state = crawls.get(job_id).ok_or(StatusCode::CONFLICT)?;
+
+        let control = &crawler_state.control;
+        match action {
+            JobAction::Cancel => {
+                // A paused crawl's workers have to wake up to stop
+                control.cancel();
+                control.resume();
+            }
+            JobAction::Pause => {
+                control.pause();
+                job.status = JobState::Paused;
+            }
+            JobAction::Resume => {
+                control.resume();
+                job.status = JobState::Running;
+            }
+        }
+        // Saved while `jobs` is locked, so it can't overwrite the job finishing
+        if let Err(e) = state.store.save_job(job).await {
+            error!("could not save crawl job {}: {}", job_id, e);
+        }
+        job.clone()
+    };
+    Ok(Json(job))
+}
+
+/// Stop a job, what it crawled so far is kept as its result
+async fn cancel_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Json<JobStatus>, StatusCode> {
+    control_job(&state, &job_id, JobAction::Cancel).await
+}
+
+/// Pause a job after the pages it's fetching
+async fn pause_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Json<JobStatus>, StatusCode> {
+    control_job(&state, &job_id, JobAction::Pause).await
+}
+
+/// Resume a paused job
+async fn resume_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Json<JobStatus>, StatusCode> {
+    control_job(&state, &job_id, JobAction::Resume).await
+}
+
+/// What's streamed from `/api/jobs/{job_id}/events`
+#[derive(Debug)]
+enum JobEvent {
+    /// A page was crawled
+    Page(PageEvent),
+    /// The crawl's counts, sent every `PROGRESS_INTERVAL`
+    Progress(CrawlStats),
+    /// The job finished, the last event
+    Done(JobStatus),
+}
+
+impl JobEvent {
+    fn to_sse(&self) -> Result<Event, axum::Error> {
+        match self {
+            JobEvent::Page(page) => Event::default().event("page").json_data(page),
+            JobEvent::Progress(stats) => Event::default().event("progress").json_data(stats),
+            JobEvent::Done(job) => Event::default().event("done").json_data(job),
+        }
+    }
+}
+
+/// A job's events as they happen
+struct JobEvents {
+    state: AppState,
+    job_id: String,
+    /// `None` once the job is done
+    crawler_state: Option<CrawlerStateRef>,
+    pages: broadcast::Receiver<PageEvent>,
+    progress: Interval,
+}
+
+impl JobEvents {
+    async fn next(&mut self) -> Option<JobEvent> {
+        let crawler_state = self.crawler_state.clone()?;
+        loop {
+            tokio::select! {
+                page = self.pages.recv() => match page {
+                    Ok(page) => return Some(JobEvent::Page(page)),
+                    // Pages missed by slow clients are in the next progress counts
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => {}
+                },
+                _ = self.progress.tick() => {}
+            }
+
+            let job = self.state.jobs.read().await.get(&self.job_id).cloned()?;
+            if !matches!(job.status, JobState::Running | JobState::Paused) {
+                self.crawler_state = None;
+                return Some(JobEvent::Done(job));
+            }
+            return Some(JobEvent::Progress(CrawlStats::snapshot(&crawler_state).await));
+        }
+    }
+}
+
+/// Stream a job's progress as server-sent events: a `page` event for every
+/// page crawled, `progress` with the counts twice a second and `done` with
+/// the job's status when it's over
+async fn job_events(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, StatusCode> {
+    let job = state
+        .jobs
+        .read()
+        .await
+        .get(&job_id)
+        .cloned()
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let crawler_state = state.crawls.read().await.get(&job_id).cloned();
+    let (crawler_state, pages) = match crawler_state {
+        Some(crawler_state) => {
+            let pages = crawler_state.page_events.subscribe();
+            (Some(crawler_state), pages)
+        }
+        None => (None, broadcast::channel(1).1),
+    };
+
+    let events = JobEvents {
+        state,
+        job_id,
+        crawler_state,
+        pages,
+        progress: tokio::time::interval(PROGRESS_INTERVAL),
+    };
+    // Jobs that are already over only get their `done` event
+    let first = events.crawler_state.is_none().then_some(JobEvent::Done(job));
+    let stream = futures::stream::unfold((first, events), |(first, mut events)| async move {
+        let event = match first {
+            Some(event) => event,
+            None => events.next().await?,
+        };
+        Some((event.to_sse(), (None, events)))
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
 /// Get job status
 async fn get_job_status(
     State(state): State<AppState>,
@@ -134,6 +441,73 @@ async fn get_job_status(
     }
 }
 
+/// Why a job has no results: it's still running, or it's unknown or failed
+async fn missing_result(state: &AppState, job_id: &str) -> StatusCode {
+    match state.jobs.read().await.get(job_id).map(|job| &job.status) {
+        Some(JobState::Pending | JobState::Running | JobState::Paused) => StatusCode::CONFLICT,
+        _ => StatusCode::NOT_FOUND,
+    }
+}
+
+/// The images a finished job downloaded
+async fn job_images(
+    state: &AppState,
+    job_id: &str,
+) -> Result<Vec<serde_json::Value>, StatusCode> {
+    match state.store.images(job_id).await {
+        Ok(Some(images)) => Ok(images),
+        Ok(None) => Err(missing_result(state, job_id).await),
+        Err(e) => {
+            error!("could not load the images of crawl job {}: {}", job_id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Get a finished job's link graph, as a crawl writes it to links.json
+async fn get_job_links(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Json<LinkGraph>, StatusCode> {
+    match state.store.link_graph(&job_id).await {
+        Ok(Some(link_graph)) => Ok(Json(link_graph)),
+        Ok(None) => Err(missing_result(&state, &job_id).await),
+        Err(e) => {
+            error!("could not load the links of crawl job {}: {}", job_id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Get the images a finished job downloaded, with the
+/// `file_name` each can be fetched by
+async fn get_job_images(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
+    job_images(&state, &job_id).await.map(Json)
+}
+
+/// Get an image a finished job downloaded
+async fn get_job_image(
+    State(state): State<AppState>,
+    Path((job_id, file_name)): Path<(String, String)>,
+) -> Result<impl IntoResponse, StatusCode> {
+    // Only files the job downloaded are served, so nothing
+    // outside its images directory can be asked for
+    let images = job_images(&state, &job_id).await?;
+    if !images.iter().any(|image| image["file_name"] == file_name.as_str()) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let path = state.images_dir.join(&job_id).join(&file_name);
+    let bytes = tokio::fs::read(&path)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    let extension = file_name.rsplit_once('.').map_or("", |(_, extension)| extension);
+    Ok(([(header::CONTENT_TYPE, extension_media_type(extension))], bytes))
+}
+
 /// List all jobs
 async fn list_jobs(
     State(state): State<AppState>,
@@ -154,7 +528,14 @@ pub fn create_router(state: AppState) -> Router {
         .route("/health", get(health_check))
         .route("/api/crawl", post(start_crawl))
         .route("/api/jobs", get(list_jobs))
-        .route("/api/jobs/:job_id", get(get_job_status))
+        .route("/api/jobs/{job_id}", get(get_job_status))
+        .route("/api/jobs/{job_id}/events", get(job_events))
+        .route("/api/jobs/{job_id}/links", get(get_job_links))
+        .route("/api/jobs/{job_id}/images", get(get_job_images))
+        .route("/api/jobs/{job_id}/images/{file_name}", get(get_job_image))
+        .route("/api/jobs/{job_id}/cancel", post(cancel_job))
+        .route("/api/jobs/{job_id}/pause", post(pause_job))
+        .route("/api/jobs/{job_id}/resume", post(resume_job))
         .with_state(state)
 }
 
@@ -162,9 +543,155 @@ pub fn create_router(state: AppState) -> Router {
 mod tests {
     use super::*;
     
+    async fn test_state() -> AppState {
+        AppState::open(std::env::temp_dir(), "sqlite::memory:")
+            .await
+            .unwrap()
+    }
+
     #[tokio::test]
     async fn test_health_check() {
         let response = health_check().await;
         assert_eq!(response, "OK");
     }
+
+    #[tokio::test]
+    async fn test_start_crawl_rejects_invalid_url() {
+        let state = test_state().await;
+        let request = CrawlRequest {
+            url: String::from("not a url"),
+            max_links: 10,
+            max_images: 0,
+            workers: 1,
+        };
+        let response = start_crawl(State(state.clone()), Json(request)).await;
+        assert_eq!(response.err(), Some(StatusCode::BAD_REQUEST));
+        assert!(state.jobs.read().await.is_empty());
+    }
+
+    fn running_job(job_id: &str) -> JobStatus {
+        JobStatus {
+            job_id: job_id.to_string(),
+            url: String::from("http://127.0.0.1:9/"),
+            status: JobState::Running,
+            pages_crawled: 0,
+            pages_attempted: 0,
+            images_downloaded: 0,
+            started_at: chrono::Utc::now().to_rfc3339(),
+            completed_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pause_and_cancel_job() {
+        let state = test_state().await;
+        let crawler = Crawler::builder()
+            .start_url("http://127.0.0.1:9/")
+            .build()
+            .unwrap();
+        let crawler_state = crawler.state().clone();
+        let job_id = String::from("job");
+        state
+            .jobs
+            .write()
+            .await
+            .insert(job_id.clone(), running_job(&job_id));
+        state
+            .crawls
+            .write()
+            .await
+            .insert(job_id.clone(), crawler_state.clone());
+
+        let job = control_job(&state, &job_id, JobAction::Pause).await.unwrap();
+        assert!(matches!(job.status, JobState::Paused));
+        assert!(crawler_state.control.is_paused());
+
+        let cancelled = control_job(&state, &job_id, JobAction::Cancel).await;
+        assert!(cancelled.is_ok());
+        assert!(crawler_state.control.is_cancelled());
+        assert!(!crawler_state.control.is_paused());
+
+        // A cancelled crawl stops before fetching anything
+        crawler.run().await.unwrap();
+        assert_eq!(crawler_state.attempted_count.load(Ordering::Relaxed), 0);
+
+        let unknown = control_job(&state, "other", JobAction::Cancel).await;
+        assert_eq!(unknown.err(), Some(StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn test_job_events() {
+        let state = test_state().await;
+        let crawler = Crawler::builder()
+            .start_url("http://127.0.0.1:9/")
+            .build()
+            .unwrap();
+        let crawler_state = crawler.state().clone();
+        state
+            .jobs
+            .write()
+            .await
+            .insert(String::from("job"), running_job("job"));
+
+        let mut events = JobEvents {
+            state: state.clone(),
+            job_id: String::from("job"),
+            crawler_state: Some(crawler_state.clone()),
+            pages: crawler_state.page_events.subscribe(),
+            progress: tokio::time::interval(PROGRESS_INTERVAL),
+        };
+        events.progress.tick().await;
+
+        let page = PageEvent {
+            url: String::from("http://127.0.0.1:9/"),
+            links: 3,
+            error: None,
+        };
+        crawler_state.page_events.send(page).unwrap();
+        assert!(matches!(events.next().await, Some(JobEvent::Page(page)) if page.links == 3));
+        assert!(matches!(events.next().await, Some(JobEvent::Progress(_))));
+
+        state.jobs.write().await.get_mut("job").unwrap().status = JobState::Completed;
+        assert!(matches!(events.next().await, Some(JobEvent::Done(_))));
+        assert!(events.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_job_results() {
+        let images_dir = std::env::temp_dir().join(format!("api_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(images_dir.join("job")).unwrap();
+        std::fs::write(images_dir.join("job").join("cat.png"), b"png").unwrap();
+        let state = AppState::open(images_dir.clone(), "sqlite::memory:")
+            .await
+            .unwrap();
+        let job = running_job("job");
+        state.store.save_job(&job).await.unwrap();
+        state.jobs.write().await.insert(String::from("job"), job);
+
+        let running = get_job_links(State(state.clone()), Path(String::from("job"))).await;
+        assert_eq!(running.err(), Some(StatusCode::CONFLICT));
+
+        let images = vec![serde_json::json!({
+            "link": "http://127.0.0.1:9/cat.png",
+            "file_name": "cat.png",
+        })];
+        state
+            .store
+            .save_result("job", &LinkGraph::default(), &images)
+            .await
+            .unwrap();
+        let links = get_job_links(State(state.clone()), Path(String::from("job"))).await;
+        assert!(links.is_ok());
+
+        let image = |name: &str| {
+            let path = Path((String::from("job"), name.to_string()));
+            get_job_image(State(state.clone()), path)
+        };
+        let response = image("cat.png").await.unwrap().into_response();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        let outside = image("../job/cat.png").await;
+        assert_eq!(outside.err(), Some(StatusCode::NOT_FOUND));
+
+        let _ = std::fs::remove_dir_all(images_dir);
+    }
 }
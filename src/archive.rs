@@ -0,0 +1,154 @@
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::ValueEnum;
+use log2::*;
+use reqwest::Client;
+use scraper::{Html, Selector};
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use url::Url;
+use uuid::Uuid;
+
+use crate::url_utils::url_file_stem;
+
+/// Base64 lines in MIME parts can't be longer than this
+const MIME_LINE_LENGTH: usize = 76;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ArchiveFormat {
+    /// A single `.mhtml` file per page with its images and CSS inlined
+    Mhtml,
+}
+
+/// A file the page depends on, stored alongside its HTML
+struct Resource {
+    url: String,
+    content_type: String,
+    body: Vec<u8>,
+}
+
+/// The images and stylesheets `html` loads, as absolute urls
+fn find_resources(html: &str, page_url: &Url) -> Vec<Url> {
+    let html_dom = Html::parse_document(html);
+    let selectors = [("img[src]", "src"), ("link[rel~=stylesheet][href]", "href")];
+
+    let mut seen = HashSet::new();
+    let mut resources = Vec::new();
+    for (selector, attr) in selectors {
+        let selector = Selector::parse(selector).unwrap();
+        for element in html_dom.select(&selector) {
+            let Some(url) = element
+                .value()
+                .attr(attr)
+                .and_then(|value| page_url.join(value).ok())
+            else {
+                continue;
+            };
+
+            if matches!(url.scheme(), "http" | "https") && seen.insert(url.to_string()) {
+                resources.push(url);
+            }
+        }
+    }
+
+    resources
+}
+
+async fn fetch_resource(url: Url, client: &Client) -> Result<Resource> {
+    let response = client.get(url.clone()).send().await?.error_for_status()?;
+    let content_type = response
+        .headers()
+        .get("content-type")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+
+    Ok(Resource {
+        url: url.to_string(),
+        content_type,
+        body: response.bytes().await?.to_vec(),
+    })
+}
+
+/// Where the archive of `url` is saved inside `archive_dir`
+fn archive_path(archive_dir: &Path, url: &Url) -> PathBuf {
+    archive_dir.join(format!("{}.mhtml", url_file_stem(url)))
+}
+
+fn write_part(mhtml: &mut String, boundary: &str, content_type: &str, location: &str, body: &[u8]) {
+    let _ = write!(
+        mhtml,
+        "--{}\r\nContent-Type: {}\r\nContent-Transfer-Encoding: base64\r\nContent-Location: {}\r\n\r\n",
+        boundary, content_type, location
+    );
+
+    let encoded = STANDARD.encode(body);
+    for line in encoded.as_bytes().chunks(MIME_LINE_LENGTH) {
+        // base64 output is always ASCII
+        mhtml.push_str(std::str::from_utf8(line).unwrap_or_default());
+        mhtml.push_str("\r\n");
+    }
+}
+
+/// Builds an MHTML document holding `html` and every resource
+fn build_mhtml(page_url: &Url, html: &str, resources: &[Resource]) -> String {
+    let boundary = format!("----MultipartBoundary--{}----", Uuid::new_v4());
+    let mut mhtml = String::new();
+
+    let _ = write!(
+        mhtml,
+        "From: <Saved by HyperCrawl>\r\n\
+         Snapshot-Content-Location: {}\r\n\
+         Date: {}\r\n\
+         MIME-Version: 1.0\r\n\
+         Content-Type: multipart/related; type=\"text/html\"; boundary=\"{}\"\r\n\r\n",
+        page_url,
+        chrono::Utc::now().to_rfc2822(),
+        boundary
+    );
+
+    write_part(
+        &mut mhtml,
+        &boundary,
+        "text/html; charset=utf-8",
+        page_url.as_str(),
+        html.as_bytes(),
+    );
+    for resource in resources {
+        write_part(
+            &mut mhtml,
+            &boundary,
+            &resource.content_type,
+            &resource.url,
+            &resource.body,
+        );
+    }
+
+    let _ = write!(mhtml, "--{}--\r\n", boundary);
+    mhtml
+}
+
+/// Saves the page at `page_url` with its images and stylesheets
+/// as a single MHTML file, returning where it was saved
+pub async fn archive_page(
+    archive_dir: &Path,
+    page_url: &Url,
+    html: &str,
+    client: &Client,
+) -> Result<PathBuf> {
+    let mut resources = Vec::new();
+    for url in find_resources(html, page_url) {
+        match fetch_resource(url.clone(), client).await {
+            Ok(resource) => resources.push(resource),
+            Err(e) => warn!("could not archive {} for {}: {}", url, page_url, e),
+        }
+    }
+
+    fs::create_dir_all(archive_dir).await?;
+    let path = archive_path(archive_dir, page_url);
+    fs::write(&path, build_mhtml(page_url, html, &resources)).await?;
+
+    Ok(path)
+}
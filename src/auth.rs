@@ -0,0 +1,169 @@
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use reqwest::header::HeaderValue;
+use std::collections::HashMap;
+use url::Url;
+
+/// How requests to a site are authenticated
+#[derive(Clone, Debug, PartialEq)]
+pub enum Credentials {
+    Basic { username: String, password: String },
+    Bearer(String),
+}
+
+impl Credentials {
+    fn authorization(&self) -> Result<HeaderValue> {
+        let value = match self {
+            Credentials::Basic { username, password } => {
+                format!(
+                    "Basic {}",
+                    STANDARD.encode(format!("{}:{}", username, password))
+                )
+            }
+            Credentials::Bearer(token) => format!("Bearer {}", token),
+        };
+
+        let mut value = HeaderValue::from_str(&value).context("invalid credentials")?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
+}
+
+/// Parses basic auth credentials written as `user:pass`
+pub fn parse_basic_auth(user_pass: &str) -> Result<Credentials> {
+    let (username, password) = user_pass
+        .split_once(':')
+        .ok_or_else(|| anyhow!("basic auth credentials should be written as user:pass"))?;
+
+    Ok(Credentials::Basic {
+        username: username.to_string(),
+        password: password.to_string(),
+    })
+}
+
+/// The `Authorization` header sent with page requests, picked by host
+#[derive(Clone, Debug, Default)]
+pub struct Auth {
+    /// Sent to hosts without their own credentials
+    default: Option<HeaderValue>,
+    /// By host, or `host:port`
+    hosts: HashMap<String, HeaderValue>,
+}
+
+impl Auth {
+    /// Sends `default` to every host, except the ones listed in
+    /// `credentials_file` which get their own credentials
+    pub fn new(default: Option<Credentials>, credentials_file: Option<&str>) -> Result<Self> {
+        let hosts = match credentials_file {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .with_context(|| format!("could not read the credentials in {}", path))?;
+                parse_credentials(&contents)
+                    .with_context(|| format!("invalid credentials file {}", path))?
+            }
+            None => HashMap::new(),
+        };
+
+        Ok(Self {
+            default: default
+                .map(|credentials| credentials.authorization())
+                .transpose()?,
+            hosts: hosts
+                .into_iter()
+                .map(|(host, credentials)| Ok((host, credentials.authorization()?)))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// The `Authorization` header to send to `url`, if any
+    pub fn authorization(&self, url: &Url) -> Option<&HeaderValue> {
+        let host = url.host_str()?.to_lowercase();
+        let host_port = url
+            .port_or_known_default()
+            .map(|port| format!("{}:{}", host, port));
+
+        host_port
+            .and_then(|host_port| self.hosts.get(&host_port))
+            .or_else(|| self.hosts.get(&host))
+            .or(self.default.as_ref())
+    }
+}
+
+/// Parses a credentials file, one host per line written as
+/// `<host> basic <user>:<pass>` or `<host> bearer <token>`
+fn parse_credentials(contents: &str) -> Result<HashMap<String, Credentials>> {
+    let mut hosts = HashMap::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let (Some(host), Some(scheme), Some(secret), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            bail!(
+                "line {} should be written as <host> <basic|bearer> <credentials>",
+                i + 1
+            );
+        };
+
+        let credentials = match scheme.to_lowercase().as_str() {
+            "basic" => parse_basic_auth(secret).with_context(|| format!("line {}", i + 1))?,
+            "bearer" => Credentials::Bearer(secret.to_string()),
+            other => bail!("unknown auth scheme '{}' on line {}", other, i + 1),
+        };
+        hosts.insert(host.to_lowercase(), credentials);
+    }
+
+    Ok(hosts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credentials_by_host() {
+        let hosts = parse_credentials(
+            "# staging sites\n\
+             staging.example.com basic admin:hunter2\n\
+             api.example.com:8443 bearer abc123\n",
+        )
+        .unwrap();
+        let auth = Auth {
+            default: Some(
+                parse_basic_auth("me:secret")
+                    .unwrap()
+                    .authorization()
+                    .unwrap(),
+            ),
+            hosts: hosts
+                .into_iter()
+                .map(|(host, credentials)| (host, credentials.authorization().unwrap()))
+                .collect(),
+        };
+        let authorization = |url: &str| {
+            auth.authorization(&Url::parse(url).unwrap())
+                .map(|value| value.to_str().unwrap().to_string())
+        };
+
+        assert_eq!(
+            authorization("https://staging.example.com/a").as_deref(),
+            Some("Basic YWRtaW46aHVudGVyMg==")
+        );
+        assert_eq!(
+            authorization("https://api.example.com:8443/v1").as_deref(),
+            Some("Bearer abc123")
+        );
+        assert_eq!(
+            authorization("https://api.example.com/v1").as_deref(),
+            Some("Basic bWU6c2VjcmV0")
+        );
+
+        assert!(parse_credentials("example.com digest a:b").is_err());
+        assert!(parse_basic_auth("no-password").is_err());
+    }
+}
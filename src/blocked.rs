@@ -0,0 +1,105 @@
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use std::fmt;
+
+/// Only the start of a page is searched, challenge markers are near the top
+const SEARCHED_BYTES: usize = 16 * 1024;
+
+/// Markers only found on challenge and block pages, whatever their status
+const CHALLENGE_MARKERS: [(&str, &str); 7] = [
+    ("cf_chl_opt", "cloudflare challenge"),
+    ("/cdn-cgi/challenge-platform/", "cloudflare challenge"),
+    ("cf-browser-verification", "cloudflare challenge"),
+    ("captcha-delivery.com", "datadome captcha"),
+    ("_Incapsula_Resource", "imperva block"),
+    ("px-captcha", "perimeterx captcha"),
+    ("/_sec/cp_challenge/", "akamai challenge"),
+];
+
+/// Markers that also show up on regular pages, e.g. a contact form's
+/// captcha, so they only count on error responses
+const ERROR_MARKERS: [(&str, &str); 4] = [
+    ("g-recaptcha", "captcha"),
+    ("h-captcha", "captcha"),
+    ("<title>Access Denied</title>", "access denied"),
+    ("<title>Attention Required!", "cloudflare block"),
+];
+
+/// A page that was a bot challenge or block page instead of the real page
+#[derive(Debug)]
+pub struct PageBlocked(pub &'static str);
+
+impl fmt::Display for PageBlocked {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "page was blocked: {}", self.0)
+    }
+}
+
+impl std::error::Error for PageBlocked {}
+
+/// The statuses block pages are usually served with
+pub fn is_block_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+    )
+}
+
+/// Why the response is a challenge or block page rather than the page asked for
+pub fn detect_block(status: StatusCode, headers: &HeaderMap, body: &str) -> Option<&'static str> {
+    if headers
+        .get("cf-mitigated")
+        .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"challenge"))
+    {
+        return Some("cloudflare challenge");
+    }
+
+    let mut end = body.len().min(SEARCHED_BYTES);
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    let head = &body[..end];
+
+    let markers = CHALLENGE_MARKERS.iter();
+    let error_markers = is_block_status(status)
+        .then_some(ERROR_MARKERS.iter())
+        .into_iter()
+        .flatten();
+    markers
+        .chain(error_markers)
+        .find(|(marker, _)| head.contains(marker))
+        .map(|(_, reason)| *reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_block() {
+        let headers = HeaderMap::new();
+        let challenge = r#"<title>Just a moment...</title><script>window._cf_chl_opt={}</script>"#;
+        assert_eq!(
+            detect_block(StatusCode::FORBIDDEN, &headers, challenge),
+            Some("cloudflare challenge")
+        );
+        assert_eq!(
+            detect_block(StatusCode::OK, &headers, challenge),
+            Some("cloudflare challenge")
+        );
+
+        let contact_form = r#"<form><div class="g-recaptcha"></div></form>"#;
+        assert_eq!(detect_block(StatusCode::OK, &headers, contact_form), None);
+        assert_eq!(
+            detect_block(StatusCode::FORBIDDEN, &headers, contact_form),
+            Some("captcha")
+        );
+
+        let mut mitigated = HeaderMap::new();
+        mitigated.insert("cf-mitigated", "challenge".parse().unwrap());
+        assert_eq!(
+            detect_block(StatusCode::FORBIDDEN, &mitigated, ""),
+            Some("cloudflare challenge")
+        );
+    }
+}
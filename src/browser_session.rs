@@ -0,0 +1,269 @@
+use anyhow::{Context, Result};
+use reqwest::cookie::{CookieStore, Jar};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, COOKIE};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use url::Url;
+
+/// Request headers that describe a single request or connection
+/// rather than the session, so they're never imported
+const SKIPPED_HEADERS: [&str; 14] = [
+    "host",
+    "connection",
+    "content-length",
+    "content-type",
+    "accept-encoding",
+    "transfer-encoding",
+    "keep-alive",
+    "upgrade",
+    "te",
+    "if-none-match",
+    "if-modified-since",
+    "referer",
+    "origin",
+    "range",
+];
+
+#[derive(Deserialize)]
+struct Har {
+    log: HarLog,
+}
+
+#[derive(Deserialize)]
+struct HarLog {
+    entries: Vec<HarEntry>,
+}
+
+#[derive(Deserialize)]
+struct HarEntry {
+    request: HarRequest,
+}
+
+#[derive(Deserialize)]
+struct HarRequest {
+    url: String,
+    #[serde(default)]
+    headers: Vec<HarHeader>,
+}
+
+#[derive(Deserialize)]
+struct HarHeader {
+    name: String,
+    value: String,
+}
+
+/// Cookies and headers exported from a browser that's logged in to
+/// the site, sent with page requests to crawl as that user
+#[derive(Clone, Default)]
+pub struct BrowserSession {
+    cookies: Arc<Jar>,
+    /// The headers the browser last sent to each host
+    headers: Arc<HashMap<String, HeaderMap>>,
+    cookie_count: usize,
+}
+
+impl BrowserSession {
+    /// Loads a HAR file saved from the browser's dev tools,
+    /// or a Netscape cookies.txt file
+    pub fn load(path: &str) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read the session in {}", path))?;
+
+        if contents.trim_start().starts_with('{') {
+            let har: Har = serde_json::from_str(&contents)
+                .with_context(|| format!("{} is not a HAR file", path))?;
+            Ok(Self::from_har(har))
+        } else {
+            Ok(Self::from_cookies_txt(&contents))
+        }
+    }
+
+    fn from_har(har: Har) -> Self {
+        let cookies = Jar::default();
+        let mut cookie_count = 0;
+        let mut headers: HashMap<String, HeaderMap> = HashMap::new();
+
+        for entry in har.log.entries {
+            let Ok(url) = Url::parse(&entry.request.url) else {
+                continue;
+            };
+            let Some(host) = url.host_str() else {
+                continue;
+            };
+
+            // Later requests overwrite earlier ones, so what's
+            // kept is what the browser sent most recently
+            let host_headers = headers.entry(host.to_string()).or_default();
+            for header in entry.request.headers {
+                let name = header.name.to_lowercase();
+                if name == "cookie" {
+                    for cookie in header.value.split(';').map(str::trim) {
+                        if cookie.contains('=') {
+                            cookies.add_cookie_str(&format!("{}; Path=/", cookie), &url);
+                            cookie_count += 1;
+                        }
+                    }
+                    continue;
+                }
+                if name.starts_with(':') || name.starts_with("sec-fetch-") {
+                    continue;
+                }
+                if SKIPPED_HEADERS.contains(&name.as_str()) {
+                    continue;
+                }
+
+                let (Ok(name), Ok(mut value)) = (
+                    HeaderName::from_bytes(name.as_bytes()),
+                    HeaderValue::from_str(&header.value),
+                ) else {
+                    continue;
+                };
+                value.set_sensitive(name == reqwest::header::AUTHORIZATION);
+                host_headers.insert(name, value);
+            }
+        }
+
+        Self {
+            cookies: Arc::new(cookies),
+            headers: Arc::new(headers),
+            cookie_count,
+        }
+    }
+
+    /// Reads cookies in the Netscape format browser extensions and curl
+    /// export: domain, subdomains, path, secure, expiry, name and value
+    /// separated by tabs
+    fn from_cookies_txt(contents: &str) -> Self {
+        let cookies = Jar::default();
+        let mut cookie_count = 0;
+        let now = chrono::Utc::now().timestamp();
+
+        for line in contents.lines() {
+            // curl marks HttpOnly cookies like a comment
+            let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [domain, subdomains, path, secure, expiry, name, value] = fields[..] else {
+                continue;
+            };
+            if expiry
+                .parse::<i64>()
+                .is_ok_and(|expiry| expiry != 0 && expiry < now)
+            {
+                continue;
+            }
+
+            let host = domain.trim_start_matches('.');
+            let secure = secure.eq_ignore_ascii_case("TRUE");
+            let scheme = if secure { "https" } else { "http" };
+            let Ok(url) = Url::parse(&format!("{}://{}{}", scheme, host, path)) else {
+                continue;
+            };
+
+            let mut cookie = format!("{}={}; Path={}", name, value, path);
+            if subdomains.eq_ignore_ascii_case("TRUE") {
+                cookie.push_str(&format!("; Domain={}", host));
+            }
+            if secure {
+                cookie.push_str("; Secure");
+            }
+            cookies.add_cookie_str(&cookie, &url);
+            cookie_count += 1;
+        }
+
+        Self {
+            cookies: Arc::new(cookies),
+            headers: Arc::new(HashMap::new()),
+            cookie_count,
+        }
+    }
+
+    pub fn cookie_count(&self) -> usize {
+        self.cookie_count
+    }
+
+    /// How many hosts there are headers for
+    pub fn host_count(&self) -> usize {
+        self.headers.len()
+    }
+
+    /// The cookies and headers to send with a request to `url`
+    pub fn headers(&self, url: &Url) -> HeaderMap {
+        let mut headers = url
+            .host_str()
+            .and_then(|host| self.headers.get(host))
+            .cloned()
+            .unwrap_or_default();
+
+        if let Some(mut cookies) = self.cookies.cookies(url) {
+            cookies.set_sensitive(true);
+            headers.insert(COOKIE, cookies);
+        }
+        headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+        headers.get(name).map(|value| value.to_str().unwrap())
+    }
+
+    #[test]
+    fn test_import_har() {
+        let har = r#"{"log": {"entries": [
+            {"request": {"url": "https://app.example.com/dashboard", "headers": [
+                {"name": ":authority", "value": "app.example.com"},
+                {"name": "Cookie", "value": "session=abc; theme=dark"},
+                {"name": "X-CSRF-Token", "value": "old"},
+                {"name": "Accept-Encoding", "value": "gzip"}
+            ]}},
+            {"request": {"url": "https://app.example.com/settings", "headers": [
+                {"name": "X-CSRF-Token", "value": "new"}
+            ]}}
+        ]}}"#;
+        let session = BrowserSession::from_har(serde_json::from_str(har).unwrap());
+
+        let headers = session.headers(&Url::parse("https://app.example.com/reports/1").unwrap());
+        assert_eq!(header(&headers, "x-csrf-token"), Some("new"));
+        let mut cookies: Vec<&str> = header(&headers, "cookie").unwrap().split("; ").collect();
+        cookies.sort();
+        assert_eq!(cookies, ["session=abc", "theme=dark"]);
+        assert_eq!(header(&headers, "accept-encoding"), None);
+
+        let other = session.headers(&Url::parse("https://example.org/").unwrap());
+        assert!(other.is_empty());
+    }
+
+    #[test]
+    fn test_import_cookies_txt() {
+        let cookies_txt = "# Netscape HTTP Cookie File\n\
+             .example.com\tTRUE\t/\tTRUE\t0\tsession\tabc\n\
+             #HttpOnly_example.com\tFALSE\t/admin\tFALSE\t0\tadmin\tyes\n\
+             example.com\tFALSE\t/\tFALSE\t1\texpired\tgone\n";
+        let session = BrowserSession::from_cookies_txt(cookies_txt);
+        assert_eq!(session.cookie_count(), 2);
+
+        let cookies = |url: &str| {
+            session
+                .headers(&Url::parse(url).unwrap())
+                .get(COOKIE)
+                .map(|value| value.to_str().unwrap().to_string())
+        };
+        assert_eq!(
+            cookies("https://www.example.com/").as_deref(),
+            Some("session=abc")
+        );
+        assert_eq!(
+            cookies("http://example.com/admin/users").as_deref(),
+            Some("admin=yes")
+        );
+        assert_eq!(cookies("http://example.com/"), None);
+    }
+}
@@ -0,0 +1,150 @@
+use chrono::{DateTime, Duration, Utc};
+use reqwest::header::{HeaderMap, HeaderName, AGE, CACHE_CONTROL, DATE, EXPIRES};
+use serde::{Deserialize, Serialize};
+
+/// The caching headers a page was served with, and until when
+/// they say the page doesn't need to be fetched again
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Caching {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_control: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+    /// `None` when the headers don't say how long the page stays fresh
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fresh_until: Option<DateTime<Utc>>,
+}
+
+impl Caching {
+    /// Reads the caching headers of a response that arrived at
+    /// `received_at`, `None` if it didn't have any
+    pub fn from_headers(headers: &HeaderMap, received_at: DateTime<Utc>) -> Option<Self> {
+        let header = |name: HeaderName| {
+            let value = headers.get(name)?.to_str().ok()?.trim();
+            Some(value.to_string())
+        };
+        let cache_control = header(CACHE_CONTROL);
+        let expires = header(EXPIRES);
+        if cache_control.is_none() && expires.is_none() {
+            return None;
+        }
+
+        // Time spent in caches on the way counts against the lifetime
+        let age = header(AGE)
+            .and_then(|age| age.parse::<u32>().ok())
+            .map_or(Duration::zero(), |age| Duration::seconds(age.into()));
+        let date = header(DATE).and_then(|date| parse_http_date(&date));
+        let fresh_until = lifetime(
+            cache_control.as_deref(),
+            expires.as_deref(),
+            date.unwrap_or(received_at),
+        )
+        .map(|lifetime| received_at + (lifetime - age).max(Duration::zero()));
+
+        Some(Self {
+            cache_control,
+            expires,
+            fresh_until,
+        })
+    }
+
+    /// Whether the page should be fetched again at `now`
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        self.fresh_until
+            .is_none_or(|fresh_until| fresh_until <= now)
+    }
+}
+
+fn parse_http_date(date: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(date)
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+/// How long a response stays fresh for. As in RFC 9111, `max-age` wins
+/// over `Expires`, which is measured against the server's `date`
+fn lifetime(
+    cache_control: Option<&str>,
+    expires: Option<&str>,
+    date: DateTime<Utc>,
+) -> Option<Duration> {
+    if let Some(cache_control) = cache_control {
+        let mut max_age = None;
+        for directive in cache_control.split(',') {
+            let directive = directive.trim().to_ascii_lowercase();
+            match directive.split_once('=') {
+                // The page has to be checked with the server every time
+                None if directive == "no-store" || directive == "no-cache" => {
+                    return Some(Duration::zero())
+                }
+                Some(("max-age", seconds)) => {
+                    max_age = seconds.trim_matches('"').parse::<u32>().ok();
+                }
+                _ => {}
+            }
+        }
+        if let Some(max_age) = max_age {
+            return Some(Duration::seconds(max_age.into()));
+        }
+    }
+
+    // Invalid dates, usually "0" or "-1", mean the page already expired
+    let expires = expires?;
+    Some(parse_http_date(expires).map_or(Duration::zero(), |expires| expires - date))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn caching(headers: &[(HeaderName, &'static str)]) -> Option<Caching> {
+        let headers: HeaderMap = headers
+            .iter()
+            .map(|(name, value)| (name.clone(), HeaderValue::from_static(value)))
+            .collect();
+        let received_at = parse_http_date("Mon, 06 Jan 2025 12:00:00 GMT").unwrap();
+        Caching::from_headers(&headers, received_at)
+    }
+
+    fn fresh_until(headers: &[(HeaderName, &'static str)]) -> Option<String> {
+        caching(headers)?
+            .fresh_until
+            .map(|fresh_until| fresh_until.to_rfc3339())
+    }
+
+    #[test]
+    fn test_fresh_until() {
+        assert_eq!(caching(&[]), None);
+        assert_eq!(
+            fresh_until(&[(CACHE_CONTROL, "public, max-age=3600"), (AGE, "600")]).as_deref(),
+            Some("2025-01-06T12:50:00+00:00")
+        );
+        // max-age wins over Expires
+        assert_eq!(
+            fresh_until(&[
+                (CACHE_CONTROL, "max-age=60"),
+                (EXPIRES, "Tue, 07 Jan 2025 12:00:00 GMT")
+            ])
+            .as_deref(),
+            Some("2025-01-06T12:01:00+00:00")
+        );
+        // Expires is measured against the server's clock, here an hour behind
+        assert_eq!(
+            fresh_until(&[
+                (EXPIRES, "Mon, 06 Jan 2025 13:00:00 GMT"),
+                (DATE, "Mon, 06 Jan 2025 11:00:00 GMT")
+            ])
+            .as_deref(),
+            Some("2025-01-06T14:00:00+00:00")
+        );
+        assert_eq!(
+            fresh_until(&[(CACHE_CONTROL, "no-cache"), (EXPIRES, "0")]).as_deref(),
+            Some("2025-01-06T12:00:00+00:00")
+        );
+        assert_eq!(fresh_until(&[(CACHE_CONTROL, "private")]), None);
+        assert!(caching(&[(CACHE_CONTROL, "private")])
+            .unwrap()
+            .is_due(Utc::now()));
+    }
+}
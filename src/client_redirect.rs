@@ -0,0 +1,77 @@
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// Scripts longer than this do more than redirect, a `location`
+/// assignment in them usually only runs on a click or a condition
+pub const REDIRECT_SCRIPT_MAX_BYTES: usize = 1024;
+
+/// `location = "..."`, `location.href = "..."` and `location.replace("...")`
+/// on `window`, `document`, `top` or `self`, or on its own
+static LOCATION_ASSIGNMENT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#"(?:\b(?:window|document|top|self)\.)?\blocation(?:\.href\s*=\s*|\s*=\s*|\.(?:replace|assign)\(\s*)["']([^"']+)["']"#,
+    )
+    .unwrap()
+});
+
+/// Where a `<meta http-equiv="refresh">` sends the browser, written as
+/// `5; url=/next`. `None` when it only reloads the page
+pub fn meta_refresh_target(content: &str) -> Option<&str> {
+    let (_delay, target) = content.split_once([';', ','])?;
+    let target = target.trim();
+    let target = match target.get(..3) {
+        Some(key) if key.eq_ignore_ascii_case("url") => target[3..].trim_start(),
+        _ => target,
+    };
+    let target = target.strip_prefix('=').unwrap_or(target).trim();
+    let target = target.trim_matches(['"', '\'']).trim();
+    (!target.is_empty()).then_some(target)
+}
+
+/// Where a short inline script sends the browser by setting `location`
+pub fn javascript_target(script: &str) -> Option<&str> {
+    if script.len() > REDIRECT_SCRIPT_MAX_BYTES {
+        return None;
+    }
+    let target = LOCATION_ASSIGNMENT
+        .captures(script)?
+        .get(1)?
+        .as_str()
+        .trim();
+    // e.g. `location.href = "#top"` or `"javascript:void(0)"`
+    let is_redirect = !target.starts_with('#') && !target.starts_with("javascript:");
+    is_redirect.then_some(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redirect_targets() {
+        assert_eq!(meta_refresh_target("0; url=/new"), Some("/new"));
+        assert_eq!(
+            meta_refresh_target("5;URL='https://example.com/'"),
+            Some("https://example.com/")
+        );
+        assert_eq!(meta_refresh_target("3, /later"), Some("/later"));
+        assert_eq!(meta_refresh_target("30"), None);
+
+        assert_eq!(
+            javascript_target(r#"window.location.href = "/moved";"#),
+            Some("/moved")
+        );
+        assert_eq!(
+            javascript_target("location.replace('https://example.com/a')"),
+            Some("https://example.com/a")
+        );
+        assert_eq!(javascript_target(r#"top.location="/b""#), Some("/b"));
+        assert_eq!(javascript_target("var hash = location.hash;"), None);
+        assert_eq!(javascript_target("location.href = '#top'"), None);
+        let long_script = format!(
+            "{}\nwindow.location = '/logout';",
+            "x();".repeat(REDIRECT_SCRIPT_MAX_BYTES)
+        );
+        assert_eq!(javascript_target(&long_script), None);
+    }
+}
@@ -0,0 +1,445 @@
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use tokio::fs;
+
+use rust_crawler::analysis::depth_histogram;
+use crate::commands::export::load_links;
+use rust_crawler::content::ContentMetrics;
+use rust_crawler::model::{link_key, Link, LinkGraph, NodeKind};
+use rust_crawler::url_utils::{normalize_url, NormalizeOptions};
+
+#[derive(Args, Clone, Debug)]
+pub struct AnalyzeArgs {
+    /// The links file written by a crawl
+    #[arg(short, long, default_value_t = String::from("links.json"))]
+    input: String,
+
+    /// A CSV or text file of urls from another source (e.g. a Search Console
+    /// or analytics export) to reconcile the crawl's coverage against
+    #[arg(long)]
+    compare_urls: Option<String>,
+
+    /// Where to write the coverage report
+    #[arg(long, default_value_t = String::from("coverage.json"))]
+    coverage_json: String,
+
+    /// Report how many pages sit at each click depth, listing
+    /// the pages more than this many clicks from the starting url
+    #[arg(long)]
+    deeper_than: Option<usize>,
+
+    /// Where to write the click depth report
+    #[arg(long, default_value_t = String::from("depth.json"))]
+    depth_json: String,
+
+    /// List this many of the most linked-to pages and the pages
+    /// with the most outlinks, along with every dead-end page
+    #[arg(long)]
+    top_links: Option<usize>,
+
+    /// Where to write the inlink and outlink report
+    #[arg(long, default_value_t = String::from("link_counts.json"))]
+    link_counts_json: String,
+
+    /// List the pages with fewer than this many words of visible text
+    #[arg(long)]
+    thin_content: Option<usize>,
+
+    /// Where to write the thin content report
+    #[arg(long, default_value_t = String::from("thin_content.json"))]
+    thin_content_json: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct CoverageReport {
+    /// Listed urls that were also crawled
+    matched: usize,
+    /// Pages the crawl fetched that the list doesn't have
+    crawled_not_listed: Vec<String>,
+    /// Listed urls the crawl never fetched
+    listed_not_crawled: Vec<String>,
+}
+
+/// Splits a CSV line into fields, handling quoted fields with `""` escapes
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+
+    fields.push(field);
+    fields
+}
+
+/// The first http(s) url in every line of `csv`. Header rows
+/// and extra columns (clicks, impressions, ...) are ignored.
+fn parse_url_list(csv: &str) -> Vec<String> {
+    csv.lines()
+        .filter_map(|line| {
+            csv_fields(line).into_iter().find_map(|field| {
+                normalize_url(field.trim(), &NormalizeOptions::default()).map(|url| url.to_string())
+            })
+        })
+        .collect()
+}
+
+fn compare_coverage(links: &LinkGraph, listed: &[String]) -> CoverageReport {
+    // Keyed without the scheme, like the link graph itself
+    let crawled: BTreeMap<&str, &str> = links
+        .into_iter()
+        .filter(|(_, link)| link.fetched_at.is_some())
+        .map(|(_, link)| (link_key(&link.url), link.url.as_str()))
+        .collect();
+    let listed_keys: HashSet<&str> = listed.iter().map(|url| link_key(url)).collect();
+
+    let mut report = CoverageReport::default();
+    let mut seen = HashSet::new();
+    for url in listed {
+        let key = link_key(url);
+        if !seen.insert(key) {
+            continue;
+        }
+
+        match crawled.contains_key(key) {
+            true => report.matched += 1,
+            false => report.listed_not_crawled.push(url.clone()),
+        }
+    }
+
+    report.crawled_not_listed = crawled
+        .iter()
+        .filter(|(key, _)| !listed_keys.contains(*key))
+        .map(|(_, url)| url.to_string())
+        .collect();
+    report.listed_not_crawled.sort();
+
+    report
+}
+
+#[derive(Debug, Default, Serialize)]
+struct DepthReport {
+    /// How many pages sit at each click depth
+    pages_by_depth: BTreeMap<usize, usize>,
+    /// Pages that can't be reached from the starting url by following links
+    unreachable: usize,
+    /// Pages deeper than the threshold, deepest first
+    deep_pages: Vec<DeepPage>,
+}
+
+#[derive(Debug, Serialize)]
+struct DeepPage {
+    url: String,
+    depth: usize,
+}
+
+fn depth_report(links: &LinkGraph, deeper_than: usize) -> DepthReport {
+    let (pages_by_depth, unreachable) = depth_histogram(links);
+
+    let mut deep_pages: Vec<DeepPage> = links
+        .into_iter()
+        .filter_map(|(_, link)| {
+            let depth = link.depth.filter(|depth| *depth > deeper_than)?;
+            Some(DeepPage {
+                url: link.url.clone(),
+                depth,
+            })
+        })
+        .collect();
+    deep_pages.sort_by(|a, b| b.depth.cmp(&a.depth).then_with(|| a.url.cmp(&b.url)));
+
+    DepthReport {
+        pages_by_depth,
+        unreachable,
+        deep_pages,
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct LinkCountReport {
+    /// The pages the most other pages link to
+    most_linked_to: Vec<LinkCount>,
+    /// The pages linking to the most other pages
+    most_outlinks: Vec<LinkCount>,
+    /// Pages fetched successfully that don't link to any page
+    dead_ends: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct LinkCount {
+    url: String,
+    count: usize,
+}
+
+/// The `top` pages with the highest `count`, ties broken by url
+fn top_pages(links: &LinkGraph, top: usize, count: impl Fn(&Link) -> usize) -> Vec<LinkCount> {
+    let mut pages: Vec<LinkCount> = links
+        .into_iter()
+        .map(|(_, link)| LinkCount {
+            url: link.url.clone(),
+            count: count(link),
+        })
+        .filter(|page| page.count > 0)
+        .collect();
+    pages.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.url.cmp(&b.url)));
+    pages.truncate(top);
+    pages
+}
+
+fn link_count_report(links: &LinkGraph, top: usize) -> LinkCountReport {
+    let mut dead_ends: Vec<String> = links
+        .into_iter()
+        .filter(|(_, link)| link.fetched_at.is_some() && link.children.is_empty())
+        .map(|(_, link)| link.url.clone())
+        .collect();
+    dead_ends.sort();
+
+    LinkCountReport {
+        most_linked_to: top_pages(links, top, |link| link.parents.len()),
+        most_outlinks: top_pages(links, top, |link| link.children.len()),
+        dead_ends,
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct ThinContentReport {
+    /// Pages that were measured, crawls from before word
+    /// counts were kept don't have any to measure
+    pages_measured: usize,
+    median_word_count: usize,
+    /// Pages with fewer words than the threshold, fewest first
+    thin_pages: Vec<ThinPage>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct ThinPage {
+    url: String,
+    word_count: usize,
+    text_ratio: f64,
+}
+
+fn thin_content_report(links: &LinkGraph, min_words: usize) -> ThinContentReport {
+    let measured: Vec<(&Link, &ContentMetrics)> = links
+        .into_iter()
+        .filter(|(_, link)| link.kind == NodeKind::Page && link.blocked.is_none())
+        .filter_map(|(_, link)| Some((link, link.content.as_ref()?)))
+        .collect();
+
+    let mut word_counts: Vec<usize> = measured
+        .iter()
+        .map(|(_, content)| content.word_count)
+        .collect();
+    word_counts.sort_unstable();
+
+    let mut thin_pages: Vec<ThinPage> = measured
+        .iter()
+        .filter(|(_, content)| content.word_count < min_words)
+        .map(|(link, content)| ThinPage {
+            url: link.url.clone(),
+            word_count: content.word_count,
+            text_ratio: content.text_ratio,
+        })
+        .collect();
+    thin_pages.sort_by(|a, b| {
+        a.word_count
+            .cmp(&b.word_count)
+            .then_with(|| a.url.cmp(&b.url))
+    });
+
+    ThinContentReport {
+        pages_measured: measured.len(),
+        median_word_count: word_counts
+            .get(word_counts.len() / 2)
+            .copied()
+            .unwrap_or_default(),
+        thin_pages,
+    }
+}
+
+pub async fn run(args: AnalyzeArgs) -> Result<()> {
+    if args.compare_urls.is_none()
+        && args.deeper_than.is_none()
+        && args.top_links.is_none()
+        && args.thin_content.is_none()
+    {
+        bail!(
+            "nothing to analyze, pass --compare-urls, --deeper-than, --top-links or --thin-content"
+        );
+    }
+
+    let links = load_links(&args.input).await?;
+
+    if let Some(deeper_than) = args.deeper_than {
+        let report = depth_report(&links, deeper_than);
+        let counts: Vec<String> = report
+            .pages_by_depth
+            .iter()
+            .map(|(depth, count)| format!("{}: {}", depth, count))
+            .collect();
+        println!(
+            "pages by click depth: {}, unreachable: {}. {} pages are deeper than {} clicks",
+            counts.join(", "),
+            report.unreachable,
+            report.deep_pages.len(),
+            deeper_than
+        );
+
+        fs::write(&args.depth_json, serde_json::to_string_pretty(&report)?).await?;
+        println!("Saved the click depth report to {}", args.depth_json);
+    }
+
+    if let Some(top) = args.top_links {
+        let report = link_count_report(&links, top);
+        if let Some(page) = report.most_linked_to.first() {
+            println!("most linked-to page: {} ({} inlinks)", page.url, page.count);
+        }
+        if let Some(page) = report.most_outlinks.first() {
+            println!(
+                "page with the most outlinks: {} ({} outlinks)",
+                page.url, page.count
+            );
+        }
+        println!("{} dead-end pages", report.dead_ends.len());
+
+        fs::write(
+            &args.link_counts_json,
+            serde_json::to_string_pretty(&report)?,
+        )
+        .await?;
+        println!(
+            "Saved the inlink and outlink report to {}",
+            args.link_counts_json
+        );
+    }
+
+    if let Some(min_words) = args.thin_content {
+        let report = thin_content_report(&links, min_words);
+        println!(
+            "{} of {} pages have fewer than {} words, the median page has {}",
+            report.thin_pages.len(),
+            report.pages_measured,
+            min_words,
+            report.median_word_count
+        );
+
+        fs::write(
+            &args.thin_content_json,
+            serde_json::to_string_pretty(&report)?,
+        )
+        .await?;
+        println!(
+            "Saved the thin content report to {}",
+            args.thin_content_json
+        );
+    }
+
+    let Some(compare_urls) = &args.compare_urls else {
+        return Ok(());
+    };
+    let list = fs::read_to_string(compare_urls)
+        .await
+        .with_context(|| format!("could not read {}", compare_urls))?;
+    let listed = parse_url_list(&list);
+
+    let report = compare_coverage(&links, &listed);
+    println!(
+        "{} listed urls crawled, {} crawled but not listed, {} listed but not crawled",
+        report.matched,
+        report.crawled_not_listed.len(),
+        report.listed_not_crawled.len()
+    );
+
+    fs::write(&args.coverage_json, serde_json::to_string_pretty(&report)?).await?;
+    println!("Saved the coverage report to {}", args.coverage_json);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_url_list() {
+        let csv = "Top pages,Clicks,Impressions\n\
+                   https://example.com/,120,4000\n\
+                   \"https://example.com/a?x=1,2#top\",3,90\n\
+                   not a url,1,1\n";
+
+        assert_eq!(
+            parse_url_list(csv),
+            vec!["https://example.com/", "https://example.com/a?x=1,2"]
+        );
+    }
+
+    #[test]
+    fn test_link_count_report() {
+        let mut links = LinkGraph::default();
+        let page = |p: &str| format!("https://example.com/{}", p);
+
+        links
+            .update(&page(""), "", &[page("a"), page("b")], &[], &[])
+            .unwrap();
+        links
+            .update(&page("a"), &page(""), &[page("b")], &[], &[])
+            .unwrap();
+        links.update(&page("b"), &page(""), &[], &[], &[]).unwrap();
+        for p in ["", "a", "b"] {
+            links.mark_fetched(&page(p), chrono::Utc::now());
+        }
+
+        let report = link_count_report(&links, 1);
+        assert_eq!(
+            report.most_linked_to,
+            vec![LinkCount {
+                url: page("b"),
+                count: 2
+            }]
+        );
+        assert_eq!(
+            report.most_outlinks,
+            vec![LinkCount {
+                url: page(""),
+                count: 2
+            }]
+        );
+        assert_eq!(report.dead_ends, vec![page("b")]);
+    }
+
+    #[test]
+    fn test_thin_content_report() {
+        let mut links = LinkGraph::default();
+        let page = |p: &str| format!("https://example.com/{}", p);
+
+        for (p, words) in [("", 800), ("a", 40), ("b", 300), ("c", 0)] {
+            links.update(&page(p), "", &[], &[], &[]).unwrap();
+            let text = "word ".repeat(words);
+            links.get_mut(&page(p)).unwrap().content = Some(ContentMetrics::measure(&text, 20_000));
+        }
+        // Not fetched, so never measured
+        links.update(&page("d"), "", &[], &[], &[]).unwrap();
+
+        let report = thin_content_report(&links, 100);
+        assert_eq!(report.pages_measured, 4);
+        assert_eq!(report.median_word_count, 300);
+        let thin: Vec<(&str, usize)> = report
+            .thin_pages
+            .iter()
+            .map(|page| (page.url.as_str(), page.word_count))
+            .collect();
+        assert_eq!(thin, [(page("c").as_str(), 0), (page("a").as_str(), 40)]);
+    }
+}
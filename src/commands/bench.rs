@@ -0,0 +1,145 @@
+use anyhow::{Context, Result};
+use axum::extract::{Path, State};
+use axum::response::Html;
+use axum::routing::get;
+use axum::Router;
+use clap::{Args, Parser};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+
+use crate::{new_crawler_state, ProgramArgs};
+use rust_crawler::worker::crawl;
+
+#[derive(Args, Clone, Debug)]
+pub struct BenchArgs {
+    /// How many pages the test site has, and the crawl visits
+    #[arg(long, default_value_t = 200)]
+    pages: usize,
+
+    /// How long the test site takes to answer each request
+    #[arg(long, default_value_t = 20)]
+    latency_ms: u64,
+
+    /// How many links every test page has
+    #[arg(long, default_value_t = 10)]
+    links_per_page: usize,
+
+    /// The worker counts to crawl the test site with
+    #[arg(long, value_delimiter = ',', default_value = "4,16,64")]
+    workers: Vec<u64>,
+}
+
+/// The generated site the benchmark crawls
+struct BenchSite {
+    pages: usize,
+    latency: Duration,
+    links_per_page: usize,
+}
+
+async fn bench_page(State(site): State<Arc<BenchSite>>, Path(page): Path<usize>) -> Html<String> {
+    tokio::time::sleep(site.latency).await;
+
+    // Spread the links over the site so every page is reachable
+    let links: String = (1..=site.links_per_page)
+        .map(|i| (page * 7 + i * 13) % site.pages)
+        .map(|target| format!("<a href=\"/page/{}\">page {}</a>\n", target, target))
+        .collect();
+
+    Html(format!(
+        "<html><head><title>Page {}</title></head><body><h1>Page {}</h1>\n{}</body></html>",
+        page, page, links
+    ))
+}
+
+/// Resident memory of the process, only known on Linux
+fn resident_memory_bytes() -> Option<usize> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: usize = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * 4096)
+}
+
+fn format_mb(bytes: usize) -> String {
+    format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+async fn bench_crawl(starting_url: &str, pages: usize, workers: u64) -> Result<()> {
+    let args = ProgramArgs::try_parse_from([
+        "rust_crawler",
+        "--starting-url",
+        starting_url,
+        "--max-links",
+        &pages.to_string(),
+        "--n-worker-threads",
+        &workers.to_string(),
+        "--no-sitemap-seeding",
+        // Every page is on one host, waiting between them would only measure the delay
+        "--delay-ms",
+        "0",
+    ])?;
+    let crawler_state = new_crawler_state(&args).await?;
+
+    let start = Instant::now();
+    let mut tasks = JoinSet::new();
+    for _ in 0..workers {
+        tasks.spawn(crawl(crawler_state.clone()));
+    }
+    while tasks.join_next().await.is_some() {}
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let crawled = crawler_state.crawled_count.load(Ordering::Relaxed);
+    let lock_wait_ms = crawler_state.lock_wait_nanos.load(Ordering::Relaxed) as f64 / 1e6;
+    let graph_bytes = crawler_state.link_graph.read().await.memory_bytes();
+
+    println!(
+        "{:>7} {:>7} {:>8.2}s {:>10.1} {:>14.3} {:>12} {:>12}",
+        workers,
+        crawled,
+        elapsed,
+        crawled as f64 / elapsed,
+        lock_wait_ms / crawled.max(1) as f64,
+        format_mb(graph_bytes),
+        resident_memory_bytes()
+            .map(format_mb)
+            .unwrap_or_else(|| String::from("-"))
+    );
+
+    Ok(())
+}
+
+/// Crawls a generated site served from this process once for
+/// every worker count, printing how fast each crawl went
+pub async fn run(args: BenchArgs) -> Result<()> {
+    let site = Arc::new(BenchSite {
+        pages: args.pages.max(1),
+        latency: Duration::from_millis(args.latency_ms),
+        links_per_page: args.links_per_page,
+    });
+    let app = Router::new()
+        .route("/page/{page}", get(bench_page))
+        .with_state(site);
+
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .context("could not start the benchmark site")?;
+    let address = listener.local_addr()?;
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    println!(
+        "Benchmarking against {} pages with {}ms latency at http://{}",
+        args.pages, args.latency_ms, address
+    );
+    println!(
+        "{:>7} {:>7} {:>9} {:>10} {:>14} {:>12} {:>12}",
+        "workers", "pages", "time", "pages/sec", "lock ms/page", "graph mem", "rss"
+    );
+
+    let starting_url = format!("http://{}/page/0", address);
+    for workers in args.workers {
+        bench_crawl(&starting_url, args.pages, workers.max(1)).await?;
+    }
+
+    Ok(())
+}
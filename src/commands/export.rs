@@ -0,0 +1,282 @@
+use anyhow::{Context, Result};
+use chrono::SecondsFormat;
+use clap::{Args, ValueEnum};
+use log2::*;
+use std::collections::HashMap;
+use std::fmt::Write;
+use tokio::fs;
+
+use rust_crawler::analysis::pagerank;
+use rust_crawler::model::{Link, LinkGraph, LinkId};
+
+/// The most urls a single sitemap file may hold
+const SITEMAP_MAX_URLS: usize = 50_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ExportFormat {
+    /// A sitemap.xml of every page fetched successfully
+    Sitemap,
+    /// Tab-separated source and target urls, one link per line
+    EdgeList,
+    /// NetworkX node-link JSON, readable with `networkx.node_link_graph`
+    Networkx,
+    /// A sparse adjacency matrix in Matrix Market format, readable with
+    /// `scipy.io.mmread`. Comments list the url of each row
+    MatrixMarket,
+}
+
+/// Which links of the crawl are exported
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ExportNodes {
+    /// Every link, including images, documents and other files
+    #[default]
+    All,
+    /// Only HTML pages and the links between them
+    Pages,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct ExportArgs {
+    /// The links file written by a crawl
+    #[arg(short, long, default_value_t = String::from("links.json"))]
+    input: String,
+
+    /// What to export the crawl as
+    #[arg(short, long, value_enum)]
+    format: ExportFormat,
+
+    /// Where to write the export, defaults to a name based on the format
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// Which links to export
+    #[arg(long, value_enum, default_value_t = ExportNodes::All)]
+    nodes: ExportNodes,
+}
+
+pub async fn load_links(path: &str) -> Result<LinkGraph> {
+    let json = fs::read_to_string(path)
+        .await
+        .with_context(|| format!("could not read {}", path))?;
+    serde_json::from_str(&json).with_context(|| format!("{} is not a links file", path))
+}
+
+pub async fn run(args: ExportArgs) -> Result<()> {
+    let mut links = load_links(&args.input).await?;
+    if args.nodes == ExportNodes::Pages {
+        links = links.pages_only();
+    }
+
+    let (contents, default_output) = match args.format {
+        ExportFormat::Sitemap => (sitemap_xml(&links), "sitemap.xml"),
+        ExportFormat::EdgeList => (edge_list_tsv(&links), "edges.tsv"),
+        ExportFormat::Networkx => (networkx_json(&links)?, "graph.json"),
+        ExportFormat::MatrixMarket => (matrix_market(&links), "adjacency.mtx"),
+    };
+
+    let output = args.output.as_deref().unwrap_or(default_output);
+    fs::write(output, contents).await?;
+    println!("Exported {} to {}", args.input, output);
+
+    Ok(())
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Builds a sitemap of every fetched page, with `lastmod` from when
+/// it was fetched and `priority` from its PageRank relative to the
+/// best ranked page
+fn sitemap_xml(links: &LinkGraph) -> String {
+    let ranks = pagerank(links);
+    let max_rank = ranks.values().cloned().fold(0.0, f64::max);
+
+    let mut pages: Vec<_> = links
+        .into_iter()
+        .filter(|(_, link)| link.fetched_at.is_some())
+        .map(|(id, link)| (link, ranks.get(id).cloned().unwrap_or(0.0)))
+        .collect();
+
+    pages.sort_by(|(a, a_rank), (b, b_rank)| {
+        b_rank.total_cmp(a_rank).then_with(|| a.url.cmp(&b.url))
+    });
+
+    if pages.len() > SITEMAP_MAX_URLS {
+        warn!(
+            "sitemap limited to the {} best ranked of {} pages",
+            SITEMAP_MAX_URLS,
+            pages.len()
+        );
+        pages.truncate(SITEMAP_MAX_URLS);
+    }
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+
+    for (link, rank) in pages {
+        let priority = if max_rank > 0.0 {
+            (rank / max_rank).clamp(0.1, 1.0)
+        } else {
+            0.5
+        };
+
+        let _ = write!(xml, "  <url>\n    <loc>{}</loc>\n", escape_xml(&link.url));
+        if let Some(fetched_at) = link.fetched_at {
+            let _ = writeln!(
+                xml,
+                "    <lastmod>{}</lastmod>",
+                fetched_at.to_rfc3339_opts(SecondsFormat::Secs, true)
+            );
+        }
+        let _ = write!(xml, "    <priority>{:.1}</priority>\n  </url>\n", priority);
+    }
+
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+/// Every page sorted by url, so exports number them the same way every time
+fn sorted_pages(links: &LinkGraph) -> Vec<(&LinkId, &Link)> {
+    let mut pages: Vec<_> = links.into_iter().collect();
+    pages.sort_by(|(_, a), (_, b)| a.url.cmp(&b.url));
+    pages
+}
+
+/// Every link between pages as (source, target) indexes into `pages`
+fn edges(pages: &[(&LinkId, &Link)]) -> Vec<(usize, usize)> {
+    let index: HashMap<&LinkId, usize> = pages
+        .iter()
+        .enumerate()
+        .map(|(i, (id, _))| (*id, i))
+        .collect();
+
+    let mut edges: Vec<(usize, usize)> = pages
+        .iter()
+        .enumerate()
+        .flat_map(|(source, (_, link))| {
+            link.children
+                .iter()
+                .filter_map(|child| index.get(child))
+                .map(move |target| (source, *target))
+        })
+        .collect();
+    edges.sort();
+    edges
+}
+
+fn edge_list_tsv(links: &LinkGraph) -> String {
+    let pages = sorted_pages(links);
+
+    let mut tsv = String::from("source\ttarget\n");
+    for (source, target) in edges(&pages) {
+        let _ = writeln!(tsv, "{}\t{}", pages[source].1.url, pages[target].1.url);
+    }
+    tsv
+}
+
+fn networkx_json(links: &LinkGraph) -> Result<String> {
+    let pages = sorted_pages(links);
+
+    let nodes: Vec<_> = pages
+        .iter()
+        .map(|(id, link)| {
+            serde_json::json!({
+                "id": id,
+                "url": link.url,
+                "title": link.title.as_ref().or(link.headings.first()),
+                "fetched": link.fetched_at.is_some(),
+                "content_type": link.content_type,
+                "kind": link.kind,
+            })
+        })
+        .collect();
+    let edges: Vec<_> = edges(&pages)
+        .into_iter()
+        .map(|(source, target)| {
+            let (source_id, source) = pages[source];
+            let (target_id, target) = pages[target];
+            serde_json::json!({
+                "source": source_id,
+                "target": target_id,
+                "nofollow": source.nofollow.contains(&target.url),
+                "redirect": source.redirected_to.as_ref() == Some(&target.url),
+                "alternate": source
+                    .alternates
+                    .iter()
+                    .any(|alternate| alternate.url == target.url),
+            })
+        })
+        .collect();
+
+    let graph = serde_json::json!({
+        "directed": true,
+        "multigraph": false,
+        "graph": {},
+        "nodes": nodes,
+        "links": edges,
+    });
+    Ok(serde_json::to_string_pretty(&graph)?)
+}
+
+fn matrix_market(links: &LinkGraph) -> String {
+    let pages = sorted_pages(links);
+    let edges = edges(&pages);
+
+    let mut mtx = String::from("%%MatrixMarket matrix coordinate pattern general\n");
+    for (i, (_, link)) in pages.iter().enumerate() {
+        let _ = writeln!(mtx, "% {} {}", i + 1, link.url);
+    }
+    let _ = writeln!(mtx, "{} {} {}", pages.len(), pages.len(), edges.len());
+    // Matrix Market rows and columns start at 1
+    for (source, target) in edges {
+        let _ = writeln!(mtx, "{} {}", source + 1, target + 1);
+    }
+    mtx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_graph_exports() {
+        let mut links = LinkGraph::default();
+        let page = |p: &str| format!("https://example.com/{}", p);
+
+        links
+            .update(&page(""), "", &[page("a"), page("b")], &[], &[])
+            .unwrap();
+        links
+            .update(&page("a"), &page(""), &[page("b")], &[], &[])
+            .unwrap();
+        links.update(&page("b"), &page(""), &[], &[], &[]).unwrap();
+
+        assert_eq!(
+            edge_list_tsv(&links),
+            "source\ttarget\n\
+             https://example.com/\thttps://example.com/a\n\
+             https://example.com/\thttps://example.com/b\n\
+             https://example.com/a\thttps://example.com/b\n"
+        );
+        assert_eq!(
+            matrix_market(&links),
+            "%%MatrixMarket matrix coordinate pattern general\n\
+             % 1 https://example.com/\n\
+             % 2 https://example.com/a\n\
+             % 3 https://example.com/b\n\
+             3 3 3\n1 2\n1 3\n2 3\n"
+        );
+
+        let graph: serde_json::Value =
+            serde_json::from_str(&networkx_json(&links).unwrap()).unwrap();
+        assert_eq!(graph["nodes"].as_array().unwrap().len(), 3);
+        assert_eq!(graph["links"].as_array().unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
+use url::Url;
+
+use rust_crawler::crawler::{scrape_page, PageClient, ScrapeOption};
+
+/// What to scrape from the page besides its links
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ScrapeField {
+    Images,
+    Titles,
+    /// Every h1 to h6 heading with its level
+    Headings,
+    /// Every link with its anchor text
+    Anchors,
+    /// Titles, meta tags, language, detected technologies and word count
+    Metadata,
+    Keywords,
+    Text,
+    Html,
+    Har,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct FetchArgs {
+    /// The page to fetch
+    url: String,
+
+    /// What to scrape from the page
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "images,titles"
+    )]
+    scrape: Vec<ScrapeField>,
+
+    /// Response headers to include, e.g. server,cache-control
+    #[arg(long, value_delimiter = ',')]
+    headers: Vec<String>,
+
+    /// How many keywords and entities to extract with `--scrape keywords`
+    #[arg(long, default_value_t = 10)]
+    keywords_per_page: usize,
+
+    /// Ask for the page uncompressed
+    #[arg(long, default_value_t = false)]
+    no_compression: bool,
+}
+
+/// Fetches one page and prints everything scraped
+/// from it as JSON, without crawling any further.
+/// Fails if the page couldn't be fetched.
+pub async fn run(args: FetchArgs) -> Result<()> {
+    let url = Url::parse(&args.url).context("invalid url")?;
+
+    let mut options = Vec::new();
+    for field in &args.scrape {
+        match field {
+            ScrapeField::Images => options.push(ScrapeOption::Images),
+            ScrapeField::Titles => options.push(ScrapeOption::Titles),
+            ScrapeField::Headings => options.push(ScrapeOption::Headings),
+            ScrapeField::Anchors => options.push(ScrapeOption::Anchors),
+            ScrapeField::Metadata => options.extend([
+                ScrapeOption::Titles,
+                ScrapeOption::Meta,
+                ScrapeOption::Language,
+                ScrapeOption::Technologies,
+                ScrapeOption::ContentMetrics,
+            ]),
+            ScrapeField::Keywords => options.push(ScrapeOption::Keywords(args.keywords_per_page)),
+            ScrapeField::Text => options.push(ScrapeOption::Text),
+            ScrapeField::Html => options.push(ScrapeOption::Html),
+            ScrapeField::Har => options.push(ScrapeOption::Har),
+        }
+    }
+
+    let headers: Vec<String> = args
+        .headers
+        .iter()
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    if !headers.is_empty() {
+        options.push(ScrapeOption::Headers(headers));
+    }
+
+    let scrape_output = scrape_page(url.clone(), &PageClient::new(!args.no_compression), &options, None).await;
+    if !scrape_output.fetched {
+        let error = scrape_output.error.unwrap_or_default();
+        bail!("could not fetch {}: {}", url, error);
+    }
+    println!("{}", serde_json::to_string_pretty(&scrape_output)?);
+
+    Ok(())
+}
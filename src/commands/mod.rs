@@ -0,0 +1,7 @@
+pub mod analyze;
+pub mod bench;
+pub mod export;
+pub mod fetch;
+pub mod monitor;
+pub mod robots;
+pub mod serve;
@@ -0,0 +1,313 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use clap::Args;
+use futures::StreamExt;
+use log2::*;
+use reqwest::Client;
+use serde::Serialize;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::fs;
+
+use rust_crawler::caching::Caching;
+use crate::commands::export::load_links;
+use rust_crawler::crawler::create_client;
+
+#[derive(Args, Clone, Debug)]
+pub struct MonitorArgs {
+    /// The urls to check, either a links file written by
+    /// a crawl or a text file with one url per line
+    #[arg(short, long, default_value_t = String::from("links.json"))]
+    input: String,
+
+    /// The SQLite database the status history is kept in
+    #[arg(short, long, default_value_t = String::from("monitor.db"))]
+    database: String,
+
+    /// Seconds to wait between checks
+    #[arg(long, default_value_t = 3600)]
+    interval: u64,
+
+    /// Check every url once, exiting with an error if any page started failing
+    #[arg(long, default_value_t = false)]
+    once: bool,
+
+    /// Post a JSON alert here when pages start failing (Slack-compatible)
+    #[arg(long)]
+    webhook: Option<String>,
+
+    /// How many urls are checked at the same time
+    #[arg(long, default_value_t = 8)]
+    concurrency: usize,
+
+    /// Check every url every round, even ones whose Cache-Control
+    /// or Expires headers say they haven't changed yet
+    #[arg(long, default_value_t = false)]
+    ignore_caching: bool,
+}
+
+/// A url being monitored
+#[derive(Debug)]
+struct MonitoredUrl {
+    url: String,
+    /// It isn't checked again until its caching headers say it may have changed
+    caching: Option<Caching>,
+}
+
+impl MonitoredUrl {
+    fn new(url: String) -> Self {
+        Self { url, caching: None }
+    }
+
+    fn is_due(&self, now: DateTime<Utc>) -> bool {
+        self.caching
+            .as_ref()
+            .is_none_or(|caching| caching.is_due(now))
+    }
+}
+
+/// A page that returned 200 on its last check and doesn't anymore
+#[derive(Debug, PartialEq, Serialize)]
+struct BrokenLink {
+    url: String,
+    /// `None` when the request itself failed
+    status: Option<u16>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Alert<'a> {
+    /// Shown by Slack-compatible webhooks
+    text: String,
+    broken: &'a [BrokenLink],
+}
+
+/// Urls from a links file start out with the caching headers the crawl saw
+async fn load_urls(path: &str) -> Result<Vec<MonitoredUrl>> {
+    if path.ends_with(".json") {
+        let links = load_links(path).await?;
+        return Ok(links
+            .into_iter()
+            .map(|(_, link)| MonitoredUrl {
+                url: link.url.clone(),
+                caching: link.caching.clone(),
+            })
+            .collect());
+    }
+
+    let text = fs::read_to_string(path)
+        .await
+        .with_context(|| format!("could not read {}", path))?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| MonitoredUrl::new(line.to_string()))
+        .collect())
+}
+
+async fn open_database(path: &str) -> Result<SqlitePool> {
+    let options = SqliteConnectOptions::from_str(path)?.create_if_missing(true);
+    // Checks are recorded one at a time, and a single connection
+    // keeps `sqlite::memory:` databases from splitting per connection
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await?;
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS checks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            url TEXT NOT NULL,
+            checked_at TEXT NOT NULL,
+            status INTEGER,
+            error TEXT
+        )",
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS checks_by_url ON checks (url, id)")
+        .execute(&pool)
+        .await?;
+
+    Ok(pool)
+}
+
+/// The outcome of checking a url
+struct Check {
+    status: Option<u16>,
+    error: Option<String>,
+    caching: Option<Caching>,
+}
+
+/// Only the status and headers are needed, so the body is never read
+async fn check_url(url: &str, client: &Client) -> Check {
+    match client.get(url).send().await {
+        Ok(response) => Check {
+            status: Some(response.status().as_u16()),
+            error: None,
+            caching: Caching::from_headers(response.headers(), Utc::now()),
+        },
+        Err(e) => Check {
+            status: None,
+            error: Some(e.to_string()),
+            caching: None,
+        },
+    }
+}
+
+/// Stores a check of `url`, returning whether the page
+/// used to return 200 and doesn't anymore
+async fn record_check(
+    pool: &SqlitePool,
+    url: &str,
+    status: Option<u16>,
+    error: Option<&str>,
+) -> Result<bool> {
+    let previous: Option<(Option<i64>,)> =
+        sqlx::query_as("SELECT status FROM checks WHERE url = ? ORDER BY id DESC LIMIT 1")
+            .bind(url)
+            .fetch_optional(pool)
+            .await?;
+
+    sqlx::query("INSERT INTO checks (url, checked_at, status, error) VALUES (?, ?, ?, ?)")
+        .bind(url)
+        .bind(Utc::now().to_rfc3339())
+        .bind(status)
+        .bind(error)
+        .execute(pool)
+        .await?;
+
+    Ok(previous == Some((Some(200),)) && status != Some(200))
+}
+
+/// Checks the urls that are due, or all of them when `ignore_caching` is set
+async fn check_round(
+    urls: &mut [MonitoredUrl],
+    pool: &SqlitePool,
+    client: &Client,
+    concurrency: usize,
+    ignore_caching: bool,
+) -> Result<Vec<BrokenLink>> {
+    let now = Utc::now();
+    let due = urls
+        .iter_mut()
+        .filter(|url| ignore_caching || url.is_due(now));
+    let results: Vec<_> = futures::stream::iter(due)
+        .map(|url| async move {
+            let check = check_url(&url.url, client).await;
+            (url, check)
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+
+    let checked = results.len();
+    let mut failing = 0;
+    let mut broken = Vec::new();
+    for (url, check) in results {
+        if check.status != Some(200) {
+            failing += 1;
+        }
+        url.caching = check.caching;
+
+        if record_check(pool, &url.url, check.status, check.error.as_deref()).await? {
+            broken.push(BrokenLink {
+                url: url.url.clone(),
+                status: check.status,
+                error: check.error,
+            });
+        }
+    }
+
+    println!(
+        "checked {} urls: {} failing, {} newly broken, {} still fresh",
+        checked,
+        failing,
+        broken.len(),
+        urls.len() - checked
+    );
+    Ok(broken)
+}
+
+async fn send_alert(webhook: &str, broken: &[BrokenLink], client: &Client) -> Result<()> {
+    let mut text = format!("{} pages started failing:", broken.len());
+    for link in broken {
+        let status = match (link.status, &link.error) {
+            (Some(status), _) => status.to_string(),
+            (None, Some(error)) => error.clone(),
+            (None, None) => String::from("unknown error"),
+        };
+        text.push_str(&format!("\n• {} ({})", link.url, status));
+    }
+
+    client
+        .post(webhook)
+        .json(&Alert { text, broken })
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+pub async fn run(args: MonitorArgs) -> Result<()> {
+    let mut urls = load_urls(&args.input).await?;
+    let pool = open_database(&args.database)
+        .await
+        .with_context(|| format!("could not open {}", args.database))?;
+    let client = create_client();
+
+    loop {
+        let broken = check_round(
+            &mut urls,
+            &pool,
+            &client,
+            args.concurrency,
+            args.ignore_caching,
+        )
+        .await?;
+
+        if !broken.is_empty() {
+            for link in &broken {
+                warn!(
+                    "{} started failing: {:?} {:?}",
+                    link.url, link.status, link.error
+                );
+            }
+
+            if let Some(webhook) = &args.webhook {
+                if let Err(e) = send_alert(webhook, &broken, &client).await {
+                    error!("could not send the alert webhook: {}", e);
+                }
+            }
+        }
+
+        if args.once {
+            if !broken.is_empty() {
+                bail!("{} pages started failing", broken.len());
+            }
+            return Ok(());
+        }
+
+        tokio::time::sleep(Duration::from_secs(args.interval)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_record_check() {
+        let pool = open_database("sqlite::memory:").await.unwrap();
+        let url = "https://example.com/a";
+
+        assert!(!record_check(&pool, url, Some(404), None).await.unwrap());
+        assert!(!record_check(&pool, url, Some(200), None).await.unwrap());
+        assert!(record_check(&pool, url, Some(500), None).await.unwrap());
+        assert!(!record_check(&pool, url, None, Some("timed out"))
+            .await
+            .unwrap());
+    }
+}
@@ -0,0 +1,73 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use url::Url;
+
+use rust_crawler::crawler::create_client;
+use rust_crawler::robots::{fetch_robots, robots_url};
+
+#[derive(Args, Clone, Debug)]
+pub struct RobotsArgs {
+    /// The url to test against its site's robots.txt
+    #[arg(short, long)]
+    url: String,
+
+    /// The user agent to test as
+    #[arg(long, default_value_t = String::from("HyperCrawler"))]
+    user_agent: String,
+}
+
+pub async fn run(args: RobotsArgs) -> Result<()> {
+    let url = Url::parse(&args.url).context("invalid url")?;
+    let robots_url = robots_url(&url)?;
+    println!("Fetching {}", robots_url);
+
+    let robots = match fetch_robots(&url, &create_client()).await {
+        Ok(robots) => robots,
+        Err(e) => {
+            println!(
+                "Could not fetch robots.txt ({}), so every url is allowed",
+                e
+            );
+            return Ok(());
+        }
+    };
+
+    let groups = robots.groups_for(&args.user_agent);
+    if groups.is_empty() {
+        println!(
+            "No group applies to user agent '{}', so every url is allowed",
+            args.user_agent
+        );
+        return Ok(());
+    }
+
+    for group in &groups {
+        println!(
+            "Using the group on line {} (User-agent: {})",
+            group.line,
+            group.user_agents.join(", ")
+        );
+    }
+
+    let verdict = match robots.is_allowed(&args.user_agent, &url) {
+        true => "ALLOWED",
+        false => "BLOCKED",
+    };
+
+    match robots.matching_rule(&args.user_agent, &url) {
+        _ if url.path() == "/robots.txt" => {
+            println!("{}: robots.txt itself can always be fetched", verdict)
+        }
+        Some(rule) => println!(
+            "{}: line {} `{}: {}` is the longest pattern matching {}",
+            verdict,
+            rule.line,
+            if rule.allow { "Allow" } else { "Disallow" },
+            rule.pattern,
+            url
+        ),
+        None => println!("{}: no rule in the group matches {}", verdict, url),
+    }
+
+    Ok(())
+}
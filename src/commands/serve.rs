@@ -0,0 +1,36 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use std::path::PathBuf;
+use tokio::net::TcpListener;
+
+use rust_crawler::api::{create_router, AppState};
+
+#[derive(Args, Clone, Debug)]
+pub struct ServeArgs {
+    /// The address to listen on
+    #[arg(long, default_value_t = String::from("127.0.0.1:8080"))]
+    listen: String,
+
+    /// Where jobs download their images to, each to a directory named after it
+    #[arg(long, default_value_t = String::from("api_images"))]
+    img_save_dir: String,
+
+    /// The SQLite database jobs and their results are kept in
+    #[arg(long, default_value_t = String::from("api_jobs.db"))]
+    database: String,
+}
+
+/// Serves the REST API for starting crawls and following their progress
+pub async fn run(args: ServeArgs) -> Result<()> {
+    let listener = TcpListener::bind(&args.listen)
+        .await
+        .with_context(|| format!("could not listen on {}", args.listen))?;
+    println!("Serving the API on http://{}", listener.local_addr()?);
+
+    let state = AppState::open(PathBuf::from(args.img_save_dir), &args.database)
+        .await
+        .with_context(|| format!("could not open the job database {}", args.database))?;
+    let app = create_router(state);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use anyhow::{bail, Context, Result};
+use encoding_rs::{Encoding, UTF_8};
+use reqwest::header::{HeaderMap, CONTENT_ENCODING, CONTENT_TYPE};
+use std::io::Read;
+
+/// The encodings pages are asked for in, best first
+pub const ACCEPT_ENCODING: &str = "zstd, br, gzip, deflate";
+
+/// The most a page may decompress to, so a small
+/// compressed body can't take up all the memory
+pub const MAX_DECODED_BYTES: usize = 64 << 20;
+
+/// Undoes every `Content-Encoding` applied to `body`, last applied first.
+/// Fails if it decompresses to more than `limit` bytes
+pub fn decode_body(headers: &HeaderMap, body: Vec<u8>, limit: usize) -> Result<Vec<u8>> {
+    let Some(content_encoding) = headers.get(CONTENT_ENCODING) else {
+        return Ok(body);
+    };
+    let content_encoding = content_encoding
+        .to_str()
+        .context("invalid Content-Encoding")?;
+
+    let mut body = body;
+    for encoding in content_encoding.rsplit(',').map(str::trim) {
+        body = match encoding.to_ascii_lowercase().as_str() {
+            "" | "identity" => body,
+            "gzip" | "x-gzip" => read_all(flate2::read::MultiGzDecoder::new(&body[..]), limit)?,
+            "deflate" => inflate(&body, limit)?,
+            "br" => read_all(
+                brotli_decompressor::Decompressor::new(&body[..], 4096),
+                limit,
+            )?,
+            "zstd" => read_all(zstd::stream::read::Decoder::new(&body[..])?, limit)?,
+            other => bail!("unsupported Content-Encoding {}", other),
+        };
+    }
+
+    Ok(body)
+}
+
+/// Decodes `body` into text with the charset from `Content-Type`,
+/// the same way `Response::text` does
+pub fn decode_text(headers: &HeaderMap, body: &[u8]) -> String {
+    let encoding = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|content_type| {
+            content_type.split(';').skip(1).find_map(|param| {
+                let (name, value) = param.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case("charset")
+                    .then(|| value.trim().trim_matches('"'))
+            })
+        })
+        .and_then(|charset| Encoding::for_label(charset.as_bytes()))
+        .unwrap_or(UTF_8);
+
+    let (text, _, _) = encoding.decode(body);
+    text.into_owned()
+}
+
+fn read_all(reader: impl Read, limit: usize) -> Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    reader.take(limit as u64 + 1).read_to_end(&mut decoded)?;
+    if decoded.len() > limit {
+        bail!("body decompresses to more than {} bytes", limit);
+    }
+    Ok(decoded)
+}
+
+/// `deflate` is meant to be zlib-wrapped, but some servers send a raw stream
+fn inflate(body: &[u8], limit: usize) -> Result<Vec<u8>> {
+    read_all(flate2::read::ZlibDecoder::new(body), limit)
+        .or_else(|_| read_all(flate2::read::DeflateDecoder::new(body), limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+    use std::io::Write;
+
+    fn headers(content_encoding: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_ENCODING,
+            HeaderValue::from_str(content_encoding).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn test_decode_body() {
+        let page = b"<html><body>hello</body></html>".repeat(10);
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(&page).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let limit = MAX_DECODED_BYTES;
+        assert_eq!(
+            decode_body(&headers("gzip"), gzip.clone(), limit).unwrap(),
+            page
+        );
+
+        let zstd = zstd::stream::encode_all(&page[..], 0).unwrap();
+        assert_eq!(
+            decode_body(&headers("zstd"), zstd.clone(), limit).unwrap(),
+            page
+        );
+
+        // Applied gzip first, then zstd
+        let both = zstd::stream::encode_all(&gzip[..], 0).unwrap();
+        assert_eq!(
+            decode_body(&headers("gzip, zstd"), both, limit).unwrap(),
+            page
+        );
+
+        // Compression bombs stop at the limit
+        assert!(decode_body(&headers("gzip"), gzip, page.len() - 1).is_err());
+        assert!(decode_body(&headers("zstd"), zstd, page.len() - 1).is_err());
+
+        assert_eq!(
+            decode_body(&HeaderMap::new(), page.clone(), limit).unwrap(),
+            page
+        );
+        assert!(decode_body(&headers("compress"), page, limit).is_err());
+    }
+
+    #[test]
+    fn test_decode_text() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/html; charset=\"ISO-8859-1\""),
+        );
+        assert_eq!(decode_text(&headers, b"caf\xe9"), "café");
+        assert_eq!(decode_text(&HeaderMap::new(), "café".as_bytes()), "café");
+    }
+}
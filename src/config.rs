@@ -0,0 +1,155 @@
+use anyhow::{bail, Context, Result};
+use std::ffi::OsString;
+use std::path::Path;
+
+/// Read from the current directory when `--config` isn't given
+pub const DEFAULT_CONFIG: &str = "hypercrawl.toml";
+
+/// The command line arguments with the options from the config file added
+/// in front of them. Options given on the command line win over the file's.
+///
+/// The file has a key for every long option, e.g.
+///
+/// ```toml
+/// starting-url = "https://example.com/"
+/// n-worker-threads = 8
+/// respect-nofollow = true
+/// exclude-pattern = ["/tag/*", "/search"]
+/// ```
+pub fn args_with_config(cli: Vec<OsString>, command: &clap::Command) -> Result<Vec<OsString>> {
+    let path = match config_path(&cli) {
+        Some(path) => path,
+        None if Path::new(DEFAULT_CONFIG).exists() => DEFAULT_CONFIG.to_string(),
+        None => return Ok(cli),
+    };
+    let contents = std::fs::read_to_string(&path)
+        .with_context(|| format!("could not read the config file {}", path))?;
+
+    let mut args = merge_config(cli, &contents, command)
+        .with_context(|| format!("invalid config file {}", path))?;
+    if config_path(&args).is_none() {
+        args.splice(1..1, [OsString::from("--config"), OsString::from(path)]);
+    }
+    Ok(args)
+}
+
+/// The value of `--config` on the command line
+fn config_path(cli: &[OsString]) -> Option<String> {
+    let mut args = cli.iter().filter_map(|arg| arg.to_str());
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(str::to_string);
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path.to_string());
+        }
+    }
+    None
+}
+
+fn merge_config(
+    cli: Vec<OsString>,
+    contents: &str,
+    command: &clap::Command,
+) -> Result<Vec<OsString>> {
+    let config: toml::Table = toml::from_str(contents)?;
+    let mut cli = cli.into_iter();
+    let mut args: Vec<OsString> = cli.next().into_iter().collect();
+    let cli: Vec<OsString> = cli.collect();
+
+    for (key, value) in config {
+        // Options are named as on the command line, or as their field
+        let (id, long) = (key.replace('-', "_"), key.replace('_', "-"));
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_id().as_str() == id || arg.get_long() == Some(long.as_str()))
+        else {
+            bail!("unknown option `{}`", key);
+        };
+        let Some(long) = arg.get_long() else {
+            bail!("`{}` can't be set in a config file", key);
+        };
+        if given(&cli, long, arg.get_short()) {
+            continue;
+        }
+
+        let flag = OsString::from(format!("--{}", long));
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                toml::Value::Boolean(value) if !arg.get_action().takes_values() => {
+                    if value {
+                        args.push(flag.clone());
+                    }
+                    continue;
+                }
+                toml::Value::Boolean(value) => value.to_string(),
+                _ => bail!("`{}` has to be a string, number, boolean or list", key),
+            };
+            args.extend([flag.clone(), OsString::from(value)]);
+        }
+    }
+
+    args.extend(cli);
+    Ok(args)
+}
+
+/// Whether the option `--long` (or `-short`) is on the command line
+fn given(cli: &[OsString], long: &str, short: Option<char>) -> bool {
+    let long_flag = format!("--{}", long);
+    let short_flag = short.map(|short| format!("-{}", short));
+    cli.iter().filter_map(|arg| arg.to_str()).any(|arg| {
+        arg == long_flag
+            || arg.starts_with(&format!("{}=", long_flag))
+            || short_flag
+                .as_ref()
+                .is_some_and(|short| !arg.starts_with("--") && arg.starts_with(short.as_str()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, Parser};
+
+    #[derive(Parser, Debug)]
+    struct Args {
+        #[arg(short, long)]
+        starting_url: Option<String>,
+        #[arg(short, long, default_value_t = 4)]
+        n_worker_threads: u64,
+        #[arg(long, default_value_t = false)]
+        respect_nofollow: bool,
+        #[arg(long = "exclude-pattern")]
+        exclude_patterns: Vec<String>,
+        #[arg(long)]
+        config: Option<String>,
+    }
+
+    #[test]
+    fn test_command_line_wins_over_config() {
+        let config = r#"
+            starting-url = "https://example.com/"
+            n_worker_threads = 8
+            respect-nofollow = true
+            exclude-pattern = ["/tag/*", "/search"]
+        "#;
+        let cli = ["rust_crawler", "-n", "2"].map(OsString::from).to_vec();
+        let args = merge_config(cli, config, &Args::command()).unwrap();
+        let args = Args::try_parse_from(args).unwrap();
+
+        assert_eq!(args.starting_url.as_deref(), Some("https://example.com/"));
+        assert_eq!(args.n_worker_threads, 2);
+        assert!(args.respect_nofollow);
+        assert_eq!(args.exclude_patterns, ["/tag/*", "/search"]);
+
+        let unknown = merge_config(Vec::new(), "max-pages = 5", &Args::command());
+        assert!(unknown.is_err());
+    }
+}
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+/// How fast an adult reads, in words per minute
+const READING_WORDS_PER_MINUTE: usize = 238;
+
+/// How much there is to read on a page
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ContentMetrics {
+    /// Words in the page's visible text
+    pub word_count: usize,
+    /// Bytes of visible text for every byte of HTML, from 0 to 1
+    pub text_ratio: f64,
+    /// Roughly how long it takes to read the page, in seconds
+    pub reading_time_s: u64,
+}
+
+impl ContentMetrics {
+    /// Measures the visible `text` of a page whose HTML is `html_bytes` long
+    pub fn measure(text: &str, html_bytes: usize) -> Self {
+        let words = text.split_whitespace();
+        let word_count = words.clone().count();
+        let text_bytes: usize = words.map(|word| word.len() + 1).sum();
+
+        let text_ratio = match html_bytes {
+            0 => 0.0,
+            // Rounded, more digits than this only make the links file harder to read
+            _ => {
+                ((text_bytes.saturating_sub(1) as f64 / html_bytes as f64).min(1.0) * 1000.0)
+                    .round()
+                    / 1000.0
+            }
+        };
+
+        Self {
+            word_count,
+            text_ratio,
+            reading_time_s: (word_count * 60).div_ceil(READING_WORDS_PER_MINUTE) as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure() {
+        let text = "  The quick\n brown   fox ".repeat(119);
+        let metrics = ContentMetrics::measure(&text, 10_000);
+        assert_eq!(metrics.word_count, 476);
+        assert_eq!(metrics.reading_time_s, 120);
+        // 476 words of 3 to 5 letters with a space between each
+        assert_eq!(metrics.text_ratio, 0.238);
+
+        let empty = ContentMetrics::measure("", 0);
+        assert_eq!((empty.word_count, empty.text_ratio), (0, 0.0));
+    }
+}
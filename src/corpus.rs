@@ -0,0 +1,269 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use scraper::{ElementRef, Html, Node, Selector};
+use std::collections::HashSet;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::sync::Mutex;
+use url::Url;
+
+use crate::url_utils::url_file_stem;
+
+/// Elements that are page chrome or code rather than content
+const SKIPPED_ELEMENTS: [&str; 13] = [
+    "head", "script", "style", "noscript", "template", "nav", "header", "footer", "aside", "form",
+    "svg", "iframe", "button",
+];
+
+/// Elements whose text flows into the surrounding paragraph
+const INLINE_ELEMENTS: [&str; 18] = [
+    "a", "abbr", "b", "br", "cite", "code", "em", "i", "kbd", "label", "mark", "q", "s", "small",
+    "span", "strong", "sub", "sup",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum PageExport {
+    /// Cleaned, deduplicated and chunked documents with
+    /// url and title front matter, ready for RAG or fine-tuning
+    Corpus,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum CorpusFormat {
+    /// Headings, lists, quotes and code blocks kept as Markdown
+    #[default]
+    Markdown,
+    /// Plain paragraphs of text
+    Text,
+}
+
+impl CorpusFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            CorpusFormat::Markdown => "md",
+            CorpusFormat::Text => "txt",
+        }
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Splits the content of `element` into paragraphs, leaving out page chrome
+fn collect_blocks(element: ElementRef, format: CorpusFormat, blocks: &mut Vec<String>) {
+    let mut inline = String::new();
+    let flush = |inline: &mut String, blocks: &mut Vec<String>, prefix: &str| {
+        let text = collapse_whitespace(inline);
+        if !text.is_empty() {
+            blocks.push(format!("{}{}", prefix, text));
+        }
+        inline.clear();
+    };
+
+    for child in element.children() {
+        if let Node::Text(text) = child.value() {
+            inline.push_str(text);
+            continue;
+        }
+
+        let Some(child) = ElementRef::wrap(child) else {
+            continue;
+        };
+        let name = child.value().name();
+
+        if SKIPPED_ELEMENTS.contains(&name) {
+            continue;
+        }
+        if INLINE_ELEMENTS.contains(&name) {
+            inline.push(' ');
+            inline.extend(child.text());
+            inline.push(' ');
+            continue;
+        }
+
+        flush(&mut inline, blocks, "");
+
+        let markdown = format == CorpusFormat::Markdown;
+        match name {
+            "pre" => {
+                let code = child.text().collect::<String>();
+                let code = code.trim_matches('\n');
+                if !code.trim().is_empty() {
+                    blocks.push(match markdown {
+                        true => format!("```\n{}\n```", code),
+                        false => code.to_string(),
+                    });
+                }
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "li" | "blockquote" if markdown => {
+                let prefix = match name {
+                    "li" => String::from("- "),
+                    "blockquote" => String::from("> "),
+                    heading => format!("{} ", "#".repeat(heading[1..].parse().unwrap_or(1))),
+                };
+
+                let mut nested = Vec::new();
+                collect_blocks(child, format, &mut nested);
+                if let Some((first, rest)) = nested.split_first() {
+                    blocks.push(format!("{}{}", prefix, first));
+                    blocks.extend(rest.iter().cloned());
+                }
+            }
+            _ => collect_blocks(child, format, blocks),
+        }
+    }
+
+    flush(&mut inline, blocks, "");
+}
+
+/// The content of a page as a Markdown or text document
+pub fn page_document(html_dom: &Html, format: CorpusFormat) -> String {
+    let mut blocks = Vec::new();
+    collect_blocks(html_dom.root_element(), format, &mut blocks);
+
+    // Repeated blocks are usually boilerplate (e.g. "Read more" links)
+    let mut seen = HashSet::new();
+    blocks.retain(|block| seen.insert(block.clone()));
+    blocks.join("\n\n")
+}
+
+/// Splits `document` into chunks of at most `max_chars` characters,
+/// breaking between paragraphs and, for long paragraphs, between words
+pub fn chunk_document(document: &str, max_chars: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+
+    let mut push = |piece: &str, separator: &str, chunk: &mut String| {
+        if !chunk.is_empty()
+            && chunk.chars().count() + separator.len() + piece.chars().count() > max_chars
+        {
+            chunks.push(std::mem::take(chunk));
+        }
+        if !chunk.is_empty() {
+            chunk.push_str(separator);
+        }
+        chunk.push_str(piece);
+    };
+
+    for paragraph in document.split("\n\n") {
+        if paragraph.chars().count() <= max_chars {
+            push(paragraph, "\n\n", &mut chunk);
+            continue;
+        }
+
+        for (i, word) in paragraph.split(' ').enumerate() {
+            push(word, if i == 0 { "\n\n" } else { " " }, &mut chunk);
+        }
+    }
+
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+/// Writes every crawled page into a directory of document chunks,
+/// skipping pages whose content was already written for another url
+pub struct CorpusWriter {
+    dir: PathBuf,
+    format: CorpusFormat,
+    chunk_size: usize,
+    /// Hashes of the documents written so far
+    written: Mutex<HashSet<u64>>,
+}
+
+impl CorpusWriter {
+    pub fn new(dir: &Path, format: CorpusFormat, chunk_size: usize) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            format,
+            chunk_size: chunk_size.max(1),
+            written: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Writes the chunks of the page at `url`, returning how many were written
+    pub async fn write_page(&self, url: &Url, html: &str) -> Result<usize> {
+        let (title, document) = {
+            let html_dom = Html::parse_document(html);
+            let title_selector = Selector::parse("title").unwrap();
+            let title = html_dom
+                .select(&title_selector)
+                .next()
+                .map(|title| collapse_whitespace(&title.text().collect::<String>()))
+                .unwrap_or_default();
+
+            (title, page_document(&html_dom, self.format))
+        };
+
+        if document.is_empty() {
+            return Ok(0);
+        }
+
+        let mut hasher = DefaultHasher::new();
+        document.hash(&mut hasher);
+        if !self.written.lock().await.insert(hasher.finish()) {
+            return Ok(0);
+        }
+
+        fs::create_dir_all(&self.dir).await?;
+
+        let chunks = chunk_document(&document, self.chunk_size);
+        let stem = url_file_stem(url);
+        for (i, chunk) in chunks.iter().enumerate() {
+            // JSON strings are valid YAML, so quoting with serde_json escapes safely
+            let front_matter = format!(
+                "---\nurl: {}\ntitle: {}\nchunk: {}\nchunks: {}\n---\n\n",
+                serde_json::to_string(url.as_str())?,
+                serde_json::to_string(&title)?,
+                i + 1,
+                chunks.len()
+            );
+
+            let path = self
+                .dir
+                .join(format!("{}-{}.{}", stem, i + 1, self.format.extension()));
+            fs::write(path, front_matter + chunk + "\n").await?;
+        }
+
+        Ok(chunks.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_document() {
+        let html = Html::parse_document(
+            "<html><head><title>T</title></head><body><nav><a href='/'>Home</a></nav>\
+             <main><h1>Guide</h1><p>Install <code>cargo</code> first.</p>\
+             <ul><li>One</li><li><p>Two</p></li></ul><pre>fn main() {}</pre>\
+             <p>Install <code>cargo</code> first.</p></main><footer>© 2024</footer></body></html>",
+        );
+
+        assert_eq!(
+            page_document(&html, CorpusFormat::Markdown),
+            "# Guide\n\nInstall cargo first.\n\n- One\n\n- Two\n\n```\nfn main() {}\n```"
+        );
+        assert_eq!(
+            page_document(&html, CorpusFormat::Text),
+            "Guide\n\nInstall cargo first.\n\nOne\n\nTwo\n\nfn main() {}"
+        );
+    }
+
+    #[test]
+    fn test_chunk_document() {
+        assert_eq!(
+            chunk_document("aaaa\n\nbb\n\ncc", 8),
+            vec!["aaaa\n\nbb", "cc"]
+        );
+        assert_eq!(
+            chunk_document("one two three four", 9),
+            vec!["one two", "three", "four"]
+        );
+    }
+}
@@ -1,58 +1,1159 @@
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use log2::*;
-use reqwest::{Client, StatusCode};
-use scraper::{Html, Selector};
-use std::{collections::VecDeque, sync::{Arc, atomic::AtomicUsize}, time::Duration};
-use tokio::sync::RwLock;
+use reqwest::{header::{HeaderMap, HeaderValue, ACCEPT_ENCODING, AUTHORIZATION, CONTENT_TYPE}, Client, ClientBuilder, Request, Response, StatusCode};
+use scraper::{Html, Node, Selector};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::{BTreeMap, HashMap}, path::PathBuf, sync::{Arc, LazyLock, atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering}}, time::{Duration, Instant}};
+use tokio::sync::{broadcast, Mutex, OwnedSemaphorePermit, RwLock, Semaphore};
+use tokio_util::sync::CancellationToken;
 use url::Url;
 
 pub fn create_client() -> Client {
     Client::builder()
-        .user_agent("Mozilla/5.0 (compatible; HyperCrawler/1.0)")
+        .user_agent(USER_AGENT)
         .timeout(Duration::from_secs(LINK_REQUEST_TIMEOUT_S))
         .build()
         .unwrap_or_else(|_| Client::new())
 }
 
+/// What pages are fetched with
+pub struct PageClient {
+    client: Client,
+    #[cfg(feature = "http3")]
+    http3: Option<Http3Client>,
+    stealth: Option<Stealth>,
+    auth: Option<Auth>,
+    oauth2: Option<OAuth2>,
+    browser_session: Option<BrowserSession>,
+    politeness: Option<Arc<Politeness>>,
+}
+
+impl PageClient {
+    /// Bodies are left compressed so both the compressed and decompressed
+    /// sizes are known. They're asked for uncompressed unless `compression` is set
+    pub fn new(compression: bool) -> Self {
+        Self {
+            client: page_client_builder(compression)
+                .build()
+                .unwrap_or_else(|_| Client::new()),
+            #[cfg(feature = "http3")]
+            http3: None,
+            stealth: None,
+            auth: None,
+            oauth2: None,
+            browser_session: None,
+            politeness: None,
+        }
+    }
+
+    /// Sends every request with browser-like headers after a random delay
+    pub fn with_stealth(mut self, stealth: Option<Stealth>) -> Self {
+        self.stealth = stealth;
+        self
+    }
+
+    /// Sends every request with the credentials `auth` has for its host
+    pub fn with_auth(mut self, auth: Option<Auth>) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Sends every request with the cookies and headers a browser would
+    pub fn with_browser_session(mut self, browser_session: Option<BrowserSession>) -> Self {
+        self.browser_session = browser_session;
+        self
+    }
+
+    /// Spaces out requests to each host, and limits and adds headers
+    /// to requests to the hosts `politeness` has overrides for
+    pub fn with_politeness(mut self, politeness: Option<Arc<Politeness>>) -> Self {
+        self.politeness = politeness;
+        self
+    }
+
+    /// Sends every request with an OAuth2 access token
+    pub fn with_oauth2(mut self, oauth2: Option<OAuth2>) -> Self {
+        self.oauth2 = oauth2;
+        self
+    }
+
+    /// Adds an OAuth2 access token to `request`, getting a new one if it expired
+    async fn authorize(&self, request: &mut Request) -> Result<()> {
+        if let Some(oauth2) = &self.oauth2 {
+            let authorization = oauth2.authorization().await?;
+            request.headers_mut().insert(AUTHORIZATION, authorization);
+        }
+        Ok(())
+    }
+
+    fn request(&self, url: &Url) -> reqwest::Result<Request> {
+        let mut request = self.client.get(url.clone());
+        if let Some(stealth) = &self.stealth {
+            request = request.headers(stealth.headers());
+        }
+        if let Some(browser_session) = &self.browser_session {
+            request = request.headers(browser_session.headers(url));
+        }
+        if let Some(headers) = self.politeness.as_ref().and_then(|politeness| politeness.headers(url)) {
+            request = request.headers(headers.clone());
+        }
+        if let Some(authorization) = self.auth.as_ref().and_then(|auth| auth.authorization(url)) {
+            request = request.header(AUTHORIZATION, authorization.clone());
+        }
+        request.build()
+    }
+
+    /// Fetches pages over HTTP/3 from hosts that advertise it
+    #[cfg(feature = "http3")]
+    pub fn with_http3(mut self, compression: bool) -> Result<Self> {
+        self.http3 = Some(Http3Client::new(page_client_builder(compression))?);
+        Ok(self)
+    }
+
+    /// Fetches `url` the way pages are fetched, outside of a crawl
+    pub async fn get(&self, url: &Url) -> Result<Response> {
+        let mut request = self.request(url)?;
+        self.authorize(&mut request).await?;
+        let _permit = self.wait_to_send(request.url()).await;
+        Ok(self.send(request).await?)
+    }
+
+    /// Fetches the robots.txt of `url`'s host like a page, `None` if it can't be
+    pub async fn robots(&self, url: &Url) -> Option<RobotsTxt> {
+        let robots_url = robots::robots_url(url).ok()?;
+        let _permit = self.wait_to_send(&robots_url).await;
+        robots::fetch_robots(url, &self.client).await.ok()
+    }
+
+    /// Waits out the stealth and politeness delays before a request to `url`.
+    /// The permit that's returned is held until the response starts arriving
+    async fn wait_to_send(&self, url: &Url) -> Option<OwnedSemaphorePermit> {
+        if let Some(stealth) = &self.stealth {
+            stealth.wait().await;
+        }
+        match &self.politeness {
+            Some(politeness) => politeness.wait(url).await,
+            None => None,
+        }
+    }
+
+    async fn send(&self, request: Request) -> reqwest::Result<Response> {
+        #[cfg(feature = "http3")]
+        if let Some(http3) = &self.http3 {
+            return http3.execute(&self.client, request).await;
+        }
+        self.client.execute(request).await
+    }
+}
+
+fn page_client_builder(compression: bool) -> ClientBuilder {
+    let accept_encoding = if compression {
+        compression::ACCEPT_ENCODING
+    } else {
+        "identity"
+    };
+    let headers = HeaderMap::from_iter([(ACCEPT_ENCODING, HeaderValue::from_static(accept_encoding))]);
+
+    Client::builder()
+        .user_agent(USER_AGENT)
+        .timeout(Duration::from_secs(LINK_REQUEST_TIMEOUT_S))
+        .default_headers(headers)
+        .no_gzip()
+        .no_brotli()
+        .no_deflate()
+}
+
+use crate::auth::Auth;
+use crate::blocked::{self, PageBlocked};
+use crate::browser_session::BrowserSession;
+use crate::caching::Caching;
+use crate::client_redirect;
+use crate::compression;
+use crate::content::ContentMetrics;
+use crate::corpus::CorpusWriter;
+use crate::dedup::DedupKey;
+use crate::frontier::{Frontier, Schedule, SectionBudget};
+use crate::handle::CrawlHandle;
+use crate::har::{HarEntry, PendingEntry};
+use crate::external_links::ExternalLinks;
+use crate::skipped::SkippedLog;
+use crate::host_report::HostReport;
+use crate::html_stream;
+#[cfg(feature = "http3")]
+use crate::http3::Http3Client;
+use crate::image_utils::{DownloadOptions, ImageDedup, ImageDomains, ImageDownloader};
+use crate::link_scope::LinkScope;
+use crate::link_sink::LinkSink;
+use crate::robots::{self, RobotsCache, RobotsTxt};
 use crate::model::Image;
-use crate::model::LinkGraph;
+use crate::keywords;
+use crate::language;
+use crate::model::{
+    is_html_media_type, Alternate, AlternateKind, Anchor, Heading, LinkGraph, RedirectKind,
+};
+use crate::oauth2::OAuth2;
+use crate::politeness::Politeness;
+use crate::session::Session;
+use crate::stealth::Stealth;
+use crate::url_utils::PathPattern;
+use crate::technologies;
+use crate::url_utils::{
+    ancestor_urls, in_path_prefixes, normalize_url, HostNormalization, NormalizeOptions, PortPolicy,
+    SiteScope,
+};
+use regex::Regex;
+
+pub const LINK_REQUEST_TIMEOUT_S: u64 = 2;
 
-const LINK_REQUEST_TIMEOUT_S: u64 = 2;
+/// The user agent pages and images are requested with
+pub const USER_AGENT: &str = "Mozilla/5.0 (compatible; HyperCrawler/1.0)";
 
 /// Enum to represent data to scrape from
 /// each link
+#[derive(Clone)]
 pub enum ScrapeOption {
     /// Find any image link with the given
     /// extensions. E.g. `Image("jpg")`
     Images,
-    Titles, // TODO Add support for page titles
+    /// The page's `<title>` and its h1 and h2 headings
+    Titles,
+    /// Every h1 to h6 heading with its level, in the order they're on the page
+    Headings,
+    /// Keep the page's HTML in the output
+    Html,
+    /// Record the request and response as a HAR entry
+    Har,
+    /// Keep the response headers with these (lowercase) names
+    Headers(Vec<String>),
+    /// Detect the frameworks, CMSes and servers the page uses
+    Technologies,
+    /// Detect the language the page is written in
+    Language,
+    /// Extract up to this many keywords and named entities
+    Keywords(usize),
+    /// Keep the visible text of the page
+    Text,
+    /// Count the words in the visible text and how long they take to read
+    ContentMetrics,
+    /// Every link with the text it's shown as
+    Anchors,
+    /// The content of every `<meta>` tag with a name or property
+    Meta,
+    /// Only take links from these parts of the page
+    LinkScope(LinkScope),
 }
 
 /// TODO : Rename this to somthing better. This
 /// should hold the <parent link, link to visit>
 /// tuple
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct LinkPath {
     pub parent: String,
     pub child: String,
+    /// Clicks from a seed to `child` along the links it was found by
+    #[serde(default)]
+    pub depth: usize,
+    /// The starting url or one of its `--seed-ancestors`, crawled
+    /// whatever the `--budget`
+    #[serde(default)]
+    pub seed: bool,
 }
 
+/// Everything scraped from a page by `scrape_page`. Urls are absolute
+#[derive(Default, Serialize)]
 pub struct ScrapeOutput {
+    /// Every link on the page, including AMP and other alternate
+    /// versions and where a meta refresh or script redirects to
     pub links: Vec<String>,
+    /// Found with `ScrapeOption::Anchors`, in the order they're on the page
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub anchors: Vec<Anchor>,
+    /// The links the page says not to follow
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub nofollow_links: Vec<String>,
     pub images: Vec<Image>,
-    pub titles: Vec<String>,
+    /// The page's `<title>`, with its whitespace collapsed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// The page's h1 and h2 headings
+    pub headings: Vec<String>,
+    /// Found with `ScrapeOption::Headings`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub outline: Vec<Heading>,
+    /// Whether the page was actually fetched. Failed
+    /// fetches come back with everything else empty.
+    pub fetched: bool,
+    /// The page's HTML, only kept with `ScrapeOption::Html`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub html: Option<String>,
+    /// The request and response, only kept with `ScrapeOption::Har`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub har_entry: Option<HarEntry>,
+    /// Response headers asked for with `ScrapeOption::Headers`
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    /// Found with `ScrapeOption::Technologies`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub technologies: Vec<String>,
+    /// Found with `ScrapeOption::Language`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+    /// Found with `ScrapeOption::Keywords`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub entities: Vec<String>,
+    /// The visible text, kept with `ScrapeOption::Text`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// Found with `ScrapeOption::ContentMetrics`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<ContentMetrics>,
+    /// Found with `ScrapeOption::Meta`, by lowercase name or property
+    /// (e.g. `description`, `og:image`). The first tag wins
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub meta: BTreeMap<String, String>,
+    /// The HTTP status the page was served with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// The SHA-256 of the page's body, after it was decompressed, in hex.
+    /// Pages with the same hash are byte for byte the same
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_hash: Option<String>,
+    /// Size of the page's body
+    #[serde(skip)]
+    pub bytes: usize,
+    /// Size of the page's body as sent, before it was decompressed
+    #[serde(skip)]
+    pub transfer_bytes: usize,
+    /// Why the page was a challenge or block page instead of the real page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocked: Option<String>,
+    /// Why the page couldn't be fetched or parsed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// How long the response took to start arriving
+    #[serde(skip)]
+    pub latency: Option<Duration>,
+    /// The media type the page was served as
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// Where the page redirected to, if it did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redirected_to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redirect_kind: Option<RedirectKind>,
+    /// The page's `rel="canonical"` url
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub canonical: Option<String>,
+    /// The page's AMP and other alternate versions, which are also in `links`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub alternates: Vec<Alternate>,
+    /// The caching headers the page was served with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caching: Option<Caching>,
 }
 
 pub struct CrawlerState {
-    pub link_queue: RwLock<VecDeque<LinkPath>>,
+    pub link_queue: RwLock<Frontier>,
     pub link_graph: RwLock<LinkGraph>,
     pub max_links: usize,
-    pub base_domain: String,
-    pub visited_count: Arc<AtomicUsize>,
+    pub max_links_per_page: Option<usize>,
+    /// Links further than this many clicks from a seed aren't followed
+    pub max_depth: Option<usize>,
+    /// Approximate memory the frontier and link graph may use
+    pub max_memory: Option<usize>,
+    /// How many bytes of pages and images may be downloaded
+    pub max_download_bytes: Option<usize>,
+    /// Whether pages are asked for compressed
+    pub compression: bool,
+    /// Makes page requests look like they come from a browser
+    pub stealth: Option<Stealth>,
+    /// Credentials sent with page requests
+    pub auth: Option<Auth>,
+    /// Gets the access tokens sent with page requests
+    pub oauth2: Option<OAuth2>,
+    /// Cookies and headers from a logged in browser, sent with page requests
+    pub browser_session: Option<BrowserSession>,
+    /// Whether pages are fetched over HTTP/3 from hosts that advertise it
+    #[cfg(feature = "http3")]
+    pub http3: bool,
+    /// Where visited pages are streamed to while crawling
+    pub link_sink: Option<Mutex<LinkSink>>,
+    /// Where the HTML of visited pages is mirrored to
+    pub mirror_dir: Option<PathBuf>,
+    /// Where page archives are saved to, if pages are archived
+    pub archive_dir: Option<PathBuf>,
+    /// Where HAR files are saved to, if they're recorded
+    pub har_dir: Option<PathBuf>,
+    /// Where page content is exported to, if it is
+    pub corpus: Option<CorpusWriter>,
+    /// Records the crawl, or replays a recording of one
+    pub session: Option<Session>,
+    /// Lowercase names of the response headers stored on each link
+    pub capture_headers: Vec<String>,
+    pub detect_technologies: bool,
+    /// Record the h1 to h6 outline of every page
+    pub outline: bool,
+    /// Record AMP and other alternate versions of pages without crawling them
+    pub skip_alternates: bool,
+    /// The delay between page requests to each host, with
+    /// concurrency and header overrides for some hosts
+    pub politeness: Arc<Politeness>,
+    /// Only follow links on pages written in these languages
+    /// (ISO 639-3 codes), every language when empty
+    pub languages: Vec<String>,
+    /// Lowercase schemes of links that are counted on each page instead of crawled
+    pub counted_schemes: Vec<String>,
+    /// Record nofollow links on their pages instead of following them
+    pub respect_nofollow: bool,
+    /// Skip pages robots.txt disallows
+    pub respect_robots: bool,
+    /// The robots.txt of each host, shared with the image downloader
+    pub robots: Arc<RobotsCache>,
+    /// How many keywords and entities to extract from each page
+    pub keywords_per_page: Option<usize>,
+    #[cfg(feature = "embeddings")]
+    pub embedder: Option<crate::embeddings::Embedder>,
+    pub site: SiteScope,
+    /// Only urls with paths starting with one of these are followed, any are if it's empty
+    pub path_prefixes: Vec<String>,
+    /// Only follow links to urls matching one of these, when there are any
+    pub include_patterns: Vec<PathPattern>,
+    /// Don't follow links to urls matching these
+    pub exclude_patterns: Vec<PathPattern>,
+    /// Which parts of pages links are taken from
+    pub link_scope: LinkScope,
+    pub normalize_options: NormalizeOptions,
+    /// Pages fetched successfully, these count against `max_links`
+    pub crawled_count: AtomicUsize,
+    /// Every fetch that finished, successful or not
+    pub attempted_count: AtomicUsize,
+    /// Fetches currently running
+    pub in_flight_count: AtomicUsize,
+    /// Fetches that got a challenge or block page
+    pub blocked_count: AtomicUsize,
+    /// Pages, errors, bytes and latency of each host
+    pub host_report: Mutex<HostReport>,
+    /// Links found to other sites, which aren't crawled
+    pub external_links: Mutex<ExternalLinks>,
+    /// Every url that wasn't crawled or downloaded, with why
+    pub skipped_log: Mutex<SkippedLog>,
+    /// Bytes of pages and images downloaded, these count against
+    /// `max_download_bytes`. Shared with the image downloader
+    pub downloaded_bytes: Arc<AtomicUsize>,
+    /// Downloads the images found on pages while crawling
+    pub image_downloader: Option<ImageDownloader>,
+    /// Total time workers spent waiting to lock the queue and graph
+    pub lock_wait_nanos: AtomicU64,
+    pub started_at: Instant,
+    /// Cancels or pauses the crawl while it runs
+    pub control: CrawlControl,
+    /// Every page crawled, for following the crawl live
+    pub page_events: broadcast::Sender<PageEvent>,
 }
 
 pub type CrawlerStateRef = Arc<CrawlerState>;
 
+impl CrawlerState {
+    pub fn budget_reached(&self) -> bool {
+        self.crawled_count.load(Ordering::Relaxed) >= self.max_links || self.bytes_budget_reached()
+    }
+
+    pub fn bytes_budget_reached(&self) -> bool {
+        self.max_download_bytes
+            .is_some_and(|max| self.downloaded_bytes.load(Ordering::Relaxed) >= max)
+    }
+
+    /// No worker is currently fetching or parsing a page,
+    /// so nothing else is going to be added to the queue
+    pub fn is_idle(&self) -> bool {
+        self.in_flight_count.load(Ordering::Relaxed) == 0
+    }
+
+    /// Reserves a page fetch. Returns `None` if the fetches
+    /// already running could use up the rest of the budget.
+    pub fn reserve_fetch(self: &Arc<Self>) -> Option<FetchSlot> {
+        let in_flight = self.in_flight_count.fetch_add(1, Ordering::Relaxed) + 1;
+        let slot = FetchSlot {
+            state: self.clone(),
+        };
+
+        if self.crawled_count.load(Ordering::Relaxed) + in_flight > self.max_links {
+            return None;
+        }
+
+        Some(slot)
+    }
+}
+
+/// How many page events are kept for slow subscribers, older ones are dropped
+const PAGE_EVENT_BUFFER: usize = 256;
+
+/// A page a crawl fetched, or tried to
+#[derive(Clone, Debug, Serialize)]
+pub struct PageEvent {
+    pub url: String,
+    /// Links found on the page
+    pub links: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The channel page events are sent on, nothing is sent
+/// while there are no subscribers
+pub fn page_events() -> broadcast::Sender<PageEvent> {
+    broadcast::channel(PAGE_EVENT_BUFFER).0
+}
+
+/// Stops or pauses a running crawl. Workers check it before taking
+/// each link off the queue, the pages being fetched are finished
+#[derive(Default)]
+pub struct CrawlControl {
+    cancelled: CancellationToken,
+    paused: AtomicBool,
+}
+
+impl CrawlControl {
+    /// Stops the crawl for good, the pages still queued aren't crawled
+    pub fn cancel(&self) {
+        self.cancelled.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.is_cancelled()
+    }
+
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+}
+
+/// A reserved page fetch, released when dropped. It's held
+/// until the page has been parsed and its links queued
+pub struct FetchSlot {
+    state: CrawlerStateRef,
+}
+
+impl FetchSlot {
+    /// Records the result of the fetch this slot was reserved for
+    pub fn finish(self, fetched: bool) {
+        self.state.attempted_count.fetch_add(1, Ordering::Relaxed);
+        if fetched {
+            self.state.crawled_count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for FetchSlot {
+    fn drop(&mut self) {
+        self.state.in_flight_count.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A crawl of a site for programs embedding the crawler. Nothing is
+/// written to disk unless images are downloaded, the link graph that's
+/// returned has every page that was found
+pub struct Crawler {
+    state: CrawlerStateRef,
+    starting_url: Url,
+    workers: usize,
+}
+
+impl Crawler {
+    pub fn builder() -> CrawlerBuilder {
+        CrawlerBuilder::default()
+    }
+
+    /// What's shared between the crawl's workers, to follow
+    /// its progress while it runs
+    pub fn state(&self) -> &CrawlerStateRef {
+        &self.state
+    }
+
+    /// Crawls until there's nothing left to crawl or `max_links`
+    /// pages have been fetched
+    pub async fn run(self) -> Result<LinkGraph> {
+        let mut workers = tokio::task::JoinSet::new();
+        for _ in 0..self.workers {
+            workers.spawn(crate::worker::crawl(self.state.clone()));
+        }
+        while let Some(result) = workers.join_next().await {
+            result??;
+        }
+        if let Some(image_downloader) = &self.state.image_downloader {
+            image_downloader.finish().await;
+        }
+
+        let mut link_graph = std::mem::take(&mut *self.state.link_graph.write().await);
+        if let Some(seed) = link_graph.get(self.starting_url.as_str()).map(|link| link.id) {
+            let depths = crate::analysis::click_depths(&link_graph, seed);
+            link_graph.set_click_depths(&depths);
+        }
+        Ok(link_graph)
+    }
+
+    /// Starts crawling in the background
+    pub fn spawn(self) -> CrawlHandle {
+        CrawlHandle::spawn(self)
+    }
+}
+
+/// Sets up a `Crawler`, everything but the starting url is optional
+pub struct CrawlerBuilder {
+    starting_url: Option<String>,
+    max_links: usize,
+    max_links_per_page: Option<usize>,
+    max_depth: Option<usize>,
+    max_memory: Option<usize>,
+    max_download_bytes: Option<usize>,
+    workers: usize,
+    delay: Duration,
+    politeness: Option<Politeness>,
+    compression: bool,
+    #[cfg(feature = "http3")]
+    http3: bool,
+    stealth: Option<Stealth>,
+    auth: Option<Auth>,
+    oauth2: Option<OAuth2>,
+    browser_session: Option<BrowserSession>,
+    session: Option<Session>,
+    include_patterns: Vec<PathPattern>,
+    exclude_patterns: Vec<PathPattern>,
+    path_prefixes: Vec<String>,
+    seed_ancestors: bool,
+    link_scope: LinkScope,
+    normalize_options: NormalizeOptions,
+    port_policy: PortPolicy,
+    host_normalization: HostNormalization,
+    respect_nofollow: bool,
+    respect_robots: bool,
+    dedup_key: DedupKey,
+    schedule: Schedule,
+    budget: Option<SectionBudget>,
+    languages: Vec<String>,
+    counted_schemes: Vec<String>,
+    capture_headers: Vec<String>,
+    detect_technologies: bool,
+    outline: bool,
+    skip_alternates: bool,
+    keywords_per_page: Option<usize>,
+    link_sink: Option<LinkSink>,
+    skipped_log: Option<SkippedLog>,
+    mirror_dir: Option<PathBuf>,
+    archive_dir: Option<PathBuf>,
+    har_dir: Option<PathBuf>,
+    corpus: Option<CorpusWriter>,
+    #[cfg(feature = "embeddings")]
+    embedder: Option<crate::embeddings::Embedder>,
+    /// Where images are downloaded to, and how many
+    images: Option<(String, usize)>,
+    images_from: Option<Regex>,
+    image_dedup: ImageDedup,
+    image_domains: ImageDomains,
+    image_referer: bool,
+}
+
+impl Default for CrawlerBuilder {
+    fn default() -> Self {
+        Self {
+            starting_url: None,
+            max_links: 100,
+            max_links_per_page: None,
+            max_depth: None,
+            max_memory: None,
+            max_download_bytes: None,
+            workers: 4,
+            delay: Duration::from_millis(500),
+            politeness: None,
+            compression: true,
+            #[cfg(feature = "http3")]
+            http3: false,
+            stealth: None,
+            auth: None,
+            oauth2: None,
+            browser_session: None,
+            session: None,
+            include_patterns: Vec::new(),
+            exclude_patterns: Vec::new(),
+            path_prefixes: Vec::new(),
+            seed_ancestors: false,
+            link_scope: LinkScope::default(),
+            normalize_options: NormalizeOptions::default(),
+            port_policy: PortPolicy::default(),
+            host_normalization: HostNormalization::default(),
+            respect_nofollow: false,
+            respect_robots: false,
+            dedup_key: DedupKey::default(),
+            schedule: Schedule::default(),
+            budget: None,
+            languages: Vec::new(),
+            counted_schemes: Vec::new(),
+            capture_headers: Vec::new(),
+            detect_technologies: false,
+            outline: false,
+            skip_alternates: false,
+            keywords_per_page: None,
+            link_sink: None,
+            skipped_log: None,
+            mirror_dir: None,
+            archive_dir: None,
+            har_dir: None,
+            corpus: None,
+            #[cfg(feature = "embeddings")]
+            embedder: None,
+            images: None,
+            images_from: None,
+            image_dedup: ImageDedup::default(),
+            image_domains: ImageDomains::default(),
+            image_referer: true,
+        }
+    }
+}
+
+impl CrawlerBuilder {
+    /// The page the crawl starts from, only pages on its site are crawled
+    pub fn start_url(mut self, url: &str) -> Self {
+        self.starting_url = Some(url.to_string());
+        self
+    }
+
+    /// How many pages to fetch, 100 by default
+    pub fn max_links(mut self, max_links: usize) -> Self {
+        self.max_links = max_links;
+        self
+    }
+
+    /// Only take this many links from each page
+    pub fn max_links_per_page(mut self, max_links_per_page: impl Into<Option<usize>>) -> Self {
+        self.max_links_per_page = max_links_per_page.into();
+        self
+    }
+
+    /// Don't follow links further than this many clicks from the starting url,
+    /// counted along the shortest path found before a page is crawled
+    pub fn max_depth(mut self, max_depth: impl Into<Option<usize>>) -> Self {
+        self.max_depth = max_depth.into();
+        self
+    }
+
+    /// Approximate memory the queue and link graph may use,
+    /// queued links are spilled to disk past it
+    pub fn max_memory(mut self, max_memory: impl Into<Option<usize>>) -> Self {
+        self.max_memory = max_memory.into();
+        self
+    }
+
+    /// Stop once this many bytes of pages and images have been downloaded
+    pub fn max_download_bytes(mut self, max_download_bytes: impl Into<Option<usize>>) -> Self {
+        self.max_download_bytes = max_download_bytes.into();
+        self
+    }
+
+    /// How many pages are fetched at once, 4 by default
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// The time between requests to the same host, 500ms by default
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Space out requests with `politeness` instead of just `delay`,
+    /// for its overrides for some hosts. Images are downloaded through it too
+    pub fn politeness(mut self, politeness: Politeness) -> Self {
+        self.politeness = Some(politeness);
+        self
+    }
+
+    /// Ask for pages compressed, which they are by default
+    pub fn compression(mut self, compression: bool) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Fetch pages over HTTP/3 from hosts that advertise it
+    #[cfg(feature = "http3")]
+    pub fn http3(mut self, http3: bool) -> Self {
+        self.http3 = http3;
+        self
+    }
+
+    /// Make page requests look like they come from a browser
+    pub fn stealth(mut self, stealth: impl Into<Option<Stealth>>) -> Self {
+        self.stealth = stealth.into();
+        self
+    }
+
+    /// Send page requests with the credentials `auth` has for their host
+    pub fn auth(mut self, auth: impl Into<Option<Auth>>) -> Self {
+        self.auth = auth.into();
+        self
+    }
+
+    /// Send page requests with access tokens from `oauth2`
+    pub fn oauth2(mut self, oauth2: impl Into<Option<OAuth2>>) -> Self {
+        self.oauth2 = oauth2.into();
+        self
+    }
+
+    /// Send page requests with the cookies and headers of a logged in browser
+    pub fn browser_session(mut self, browser_session: impl Into<Option<BrowserSession>>) -> Self {
+        self.browser_session = browser_session.into();
+        self
+    }
+
+    /// Record the crawl, or replay a recording of one
+    pub fn session(mut self, session: impl Into<Option<Session>>) -> Self {
+        self.session = session.into();
+        self
+    }
+
+    /// Only follow links to urls matching this, or one of the other
+    /// patterns given. See `PathPattern::parse`
+    pub fn include_pattern(mut self, pattern: PathPattern) -> Self {
+        self.include_patterns.push(pattern);
+        self
+    }
+
+    /// Don't follow links to urls matching this
+    pub fn exclude_pattern(mut self, pattern: PathPattern) -> Self {
+        self.exclude_patterns.push(pattern);
+        self
+    }
+
+    /// Only follow links to urls whose path starts with
+    /// this, or one of the other prefixes given
+    pub fn path_prefix(mut self, prefix: &str) -> Self {
+        self.path_prefixes.push(prefix.to_string());
+        self
+    }
+
+    /// Also crawl the parent directories of the starting url,
+    /// after the starting url and the pages it links to
+    pub fn seed_ancestors(mut self, seed_ancestors: bool) -> Self {
+        self.seed_ancestors = seed_ancestors;
+        self
+    }
+
+    /// Which parts of pages links are taken from
+    pub fn link_scope(mut self, link_scope: LinkScope) -> Self {
+        self.link_scope = link_scope;
+        self
+    }
+
+    /// How urls are rewritten before they're queued
+    pub fn normalize_options(mut self, normalize_options: NormalizeOptions) -> Self {
+        self.normalize_options = normalize_options;
+        self
+    }
+
+    /// Whether urls on a different port of the starting url's host are part of the site
+    pub fn port_policy(mut self, port_policy: PortPolicy) -> Self {
+        self.port_policy = port_policy;
+        self
+    }
+
+    /// How hosts are compared when deduplicating and telling
+    /// whether urls are part of the site
+    pub fn host_normalization(mut self, host_normalization: HostNormalization) -> Self {
+        self.host_normalization = host_normalization;
+        self
+    }
+
+    /// Don't follow links marked `rel="nofollow"`
+    pub fn respect_nofollow(mut self, respect_nofollow: bool) -> Self {
+        self.respect_nofollow = respect_nofollow;
+        self
+    }
+
+    /// Don't crawl pages robots.txt disallows
+    pub fn respect_robots(mut self, respect_robots: bool) -> Self {
+        self.respect_robots = respect_robots;
+        self
+    }
+
+    /// Which urls are the same page and only crawled once,
+    /// by default urls differing only in their scheme
+    pub fn dedup_key(mut self, dedup_key: DedupKey) -> Self {
+        self.dedup_key = dedup_key;
+        self
+    }
+
+    /// The order queued links are crawled in, taking turns between
+    /// hosts or sections keeps one of them from using up `max_links`
+    pub fn schedule(mut self, schedule: Schedule) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    /// Splits `max_links` between sections of the site
+    pub fn budget(mut self, budget: impl Into<Option<SectionBudget>>) -> Self {
+        self.budget = budget.into();
+        self
+    }
+
+    /// Only follow links on pages written in these languages
+    /// (ISO 639-3 codes), every language when empty
+    pub fn languages(mut self, languages: Vec<String>) -> Self {
+        self.languages = languages;
+        self
+    }
+
+    /// Count the links with these schemes on each page instead of crawling them
+    pub fn counted_schemes(mut self, schemes: &[String]) -> Self {
+        self.counted_schemes = schemes
+            .iter()
+            .map(|scheme| scheme.trim().trim_end_matches(':').to_lowercase())
+            .filter(|scheme| !scheme.is_empty())
+            .collect();
+        self
+    }
+
+    /// Store these response headers on each link
+    pub fn capture_headers(mut self, names: &[String]) -> Self {
+        self.capture_headers = names
+            .iter()
+            .map(|name| name.trim().to_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect();
+        self
+    }
+
+    /// Detect the technologies each page is built with
+    pub fn detect_technologies(mut self, detect_technologies: bool) -> Self {
+        self.detect_technologies = detect_technologies;
+        self
+    }
+
+    /// Record the h1 to h6 outline of every page
+    pub fn outline(mut self, outline: bool) -> Self {
+        self.outline = outline;
+        self
+    }
+
+    /// Record AMP and other alternate versions of pages without crawling them
+    pub fn skip_alternates(mut self, skip_alternates: bool) -> Self {
+        self.skip_alternates = skip_alternates;
+        self
+    }
+
+    /// Extract this many keywords and entities from each page
+    pub fn keywords_per_page(mut self, keywords_per_page: impl Into<Option<usize>>) -> Self {
+        self.keywords_per_page = keywords_per_page.into();
+        self
+    }
+
+    /// Stream every page to `link_sink` as it's crawled
+    pub fn link_sink(mut self, link_sink: impl Into<Option<LinkSink>>) -> Self {
+        self.link_sink = link_sink.into();
+        self
+    }
+
+    /// Log every url that isn't crawled to `skipped_log`,
+    /// they're only counted by default
+    pub fn skipped_log(mut self, skipped_log: SkippedLog) -> Self {
+        self.skipped_log = Some(skipped_log);
+        self
+    }
+
+    /// Mirror the HTML of every page to this directory
+    pub fn mirror_dir(mut self, mirror_dir: impl Into<Option<PathBuf>>) -> Self {
+        self.mirror_dir = mirror_dir.into();
+        self
+    }
+
+    /// Archive every page to this directory
+    pub fn archive_dir(mut self, archive_dir: impl Into<Option<PathBuf>>) -> Self {
+        self.archive_dir = archive_dir.into();
+        self
+    }
+
+    /// Record a HAR file of every page in this directory
+    pub fn har_dir(mut self, har_dir: impl Into<Option<PathBuf>>) -> Self {
+        self.har_dir = har_dir.into();
+        self
+    }
+
+    /// Export the content of every page to `corpus`
+    pub fn corpus(mut self, corpus: impl Into<Option<CorpusWriter>>) -> Self {
+        self.corpus = corpus.into();
+        self
+    }
+
+    /// Embed the text of every page with `embedder`
+    #[cfg(feature = "embeddings")]
+    pub fn embedder(mut self, embedder: impl Into<Option<crate::embeddings::Embedder>>) -> Self {
+        self.embedder = embedder.into();
+        self
+    }
+
+    /// Download up to `max_images` of the images found on pages to
+    /// `directory`, which has to exist. They aren't downloaded by default
+    pub fn download_images(mut self, directory: &str, max_images: usize) -> Self {
+        self.images = Some((directory.to_string(), max_images));
+        self
+    }
+
+    /// Only download images found on pages whose path matches
+    pub fn images_from(mut self, images_from: impl Into<Option<Regex>>) -> Self {
+        self.images_from = images_from.into();
+        self
+    }
+
+    /// What's done with an image found on more than one page, by
+    /// default it's downloaded once
+    pub fn image_dedup(mut self, image_dedup: ImageDedup) -> Self {
+        self.image_dedup = image_dedup;
+        self
+    }
+
+    /// The hosts images may be downloaded from, any by default
+    pub fn image_domains(mut self, image_domains: ImageDomains) -> Self {
+        self.image_domains = image_domains;
+        self
+    }
+
+    /// Send the page an image was found on as the Referer, which it is by default
+    pub fn image_referer(mut self, image_referer: bool) -> Self {
+        self.image_referer = image_referer;
+        self
+    }
+
+    pub fn build(self) -> Result<Crawler> {
+        let starting_url = self.starting_url.as_deref().context("missing starting url")?;
+        let normalize_options = self.normalize_options;
+        let starting_url = normalize_url(starting_url, &normalize_options)
+            .context("starting url must be an http(s) url")?;
+        let site = SiteScope::from_url(&starting_url, self.port_policy)
+            .context("starting url must have a host")?
+            .with_host_normalization(self.host_normalization);
+
+        let mut link_queue = Frontier::with_dedup_key(self.dedup_key);
+        link_queue.set_host_normalization(self.host_normalization);
+        link_queue.set_schedule(self.schedule);
+        if let Some(budget) = self.budget {
+            link_queue.set_budget(budget, self.max_links);
+        }
+        // Links are taken from the back of the queue, so the ancestors are
+        // visited after the starting url and the pages it links to, nearest first
+        if self.seed_ancestors {
+            let ancestors = ancestor_urls(&starting_url)
+                .into_iter()
+                .filter(|ancestor| in_path_prefixes(ancestor, &self.path_prefixes));
+            for ancestor in ancestors.rev() {
+                if let Some(ancestor) = normalize_url(ancestor.as_str(), &normalize_options) {
+                    link_queue.push_back(LinkPath {
+                        child: ancestor.to_string(),
+                        seed: true,
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+        link_queue.push_back(LinkPath {
+            child: starting_url.to_string(),
+            seed: true,
+            ..Default::default()
+        });
+
+        let downloaded_bytes = Arc::new(AtomicUsize::new(0));
+        let robots = Arc::new(RobotsCache::default());
+        let politeness = Arc::new(self.politeness.unwrap_or_else(|| Politeness::new(self.delay)));
+        let image_downloader = self.images.map(|(directory, max_images)| {
+            let options = DownloadOptions {
+                max_images,
+                max_bytes: self.max_download_bytes,
+                politeness: politeness.clone(),
+                images_from: self.images_from,
+                dedup: self.image_dedup,
+                domains: self.image_domains,
+                referer: self.image_referer,
+                robots: robots.clone(),
+            };
+            ImageDownloader::start(&directory, options, downloaded_bytes.clone())
+        });
+
+        let state = CrawlerState {
+            link_queue: RwLock::new(link_queue),
+            link_graph: RwLock::new(LinkGraph::with_site(site.clone())),
+            max_links: self.max_links,
+            max_links_per_page: self.max_links_per_page,
+            max_depth: self.max_depth,
+            max_memory: self.max_memory,
+            max_download_bytes: self.max_download_bytes,
+            compression: self.compression,
+            stealth: self.stealth,
+            auth: self.auth,
+            oauth2: self.oauth2,
+            browser_session: self.browser_session,
+            #[cfg(feature = "http3")]
+            http3: self.http3,
+            link_sink: self.link_sink.map(Mutex::new),
+            mirror_dir: self.mirror_dir,
+            archive_dir: self.archive_dir,
+            har_dir: self.har_dir,
+            corpus: self.corpus,
+            session: self.session,
+            capture_headers: self.capture_headers,
+            detect_technologies: self.detect_technologies,
+            outline: self.outline,
+            skip_alternates: self.skip_alternates,
+            politeness,
+            languages: self.languages,
+            counted_schemes: self.counted_schemes,
+            respect_nofollow: self.respect_nofollow,
+            respect_robots: self.respect_robots,
+            robots,
+            keywords_per_page: self.keywords_per_page,
+            #[cfg(feature = "embeddings")]
+            embedder: self.embedder,
+            site,
+            path_prefixes: self.path_prefixes,
+            include_patterns: self.include_patterns,
+            exclude_patterns: self.exclude_patterns,
+            link_scope: self.link_scope,
+            normalize_options,
+            crawled_count: AtomicUsize::new(0),
+            attempted_count: AtomicUsize::new(0),
+            in_flight_count: AtomicUsize::new(0),
+            blocked_count: AtomicUsize::new(0),
+            host_report: Mutex::new(HostReport::default()),
+            external_links: Mutex::new(ExternalLinks::default()),
+            skipped_log: Mutex::new(self.skipped_log.unwrap_or_else(SkippedLog::counting)),
+            downloaded_bytes,
+            image_downloader,
+            lock_wait_nanos: AtomicU64::new(0),
+            started_at: Instant::now(),
+            control: CrawlControl::default(),
+            page_events: page_events(),
+        };
+
+        Ok(Crawler {
+            state: Arc::new(state),
+            starting_url,
+            workers: self.workers,
+        })
+    }
+
+    /// Builds the crawler and runs it
+    pub async fn run(self) -> Result<LinkGraph> {
+        self.build()?.run().await
+    }
+}
+
 /// This will turn relative urls into
 /// full urls.
 /// E.g. get_url("/services/", "https://google.com/") -> "https://google.com/service/"
@@ -67,23 +1168,33 @@ fn get_url(path: &str, root_url: Url) -> Result<Url> {
         .ok_or(anyhow!("could not join relative path"))
 }
 
-// TODO : we're gonna need to know the ID of the URL
-fn get_images(html_dom: &Html, root_url: &Url) -> Vec<Image> {
-    let img_selector = Selector::parse("img[src]").unwrap();
+/// The (link, alt text) of every image on the page
+fn get_image_sources(html_dom: &Html) -> Vec<(String, String)> {
+    let img_selector = Selector::parse("img[src], img[srcset]").unwrap();
 
-    let image_links = html_dom
+    html_dom
         .select(&img_selector)
-        .filter(|e| e.value().attr("src").is_some())
-        .map(|e| {
-            (
-                e.value().attr("src").unwrap(),
-                e.value().attr("alt").unwrap_or(""),
-            )
+        .flat_map(|e| {
+            let alt = e.value().attr("alt").unwrap_or("");
+            // Other srcset candidates are resized copies of `src`,
+            // inlined images are often only found there
+            let inlined = e.value().attr("srcset").map(srcset_data_uris);
+            e.value()
+                .attr("src")
+                .into_iter()
+                .chain(inlined.into_iter().flatten())
+                .map(move |link| (link.to_string(), alt.to_string()))
         })
-        .map(|(link, alt)| Image {
-            link: link.to_string(),
-            alt: alt.to_string(),
-        });
+        .collect()
+}
+
+// TODO : we're gonna need to know the ID of the URL
+fn get_images(image_sources: Vec<(String, String)>, root_url: &Url) -> Vec<Image> {
+    let image_links = image_sources.into_iter().map(|(link, alt)| Image {
+        link,
+        alt,
+        ..Default::default()
+    });
 
     let mut result: Vec<Image> = Default::default();
     for image in image_links {
@@ -102,96 +1213,702 @@ fn get_images(html_dom: &Html, root_url: &Url) -> Vec<Image> {
     result
 }
 
-/// This function will scrape all the titles from
-/// the given page's DOM -> title tags, h1, and h2 tags
-fn get_titles(html_dom: &Html) -> Vec<String> {
-    let mut titles: Vec<String> = Default::default();
+/// The `data:` urls in an `srcset` attribute. These contain commas
+/// themselves, so the candidates can't just be split on commas.
+pub fn srcset_data_uris(srcset: &str) -> Vec<&str> {
+    let mut uris = Vec::new();
+    let mut position = 0;
+
+    while let Some(offset) = srcset[position..].find("data:") {
+        let start = position + offset;
+        let candidate_start = srcset[..start]
+            .trim_end()
+            .chars()
+            .next_back()
+            .is_none_or(|c| c == ',');
+
+        let end = srcset[start..]
+            .find(char::is_whitespace)
+            .map_or(srcset.len(), |len| start + len);
+        if candidate_start {
+            uris.push(srcset[start..end].trim_end_matches(','));
+        }
+        position = end;
+    }
+
+    uris
+}
+
+/// The page's `<title>`, the first one if there are several
+fn get_title(html_dom: &Html) -> Option<String> {
+    let title_selector = Selector::parse("title").unwrap();
+    let title = html_dom.select(&title_selector).next()?;
+    Some(title.text().collect::<String>())
+}
+
+/// Every h1 to h6 heading on the page, in document order
+fn get_outline(html_dom: &Html) -> Vec<Heading> {
+    let heading_selector = Selector::parse("h1, h2, h3, h4, h5, h6").unwrap();
+
+    html_dom
+        .select(&heading_selector)
+        .filter_map(|e| {
+            Some(Heading {
+                level: heading_level(e.value().name())?,
+                text: e.text().collect::<String>(),
+            })
+        })
+        .collect()
+}
+
+/// Where the page sends the browser without an HTTP redirect, a
+/// meta refresh wins over a script since it works without one
+fn get_client_redirect(html_dom: &Html) -> Option<(RedirectKind, String)> {
+    let refresh_selector = Selector::parse(r#"meta[http-equiv="refresh" i]"#).unwrap();
+    let meta_refresh = html_dom.select(&refresh_selector).find_map(|meta| {
+        client_redirect::meta_refresh_target(meta.value().attr("content")?)
+    });
+    if let Some(target) = meta_refresh {
+        return Some((RedirectKind::MetaRefresh, target.to_string()));
+    }
+
+    let script_selector = Selector::parse("script:not([src])").unwrap();
+    html_dom.select(&script_selector).find_map(|script| {
+        let script = script.text().collect::<String>();
+        let target = client_redirect::javascript_target(&script)?;
+        Some((RedirectKind::Javascript, target.to_string()))
+    })
+}
+
+/// The level of a heading tag, e.g. 2 for `h2`
+pub fn heading_level(tag: &str) -> Option<u8> {
+    let level = tag.strip_prefix(['h', 'H'])?.parse().ok()?;
+    (1..=6).contains(&level).then_some(level)
+}
 
-    for tag in ["h1", "h2", "title"] {
-        let title_selector = Selector::parse(tag).unwrap();
+/// The text of the page's h1 headings, then its h2 headings
+fn get_headings(outline: &[Heading]) -> Vec<String> {
+    let mut headings: Vec<String> = Default::default();
 
-        titles.extend(
-            html_dom
-                .select(&title_selector)
-                .map(|e| e.text().collect::<String>()),
+    for level in [1, 2] {
+        headings.extend(
+            outline
+                .iter()
+                .filter(|heading| heading.level == level)
+                .map(|heading| heading.text.clone()),
         );
     }
 
-    titles
+    headings
+}
+
+/// Collapses the whitespace in a title the way browsers show it,
+/// `None` if nothing is left
+pub fn clean_title(title: &str) -> Option<String> {
+    let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!title.is_empty()).then_some(title)
+}
+
+/// Whether a space separated `rel` attribute has `value`
+pub fn has_rel(rel: &str, value: &str) -> bool {
+    rel.split_ascii_whitespace()
+        .any(|rel| rel.eq_ignore_ascii_case(value))
+}
+
+/// What kind of alternate version of the page a `<link>` points
+/// to, `None` if it isn't one. Alternates in other languages and
+/// feeds have no `media` query, they aren't the same page
+pub fn alternate_kind(rel: &str, media: Option<&str>) -> Option<AlternateKind> {
+    if has_rel(rel, "amphtml") {
+        Some(AlternateKind::Amp)
+    } else if has_rel(rel, "alternate") && media.is_some_and(|media| !media.trim().is_empty()) {
+        Some(AlternateKind::Media)
+    } else {
+        None
+    }
+}
+
+/// Whether a `rel` attribute or robots meta tag says not to follow links
+pub fn is_nofollow(directives: Option<&str>) -> bool {
+    directives.is_some_and(|directives| {
+        directives
+            .split(|c: char| c == ',' || c.is_ascii_whitespace())
+            .any(|directive| directive.eq_ignore_ascii_case("nofollow") || directive.eq_ignore_ascii_case("none"))
+    })
+}
+
+/// Elements whose text isn't part of what a reader sees
+pub const SKIPPED_TEXT_ELEMENTS: [&str; 5] = ["head", "script", "style", "noscript", "template"];
+
+/// This function will collect the visible text
+/// of the given page's DOM, one space between nodes
+fn get_text(html_dom: &Html) -> String {
+    let mut text = String::new();
+
+    for node in html_dom.root_element().descendants() {
+        let Node::Text(node_text) = node.value() else {
+            continue;
+        };
+
+        let hidden = node.ancestors().any(|ancestor| {
+            ancestor
+                .value()
+                .as_element()
+                .is_some_and(|e| SKIPPED_TEXT_ELEMENTS.contains(&e.name()))
+        });
+        if !hidden {
+            text.push_str(node_text);
+            text.push(' ');
+        }
+    }
+
+    text
 }
 
-/// Given a `url` and a `client`, it will parse the
-/// HTML in a DOM structure, and scrape all the information
-/// requested. It will find links by default.
-/// It may return an error if the request fails.
-async fn scrape_page_helper(
+/// A page that's been fetched with `fetch_page` but not parsed yet
+pub struct FetchedPage {
+    html: String,
+    status: StatusCode,
+    /// The SHA-256 of the decompressed body, in hex
+    body_hash: String,
+    /// Where the page redirected to, if it did
+    redirected_to: Option<String>,
+    /// Size of the body as sent, before it was decompressed
+    transfer_bytes: usize,
+    /// How long the response took to start arriving
+    latency: Duration,
+    response_headers: HeaderMap,
+    /// The headers asked for with `ScrapeOption::Headers`
+    headers: HashMap<String, String>,
+    har_entry: Option<HarEntry>,
+}
+
+/// Fetching is IO-bound and parsing CPU-bound, so pages are parsed on
+/// the blocking thread pool with this many parsed at once, whichever
+/// worker fetched them. A big page never stalls the executor.
+static PARSE_PERMITS: LazyLock<Semaphore> = LazyLock::new(|| {
+    Semaphore::new(std::thread::available_parallelism().map_or(4, |n| n.get()))
+});
+
+/// Given a `url` and the `page` fetched from it, it will parse
+/// the HTML in a DOM structure on the blocking thread pool,
+/// and scrape all the information requested.
+async fn parse_page_blocking(
     url: Url,
-    client: &Client,
+    page: FetchedPage,
     options: &[ScrapeOption],
 ) -> Result<ScrapeOutput> {
-    let response = client.get(url.clone()).send().await?;
+    let _permit = PARSE_PERMITS.acquire().await?;
+    let options = options.to_vec();
+    let output = tokio::task::spawn_blocking(move || parse_page(&url, page, &options)).await?;
+    Ok(output)
+}
+
+/// Fetches the page at `url` for `parse_fetched_page`, the first half
+/// of `scrape_page`. It fails if the request fails or the page is blocked.
+pub async fn fetch_page(
+    url: &Url,
+    client: &PageClient,
+    options: &[ScrapeOption],
+    session: Option<&Session>,
+) -> Result<FetchedPage> {
+    let mut request = client.request(url)?;
+    client.authorize(&mut request).await?;
+    let pending_har_entry = options
+        .iter()
+        .any(|o| matches!(o, ScrapeOption::Har))
+        .then(|| PendingEntry::new(&request));
+
+    // Recorded and replayed fetches don't go over HTTP/3 or wait for stealth delays,
+    // the delays aren't part of the request's latency
+    let _permit = match session {
+        Some(_) => None,
+        None => client.wait_to_send(request.url()).await,
+    };
+    let request_start = Instant::now();
+    let response = match session {
+        Some(session) => session.execute(&client.client, request).await?,
+        None => client.send(request).await?,
+    };
+    let wait = request_start.elapsed();
+    let redirected_to = (response.url() != url).then(|| response.url().to_string());
+
+    // Block pages are often sent with an error status, they're read to tell them apart
+    let status = response.status();
+    if let (StatusCode::UNAUTHORIZED, Some(oauth2)) = (status, &client.oauth2) {
+        oauth2.invalidate().await;
+    }
+    if status != StatusCode::OK && !blocked::is_block_status(status) {
+        bail!("page returned invalid response");
+    }
 
-    if response.status() != StatusCode::OK {
+    let mut headers = HashMap::new();
+    for option in options {
+        if let ScrapeOption::Headers(names) = option {
+            headers.extend(
+                names
+                    .iter()
+                    .filter_map(|name| Some((name.clone(), response.headers().get(name)?)))
+                    .map(|(name, value)| (name, String::from_utf8_lossy(value.as_bytes()).to_string())),
+            );
+        }
+    }
+
+    let response_headers = response.headers().clone();
+    let mut har_entry = pending_har_entry.map(|entry| entry.response_received(&response, wait));
+    let body = response.bytes().await?.to_vec();
+    let transfer_bytes = body.len();
+    // Decompressing a big page would hold up the other workers' tasks
+    let body = {
+        let headers = response_headers.clone();
+        tokio::task::spawn_blocking(move || {
+            compression::decode_body(&headers, body, compression::MAX_DECODED_BYTES)
+        })
+        .await??
+    };
+    let html = compression::decode_text(&response_headers, &body);
+    if let Some(reason) = blocked::detect_block(status, &response_headers, &html) {
+        return Err(PageBlocked(reason).into());
+    }
+    if status != StatusCode::OK {
         bail!("page returned invalid response");
     }
+    if let Some(entry) = har_entry.as_mut() {
+        entry.set_body(body.len(), transfer_bytes, request_start.elapsed() - wait);
+    }
+
+    Ok(FetchedPage {
+        html,
+        status,
+        body_hash: format!("{:x}", Sha256::digest(&body)),
+        redirected_to,
+        transfer_bytes,
+        latency: wait,
+        response_headers,
+        headers,
+        har_entry,
+    })
+}
+
+/// What's extracted from every page, whether it was
+/// parsed into a DOM or streamed through a tokenizer
+pub struct PageContent {
+    /// The `href` of every link, as written on the page
+    pub links: Vec<String>,
+    /// The text of each of `links`, in the same order
+    pub anchor_texts: Vec<String>,
+    /// The links marked `rel="nofollow"`, or all of them when the
+    /// page's robots meta tag says not to follow its links
+    pub nofollow_links: Vec<String>,
+    /// The (link, alt text) of every image, as written on the page
+    pub image_sources: Vec<(String, String)>,
+    pub title: Option<String>,
+    /// The h1 to h6 headings, in document order
+    pub outline: Vec<Heading>,
+    /// Where a meta refresh or short inline script sends the browser, as written
+    pub client_redirect: Option<(RedirectKind, String)>,
+    /// The `rel="canonical"` url, as written
+    pub canonical: Option<String>,
+    /// The `<html lang>` attribute, e.g. `en-GB`
+    pub html_lang: Option<String>,
+    /// The AMP and other alternate versions, as written
+    pub alternates: Vec<Alternate>,
+    /// The (name or property, content) of every `<meta>` tag with one
+    pub meta: Vec<(String, String)>,
+    /// The visible text, empty unless it was asked for
+    pub text: String,
+}
+
+impl PageContent {
+    pub(crate) fn from_dom(html_dom: &Html, with_text: bool, link_scope: Option<&LinkScope>) -> Self {
+        let link_selector = Selector::parse("a").unwrap();
+        let mut links = Vec::new();
+        let mut anchor_texts = Vec::new();
+        let mut nofollow_links = Vec::new();
+        for anchor in html_dom.select(&link_selector) {
+            let Some(href) = anchor.value().attr("href") else {
+                continue;
+            };
+            if link_scope.is_some_and(|link_scope| !link_scope.contains(anchor)) {
+                continue;
+            }
+            if is_nofollow(anchor.value().attr("rel")) {
+                nofollow_links.push(href.to_string());
+            }
+            links.push(href.to_string());
+            anchor_texts.push(anchor.text().collect());
+        }
+
+        let meta_selector = Selector::parse("meta[content]").unwrap();
+        let meta = html_dom
+            .select(&meta_selector)
+            .filter_map(|meta| {
+                let name = meta.value().attr("name").or(meta.value().attr("property"))?;
+                Some((name.to_string(), meta.value().attr("content")?.to_string()))
+            })
+            .collect();
+
+        let mut canonical = None;
+        let mut alternates = Vec::new();
+        let rel_selector = Selector::parse("link[rel][href]").unwrap();
+        for link in html_dom.select(&rel_selector) {
+            let (Some(rel), Some(href)) = (link.value().attr("rel"), link.value().attr("href"))
+            else {
+                continue;
+            };
+            if canonical.is_none() && has_rel(rel, "canonical") {
+                canonical = Some(href.to_string());
+            }
+            let media = link.value().attr("media");
+            if let Some(kind) = alternate_kind(rel, media) {
+                alternates.push(Alternate {
+                    url: href.to_string(),
+                    kind,
+                    media: media.map(str::to_string),
+                });
+            }
+        }
+
+        let robots_selector = Selector::parse(r#"meta[name="robots" i]"#).unwrap();
+        if html_dom
+            .select(&robots_selector)
+            .any(|meta| is_nofollow(meta.value().attr("content")))
+        {
+            nofollow_links = links.clone();
+        }
+
+        Self {
+            links,
+            anchor_texts,
+            nofollow_links,
+            image_sources: get_image_sources(html_dom),
+            title: get_title(html_dom),
+            outline: get_outline(html_dom),
+            client_redirect: get_client_redirect(html_dom),
+            canonical,
+            alternates,
+            meta,
+            html_lang: html_dom
+                .root_element()
+                .value()
+                .attr("lang")
+                .map(str::to_string),
+            text: if with_text {
+                get_text(html_dom)
+            } else {
+                String::new()
+            },
+        }
+    }
+}
 
-    let html = response.text().await?;
+/// Extracts what `options` asks for from a fetched page
+fn parse_page(url: &Url, page: FetchedPage, options: &[ScrapeOption]) -> ScrapeOutput {
+    let FetchedPage {
+        html,
+        status,
+        body_hash,
+        redirected_to,
+        transfer_bytes,
+        latency,
+        response_headers,
+        headers,
+        har_entry,
+    } = page;
+    let caching = Caching::from_headers(&response_headers, chrono::Utc::now());
 
-    let html_dom = scraper::Html::parse_document(&html);
+    // Images, PDFs and other files that got linked to aren't parsed for links
+    let content_type = media_type(&response_headers);
+    if content_type
+        .as_deref()
+        .is_some_and(|content_type| !is_html_media_type(content_type))
+    {
+        return ScrapeOutput {
+            fetched: true,
+            status: Some(status.as_u16()),
+            body_hash: Some(body_hash),
+            har_entry,
+            headers,
+            bytes: html.len(),
+            transfer_bytes,
+            latency: Some(latency),
+            content_type,
+            redirect_kind: redirected_to.is_some().then_some(RedirectKind::Http),
+            redirected_to,
+            caching,
+            ..Default::default()
+        };
+    }
+
+    let needs_text = options.iter().any(|o| {
+        matches!(
+            o,
+            ScrapeOption::Language
+                | ScrapeOption::Keywords(_)
+                | ScrapeOption::Text
+                | ScrapeOption::ContentMetrics
+        )
+    });
+
+    // Very large pages are streamed through a tokenizer instead of
+    // parsed into a DOM, unless something needs the DOM itself
+    let link_scope = options.iter().find_map(|o| match o {
+        ScrapeOption::LinkScope(link_scope) => Some(link_scope),
+        _ => None,
+    });
+    let needs_dom = link_scope.is_some()
+        || options
+            .iter()
+            .any(|o| matches!(o, ScrapeOption::Technologies));
+    let streamed = if html.len() >= html_stream::STREAMING_THRESHOLD_BYTES && !needs_dom {
+        html_stream::extract_page(&html, needs_text)
+            .map_err(|e| warn!("could not stream {}, parsing it instead: {}", url, e))
+            .ok()
+    } else {
+        None
+    };
+
+    let (html_dom, content) = match streamed {
+        Some(content) => (None, content),
+        None => {
+            let html_dom = Html::parse_document(&html);
+            let content = PageContent::from_dom(&html_dom, needs_text, link_scope);
+            (Some(html_dom), content)
+        }
+    };
+    let PageContent {
+        mut links,
+        anchor_texts,
+        nofollow_links,
+        image_sources,
+        title: page_title,
+        outline: page_outline,
+        client_redirect,
+        canonical,
+        alternates,
+        meta: page_meta,
+        html_lang,
+        text,
+    } = content;
+    // Alternate versions are crawled like any other page linked to
+    links.extend(alternates.iter().map(|alternate| alternate.url.clone()));
 
-    let link_selector = Selector::parse("a").unwrap();
-    let links: Vec<String> = html_dom
-        .select(&link_selector)
-        .filter_map(|e| e.value().attr("href"))
-        .map(|href| href.to_string())
-        .collect();
+    // A page sending the browser on with a meta refresh or script is a
+    // redirect too, and where it goes is followed like any other link
+    let (redirected_to, redirect_kind) = match (redirected_to, client_redirect) {
+        (Some(redirected_to), _) => (Some(redirected_to), Some(RedirectKind::Http)),
+        (None, Some((kind, target))) => match url.join(&target) {
+            Ok(target) if target != *url => {
+                links.push(target.to_string());
+                (Some(target.to_string()), Some(kind))
+            }
+            _ => (None, None),
+        },
+        (None, None) => (None, None),
+    };
 
     // Now also want to get the scrape data
     let mut images: Vec<Image> = Vec::new();
-    let mut titles: Vec<String> = Vec::new();
+    let mut title = None;
+    let mut headings: Vec<String> = Vec::new();
+    let mut outline: Vec<Heading> = Vec::new();
+    let mut keep_html = false;
+    let mut technologies: Vec<String> = Vec::new();
+    let mut lang = None;
+    let mut keywords: Vec<String> = Vec::new();
+    let mut entities: Vec<String> = Vec::new();
+    let mut keep_text = false;
+    let mut content = None;
+    let mut anchors: Vec<Anchor> = Vec::new();
+    let mut meta: BTreeMap<String, String> = BTreeMap::new();
+    let mut image_sources = Some(image_sources);
     for option in options {
         match option {
             ScrapeOption::Images => {
-                images = get_images(&html_dom, &url);
+                images = get_images(image_sources.take().unwrap_or_default(), url);
             }
             ScrapeOption::Titles => {
-                titles = get_titles(&html_dom);
+                title = page_title.as_deref().and_then(clean_title);
+                headings = get_headings(&page_outline);
+            }
+            ScrapeOption::Headings => {
+                outline = page_outline
+                    .iter()
+                    .map(|heading| Heading {
+                        level: heading.level,
+                        text: clean_title(&heading.text).unwrap_or_default(),
+                    })
+                    .collect();
             }
+            ScrapeOption::Html => {
+                keep_html = true;
+            }
+            ScrapeOption::Technologies => {
+                if let Some(html_dom) = &html_dom {
+                    technologies = technologies::detect(&response_headers, html_dom);
+                }
+            }
+            ScrapeOption::Language => {
+                // The language the page says it's in wins over guessing from its text
+                lang = html_lang
+                    .as_deref()
+                    .and_then(language::parse_html_lang)
+                    .or_else(|| language::detect_language(&text));
+            }
+            ScrapeOption::Keywords(max) => {
+                keywords = keywords::extract_keywords(&text, *max);
+                entities = keywords::extract_entities(&text, *max);
+            }
+            ScrapeOption::Text => {
+                keep_text = true;
+            }
+            ScrapeOption::ContentMetrics => {
+                content = Some(ContentMetrics::measure(&text, html.len()));
+            }
+            ScrapeOption::Anchors => {
+                anchors = links
+                    .iter()
+                    .zip(&anchor_texts)
+                    .map(|(link, text)| Anchor {
+                        url: link.clone(),
+                        text: clean_title(text).unwrap_or_default(),
+                    })
+                    .collect();
+            }
+            ScrapeOption::Meta => {
+                for (name, content) in &page_meta {
+                    meta.entry(name.to_lowercase())
+                        .or_insert_with(|| content.trim().to_string());
+                }
+            }
+            // Used while fetching and parsing
+            ScrapeOption::Har | ScrapeOption::Headers(_) | ScrapeOption::LinkScope(_) => {}
         }
     }
 
-    Ok(ScrapeOutput {
+    let bytes = html.len();
+    ScrapeOutput {
         links,
+        anchors,
+        nofollow_links,
         images,
-        titles,
-    })
+        title,
+        headings,
+        outline,
+        fetched: true,
+        html: keep_html.then_some(html),
+        har_entry,
+        headers,
+        technologies,
+        lang,
+        keywords,
+        entities,
+        text: keep_text.then_some(text),
+        content,
+        meta,
+        status: Some(status.as_u16()),
+        body_hash: Some(body_hash),
+        bytes,
+        transfer_bytes,
+        blocked: None,
+        error: None,
+        latency: Some(latency),
+        content_type,
+        redirected_to,
+        redirect_kind,
+        canonical,
+        alternates,
+        caching,
+    }
 }
 
-/// Given a `url`, and a `client`, it will crawl
-/// the HTML in `url` and find all the links in the
-/// page, returning them as a vector of strings
-pub async fn scrape_page(url: Url, client: &Client, options: &[ScrapeOption]) -> ScrapeOutput {
+/// The `Content-Type` of a response without its parameters, e.g. `text/html`
+pub fn media_type(headers: &HeaderMap) -> Option<String> {
+    let content_type = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+    let media_type = content_type.split(';').next()?.trim();
+    (!media_type.is_empty()).then(|| media_type.to_ascii_lowercase())
+}
+
+/// Fetches the page at `url` with `client` and scrapes it, without
+/// crawling any further. The links are always found, everything else
+/// only with its `ScrapeOption`, and every url in the output is absolute.
+///
+/// ```ignore
+/// let client = PageClient::new(true);
+/// let page = scrape_page(url, &client, &[ScrapeOption::Anchors, ScrapeOption::Meta], None).await;
+/// for anchor in page.anchors {
+///     println!("{} -> {}", anchor.text, anchor.url);
+/// }
+/// ```
+///
+/// It doesn't fail: a page that couldn't be fetched or parsed comes back
+/// with `fetched` false and its error. With a `session` the fetch is
+/// recorded or replayed.
+pub async fn scrape_page(
+    url: Url,
+    client: &PageClient,
+    options: &[ScrapeOption],
+    session: Option<&Session>,
+) -> ScrapeOutput {
+    let page = fetch_page(&url, client, options, session).await;
+    parse_fetched_page(url, page, options).await
+}
+
+/// Parses a page fetched with `fetch_page` and scrapes it, the second
+/// half of `scrape_page`. Crawl workers fetch the next page while the
+/// last one is parsed, so they call the two halves separately.
+pub async fn parse_fetched_page(
+    url: Url,
+    page: Result<FetchedPage>,
+    options: &[ScrapeOption],
+) -> ScrapeOutput {
+    let parsed = match page {
+        Ok(page) => parse_page_blocking(url.clone(), page, options).await,
+        Err(e) => Err(e),
+    };
     // This will get all the "href" tags in all the anchors
-    // TODO : Pass in the options
-    let mut scrape_output = match scrape_page_helper(url.clone(), client, options).await {
+    let mut scrape_output = match parsed {
         Ok(output) => output,
         Err(e) => {
             error!("Could not find links: {}", e);
+            let blocked = e.downcast_ref::<PageBlocked>().map(|blocked| blocked.0.to_string());
             ScrapeOutput {
-                images: Default::default(),
-                links: Default::default(),
-                titles: Default::default(),
+                blocked,
+                error: Some(e.to_string()),
+                ..Default::default()
             }
         }
     };
 
     // Turn all links into absolute links
-    scrape_output.links = scrape_output
-        .links
-        .iter()
-        .filter_map(|l| get_url(l, url.clone()).ok())
-        .map(|url| url.to_string())
-        .collect();
+    let absolute = |links: &[String]| -> Vec<String> {
+        links
+            .iter()
+            .filter_map(|l| get_url(l, url.clone()).ok())
+            .map(|url| url.to_string())
+            .collect()
+    };
+    scrape_output.links = absolute(&scrape_output.links);
+    scrape_output.anchors.retain_mut(|anchor| {
+        let Ok(anchor_url) = get_url(&anchor.url, url.clone()) else {
+            return false;
+        };
+        anchor.url = anchor_url.to_string();
+        true
+    });
+    scrape_output.nofollow_links = absolute(&scrape_output.nofollow_links);
+    scrape_output.canonical = scrape_output
+        .canonical
+        .take()
+        .and_then(|canonical| get_url(&canonical, url.clone()).ok())
+        .map(|canonical| canonical.to_string());
+    scrape_output.alternates.retain_mut(|alternate| {
+        let Ok(alternate_url) = get_url(&alternate.url, url.clone()) else {
+            return false;
+        };
+        alternate.url = alternate_url.to_string();
+        true
+    });
 
     scrape_output
 }
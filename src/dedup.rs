@@ -0,0 +1,115 @@
+use anyhow::{bail, Result};
+use std::fmt;
+use std::sync::Arc;
+use url::{Position, Url};
+
+use crate::url_utils::HostNormalization;
+
+/// Maps a url to the key urls are deduplicated by
+pub type DedupFn = Arc<dyn Fn(&Url) -> String + Send + Sync>;
+
+/// Which urls are the same page, so only one of them is crawled
+#[derive(Clone, Default)]
+pub enum DedupKey {
+    /// The whole normalized url, except whether it's http or https
+    #[default]
+    Url,
+    /// The host and path, urls differing only in their query are the same page
+    Path,
+    /// Like `Url`, and a page whose `rel="canonical"` url is queued
+    /// is a duplicate of it, and the other way around
+    Canonical,
+    /// Anything else, e.g. to ignore some query parameters
+    Custom(DedupFn),
+}
+
+impl DedupKey {
+    /// Parses `--dedup-key`, custom keys can only be set from the library
+    pub fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "url" => DedupKey::Url,
+            "path" => DedupKey::Path,
+            "canonical" => DedupKey::Canonical,
+            _ => bail!(
+                "unknown dedup key '{}', expected url, path or canonical",
+                name
+            ),
+        })
+    }
+
+    /// The key of `url`, urls with the same key are crawled once.
+    /// Custom keys get the url without its host normalized.
+    pub fn key(&self, url: &str, host: HostNormalization) -> String {
+        let parsed = match self {
+            DedupKey::Url | DedupKey::Canonical => None,
+            DedupKey::Path | DedupKey::Custom(_) => Url::parse(url).ok(),
+        };
+        match (self, parsed) {
+            (DedupKey::Path, Some(url)) => host.key(&url[..Position::AfterPath]).into(),
+            (DedupKey::Custom(key), Some(url)) => key(&url),
+            _ => host.key(url).to_string(),
+        }
+    }
+}
+
+impl fmt::Display for DedupKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            DedupKey::Url => "url",
+            DedupKey::Path => "path",
+            DedupKey::Canonical => "canonical",
+            DedupKey::Custom(_) => "custom",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Debug for DedupKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DedupKey({})", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::link_key;
+
+    #[test]
+    fn test_dedup_keys() {
+        let keep = HostNormalization::Keep;
+        let a = "https://example.com/list?page=2";
+        let b = "http://example.com/list?page=3&utm_source=feed";
+
+        assert_ne!(DedupKey::Url.key(a, keep), DedupKey::Url.key(b, keep));
+        assert_eq!(DedupKey::Path.key(a, keep), "example.com/list");
+        assert_eq!(DedupKey::Path.key(a, keep), DedupKey::Path.key(b, keep));
+
+        let www = "https://www.example.com/list?page=4";
+        assert_ne!(DedupKey::Path.key(www, keep), DedupKey::Path.key(a, keep));
+        let strip_www = HostNormalization::StripWww;
+        assert_eq!(
+            DedupKey::Path.key(www, strip_www),
+            DedupKey::Path.key(a, keep)
+        );
+
+        // Everything but the tracking parameters
+        let custom = DedupKey::Custom(Arc::new(|url: &Url| {
+            let mut url = url.clone();
+            let query: Vec<(String, String)> = url
+                .query_pairs()
+                .filter(|(name, _)| !name.starts_with("utm_"))
+                .map(|(name, value)| (name.into(), value.into()))
+                .collect();
+            url.query_pairs_mut().clear().extend_pairs(query);
+            link_key(url.as_str()).to_string()
+        }));
+        assert_eq!(
+            custom.key(b, keep),
+            DedupKey::Url.key("https://example.com/list?page=3", keep)
+        );
+
+        assert!(DedupKey::parse("path").is_ok());
+        assert!(DedupKey::parse("host").is_err());
+    }
+}
@@ -0,0 +1,115 @@
+use anyhow::{bail, Context, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::model::LinkId;
+
+/// Longest page text sent to the endpoint, most embedding
+/// models only read the first few thousand tokens anyway
+const MAX_INPUT_CHARS: usize = 8000;
+
+#[derive(Serialize)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a str,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    embedding: Vec<f32>,
+}
+
+/// A page's embedding as written to the vectors file
+#[derive(Serialize)]
+struct PageVector<'a> {
+    id: LinkId,
+    url: &'a str,
+    model: &'a str,
+    embedding: &'a [f32],
+}
+
+/// Embeds the text of every crawled page with an OpenAI-compatible
+/// `/embeddings` endpoint (OpenAI, or a local model served by e.g.
+/// Ollama or llama.cpp) and appends the vectors to a JSON lines file
+pub struct Embedder {
+    client: Client,
+    endpoint: String,
+    model: String,
+    api_key: Option<String>,
+    file: Mutex<File>,
+}
+
+impl Embedder {
+    /// `endpoint` is the API's base url, e.g. `https://api.openai.com/v1`
+    pub async fn create(
+        endpoint: &str,
+        model: &str,
+        api_key: Option<String>,
+        path: &str,
+    ) -> Result<Self> {
+        Ok(Self {
+            client: Client::new(),
+            endpoint: format!("{}/embeddings", endpoint.trim_end_matches('/')),
+            model: model.to_string(),
+            api_key,
+            file: Mutex::new(File::create(path).await?),
+        })
+    }
+
+    async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let input = match text.char_indices().nth(MAX_INPUT_CHARS) {
+            Some((end, _)) => &text[..end],
+            None => text,
+        };
+
+        let mut request = self.client.post(&self.endpoint).json(&EmbeddingRequest {
+            model: &self.model,
+            input,
+        });
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            bail!("embeddings endpoint returned status {}", response.status());
+        }
+
+        let response: EmbeddingResponse = response.json().await?;
+        response
+            .data
+            .into_iter()
+            .next()
+            .map(|data| data.embedding)
+            .context("embeddings endpoint returned no embedding")
+    }
+
+    /// Embeds `text` and writes it to the vectors file as the page `id`
+    pub async fn embed_page(&self, id: LinkId, url: &str, text: &str) -> Result<()> {
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let embedding = self.embed(&text).await?;
+        let mut line = serde_json::to_vec(&PageVector {
+            id,
+            url,
+            model: &self.model,
+            embedding: &embedding,
+        })?;
+        line.push(b'\n');
+
+        // Whole lines at a time, other workers write to the same file
+        self.file.lock().await.write_all(&line).await?;
+        Ok(())
+    }
+}
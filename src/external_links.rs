@@ -0,0 +1,92 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use tokio::fs;
+use url::Url;
+
+/// Links to pages outside the crawled site, with the pages linking
+/// to them. They're recorded without being crawled.
+#[derive(Debug, Default)]
+pub struct ExternalLinks {
+    referrers: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// Quotes `field` if it has anything CSV gives a meaning to
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl ExternalLinks {
+    pub fn record(&mut self, url: &str, referrer: &str) {
+        self.referrers
+            .entry(url.to_string())
+            .or_default()
+            .insert(referrer.to_string());
+    }
+
+    /// How many different external urls were found
+    pub fn len(&self) -> usize {
+        self.referrers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.referrers.is_empty()
+    }
+
+    /// How many different hosts the external urls are on
+    pub fn host_count(&self) -> usize {
+        self.referrers
+            .keys()
+            .filter_map(|url| Url::parse(url).ok()?.host_str().map(String::from))
+            .collect::<BTreeSet<_>>()
+            .len()
+    }
+
+    /// One row for every page linking to every external url
+    fn to_csv(&self) -> String {
+        let mut csv = String::from("url,host,referrer\n");
+        for (url, referrers) in &self.referrers {
+            let host = Url::parse(url)
+                .ok()
+                .and_then(|url| url.host_str().map(String::from))
+                .unwrap_or_default();
+            for referrer in referrers {
+                let _ = writeln!(csv, "{},{},{}", csv_field(url), host, csv_field(referrer));
+            }
+        }
+        csv
+    }
+
+    pub async fn write_csv(&self, destination: &str) -> Result<()> {
+        fs::write(destination, self.to_csv()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_external_links_csv() {
+        let mut external_links = ExternalLinks::default();
+        external_links.record("https://github.com/a", "https://example.com/");
+        external_links.record("https://github.com/a", "https://example.com/about");
+        external_links.record("https://github.com/a", "https://example.com/");
+        external_links.record("https://docs.rs/x?a=1,2", "https://example.com/");
+
+        assert_eq!(external_links.len(), 2);
+        assert_eq!(external_links.host_count(), 2);
+        assert_eq!(
+            external_links.to_csv(),
+            "url,host,referrer\n\
+             \"https://docs.rs/x?a=1,2\",docs.rs,https://example.com/\n\
+             https://github.com/a,github.com,https://example.com/\n\
+             https://github.com/a,github.com,https://example.com/about\n"
+        );
+    }
+}
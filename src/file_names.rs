@@ -0,0 +1,89 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// Characters Windows doesn't allow in file names
+const INVALID_CHARS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Names Windows reserves for devices, with or without an extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Longest file name we generate, in bytes. File systems allow 255, this
+/// leaves room for nested directories under Windows' 260 character paths.
+const MAX_FILE_NAME_BYTES: usize = 100;
+
+/// Makes `name` a valid file name on Windows, macOS and Linux. Invalid
+/// and control characters become `_`, reserved device names get a `_`
+/// prefix and long names are shortened, with a hash keeping them unique.
+pub fn sanitize_file_name(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            c if INVALID_CHARS.contains(&c) || c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    // Windows silently drops trailing dots and spaces
+    let trimmed_len = sanitized.trim_end_matches(['.', ' ']).len();
+    sanitized.truncate(trimmed_len);
+    if sanitized.is_empty() {
+        return String::from("_");
+    }
+
+    let base_name = sanitized.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES.contains(&base_name.to_uppercase().as_str()) {
+        sanitized.insert(0, '_');
+    }
+
+    if sanitized.len() > MAX_FILE_NAME_BYTES {
+        sanitized = shorten(&sanitized, name);
+    }
+
+    sanitized
+}
+
+/// Cuts `sanitized` down to the maximum length, keeping its extension
+fn shorten(sanitized: &str, original: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    original.hash(&mut hasher);
+    let hash = format!("_{:x}", hasher.finish());
+
+    let (stem, extension) = match sanitized.rsplit_once('.') {
+        Some((stem, extension)) if extension.len() <= 10 && !stem.is_empty() => {
+            (stem, format!(".{}", extension))
+        }
+        _ => (sanitized, String::new()),
+    };
+
+    let mut stem_len = MAX_FILE_NAME_BYTES - hash.len() - extension.len();
+    while !stem.is_char_boundary(stem_len) {
+        stem_len -= 1;
+    }
+
+    format!("{}{}{}", &stem[..stem_len], hash, extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_file_name() {
+        assert_eq!(sanitize_file_name("a:b?c*.html"), "a_b_c_.html");
+        assert_eq!(sanitize_file_name("[::1]_8080"), "[__1]_8080");
+        assert_eq!(sanitize_file_name("con.txt"), "_con.txt");
+        assert_eq!(sanitize_file_name("page. . "), "page");
+        assert_eq!(sanitize_file_name(".."), "_");
+
+        let long = format!("{}.html", "é".repeat(200));
+        let shortened = sanitize_file_name(&long);
+        assert!(shortened.len() <= MAX_FILE_NAME_BYTES);
+        assert!(shortened.ends_with(".html"));
+        assert_ne!(
+            shortened,
+            sanitize_file_name(&format!("{}x.html", "é".repeat(200)))
+        );
+    }
+}
@@ -0,0 +1,592 @@
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use log2::*;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use url::{Position, Url};
+use uuid::Uuid;
+
+use crate::crawler::LinkPath;
+use crate::dedup::DedupKey;
+use crate::memory::string_bytes;
+use crate::url_utils::{in_path_prefixes, parse_path_prefix, HostNormalization};
+
+/// How many spilled links are read back into memory at once
+const SPILL_REFILL_BATCH: usize = 1000;
+
+/// The order links are taken off the frontier in
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Schedule {
+    /// The links found last first
+    #[default]
+    Queue,
+    /// Take turns between hosts, so one host's links don't hold up the others
+    Hosts,
+    /// Take turns between sections of the site, by the first segment
+    /// of their path like /blog/ or /docs/
+    Sections,
+}
+
+impl Schedule {
+    /// The section `url` takes its turns in
+    fn section(self, url: &str) -> String {
+        let url = match self {
+            Schedule::Queue => return String::new(),
+            Schedule::Hosts | Schedule::Sections => Url::parse(url),
+        };
+        let Ok(url) = url else {
+            return String::new();
+        };
+        let host = &url[Position::BeforeHost..Position::AfterPort];
+        // Pages at the root of the site are a section together
+        let directory = url
+            .path_segments()
+            .map(|segments| segments.collect::<Vec<_>>())
+            .filter(|segments| segments.len() > 1)
+            .map_or("", |segments| segments[0]);
+        match self {
+            Schedule::Sections => format!("{}/{}", host, directory),
+            _ => host.to_string(),
+        }
+    }
+}
+
+/// How `max_links` is split between sections of the site, from
+/// `--budget "/blog=40%,/docs=40%,*=20%"`
+#[derive(Clone, Debug)]
+pub struct SectionBudget {
+    /// Path prefixes and the percentage of pages they get,
+    /// `None` for `*`, the pages under none of the prefixes
+    shares: Vec<(Option<String>, usize)>,
+}
+
+impl SectionBudget {
+    pub fn parse(budget: &str) -> Result<Self> {
+        let mut shares = Vec::new();
+        for share in budget
+            .split(',')
+            .map(str::trim)
+            .filter(|share| !share.is_empty())
+        {
+            let Some((prefix, percent)) = share.rsplit_once('=') else {
+                bail!("a budget is written as prefix=percent, e.g. /blog=40%");
+            };
+            let prefix = match prefix.trim() {
+                "*" => None,
+                prefix => Some(parse_path_prefix(prefix)?),
+            };
+            let percent: usize = percent.trim().trim_end_matches('%').parse()?;
+            shares.push((prefix, percent));
+        }
+
+        if shares.is_empty() {
+            bail!("the budget has no sections");
+        }
+        if shares.iter().map(|(_, percent)| percent).sum::<usize>() > 100 {
+            bail!("the budget adds up to more than 100%");
+        }
+        Ok(Self { shares })
+    }
+
+    /// The share `url` comes out of: the longest prefix it's under, or `*`
+    fn share(&self, url: &str) -> Option<usize> {
+        let url = Url::parse(url).ok()?;
+        let prefixed = self
+            .shares
+            .iter()
+            .enumerate()
+            .filter_map(|(share, (prefix, _))| Some((share, prefix.as_ref()?)))
+            .filter(|(_, prefix)| in_path_prefixes(&url, std::slice::from_ref(*prefix)))
+            .max_by_key(|(_, prefix)| prefix.len());
+        match prefixed {
+            Some((share, _)) => Some(share),
+            None => self.shares.iter().position(|(prefix, _)| prefix.is_none()),
+        }
+    }
+}
+
+impl fmt::Display for SectionBudget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let shares: Vec<String> = self
+            .shares
+            .iter()
+            .map(|(prefix, percent)| format!("{}={}%", prefix.as_deref().unwrap_or("*"), percent))
+            .collect();
+        write!(f, "{}", shares.join(", "))
+    }
+}
+
+/// What a link takes its turns in
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Section {
+    /// A section of `Schedule`
+    Scheduled(String),
+    /// A share of the budget
+    Share(usize),
+    /// Not in the budget, never crawled
+    Unbudgeted,
+}
+
+/// The queue of links waiting to be visited. Every url
+/// only ever enters the queue once, no matter how many
+/// pages link to it. Urls with the same `dedup_key` are
+/// the same url.
+#[derive(Default)]
+pub struct Frontier {
+    /// The queued links of each section, with `Schedule::Queue`
+    /// there's a single section
+    sections: HashMap<Section, VecDeque<LinkPath>>,
+    /// The sections with links queued, the next to take a link from first
+    turns: VecDeque<Section>,
+    /// How many links are in `sections`
+    queued: usize,
+    schedule: Schedule,
+    budget: Option<SectionBudget>,
+    /// How many more pages each share of `budget` can crawl
+    remaining: Vec<usize>,
+    /// Links of sections whose budget ran out, to be recorded as skipped
+    over_budget: Vec<LinkPath>,
+    enqueued: HashSet<String>,
+    /// The shallowest depth each queued url has been found at, by `dedup_key`
+    queued_depths: HashMap<String, usize>,
+    /// Approximate memory used by `sections`, `enqueued` and `queued_depths`
+    memory_bytes: usize,
+    /// Once `memory_bytes` goes over this, new links are
+    /// written to a file on disk instead of kept in memory
+    memory_limit: Option<usize>,
+    spill: Option<SpillFile>,
+    dedup_key: DedupKey,
+    host_normalization: HostNormalization,
+}
+
+impl Frontier {
+    pub fn with_dedup_key(dedup_key: DedupKey) -> Self {
+        Self {
+            dedup_key,
+            ..Default::default()
+        }
+    }
+
+    pub fn dedup_key(&self) -> &DedupKey {
+        &self.dedup_key
+    }
+
+    /// Sets how hosts are compared when deduplicating, before any links are queued
+    pub fn set_host_normalization(&mut self, host_normalization: HostNormalization) {
+        self.host_normalization = host_normalization;
+    }
+
+    /// Sets the order links are taken off the queue in, before any are queued
+    pub fn set_schedule(&mut self, schedule: Schedule) {
+        self.schedule = schedule;
+    }
+
+    /// Splits `max_links` between sections of the site, which take turns.
+    /// The seeds are crawled whatever the budget, pages
+    /// from the sitemaps take their share like any other link
+    pub fn set_budget(&mut self, budget: SectionBudget, max_links: usize) {
+        self.remaining = budget
+            .shares
+            .iter()
+            .map(|(_, percent)| max_links * percent / 100)
+            .collect();
+        self.budget = Some(budget);
+    }
+
+    /// Adds `path` to the back of the queue, returning
+    /// false if its url has been queued before
+    pub fn push_back(&mut self, path: LinkPath) -> bool {
+        if !self.enqueue(&path) {
+            return false;
+        }
+
+        if self
+            .memory_limit
+            .is_some_and(|limit| self.memory_bytes > limit)
+        {
+            match self.spill_link(&path) {
+                Ok(()) => return true,
+                Err(e) => error!("could not spill link to disk: {}", e),
+            }
+        }
+
+        self.memory_bytes += path_bytes(&path);
+        self.section(&path).push_back(path);
+        true
+    }
+
+    /// Adds `path` to the front of the queue, returning
+    /// false if its url has been queued before
+    pub fn push_front(&mut self, path: LinkPath) -> bool {
+        if !self.enqueue(&path) {
+            return false;
+        }
+
+        self.memory_bytes += path_bytes(&path);
+        self.section(&path).push_front(path);
+        true
+    }
+
+    /// Takes the last link of the section whose turn it is, at
+    /// the shallowest depth its url was found at while queued
+    pub fn pop_back(&mut self) -> Option<LinkPath> {
+        if self.queued == 0 {
+            self.refill_from_spill();
+        }
+
+        loop {
+            let section = self.turns.pop_front()?;
+            let mut links = self.sections.remove(&section)?;
+            if !self.take_budget(&section) {
+                for path in links {
+                    self.queued -= 1;
+                    self.memory_bytes = self.memory_bytes.saturating_sub(path_bytes(&path));
+                    self.queued_depth(&path);
+                    self.over_budget.push(path);
+                }
+                continue;
+            }
+
+            let mut path = links.pop_back()?;
+            if !links.is_empty() {
+                self.sections.insert(section.clone(), links);
+                self.turns.push_back(section);
+            }
+
+            self.queued -= 1;
+            self.memory_bytes = self.memory_bytes.saturating_sub(path_bytes(&path));
+            if let Some(depth) = self.queued_depth(&path) {
+                path.depth = depth;
+            }
+            return Some(path);
+        }
+    }
+
+    /// The links dropped since this was last called because
+    /// their section of the budget ran out
+    pub fn take_over_budget(&mut self) -> Vec<LinkPath> {
+        std::mem::take(&mut self.over_budget)
+    }
+
+    /// How many different links have ever been queued
+    pub fn discovered(&self) -> usize {
+        self.enqueued.len()
+    }
+
+    /// How many links are waiting, in memory or spilled to disk
+    pub fn len(&self) -> usize {
+        self.queued + self.spill.as_ref().map_or(0, |spill| spill.pending)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queued == 0 && self.spill.as_ref().is_none_or(|spill| spill.pending == 0)
+    }
+
+    /// The queue of the section `path` is in, it gets
+    /// a turn if it didn't have one. Counts a link as queued
+    fn section(&mut self, path: &LinkPath) -> &mut VecDeque<LinkPath> {
+        let section = match &self.budget {
+            Some(budget) if !path.seed => match budget.share(&path.child) {
+                Some(share) => Section::Share(share),
+                None => Section::Unbudgeted,
+            },
+            _ => Section::Scheduled(self.schedule.section(&path.child)),
+        };
+        if !self.sections.contains_key(&section) {
+            self.turns.push_back(section.clone());
+        }
+        self.queued += 1;
+        self.sections.entry(section).or_default()
+    }
+
+    /// Whether `section` can crawl another page, using up one if it can
+    fn take_budget(&mut self, section: &Section) -> bool {
+        let share = match section {
+            Section::Scheduled(_) => return true,
+            Section::Share(share) => *share,
+            Section::Unbudgeted => return false,
+        };
+        match self.remaining.get_mut(share) {
+            Some(remaining) if *remaining > 0 => {
+                *remaining -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Sets how much memory the queued links may use before
+    /// new links start being spilled to disk
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+    }
+
+    /// Records `url` as queued without queueing it, returning
+    /// false if it has been queued before
+    pub fn mark_seen(&mut self, url: &str) -> bool {
+        let key = self.key(url);
+        if self.enqueued.contains(&key) {
+            return false;
+        }
+
+        self.memory_bytes += string_bytes(&key);
+        self.enqueued.insert(key)
+    }
+
+    fn key(&self, url: &str) -> String {
+        self.dedup_key.key(url, self.host_normalization)
+    }
+
+    /// Marks the url of `path` as seen, returning false if it has been
+    /// before. A url that's still queued keeps the shallowest depth
+    fn enqueue(&mut self, path: &LinkPath) -> bool {
+        let key = self.key(&path.child);
+        if let Some(depth) = self.queued_depths.get_mut(&key) {
+            *depth = (*depth).min(path.depth);
+            return false;
+        }
+        if !self.mark_seen(&path.child) {
+            return false;
+        }
+
+        self.memory_bytes += string_bytes(&key) + std::mem::size_of::<usize>();
+        self.queued_depths.insert(key, path.depth);
+        true
+    }
+
+    /// Forgets the depth of `path` now it's left the queue, returning it
+    fn queued_depth(&mut self, path: &LinkPath) -> Option<usize> {
+        let key = self.key(&path.child);
+        let depth = self.queued_depths.remove(&key)?;
+        self.memory_bytes = self
+            .memory_bytes
+            .saturating_sub(string_bytes(&key) + std::mem::size_of::<usize>());
+        Some(depth)
+    }
+
+    fn spill_link(&mut self, path: &LinkPath) -> Result<()> {
+        if self.spill.is_none() {
+            let spill = SpillFile::create()?;
+            warn!(
+                "frontier is over its memory limit, spilling links to {:?}",
+                spill.path
+            );
+            self.spill = Some(spill);
+        }
+
+        if let Some(spill) = self.spill.as_mut() {
+            spill.write(path)?;
+        }
+
+        Ok(())
+    }
+
+    fn refill_from_spill(&mut self) {
+        let Some(spill) = self.spill.as_mut() else {
+            return;
+        };
+
+        match spill.read(SPILL_REFILL_BATCH) {
+            Ok(paths) => {
+                for path in paths {
+                    self.memory_bytes += path_bytes(&path);
+                    self.section(&path).push_front(path);
+                }
+            }
+            Err(e) => error!("could not read spilled links: {}", e),
+        }
+    }
+}
+
+fn path_bytes(path: &LinkPath) -> usize {
+    string_bytes(&path.parent) + string_bytes(&path.child)
+}
+
+/// A JSON lines file of links that didn't fit in memory.
+/// It's removed once the frontier is dropped.
+struct SpillFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    reader: BufReader<File>,
+    /// Links written but not read back yet
+    pending: usize,
+}
+
+impl SpillFile {
+    fn create() -> Result<Self> {
+        let path =
+            std::env::temp_dir().join(format!("hypercrawl-frontier-{}.jsonl", Uuid::new_v4()));
+        let writer = BufWriter::new(
+            OpenOptions::new()
+                .create_new(true)
+                .append(true)
+                .open(&path)?,
+        );
+        let reader = BufReader::new(File::open(&path)?);
+
+        Ok(Self {
+            path,
+            writer,
+            reader,
+            pending: 0,
+        })
+    }
+
+    fn write(&mut self, path: &LinkPath) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, path)?;
+        self.writer.write_all(b"\n")?;
+        self.pending += 1;
+        Ok(())
+    }
+
+    fn read(&mut self, max: usize) -> Result<Vec<LinkPath>> {
+        self.writer.flush()?;
+
+        let mut paths = Vec::new();
+        let mut line = String::new();
+        while paths.len() < max && self.pending > 0 {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                break;
+            }
+
+            paths.push(serde_json::from_str(&line)?);
+            self.pending -= 1;
+        }
+
+        Ok(paths)
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(child: &str) -> LinkPath {
+        LinkPath {
+            child: child.to_string(),
+            depth: child.len(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_spilled_links_are_read_back() {
+        let mut frontier = Frontier::default();
+        frontier.set_memory_limit(Some(0));
+
+        assert!(frontier.push_back(path("https://example.com/a")));
+        assert!(frontier.push_back(path("https://example.com/b")));
+        assert!(frontier.push_back(path("https://example.com/c")));
+        assert!(!frontier.push_back(LinkPath {
+            depth: 100,
+            ..path("http://example.com/b")
+        }));
+        assert!(frontier.spill.is_some());
+
+        let mut popped = Vec::new();
+        while let Some(path) = frontier.pop_back() {
+            // Spilled links keep their depth
+            assert_eq!(path.depth, path.child.len());
+            popped.push(path.child);
+        }
+        popped.sort();
+
+        assert_eq!(
+            popped,
+            vec![
+                "https://example.com/a",
+                "https://example.com/b",
+                "https://example.com/c"
+            ]
+        );
+        assert!(frontier.is_empty());
+    }
+
+    #[test]
+    fn test_queued_links_keep_the_shallowest_depth() {
+        let mut frontier = Frontier::default();
+        let link = |depth| LinkPath {
+            child: String::from("https://example.com/deep"),
+            depth,
+            ..Default::default()
+        };
+        assert!(frontier.push_back(link(5)));
+        assert!(!frontier.push_back(link(2)));
+        assert!(!frontier.push_back(link(3)));
+        assert_eq!(frontier.pop_back().unwrap().depth, 2);
+
+        // Once it's been taken, it isn't queued again
+        assert!(!frontier.push_back(link(1)));
+        assert!(frontier.is_empty());
+    }
+
+    #[test]
+    fn test_sections_take_turns() {
+        let mut frontier = Frontier::default();
+        frontier.set_schedule(Schedule::Sections);
+        for link in ["/blog/1", "/blog/2", "/blog/3", "/docs/1", "/about"] {
+            frontier.push_back(path(&format!("https://example.com{}", link)));
+        }
+
+        let popped: Vec<String> = std::iter::from_fn(|| frontier.pop_back())
+            .map(|path| path.child.replace("https://example.com", ""))
+            .collect();
+        assert_eq!(
+            popped,
+            ["/blog/3", "/docs/1", "/about", "/blog/2", "/blog/1"]
+        );
+        assert!(frontier.is_empty());
+    }
+
+    #[test]
+    fn test_section_budget() {
+        let mut frontier = Frontier::default();
+        let budget = SectionBudget::parse("/blog=50%, *=25%").unwrap();
+        assert_eq!(budget.to_string(), "/blog=50%, *=25%");
+        frontier.set_budget(budget, 4);
+        frontier.push_back(LinkPath {
+            child: String::from("https://example.com/"),
+            seed: true,
+            ..Default::default()
+        });
+        for link in ["/blog/1", "/blog/2", "/blog/3", "/docs/1", "/docs/2"] {
+            frontier.push_back(path(&format!("https://example.com{}", link)));
+        }
+
+        let popped: Vec<String> = std::iter::from_fn(|| frontier.pop_back())
+            .map(|path| path.child.replace("https://example.com", ""))
+            .collect();
+        assert_eq!(popped, ["/", "/blog/3", "/docs/2", "/blog/2"]);
+        assert_eq!(frontier.take_over_budget().len(), 2);
+        assert!(frontier.is_empty());
+
+        // Pages from the sitemaps are queued at depth 0 like the seeds,
+        // but take their share of the budget
+        let mut frontier = Frontier::default();
+        frontier.set_budget(SectionBudget::parse("/blog=50%").unwrap(), 2);
+        for link in ["/blog/1", "/blog/2", "/docs/1"] {
+            frontier.push_front(LinkPath {
+                child: format!("https://example.com{}", link),
+                ..Default::default()
+            });
+        }
+        let popped: Vec<String> = std::iter::from_fn(|| frontier.pop_back())
+            .map(|path| path.child.replace("https://example.com", ""))
+            .collect();
+        assert_eq!(popped, ["/blog/1"]);
+        assert_eq!(frontier.take_over_budget().len(), 2);
+
+        assert!(SectionBudget::parse("/blog=60%,/docs=60%").is_err());
+        assert!(SectionBudget::parse("/blog").is_err());
+    }
+}
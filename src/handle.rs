@@ -0,0 +1,154 @@
+use anyhow::Result;
+use futures::Stream;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::crawler::{Crawler, CrawlerStateRef, PageEvent};
+use crate::model::LinkGraph;
+use crate::stats::CrawlStats;
+
+/// A crawl running in the background, with the same controls as the
+/// REST API's jobs
+///
+/// ```no_run
+/// # async fn crawl() -> anyhow::Result<()> {
+/// use futures::StreamExt;
+/// use rust_crawler::Crawler;
+///
+/// let handle = Crawler::builder()
+///     .start_url("https://example.com/")
+///     .build()?
+///     .spawn();
+/// let mut events = std::pin::pin!(handle.events());
+/// while let Some(page) = events.next().await {
+///     println!("{} ({} links)", page.url, page.links);
+///     if handle.stats().await.crawled >= 10 {
+///         handle.cancel();
+///     }
+/// }
+/// let link_graph = handle.wait().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct CrawlHandle {
+    state: CrawlerStateRef,
+    task: JoinHandle<Result<LinkGraph>>,
+    /// Cancelled once the crawl is over, to end the event streams
+    finished: CancellationToken,
+}
+
+impl CrawlHandle {
+    pub(crate) fn spawn(crawler: Crawler) -> Self {
+        let state = crawler.state().clone();
+        let finished = CancellationToken::new();
+        let task = {
+            let finished = finished.clone();
+            tokio::spawn(async move {
+                let result = crawler.run().await;
+                finished.cancel();
+                result
+            })
+        };
+
+        Self {
+            state,
+            task,
+            finished,
+        }
+    }
+
+    /// The crawl's counts right now
+    pub async fn stats(&self) -> CrawlStats {
+        CrawlStats::snapshot(&self.state).await
+    }
+
+    /// Stops taking links off the queue, the pages being fetched are finished
+    pub fn pause(&self) {
+        self.state.control.pause();
+    }
+
+    pub fn resume(&self) {
+        self.state.control.resume();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.control.is_paused()
+    }
+
+    /// Stops the crawl for good, `wait` returns what was crawled so far
+    pub fn cancel(&self) {
+        // A paused crawl's workers have to wake up to stop
+        self.state.control.cancel();
+        self.state.control.resume();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.is_cancelled()
+    }
+
+    /// Every page crawled from now on, ending when the crawl does.
+    /// Pages are missed if the stream isn't read fast enough
+    pub fn events(&self) -> impl Stream<Item = PageEvent> + 'static {
+        let pages = self.state.page_events.subscribe();
+        let finished = self.finished.clone();
+        futures::stream::unfold((pages, finished), |(mut pages, finished)| async move {
+            loop {
+                // Pages sent before the crawl finished come first
+                let page = tokio::select! {
+                    biased;
+                    page = pages.recv() => page,
+                    _ = finished.cancelled() => return None,
+                };
+                match page {
+                    Ok(page) => return Some((page, (pages, finished))),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+
+    /// Waits for the crawl to finish, returning its link graph
+    pub async fn wait(self) -> Result<LinkGraph> {
+        self.task.await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use std::sync::atomic::Ordering;
+
+    #[tokio::test]
+    async fn test_crawl_handle() {
+        let crawler = Crawler::builder()
+            .start_url("http://127.0.0.1:9/")
+            .build()
+            .unwrap();
+        let state = crawler.state().clone();
+        // Paused before its workers start, so nothing is fetched yet
+        state.control.pause();
+        let handle = crawler.spawn();
+        assert!(handle.is_paused());
+
+        let events = handle.events();
+        let page = PageEvent {
+            url: String::from("http://127.0.0.1:9/"),
+            links: 0,
+            error: None,
+        };
+        state.page_events.send(page).unwrap();
+        assert_eq!(handle.stats().await.attempted, 0);
+
+        handle.cancel();
+        assert!(!handle.is_paused());
+        let pages: Vec<PageEvent> = events.collect().await;
+        assert_eq!(pages.len(), 1);
+        assert!(handle.is_finished());
+
+        handle.wait().await.unwrap();
+        assert_eq!(state.attempted_count.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,268 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use reqwest::header::HeaderMap;
+use reqwest::{Request, Response};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs;
+use url::Url;
+
+use crate::url_utils::url_file_stem;
+
+/// HAR uses -1 for timings it doesn't know
+const UNKNOWN_TIMING: f64 = -1.0;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct HarHeader {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarRequest {
+    pub method: String,
+    pub url: String,
+    pub http_version: String,
+    pub cookies: Vec<HarHeader>,
+    pub headers: Vec<HarHeader>,
+    pub query_string: Vec<HarHeader>,
+    pub headers_size: i64,
+    pub body_size: i64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarContent {
+    pub size: usize,
+    /// Bytes saved by compression
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<usize>,
+    pub mime_type: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarResponse {
+    pub status: u16,
+    pub status_text: String,
+    pub http_version: String,
+    pub cookies: Vec<HarHeader>,
+    pub headers: Vec<HarHeader>,
+    pub content: HarContent,
+    #[serde(rename = "redirectURL")]
+    pub redirect_url: String,
+    pub headers_size: i64,
+    pub body_size: i64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct HarTimings {
+    pub blocked: f64,
+    pub dns: f64,
+    pub connect: f64,
+    pub ssl: f64,
+    pub send: f64,
+    pub wait: f64,
+    pub receive: f64,
+}
+
+/// A single request/response pair in HAR format
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarEntry {
+    pub pageref: String,
+    pub started_date_time: DateTime<Utc>,
+    pub time: f64,
+    pub request: HarRequest,
+    pub response: HarResponse,
+    pub cache: serde_json::Value,
+    pub timings: HarTimings,
+    #[serde(rename = "serverIPAddress", skip_serializing_if = "Option::is_none")]
+    pub server_ip_address: Option<String>,
+}
+
+#[derive(Serialize)]
+struct HarCreator {
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HarPageTimings {
+    on_content_load: f64,
+    on_load: f64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HarPage {
+    started_date_time: DateTime<Utc>,
+    id: String,
+    title: String,
+    page_timings: HarPageTimings,
+}
+
+#[derive(Serialize)]
+struct HarLog<'a> {
+    version: &'static str,
+    creator: HarCreator,
+    pages: Vec<HarPage>,
+    entries: Vec<&'a HarEntry>,
+}
+
+#[derive(Serialize)]
+struct Har<'a> {
+    log: HarLog<'a>,
+}
+
+/// Credentials are marked sensitive and left out of the HAR file
+fn har_headers(headers: &HeaderMap) -> Vec<HarHeader> {
+    headers
+        .iter()
+        .map(|(name, value)| HarHeader {
+            name: name.to_string(),
+            value: if value.is_sensitive() {
+                String::from("[redacted]")
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).to_string()
+            },
+        })
+        .collect()
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// The request half of an entry, captured before it's sent
+pub struct PendingEntry {
+    started_date_time: DateTime<Utc>,
+    request: HarRequest,
+}
+
+impl PendingEntry {
+    pub fn new(request: &Request) -> Self {
+        Self {
+            started_date_time: Utc::now(),
+            request: HarRequest {
+                method: request.method().to_string(),
+                url: request.url().to_string(),
+                http_version: format!("{:?}", request.version()),
+                cookies: Vec::new(),
+                headers: har_headers(request.headers()),
+                query_string: request
+                    .url()
+                    .query_pairs()
+                    .map(|(name, value)| HarHeader {
+                        name: name.to_string(),
+                        value: value.to_string(),
+                    })
+                    .collect(),
+                headers_size: -1,
+                body_size: 0,
+            },
+        }
+    }
+
+    /// Adds the response to the entry. `wait` is how long the response
+    /// headers took to arrive. The body is added with `HarEntry::set_body`
+    /// once it's been read.
+    pub fn response_received(self, response: &Response, wait: Duration) -> HarEntry {
+        let status = response.status();
+        let mime_type = response
+            .headers()
+            .get("content-type")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let redirect_url = response
+            .headers()
+            .get("location")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+
+        HarEntry {
+            pageref: self.request.url.clone(),
+            started_date_time: self.started_date_time,
+            time: millis(wait),
+            request: self.request,
+            response: HarResponse {
+                status: status.as_u16(),
+                status_text: status.canonical_reason().unwrap_or_default().to_string(),
+                http_version: format!("{:?}", response.version()),
+                cookies: Vec::new(),
+                headers: har_headers(response.headers()),
+                content: HarContent {
+                    size: 0,
+                    compression: None,
+                    mime_type,
+                },
+                redirect_url,
+                headers_size: -1,
+                body_size: 0,
+            },
+            cache: serde_json::json!({}),
+            timings: HarTimings {
+                blocked: UNKNOWN_TIMING,
+                dns: UNKNOWN_TIMING,
+                connect: UNKNOWN_TIMING,
+                ssl: UNKNOWN_TIMING,
+                send: 0.0,
+                wait: millis(wait),
+                receive: 0.0,
+            },
+            server_ip_address: response.remote_addr().map(|addr| addr.ip().to_string()),
+        }
+    }
+}
+
+impl HarEntry {
+    /// Records the size of the response body, decompressed and as
+    /// sent, and how long it took to read
+    pub fn set_body(&mut self, content_size: usize, body_size: usize, receive: Duration) {
+        self.response.content.size = content_size;
+        self.response.content.compression = content_size.checked_sub(body_size).filter(|saved| *saved > 0);
+        self.response.body_size = body_size as i64;
+        self.timings.receive = millis(receive);
+        self.time = self.timings.wait + self.timings.receive;
+    }
+}
+
+/// Writes a HAR file for the page at `url` into `har_dir`,
+/// returning where it was saved
+pub async fn write_har(
+    har_dir: &Path,
+    url: &Url,
+    title: &str,
+    entry: &HarEntry,
+) -> Result<PathBuf> {
+    let har = Har {
+        log: HarLog {
+            version: "1.2",
+            creator: HarCreator {
+                name: "HyperCrawl",
+                version: env!("CARGO_PKG_VERSION"),
+            },
+            pages: vec![HarPage {
+                started_date_time: entry.started_date_time,
+                id: entry.pageref.clone(),
+                title: title.to_string(),
+                page_timings: HarPageTimings {
+                    on_content_load: UNKNOWN_TIMING,
+                    on_load: UNKNOWN_TIMING,
+                },
+            }],
+            entries: vec![entry],
+        },
+    };
+
+    fs::create_dir_all(har_dir).await?;
+    let path = har_dir.join(format!("{}.har", url_file_stem(url)));
+    fs::write(&path, serde_json::to_string_pretty(&har)?).await?;
+
+    Ok(path)
+}
@@ -0,0 +1,142 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+use tokio::fs;
+use url::Url;
+
+use crate::crawler::ScrapeOutput;
+
+/// How many hosts are listed in the crawl summary
+pub const SUMMARY_HOSTS: usize = 10;
+
+/// What happened on one host during a crawl
+#[derive(Debug, Default, PartialEq)]
+pub struct HostStats {
+    /// Pages fetched successfully
+    pub pages: usize,
+    /// Fetches that failed, including blocked ones
+    pub errors: usize,
+    /// Fetches that got a challenge or block page
+    pub blocked: usize,
+    /// Bytes of pages downloaded, as sent
+    pub bytes: usize,
+    /// Requests robots.txt disallowed
+    pub robots_blocks: usize,
+    total_latency: Duration,
+    timed_fetches: usize,
+}
+
+impl HostStats {
+    /// The average time pages took to start arriving
+    pub fn average_latency(&self) -> Option<Duration> {
+        (self.timed_fetches > 0).then(|| self.total_latency / self.timed_fetches as u32)
+    }
+}
+
+/// Per-host stats, kept up to date while crawling
+#[derive(Debug, Default)]
+pub struct HostReport {
+    hosts: BTreeMap<String, HostStats>,
+}
+
+fn host(url: &Url) -> String {
+    url.host_str().unwrap_or("unknown").to_string()
+}
+
+impl HostReport {
+    pub fn record_fetch(&mut self, url: &Url, output: &ScrapeOutput) {
+        let stats = self.hosts.entry(host(url)).or_default();
+        if output.fetched {
+            stats.pages += 1;
+        } else {
+            stats.errors += 1;
+        }
+        if output.blocked.is_some() {
+            stats.blocked += 1;
+        }
+        stats.bytes += output.transfer_bytes;
+        if let Some(latency) = output.latency {
+            stats.total_latency += latency;
+            stats.timed_fetches += 1;
+        }
+    }
+
+    pub fn record_robots_block(&mut self, link: &str) {
+        if let Ok(url) = Url::parse(link) {
+            self.hosts.entry(host(&url)).or_default().robots_blocks += 1;
+        }
+    }
+
+    /// Every host, the most crawled first
+    pub fn by_pages(&self) -> Vec<(&str, &HostStats)> {
+        let mut hosts: Vec<_> = self
+            .hosts
+            .iter()
+            .map(|(host, stats)| (host.as_str(), stats))
+            .collect();
+        hosts.sort_by(|(a, a_stats), (b, b_stats)| {
+            b_stats.pages.cmp(&a_stats.pages).then_with(|| a.cmp(b))
+        });
+        hosts
+    }
+
+    fn to_csv(&self) -> String {
+        let mut csv =
+            String::from("host,pages,errors,blocked,bytes,avg_latency_ms,robots_blocks\n");
+        for (host, stats) in self.by_pages() {
+            let latency = stats
+                .average_latency()
+                .map(|latency| latency.as_millis().to_string())
+                .unwrap_or_default();
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{},{},{}",
+                host,
+                stats.pages,
+                stats.errors,
+                stats.blocked,
+                stats.bytes,
+                latency,
+                stats.robots_blocks
+            );
+        }
+        csv
+    }
+
+    pub async fn write_csv(&self, destination: &str) -> Result<()> {
+        fs::write(destination, self.to_csv()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_report_csv() {
+        let mut report = HostReport::default();
+        let page = |fetched, latency_ms: Option<u64>| ScrapeOutput {
+            fetched,
+            transfer_bytes: if fetched { 1000 } else { 0 },
+            latency: latency_ms.map(Duration::from_millis),
+            ..Default::default()
+        };
+
+        let docs = Url::parse("https://docs.example.com/a").unwrap();
+        report.record_fetch(&docs, &page(true, Some(100)));
+        report.record_fetch(&docs, &page(true, Some(300)));
+        let www = Url::parse("https://www.example.com/").unwrap();
+        report.record_fetch(&www, &page(false, None));
+        report.record_robots_block("https://cdn.example.com/private/a.png");
+
+        assert_eq!(
+            report.to_csv(),
+            "host,pages,errors,blocked,bytes,avg_latency_ms,robots_blocks\n\
+             docs.example.com,2,0,0,2000,200,0\n\
+             cdn.example.com,0,0,0,0,,1\n\
+             www.example.com,0,1,0,0,,0\n"
+        );
+    }
+}
@@ -0,0 +1,488 @@
+use anyhow::{anyhow, Result};
+use lol_html::{doc_text, element, text, EndTagHandler, HtmlRewriter, Settings};
+use markup5ever::data::{C1_REPLACEMENTS, NAMED_ENTITIES};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use crate::client_redirect::{self, REDIRECT_SCRIPT_MAX_BYTES};
+use crate::crawler::{
+    alternate_kind, has_rel, heading_level, is_nofollow, srcset_data_uris, PageContent,
+    SKIPPED_TEXT_ELEMENTS,
+};
+use crate::model::{Alternate, Heading, RedirectKind};
+
+/// Pages at least this big are streamed instead of parsed into a DOM
+pub const STREAMING_THRESHOLD_BYTES: usize = 1 << 20;
+
+/// How much of the page is fed to the tokenizer at once
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// Extracts the links, images, title, heading outline and (if `with_text`) the visible
+/// text of a page by streaming it through an HTML tokenizer. No DOM is
+/// built, so very large pages need a lot less memory and time.
+pub fn extract_page(html: &str, with_text: bool) -> Result<PageContent> {
+    let links = RefCell::new(Vec::new());
+    let anchor_texts: RefCell<Vec<String>> = RefCell::new(Vec::new());
+    let nofollow_links = RefCell::new(Vec::new());
+    let page_nofollow = Cell::new(false);
+    let image_sources = RefCell::new(Vec::new());
+    // Only the first title is kept, `None` until it starts
+    let title: RefCell<Option<String>> = RefCell::new(None);
+    let in_title = Cell::new(false);
+    let outline: RefCell<Vec<Heading>> = RefCell::new(Vec::new());
+    let meta_refresh: RefCell<Option<String>> = RefCell::new(None);
+    let canonical: RefCell<Option<String>> = RefCell::new(None);
+    let html_lang: RefCell<Option<String>> = RefCell::new(None);
+    let alternates: RefCell<Vec<Alternate>> = RefCell::new(Vec::new());
+    let meta: RefCell<Vec<(String, String)>> = RefCell::new(Vec::new());
+    // The text of every inline script, until it's too long to be a redirect
+    let scripts: RefCell<Vec<String>> = RefCell::new(Vec::new());
+    let in_inline_script = Cell::new(false);
+    let text = RefCell::new(String::new());
+    // How many elements whose text isn't visible we're inside of
+    let hidden_depth = Rc::new(Cell::new(0_usize));
+    // `</head>` is often left out, the body starting ends the head too
+    let in_head = Rc::new(Cell::new(false));
+
+    let mut element_content_handlers = vec![
+        element!("a[href]", |el| {
+            if let Some(href) = el.get_attribute("href") {
+                let href = decode_attribute(&href);
+                if is_nofollow(el.get_attribute("rel").as_deref()) {
+                    nofollow_links.borrow_mut().push(href.clone());
+                }
+                links.borrow_mut().push(href);
+                anchor_texts.borrow_mut().push(String::new());
+            }
+            Ok(())
+        }),
+        text!("a[href]", |chunk| {
+            if let Some(anchor_text) = anchor_texts.borrow_mut().last_mut() {
+                anchor_text.push_str(&decode_entities(chunk.as_str()));
+            }
+            Ok(())
+        }),
+        element!("meta[content]", |el| {
+            let name = el.get_attribute("name").or_else(|| el.get_attribute("property"));
+            if let (Some(name), Some(content)) = (name, el.get_attribute("content")) {
+                meta.borrow_mut().push((decode_attribute(&name), decode_attribute(&content)));
+            }
+            Ok(())
+        }),
+        element!("meta[name]", |el| {
+            let is_robots = el
+                .get_attribute("name")
+                .is_some_and(|name| name.eq_ignore_ascii_case("robots"));
+            if is_robots && is_nofollow(el.get_attribute("content").as_deref()) {
+                page_nofollow.set(true);
+            }
+            Ok(())
+        }),
+        element!("meta[http-equiv][content]", |el| {
+            let is_refresh = el
+                .get_attribute("http-equiv")
+                .is_some_and(|equiv| equiv.eq_ignore_ascii_case("refresh"));
+            let mut meta_refresh = meta_refresh.borrow_mut();
+            if is_refresh && meta_refresh.is_none() {
+                let content = decode_attribute(&el.get_attribute("content").unwrap_or_default());
+                *meta_refresh = client_redirect::meta_refresh_target(&content).map(str::to_string);
+            }
+            Ok(())
+        }),
+        element!("html[lang]", |el| {
+            html_lang.borrow_mut().get_or_insert_with(|| {
+                decode_attribute(&el.get_attribute("lang").unwrap_or_default())
+            });
+            Ok(())
+        }),
+        element!("link[rel][href]", |el| {
+            let (Some(rel), Some(href)) = (el.get_attribute("rel"), el.get_attribute("href"))
+            else {
+                return Ok(());
+            };
+            let mut canonical = canonical.borrow_mut();
+            if canonical.is_none() && has_rel(&rel, "canonical") {
+                *canonical = Some(decode_attribute(&href));
+            }
+            let media = el.get_attribute("media");
+            if let Some(kind) = alternate_kind(&rel, media.as_deref()) {
+                alternates.borrow_mut().push(Alternate {
+                    url: decode_attribute(&href),
+                    kind,
+                    media,
+                });
+            }
+            Ok(())
+        }),
+        element!("script", |el| {
+            let inline = !el.has_attribute("src");
+            in_inline_script.set(inline);
+            if inline {
+                scripts.borrow_mut().push(String::new());
+            }
+            Ok(())
+        }),
+        text!("script", |chunk| {
+            if let (true, Some(script)) = (in_inline_script.get(), scripts.borrow_mut().last_mut())
+            {
+                if script.len() <= REDIRECT_SCRIPT_MAX_BYTES {
+                    script.push_str(chunk.as_str());
+                }
+            }
+            Ok(())
+        }),
+        element!("img[src], img[srcset]", |el| {
+            let alt = decode_attribute(&el.get_attribute("alt").unwrap_or_default());
+            let mut image_sources = image_sources.borrow_mut();
+            if let Some(src) = el.get_attribute("src") {
+                image_sources.push((decode_attribute(&src), alt.clone()));
+            }
+            if let Some(srcset) = el.get_attribute("srcset") {
+                for uri in srcset_data_uris(&srcset) {
+                    image_sources.push((uri.to_string(), alt.clone()));
+                }
+            }
+            Ok(())
+        }),
+        element!("title", |_| {
+            let mut title = title.borrow_mut();
+            in_title.set(title.is_none());
+            title.get_or_insert_with(String::new);
+            Ok(())
+        }),
+        text!("title", |chunk| {
+            if let (true, Some(title)) = (in_title.get(), title.borrow_mut().as_mut()) {
+                title.push_str(&decode_entities(chunk.as_str()));
+            }
+            Ok(())
+        }),
+        element!("h1, h2, h3, h4, h5, h6", |el| {
+            if let Some(level) = heading_level(&el.tag_name()) {
+                outline.borrow_mut().push(Heading {
+                    level,
+                    text: String::new(),
+                });
+            }
+            Ok(())
+        }),
+        text!("h1, h2, h3, h4, h5, h6", |chunk| {
+            if let Some(heading) = outline.borrow_mut().last_mut() {
+                heading.text.push_str(&decode_entities(chunk.as_str()));
+            }
+            Ok(())
+        }),
+    ];
+
+    if with_text {
+        let hidden_selector = SKIPPED_TEXT_ELEMENTS
+            .iter()
+            .filter(|tag| **tag != "head")
+            .copied()
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let depth = hidden_depth.clone();
+        element_content_handlers.push(element!(hidden_selector, move |el| {
+            let depth = depth.clone();
+            depth.set(depth.get() + 1);
+            let on_end: EndTagHandler<'static> = Box::new(move |_| {
+                depth.set(depth.get().saturating_sub(1));
+                Ok(())
+            });
+            el.on_end_tag(on_end)
+        }));
+
+        let head = in_head.clone();
+        element_content_handlers.push(element!("head", move |el| {
+            let head = head.clone();
+            head.set(true);
+            let on_end: EndTagHandler<'static> = Box::new(move |_| {
+                head.set(false);
+                Ok(())
+            });
+            el.on_end_tag(on_end)
+        }));
+
+        let head = in_head.clone();
+        element_content_handlers.push(element!("body", move |_| {
+            head.set(false);
+            Ok(())
+        }));
+    }
+
+    let document_content_handlers = if with_text {
+        vec![doc_text!(|chunk| {
+            if hidden_depth.get() == 0 && !in_head.get() {
+                let mut text = text.borrow_mut();
+                text.push_str(&decode_entities(chunk.as_str()));
+                if chunk.last_in_text_node() {
+                    text.push(' ');
+                }
+            }
+            Ok(())
+        })]
+    } else {
+        Vec::new()
+    };
+
+    let settings = element_content_handlers
+        .into_iter()
+        .fold(Settings::new(), Settings::append_element_content_handler);
+    let settings = document_content_handlers
+        .into_iter()
+        .fold(settings, Settings::append_document_content_handler);
+
+    let mut rewriter = HtmlRewriter::new(
+        settings,
+        // Only the handlers matter, the rewritten page is thrown away
+        |_: &[u8]| {},
+    );
+
+    for chunk in html.as_bytes().chunks(CHUNK_BYTES) {
+        rewriter
+            .write(chunk)
+            .map_err(|e| anyhow!("could not tokenize page: {}", e))?;
+    }
+    rewriter
+        .end()
+        .map_err(|e| anyhow!("could not tokenize page: {}", e))?;
+
+    let client_redirect = match meta_refresh.into_inner() {
+        Some(target) => Some((RedirectKind::MetaRefresh, target)),
+        None => scripts.into_inner().iter().find_map(|script| {
+            let target = client_redirect::javascript_target(script)?;
+            Some((RedirectKind::Javascript, target.to_string()))
+        }),
+    };
+
+    let links = links.into_inner();
+    let nofollow_links = if page_nofollow.get() {
+        links.clone()
+    } else {
+        nofollow_links.into_inner()
+    };
+
+    Ok(PageContent {
+        links,
+        anchor_texts: anchor_texts.into_inner(),
+        nofollow_links,
+        image_sources: image_sources.into_inner(),
+        title: title.into_inner(),
+        outline: outline.into_inner(),
+        client_redirect,
+        canonical: canonical.into_inner(),
+        alternates: alternates.into_inner(),
+        meta: meta.into_inner(),
+        html_lang: html_lang.into_inner(),
+        text: text.into_inner(),
+    })
+}
+
+/// Decodes the character references the tokenizer leaves
+/// in text, e.g. `&amp;`, `&hellip;` and `&#8217;`
+fn decode_entities(text: &str) -> String {
+    decode(text, false)
+}
+
+/// Decodes the character references in an attribute value, where
+/// `&copy=` is left alone as it's more likely part of a url
+fn decode_attribute(value: &str) -> String {
+    decode(value, true)
+}
+
+fn decode(text: &str, in_attribute: bool) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        match decode_reference(rest, in_attribute) {
+            Some((characters, length)) => {
+                decoded.extend(characters.into_iter().flatten());
+                rest = &rest[length..];
+            }
+            None => decoded.push('&'),
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Decodes the character reference at the start of `reference`, the text
+/// after an `&`, returning its characters and how long it is. Like a
+/// browser, any HTML5 entity is decoded, and the legacy ones like `&copy`
+/// and numeric references don't need their `;`
+fn decode_reference(reference: &str, in_attribute: bool) -> Option<([Option<char>; 2], usize)> {
+    if let Some(number) = reference.strip_prefix('#') {
+        let (prefix, radix) = match number.strip_prefix(['x', 'X']) {
+            Some(_) => (2, 16),
+            None => (1, 10),
+        };
+        let digits = &reference[prefix..];
+        let digits = &digits[..digits
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or(digits.len())];
+        if digits.is_empty() {
+            return None;
+        }
+
+        let code = u32::from_str_radix(digits, radix).unwrap_or(u32::MAX);
+        let character = match code {
+            0x80..=0x9f => C1_REPLACEMENTS[(code - 0x80) as usize],
+            0 => None,
+            _ => char::from_u32(code),
+        }
+        .unwrap_or('\u{fffd}');
+        let length = prefix + digits.len();
+        let length = length + usize::from(reference[length..].starts_with(';'));
+        return Some(([Some(character), None], length));
+    }
+
+    let name_length = reference
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(reference.len());
+    let length = name_length + usize::from(reference[name_length..].starts_with(';'));
+    if in_attribute && length == name_length && reference[length..].starts_with('=') {
+        return None;
+    }
+    // Prefixes of the names are in the table too, as zeros
+    let (first, second) = NAMED_ENTITIES
+        .get(&reference[..length])
+        .filter(|(first, _)| *first != 0)?;
+    // A few entities are two characters, e.g. `&NotEqualTilde;`
+    let second = char::from_u32(*second).filter(|c| *c != '\0');
+    Some(([char::from_u32(*first), second], length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::AlternateKind;
+
+    #[test]
+    fn test_extract_page() {
+        let html = r#"<html lang="en-GB"><head><title>Big &amp; slow</title>
+            <meta property="og:title" content="Big">
+            <script>var a = "<a href='/script'>";</script></head>
+            <body><h1>Intro</h1><h3>Setup</h3><p>Hello <b>world</b></p>
+            <a href="/a?x=1&amp;y=2">A</a><img src="/i.png" alt="pic">
+            <noscript>enable js</noscript></body></html>"#;
+
+        let page = extract_page(html, true).unwrap();
+        assert_eq!(page.links, vec!["/a?x=1&y=2"]);
+        assert_eq!(page.anchor_texts, vec!["A"]);
+        assert_eq!(
+            page.meta,
+            vec![(String::from("og:title"), String::from("Big"))]
+        );
+        assert_eq!(
+            page.image_sources,
+            vec![(String::from("/i.png"), String::from("pic"))]
+        );
+        assert_eq!(page.title.as_deref(), Some("Big & slow"));
+        assert_eq!(page.html_lang.as_deref(), Some("en-GB"));
+        assert_eq!(
+            page.outline,
+            vec![
+                Heading {
+                    level: 1,
+                    text: String::from("Intro")
+                },
+                Heading {
+                    level: 3,
+                    text: String::from("Setup")
+                },
+            ]
+        );
+        assert!(page.text.contains("Hello") && page.text.contains("world"));
+        assert!(!page.text.contains("enable js") && !page.text.contains("Big"));
+    }
+
+    #[test]
+    fn test_client_redirect() {
+        let html = r#"<script src="/app.js"></script>
+            <script>if (!window.ready) { window.location.href = "/moved"; }</script>"#;
+        assert_eq!(
+            extract_page(html, false).unwrap().client_redirect,
+            Some((RedirectKind::Javascript, String::from("/moved")))
+        );
+
+        let html = r#"<head><meta http-equiv="Refresh" content="0; url=/new?a=1&amp;b=2">
+            <script>location.replace("/moved")</script></head>"#;
+        assert_eq!(
+            extract_page(html, false).unwrap().client_redirect,
+            Some((RedirectKind::MetaRefresh, String::from("/new?a=1&b=2")))
+        );
+    }
+
+    #[test]
+    fn test_alternates() {
+        let html = r#"<head><link rel="canonical" href="https://example.com/a">
+            <link rel="amphtml" href="/amp/a"><link rel="alternate" hreflang="fr" href="/fr/a">
+            <link rel="alternate" media="only screen and (max-width: 640px)" href="https://m.example.com/a">
+            </head>"#;
+
+        let page = extract_page(html, false).unwrap();
+        assert_eq!(page.canonical.as_deref(), Some("https://example.com/a"));
+        let alternates: Vec<_> = page
+            .alternates
+            .iter()
+            .map(|alternate| (alternate.kind, alternate.url.as_str()))
+            .collect();
+        assert_eq!(
+            alternates,
+            [
+                (AlternateKind::Amp, "/amp/a"),
+                (AlternateKind::Media, "https://m.example.com/a")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nofollow_links() {
+        let html = r#"<a href="/a">A</a><a href="/b" rel="ugc NoFollow">B</a>"#;
+        assert_eq!(extract_page(html, false).unwrap().nofollow_links, vec!["/b"]);
+
+        let html = r#"<head><meta name="ROBOTS" content="noindex, nofollow"></head>
+            <a href="/a">A</a><a href="/b">B</a>"#;
+        assert_eq!(
+            extract_page(html, false).unwrap().nofollow_links,
+            vec!["/a", "/b"]
+        );
+    }
+
+    #[test]
+    fn test_decode_entities() {
+        assert_eq!(
+            decode_entities("a &amp; b &#8217; &#x41; &bogus; &"),
+            "a & b ’ A &bogus; &"
+        );
+        assert_eq!(
+            decode_entities("&hellip;&mdash;&eacute;&NotEqualTilde;&copy 2024 &#150;&#0;"),
+            "…—é\u{2242}\u{338}© 2024 –\u{fffd}"
+        );
+    }
+
+    #[test]
+    fn test_entities_match_the_dom() {
+        let html = r#"<html><head><title>Caf&eacute; &ndash; Men&uuml;</title></head>
+            <body><h2>Q&amp;A &hellip; &laquo;FAQ&raquo;</h2>
+            <a href="/a?x=1&amp;y=2&copy=3">Na&iuml;ve &rarr; next&nbsp;page</a>
+            <img src="/i.png" alt="&quot;Logo&quot; &trade; &#x2603; &#8364;"></body></html>"#;
+
+        let streamed = extract_page(html, false).unwrap();
+        let dom = PageContent::from_dom(&scraper::Html::parse_document(html), false, None);
+        assert_eq!(streamed.title, dom.title);
+        assert_eq!(streamed.title.as_deref(), Some("Café – Menü"));
+        assert_eq!(streamed.outline, dom.outline);
+        assert_eq!(streamed.links, dom.links);
+        assert_eq!(streamed.anchor_texts, dom.anchor_texts);
+        assert_eq!(streamed.image_sources, dom.image_sources);
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::Result;
+use log2::*;
+use reqwest::{header::HeaderMap, Client, ClientBuilder, Request, Response, Version};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use url::Url;
+
+/// Whether a host can be reached over HTTP/3
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Support {
+    /// It advertised HTTP/3 on the port it was reached on
+    Advertised,
+    /// HTTP/3 requests to it failed, it isn't tried again
+    Broken,
+}
+
+/// Sends requests over HTTP/3 to hosts that advertise it with
+/// `Alt-Svc`, and over HTTP/1.1 or HTTP/2 to every other host
+/// or when an HTTP/3 request fails
+pub struct Http3Client {
+    client: Client,
+    hosts: Mutex<HashMap<String, Support>>,
+}
+
+impl Http3Client {
+    /// `builder` should be set up the same way as the fallback client
+    pub fn new(builder: ClientBuilder) -> Result<Self> {
+        Ok(Self {
+            client: builder.use_rustls_tls().http3_prior_knowledge().build()?,
+            hosts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub async fn execute(&self, fallback: &Client, request: Request) -> reqwest::Result<Response> {
+        let host = host_key(request.url());
+
+        if self.support(&host) == Some(Support::Advertised) {
+            if let Some(mut h3_request) = request.try_clone() {
+                *h3_request.version_mut() = Version::HTTP_3;
+                match self.client.execute(h3_request).await {
+                    Ok(response) => return Ok(response),
+                    Err(e) => {
+                        warn!("HTTP/3 request to {} failed, falling back: {}", host, e);
+                        self.set_support(host.clone(), Support::Broken);
+                    }
+                }
+            }
+        }
+
+        let response = fallback.execute(request).await?;
+        if self.support(&host).is_none() && advertises_http3(response.url(), response.headers()) {
+            info!("{} advertised HTTP/3, using it from now on", host);
+            self.set_support(host, Support::Advertised);
+        }
+        Ok(response)
+    }
+
+    fn support(&self, host: &str) -> Option<Support> {
+        let hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        hosts.get(host).copied()
+    }
+
+    fn set_support(&self, host: String, support: Support) {
+        let mut hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        hosts.insert(host, support);
+    }
+}
+
+fn host_key(url: &Url) -> String {
+    format!(
+        "{}:{}",
+        url.host_str().unwrap_or_default(),
+        url.port_or_known_default().unwrap_or_default()
+    )
+}
+
+/// Whether the `Alt-Svc` header offers HTTP/3 on the same host and port.
+/// Other alternatives would need the request sent somewhere else
+fn advertises_http3(url: &Url, headers: &HeaderMap) -> bool {
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return false;
+    };
+    let same_port = format!(":{}", port);
+    let same_host = format!("{}:{}", host, port);
+
+    headers
+        .get_all("alt-svc")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|alternative| {
+            let protocol_and_authority = alternative.split(';').next()?;
+            let (protocol, authority) = protocol_and_authority.trim().split_once('=')?;
+            Some((protocol.trim(), authority.trim().trim_matches('"')))
+        })
+        .any(|(protocol, authority)| {
+            protocol == "h3" && (authority == same_port || authority == same_host)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_advertises_http3() {
+        let url = Url::parse("https://example.com/page").unwrap();
+        let advertises = |alt_svc: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("alt-svc", HeaderValue::from_static(alt_svc));
+            advertises_http3(&url, &headers)
+        };
+
+        assert!(advertises("h3=\":443\"; ma=86400"));
+        assert!(advertises("h2=\":443\", h3=\"example.com:443\""));
+        assert!(!advertises("h3=\":8443\"; ma=86400"));
+        assert!(!advertises("h3-29=\":443\""));
+        assert!(!advertises("clear"));
+        assert!(!advertises_http3(&url, &HeaderMap::new()));
+    }
+}
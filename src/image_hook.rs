@@ -0,0 +1,179 @@
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use futures::StreamExt;
+use log2::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::image_utils::log_removed;
+use crate::model::Image;
+use crate::skipped::SkipReason;
+
+/// How many images are handed to a hook at once
+const HOOK_CONCURRENCY: usize = 4;
+
+/// What a hook decided about an image
+#[derive(Debug, PartialEq)]
+pub enum HookVerdict {
+    /// Keep the image, with what the hook said about it, e.g. a class
+    Keep(Option<String>),
+    /// Delete the image and leave it out of the database
+    Reject,
+}
+
+/// Runs on every downloaded image, to classify, filter or upload
+/// images without changing how they're downloaded
+pub trait ImageHook: Send + Sync {
+    /// Looks at `image`, saved at `path`
+    fn process<'a>(
+        &'a self,
+        path: &'a Path,
+        image: &'a Image,
+    ) -> BoxFuture<'a, Result<HookVerdict>>;
+}
+
+/// A shell command run for every image, with the image's path as `$1` and
+/// its metadata as JSON on stdin. The image is kept if it exits successfully,
+/// and what it prints is recorded as the image's label
+pub struct CommandHook {
+    command: String,
+}
+
+impl CommandHook {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+        }
+    }
+
+    async fn run(&self, path: &Path, image: &Image) -> Result<HookVerdict> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .arg("image-hook")
+            .arg(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("could not run the image hook `{}`", self.command))?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            // Hooks that only look at the file don't read their input
+            let _ = stdin.write_all(&serde_json::to_vec(image)?).await;
+        }
+        let output = child.wait_with_output().await?;
+
+        if !output.status.success() {
+            return Ok(HookVerdict::Reject);
+        }
+        let label = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        Ok(HookVerdict::Keep((!label.is_empty()).then_some(label)))
+    }
+}
+
+impl ImageHook for CommandHook {
+    fn process<'a>(
+        &'a self,
+        path: &'a Path,
+        image: &'a Image,
+    ) -> BoxFuture<'a, Result<HookVerdict>> {
+        Box::pin(self.run(path, image))
+    }
+}
+
+/// Runs `hook` on every image saved in `directory`. Rejected images are deleted,
+/// marked as removed in the download log, their file name is cleared and
+/// they're returned. An image is kept if the hook fails on it
+pub async fn run_image_hook(
+    hook: &dyn ImageHook,
+    images: &mut HashMap<String, Image>,
+    directory: &Path,
+) -> Vec<Image> {
+    let saved: Vec<(String, PathBuf)> = images
+        .iter()
+        .filter_map(|(name, image)| {
+            let path = directory.join(image.file_name.as_ref()?);
+            Some((name.clone(), path))
+        })
+        .collect();
+
+    let verdicts: Vec<(String, PathBuf, Result<HookVerdict>)> = futures::stream::iter(saved)
+        .map(|(name, path)| {
+            let image = &images[&name];
+            async move {
+                let verdict = hook.process(&path, image).await;
+                (name, path, verdict)
+            }
+        })
+        .buffer_unordered(HOOK_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut rejected = Vec::new();
+    for (name, path, verdict) in verdicts {
+        let Some(image) = images.get_mut(&name) else {
+            continue;
+        };
+        match verdict {
+            Ok(HookVerdict::Keep(label)) => image.label = label,
+            Ok(HookVerdict::Reject) => {
+                if let Err(e) = tokio::fs::remove_file(&path).await {
+                    error!("could not delete rejected image {}: {}", path.display(), e);
+                }
+                if let Some(file_name) = image.file_name.take() {
+                    let reason = SkipReason::ImageHook.to_string();
+                    if let Err(e) = log_removed(directory, &file_name, &reason).await {
+                        error!("could not log the removal of {}: {}", file_name, e);
+                    }
+                }
+                rejected.push(image.clone());
+            }
+            Err(e) => error!("image hook failed on {}: {}", path.display(), e),
+        }
+    }
+
+    rejected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_utils::DOWNLOAD_LOG;
+
+    #[tokio::test]
+    async fn test_command_hook() {
+        let directory = std::env::temp_dir().join(format!("image_hook_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let mut images = HashMap::new();
+        for (name, bytes) in [("icon", 3), ("photo", 300)] {
+            std::fs::write(directory.join(name), vec![0; bytes]).unwrap();
+            let image = Image {
+                link: format!("https://example.com/{}.png", name),
+                file_name: Some(name.to_string()),
+                ..Default::default()
+            };
+            images.insert(name.to_string(), image);
+        }
+
+        // Rejects images under 100 bytes, labels the rest with their link
+        let hook = CommandHook::new(
+            r#"[ "$(wc -c < "$1")" -ge 100 ] && sed 's/.*"link":"\([^"]*\)".*/\1/'"#,
+        );
+        let rejected = run_image_hook(&hook, &mut images, &directory).await;
+
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(images["icon"].file_name, None);
+        assert!(!directory.join("icon").exists());
+        // Only the rejection is logged here, the downloads were logged by the downloader
+        let log = std::fs::read_to_string(directory.join(DOWNLOAD_LOG)).unwrap();
+        assert_eq!(log, "{\"file_name\":\"icon\",\"removed\":\"image_hook\"}\n");
+        assert_eq!(
+            images["photo"].label.as_deref(),
+            Some("https://example.com/photo.png")
+        );
+        let _ = std::fs::remove_dir_all(directory);
+    }
+}
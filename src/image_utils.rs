@@ -18,43 +18,217 @@ input:
 }
 */
 
-use anyhow::{anyhow, bail, Result};
-use std::collections::HashMap;
-use std::path::Path;
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::ValueEnum;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use futures::StreamExt;
 use log2::*;
-use reqwest::{Client, Response};
-use tokio::fs::{create_dir, File};
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+use reqwest::header::REFERER;
+use reqwest::{Client, Response, StatusCode};
+use serde::Serialize;
+use tokio::fs::{self, File, OpenOptions};
 use tokio::io::AsyncWriteExt;
-use tokio_stream::StreamExt;
-use tokio::time::sleep;
+use tokio::sync::mpsc;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::{sleep, sleep_until, Instant};
 use url::Url;
 use uuid::Uuid;
 
+use crate::crawler;
+use crate::file_names::sanitize_file_name;
 use crate::model::{Image, LinkGraph};
+use crate::politeness::Politeness;
+use crate::robots::{self, RobotsCache};
+use crate::url_utils::is_same_domain;
+
+/// How much of an image is read before sniffing its format
+const SNIFF_BYTES: usize = 256;
+
+/// How many images are downloaded at once
+const IMAGE_CONCURRENCY: usize = 4;
+
+/// Every image is appended to this file in the save directory as soon as
+/// it's downloaded, so an interrupted crawl still knows what it saved.
+/// Images deleted afterwards get a `removed` record, see `read_download_log`.
+/// `database.json` is written once the crawl is over
+pub const DOWNLOAD_LOG: &str = "database.jsonl";
+
+/// How many images can wait to be downloaded before
+/// the crawl waits for downloads to catch up
+const IMAGE_QUEUE_SIZE: usize = 256;
+
+/// Longest a server's `Retry-After` is waited for
+const MAX_RETRY_AFTER_S: u64 = 30;
+
+/// The name an image is saved under, the same for every run
+fn image_name(link: &str) -> String {
+    Uuid::new_v5(&Uuid::NAMESPACE_URL, link.as_bytes()).to_string()
+}
+
+/// What's done with an image found on more than one page
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ImageDedup {
+    /// Download it once and list every page it's on
+    #[default]
+    Once,
+    /// Download a copy of it for every page it's on
+    PerPage,
+    /// Don't download it, images on many pages are usually logos and icons
+    Skip,
+}
+
+impl ImageDedup {
+    /// The name the image at `link` on `page` is saved under
+    pub fn image_name(self, link: &str, page: &str) -> String {
+        match self {
+            ImageDedup::PerPage => image_name(&format!("{} {}", page, link)),
+            ImageDedup::Once | ImageDedup::Skip => image_name(link),
+        }
+    }
+}
+
+/// The hosts images may be downloaded from. Domains match
+/// themselves and their subdomains
+#[derive(Clone, Debug, Default)]
+pub struct ImageDomains {
+    /// When not empty, only images on these domains are kept
+    pub allow: Vec<String>,
+    /// Images on these domains are dropped, even if they're allowed
+    pub block: Vec<String>,
+}
+
+impl ImageDomains {
+    /// Whether the image at `link` is kept. `data:` images aren't on
+    /// any host, they're always kept
+    pub fn allows(&self, link: &str) -> bool {
+        let Ok(url) = Url::parse(link) else {
+            return false;
+        };
+        if url.scheme() == "data" {
+            return true;
+        }
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let on = |domains: &[String]| domains.iter().any(|domain| is_same_domain(host, domain));
+
+        (self.allow.is_empty() || on(&self.allow)) && !on(&self.block)
+    }
+}
 
 /// Convert all the images in the found scraped
-/// links to the (Uuid name, image) format
-pub fn convert_links_to_images(links: &LinkGraph) -> HashMap<String, Image> {
-    links
-        .into_iter()
-        .flat_map(|(_, link)| link.images.clone())
-        .map(|img| (Uuid::new_v4().to_string(), img))
+/// links to the (Uuid name, image) format. Images
+/// found on several pages are listed once, along
+/// with every page they were found on, unless
+/// each page gets its own copy with `dedup`.
+/// Images `domains` doesn't allow are left out.
+pub fn convert_links_to_images(
+    links: &LinkGraph,
+    dedup: ImageDedup,
+    domains: &ImageDomains,
+) -> HashMap<String, Image> {
+    let mut images: HashMap<String, Image> = HashMap::new();
+    for (id, link) in links {
+        for image in link
+            .images
+            .iter()
+            .filter(|image| domains.allows(&image.link))
+        {
+            let entry = images
+                .entry(dedup.image_name(&image.link, &link.url))
+                .or_insert_with(|| image.clone());
+            entry.pages.push(link.url.clone());
+            entry.page_ids.push(*id);
+        }
+    }
+
+    images
+}
+
+/// A downloaded image, along with the name the server suggested for it
+pub struct DownloadedImage {
+    path: PathBuf,
+    disposition_name: Option<String>,
+    bytes: usize,
+    /// The media type of images saved from `data:` urls
+    inline_media_type: Option<String>,
+}
+
+/// A server asked for requests to be retried after a while
+#[derive(Debug)]
+struct RetryAfter(Duration);
+
+impl fmt::Display for RetryAfter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "server asked to retry after {}s", self.0.as_secs())
+    }
+}
+
+impl std::error::Error for RetryAfter {}
+
+/// robots.txt doesn't let us download an image
+#[derive(Debug)]
+pub struct RobotsDisallowed;
+
+impl fmt::Display for RobotsDisallowed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "disallowed by robots.txt")
+    }
+}
+
+impl std::error::Error for RobotsDisallowed {}
+
+/// Keeps only the images found on a page whose path matches `pattern`,
+/// returning the ones removed
+pub fn retain_images_from(images: &mut HashMap<String, Image>, pattern: &Regex) -> Vec<Image> {
+    images
+        .extract_if(|_, image| {
+            !image
+                .pages
+                .iter()
+                .any(|page| Url::parse(page).is_ok_and(|url| pattern.is_match(url.path())))
+        })
+        .map(|(_, image)| image)
         .collect()
 }
 
-async fn download_image(link: &str, destination: &str, client: &Client) -> Result<()> {
+async fn download_image(
+    link: &str,
+    destination: &Path,
+    client: &Client,
+    politeness: &Politeness,
+    url: &Url,
+    referer: Option<&str>,
+    reserved: bool,
+) -> Result<DownloadedImage> {
     const MAX_RETRIES: u32 = 3;
     let mut last_error = None;
 
     for attempt in 0..MAX_RETRIES {
-        match try_download_image(link, destination, client).await {
-            Ok(()) => return Ok(()),
+        // The first request was reserved when the download was started
+        let _permit = match attempt > 0 || !reserved {
+            true => politeness.wait(url).await,
+            false => None,
+        };
+        match try_download_image(link, destination, client, referer).await {
+            Ok(downloaded) => return Ok(downloaded),
             Err(e) => {
+                let backoff = match e.downcast_ref::<RetryAfter>() {
+                    Some(RetryAfter(wait)) => *wait,
+                    None => Duration::from_millis(500 * (attempt + 1) as u64),
+                };
                 last_error = Some(e);
                 if attempt < MAX_RETRIES - 1 {
-                    sleep(Duration::from_millis(500 * (attempt + 1) as u64)).await;
+                    sleep(backoff).await;
                 }
             }
         }
@@ -63,87 +237,1194 @@ async fn download_image(link: &str, destination: &str, client: &Client) -> Resul
     Err(last_error.unwrap_or_else(|| anyhow!("download failed after {} attempts", MAX_RETRIES)))
 }
 
-async fn try_download_image(link: &str, destination: &str, client: &Client) -> Result<()> {
-    let res = client.get(link).send().await?;
-    let extension = get_extension(&res)?;
-    let mut file = File::create(format!("{}.{}", destination, extension)).await?;
+/// Downloads `link` to `destination` with the image's extension added.
+/// When the server suggests a file name for the same type of image, it's
+/// used instead of the file name of `destination`, unless a different
+/// image already has that name. The image is written to a `.part` file
+/// first, so there's never a partly downloaded image under its name.
+/// `referer` is the page the image is on, some CDNs refuse hotlinked
+/// images requested without one.
+async fn try_download_image(
+    link: &str,
+    destination: &Path,
+    client: &Client,
+    referer: Option<&str>,
+) -> Result<DownloadedImage> {
+    let mut request = client.get(link);
+    if let Some(referer) = referer {
+        request = request.header(REFERER, referer);
+    }
+    let res = request.send().await?;
+    if !res.status().is_success() {
+        let retry_after = res
+            .headers()
+            .get("retry-after")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|secs| secs.trim().parse::<u64>().ok());
+
+        match (res.status(), retry_after) {
+            (StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE, Some(secs)) => {
+                let wait = Duration::from_secs(secs.min(MAX_RETRY_AFTER_S));
+                return Err(RetryAfter(wait).into());
+            }
+            (status, _) => bail!("image returned status {}", status),
+        }
+    }
+
+    let declared_extension = get_extension(&res);
+    let disposition_name = res
+        .headers()
+        .get("content-disposition")
+        .and_then(|h| h.to_str().ok())
+        .and_then(disposition_file_name);
+
+    // Read the start of the body up front, it's needed to sniff
+    // the format when neither the headers nor the url give it away
     let mut stream = res.bytes_stream();
+    let mut head = Vec::new();
+    while head.len() < SNIFF_BYTES {
+        match stream.next().await {
+            Some(item) => head.extend_from_slice(&item?),
+            None => break,
+        }
+    }
+
+    let sniffed_extension = sniff_extension(&head);
+    let extension = match declared_extension {
+        Some(extension) => extension,
+        None => sniffed_extension
+            .ok_or_else(|| anyhow!("could not determine image extension"))?
+            .to_string(),
+    };
+
+    let mut partial_path = destination.as_os_str().to_owned();
+    partial_path.push(".part");
+    let partial_path = PathBuf::from(partial_path);
+    let written: Result<usize> = async {
+        let mut file = File::create(&partial_path).await?;
+        file.write_all(&head).await?;
+        let mut bytes = head.len();
+
+        while let Some(item) = stream.next().await {
+            let item = item?;
+            bytes += item.len();
+            file.write_all(&item).await?;
+        }
+        file.flush().await?;
+        Ok(bytes)
+    }
+    .await;
+    let bytes = match written {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            let _ = fs::remove_file(&partial_path).await;
+            return Err(e);
+        }
+    };
 
-    while let Some(item) = stream.next().await {
-        file.write_all(&item?).await?;
+    let mut path = destination.with_extension(&extension);
+    let named_file = disposition_name.as_deref().and_then(|name| {
+        disposition_save_name(name, sniffed_extension.unwrap_or(&extension))
+    });
+    if let Some(file_name) = named_file {
+        let named_path = destination.with_file_name(&file_name);
+        // Creating the file claims the name, so two images downloaded at
+        // once with the same name can't both be renamed to it. One
+        // downloaded again, e.g. by an earlier crawl, is replaced.
+        let claimed = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&named_path)
+            .await
+            .is_ok();
+        path = if claimed || same_contents(&named_path, &partial_path).await {
+            named_path
+        } else {
+            let uuid = destination.file_name().unwrap_or_default().to_string_lossy();
+            destination.with_file_name(sanitize_file_name(&format!("{}_{}", uuid, file_name)))
+        };
     }
 
-    Ok(())
+    if let Err(e) = fs::rename(&partial_path, &path).await {
+        let _ = fs::remove_file(&partial_path).await;
+        return Err(e.into());
+    }
+
+    Ok(DownloadedImage {
+        path,
+        disposition_name,
+        bytes,
+        inline_media_type: None,
+    })
+}
+
+/// The file name to save an image of type `extension` under when the server
+/// suggested `name`. `None` if the name is for a different type of file
+fn disposition_save_name(name: &str, extension: &str) -> Option<String> {
+    let file_name = sanitize_file_name(name);
+    let Some((_, named_extension)) = file_name.rsplit_once('.') else {
+        return Some(format!("{}.{}", file_name, extension));
+    };
+
+    let canonical = |extension: &str| match extension.to_ascii_lowercase().as_str() {
+        "jpeg" | "jpe" | "jfif" => String::from("jpg"),
+        "tiff" => String::from("tif"),
+        other => other.to_string(),
+    };
+    (canonical(named_extension) == canonical(extension)).then_some(file_name)
 }
 
-fn get_extension(res: &Response) -> Result<String> {
-    if let Some(content_type) = res.headers().get("content-type").and_then(|h| h.to_str().ok()) {
-        if let Some(ext) = match content_type {
-            "image/gif" => Some("gif"),
-            "image/jpeg" | "image/jpg" => Some("jpg"),
-            "image/png" => Some("png"),
-            "image/svg+xml" => Some("svg"),
-            "image/webp" => Some("webp"),
-            "image/tiff" | "image/tif" => Some("tif"),
-            "image/avif" => Some("avif"),
-            "image/bmp" => Some("bmp"),
-            _ => None,
-        } {
-            return Ok(ext.to_string());
+async fn same_contents(a: &Path, b: &Path) -> bool {
+    match (fs::read(a).await, fs::read(b).await) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// The file name suggested by a `Content-Disposition` header, preferring
+/// the encoded `filename*` parameter over the plain `filename` one.
+/// Any directories in the name are dropped.
+fn disposition_file_name(header: &str) -> Option<String> {
+    let mut encoded_name = None;
+    let mut plain_name = None;
+
+    for param in header.split(';').skip(1) {
+        let Some((key, value)) = param.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+
+        match key.trim().to_ascii_lowercase().as_str() {
+            // e.g. `UTF-8''na%C3%AFve.png`, charset'language'name
+            "filename*" => {
+                encoded_name = value
+                    .splitn(3, '\'')
+                    .nth(2)
+                    .and_then(|name| percent_decode_str(name).decode_utf8().ok())
+                    .map(|name| name.into_owned());
+            }
+            "filename" => plain_name = Some(value.trim_matches('"').to_string()),
+            _ => {}
         }
     }
 
+    encoded_name
+        .or(plain_name)
+        .and_then(|name| name.rsplit(['/', '\\']).next().map(str::to_string))
+        .filter(|name| !name.trim().is_empty())
+}
+
+/// The extension for the image in `res`, going by its
+/// Content-Type header and then the extension in its url
+fn get_extension(res: &Response) -> Option<String> {
+    if let Some(ext) = res
+        .headers()
+        .get("content-type")
+        .and_then(|h| h.to_str().ok())
+        .and_then(media_type_extension)
+    {
+        return Some(ext.to_string());
+    }
+
     if let Ok(url) = res.url().as_str().parse::<Url>() {
-        if let Some(path) = url.path_segments() {
-            if let Some(last) = path.last() {
+        if let Some(mut path) = url.path_segments() {
+            if let Some(last) = path.next_back() {
                 if let Some(dot_idx) = last.rfind('.') {
                     let ext = &last[dot_idx + 1..];
-                    if !ext.is_empty() && ext.len() <= 5 {
-                        return Ok(ext.to_lowercase());
+                    // Anything else isn't a real extension and may not be a valid file name
+                    if !ext.is_empty()
+                        && ext.len() <= 5
+                        && ext.chars().all(|c| c.is_ascii_alphanumeric())
+                    {
+                        return Some(ext.to_lowercase());
                     }
                 }
             }
         }
     }
 
-    bail!("could not determine image extension")
+    None
+}
+
+fn media_type_extension(media_type: &str) -> Option<&'static str> {
+    match media_type {
+        "image/gif" => Some("gif"),
+        "image/jpeg" | "image/jpg" => Some("jpg"),
+        "image/png" => Some("png"),
+        "image/svg+xml" => Some("svg"),
+        "image/webp" => Some("webp"),
+        "image/tiff" | "image/tif" => Some("tif"),
+        "image/avif" => Some("avif"),
+        "image/bmp" => Some("bmp"),
+        _ => None,
+    }
+}
+
+/// The media type of an image saved with `extension`
+pub fn extension_media_type(extension: &str) -> &'static str {
+    match extension.to_ascii_lowercase().as_str() {
+        "gif" => "image/gif",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "tif" | "tiff" => "image/tiff",
+        "avif" => "image/avif",
+        "bmp" => "image/bmp",
+        "ico" => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+/// An image inlined in a page as a `data:` url
+struct DataUri {
+    media_type: String,
+    data: Vec<u8>,
+}
+
+/// Decodes `data:[<media type>][;base64],<data>` urls
+fn decode_data_uri(uri: &str) -> Result<DataUri> {
+    let (header, data) = uri
+        .strip_prefix("data:")
+        .and_then(|uri| uri.split_once(','))
+        .ok_or_else(|| anyhow!("invalid data url"))?;
+
+    let mut params = header.split(';');
+    let media_type = params.next().unwrap_or_default().trim().to_ascii_lowercase();
+    let is_base64 = params.any(|param| param.trim().eq_ignore_ascii_case("base64"));
+
+    let data = if is_base64 {
+        let data: String = percent_decode_str(data)
+            .decode_utf8()?
+            .chars()
+            .filter(|c| !c.is_ascii_whitespace())
+            .collect();
+        STANDARD.decode(data)?
+    } else {
+        percent_decode_str(data).collect()
+    };
+
+    Ok(DataUri { media_type, data })
+}
+
+/// Saves an image inlined as a `data:` url to `destination`,
+/// with the extension its media type or contents suggest
+async fn save_data_uri(data_uri: &DataUri, destination: &Path) -> Result<DownloadedImage> {
+    let extension = media_type_extension(&data_uri.media_type)
+        .or_else(|| sniff_extension(&data_uri.data))
+        .ok_or_else(|| anyhow!("could not determine image extension"))?;
+
+    let path = destination.with_extension(extension);
+    fs::write(&path, &data_uri.data).await?;
+
+    Ok(DownloadedImage {
+        path,
+        disposition_name: None,
+        bytes: data_uri.data.len(),
+        inline_media_type: Some(data_uri.media_type.clone()),
+    })
+}
+
+/// Guesses the extension of an image from the magic bytes at
+/// the start of its contents, for servers that don't say what
+/// they're sending, e.g. CDNs serving `application/octet-stream`
+fn sniff_extension(head: &[u8]) -> Option<&'static str> {
+    let extension = match head {
+        [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => "png",
+        [0xFF, 0xD8, 0xFF, ..] => "jpg",
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => "gif",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "webp",
+        [_, _, _, _, b'f', b't', b'y', b'p', b'a', b'v', b'i', b'f' | b's', ..] => "avif",
+        [b'I', b'I', 0x2A, 0x00, ..] | [b'M', b'M', 0x00, 0x2A, ..] => "tif",
+        [0x00, 0x00, 0x01, 0x00, ..] => "ico",
+        [b'B', b'M', ..] => "bmp",
+        _ => {
+            let text = String::from_utf8_lossy(head);
+            let text = text.trim_start_matches('\u{feff}').trim_start();
+            if (text.starts_with("<svg") || text.starts_with("<?xml")) && text.contains("<svg") {
+                "svg"
+            } else {
+                return None;
+            }
+        }
+    };
+
+    Some(extension)
+}
+
+/// Options for the `ImageDownloader`
+pub struct DownloadOptions {
+    pub max_images: usize,
+    /// Stop once this many bytes of pages and images have been
+    /// downloaded, the image that goes over the limit is kept
+    pub max_bytes: Option<usize>,
+    /// Shared with the crawl, so a host serving both pages and
+    /// images isn't sent requests any faster than pages alone
+    pub politeness: Arc<Politeness>,
+    /// Only download images found on pages whose path matches
+    pub images_from: Option<Regex>,
+    pub dedup: ImageDedup,
+    pub domains: ImageDomains,
+    /// Send the page an image was found on as the Referer
+    pub referer: bool,
+    /// Shared with the crawl when it checks robots.txt too
+    pub robots: Arc<RobotsCache>,
+}
+
+/// The result of downloading each image, as (image name, link, result)
+pub type ImageDownloads = Vec<(String, String, Result<DownloadedImage>)>;
+
+/// An image waiting to be downloaded
+struct QueuedImage {
+    name: String,
+    link: String,
+    /// The page it was found on
+    referer: Option<String>,
+}
+
+/// Downloads images in the background while the crawl runs. Pages queue
+/// their images as they're scraped, the queue is bounded so slow image
+/// hosts slow the crawl down instead of piling up downloads in memory.
+pub struct ImageDownloader {
+    sender: Mutex<Option<mpsc::Sender<QueuedImage>>>,
+    worker: Mutex<Option<JoinHandle<ImageDownloads>>>,
+    /// Names of the images queued so far, each is only downloaded once
+    queued: Mutex<HashSet<String>>,
+    /// How many pages each image was found on and the first of them, with
+    /// `ImageDedup::Skip` images are only queued once the crawl is over and that's known
+    page_counts: Mutex<BTreeMap<String, (usize, String)>>,
+    max_images: usize,
+    images_from: Option<Regex>,
+    dedup: ImageDedup,
+    domains: ImageDomains,
+    referer: bool,
+    /// Images downloaded so far
+    downloaded: Arc<AtomicUsize>,
+}
+
+impl ImageDownloader {
+    /// Starts downloading to `save_directory`. `downloaded_bytes` is
+    /// shared with the crawl so pages and images share `max_bytes`
+    pub fn start(
+        save_directory: &str,
+        options: DownloadOptions,
+        downloaded_bytes: Arc<AtomicUsize>,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(IMAGE_QUEUE_SIZE);
+        let downloaded = Arc::new(AtomicUsize::new(0));
+        let context = DownloadContext {
+            directory: PathBuf::from(save_directory),
+            client: Client::builder()
+                .user_agent(crawler::USER_AGENT)
+                .build()
+                .unwrap_or_else(|_| Client::new()),
+            politeness: options.politeness,
+            robots: options.robots,
+            downloaded_bytes,
+            downloaded: downloaded.clone(),
+            max_bytes: options.max_bytes,
+            download_log: create_download_log(save_directory),
+        };
+
+        Self {
+            sender: Mutex::new(Some(sender)),
+            worker: Mutex::new(Some(tokio::spawn(run_downloads(context, receiver)))),
+            queued: Mutex::new(HashSet::new()),
+            page_counts: Mutex::new(BTreeMap::new()),
+            max_images: options.max_images,
+            images_from: options.images_from,
+            dedup: options.dedup,
+            domains: options.domains,
+            referer: options.referer,
+            downloaded,
+        }
+    }
+
+    /// How many images have been downloaded so far
+    pub fn downloaded_count(&self) -> usize {
+        self.downloaded.load(Ordering::Relaxed)
+    }
+
+    /// Queues the images found on `page` for download, waiting
+    /// for room in the queue if downloads are falling behind
+    pub async fn queue(&self, images: &[Image], page: &Url) {
+        if self
+            .images_from
+            .as_ref()
+            .is_some_and(|pattern| !pattern.is_match(page.path()))
+        {
+            return;
+        }
+        let images = images
+            .iter()
+            .filter(|image| self.domains.allows(&image.link));
+
+        if self.dedup == ImageDedup::Skip {
+            let mut page_counts = self.page_counts.lock().unwrap();
+            let links: HashSet<&str> = images.map(|image| image.link.as_str()).collect();
+            for link in links {
+                page_counts
+                    .entry(link.to_string())
+                    .or_insert_with(|| (0, page.to_string()))
+                    .0 += 1;
+            }
+            return;
+        }
+
+        for image in images {
+            let name = self.dedup.image_name(&image.link, page.as_str());
+            if !self.send(name, &image.link, page.as_str()).await {
+                return;
+            }
+        }
+    }
+
+    /// Queues an image found on `page` for download unless it
+    /// already was, `false` once no more images can be queued
+    async fn send(&self, name: String, link: &str, page: &str) -> bool {
+        let Some(sender) = self.sender.lock().unwrap().clone() else {
+            return false;
+        };
+        {
+            let mut queued = self.queued.lock().unwrap();
+            if queued.len() >= self.max_images || !queued.insert(name.clone()) {
+                return true;
+            }
+        }
+
+        let image = QueuedImage {
+            name,
+            link: link.to_string(),
+            referer: self.referer.then(|| page.to_string()),
+        };
+        sender.send(image).await.is_ok()
+    }
+
+    /// Stops taking new images and waits for the queued ones to download
+    pub async fn finish(&self) -> ImageDownloads {
+        let page_counts = std::mem::take(&mut *self.page_counts.lock().unwrap());
+        for (link, (_, page)) in page_counts
+            .into_iter()
+            .filter(|(_, (pages, _))| *pages == 1)
+        {
+            if !self.send(image_name(&link), &link, &page).await {
+                break;
+            }
+        }
+
+        self.sender.lock().unwrap().take();
+        let worker = self.worker.lock().unwrap().take();
+
+        match worker {
+            Some(worker) => worker.await.unwrap_or_else(|e| {
+                error!("image downloads failed: {}", e);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Takes queued images off `receiver` and downloads them, several at
+/// once, until the queue is closed. Images wait in a queue for their
+/// host and hosts take turns, a download is only started for a host
+/// that may be sent a request right away. A page full of images from
+/// one host then doesn't hold up the images from every other host.
+async fn run_downloads(
+    context: DownloadContext,
+    mut receiver: mpsc::Receiver<QueuedImage>,
+) -> ImageDownloads {
+    let context = Arc::new(context);
+    // Inline images are queued under an empty host, they're never waited for
+    let mut host_queues: HashMap<String, VecDeque<QueuedImage>> = HashMap::new();
+    // The hosts with images queued, the next to download from first
+    let mut turns: VecDeque<String> = VecDeque::new();
+    let mut queued = 0;
+    let mut receiving = true;
+    let mut downloads = JoinSet::new();
+    let mut results = Vec::new();
+    let mut skipped = 0;
+
+    while receiving || queued > 0 || !downloads.is_empty() {
+        let mut next_ready: Option<Instant> = None;
+        let mut untried = turns.len();
+        while untried > 0 && downloads.len() < IMAGE_CONCURRENCY {
+            let Some(host) = turns.pop_front() else {
+                break;
+            };
+            let Some(queue) = host_queues.get_mut(&host) else {
+                continue;
+            };
+            let url = queue.front().and_then(|image| Url::parse(&image.link).ok());
+            let permit = match (host.is_empty(), url) {
+                (false, Some(url)) => match context.politeness.try_wait(&url).await {
+                    Ok(permit) => permit,
+                    Err(ready_at) => {
+                        next_ready = Some(next_ready.map_or(ready_at, |next| next.min(ready_at)));
+                        turns.push_back(host);
+                        untried -= 1;
+                        continue;
+                    }
+                },
+                _ => None,
+            };
+
+            let image = queue.pop_front();
+            if queue.is_empty() {
+                host_queues.remove(&host);
+                untried -= 1;
+            } else {
+                turns.push_back(host);
+            }
+            let Some(QueuedImage {
+                name,
+                link,
+                referer,
+            }) = image
+            else {
+                continue;
+            };
+            queued -= 1;
+
+            let context = context.clone();
+            downloads.spawn(async move {
+                let result = context.download(&name, &link, referer.as_deref()).await;
+                drop(permit);
+                if let Ok(downloaded) = &result {
+                    context.downloaded.fetch_add(1, Ordering::Relaxed);
+                    context
+                        .log_download(&name, &link, referer.as_deref(), downloaded)
+                        .await;
+                }
+                (name, link, result)
+            });
+        }
+
+        tokio::select! {
+            image = receiver.recv(), if receiving && queued < IMAGE_QUEUE_SIZE => match image {
+                Some(_) if context.budget_reached() => skipped += 1,
+                Some(image) => {
+                    let host = Url::parse(&image.link)
+                        .ok()
+                        .and_then(|url| url.host_str().map(str::to_string))
+                        .unwrap_or_default();
+                    let queue = host_queues.entry(host.clone()).or_default();
+                    if queue.is_empty() {
+                        turns.push_back(host);
+                    }
+                    queue.push_back(image);
+                    queued += 1;
+                }
+                None => receiving = false,
+            },
+            Some(finished) = downloads.join_next() => match finished {
+                Ok(download) => results.push(download),
+                Err(e) => error!("image download failed: {}", e),
+            },
+            _ = sleep_until(next_ready.unwrap_or_else(Instant::now)), if next_ready.is_some() => {}
+        }
+    }
+
+    if skipped > 0 {
+        warn!(
+            "download limit reached, {} images weren't downloaded",
+            skipped
+        );
+    }
+
+    results
+}
+
+/// What's shared between every image download
+struct DownloadContext {
+    directory: PathBuf,
+    client: Client,
+    politeness: Arc<Politeness>,
+    robots: Arc<RobotsCache>,
+    downloaded_bytes: Arc<AtomicUsize>,
+    /// Shared with the `ImageDownloader`
+    downloaded: Arc<AtomicUsize>,
+    max_bytes: Option<usize>,
+    /// `DOWNLOAD_LOG`, `None` if it couldn't be created
+    download_log: Option<tokio::sync::Mutex<File>>,
 }
 
-/// Takes in the hashmap (image name, image info), downloads the images
-/// and saves them to disk.
-pub async fn download_images(
-    images: &HashMap<String, Image>,
-    save_directory: &str,
-    max_links: u64,
-) -> Result<()> {
-    let directory_path = Path::new(&save_directory);
-    if !directory_path.is_dir() {
-        // bail!("given save directory is invalid");
-        create_dir(directory_path).await?;
+/// A downloaded image as written to `DOWNLOAD_LOG`
+#[derive(Serialize)]
+struct DownloadRecord<'a> {
+    name: &'a str,
+    link: &'a str,
+    file_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disposition_name: Option<&'a str>,
+    bytes: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    found_on: Option<&'a str>,
+}
+
+impl DownloadContext {
+    fn budget_reached(&self) -> bool {
+        self.max_bytes
+            .is_some_and(|max| self.downloaded_bytes.load(Ordering::Relaxed) >= max)
+    }
+
+    /// Downloads the image at `link` found on the page `referer`, saving it as `name`
+    async fn download(
+        &self,
+        name: &str,
+        link: &str,
+        referer: Option<&str>,
+    ) -> Result<DownloadedImage> {
+        if self.budget_reached() {
+            bail!("download limit reached");
+        }
+
+        // directory + name, the extension is added once it's known
+        let destination = self.directory.join(sanitize_file_name(name));
+        fs::create_dir_all(&self.directory).await?;
+
+        let result = if link.starts_with("data:") {
+            let data_uri = decode_data_uri(link)?;
+            save_data_uri(&data_uri, &destination).await
+        } else {
+            let url = Url::parse(link)?;
+            url.host_str().context("image link has no host")?;
+            // `run_downloads` reserved one request to the host, robots.txt
+            // takes it when it has to be fetched first
+            let mut fetched_robots = false;
+            let fetch_robots = async {
+                fetched_robots = true;
+                robots::fetch_robots(&url, &self.client).await.ok()
+            };
+            if !self.robots.allows(&url, fetch_robots).await {
+                return Err(RobotsDisallowed.into());
+            }
+
+            download_image(
+                link,
+                &destination,
+                &self.client,
+                &self.politeness,
+                &url,
+                referer,
+                !fetched_robots,
+            )
+            .await
+        };
+
+        if let Ok(downloaded) = &result {
+            self.downloaded_bytes
+                .fetch_add(downloaded.bytes, Ordering::Relaxed);
+        }
+        result
     }
 
-    let client = reqwest::Client::new();
-    for (name, image) in images.iter().take(max_links as usize) {
-        // directory + name + extension
-        let destination_path = directory_path.join(name);
-        let destination = destination_path
-            .to_str()
-            .ok_or_else(|| anyhow!("could not get destination path"))?;
+    /// Appends a downloaded image to the download log
+    async fn log_download(
+        &self,
+        name: &str,
+        link: &str,
+        found_on: Option<&str>,
+        downloaded: &DownloadedImage,
+    ) {
+        let Some(download_log) = &self.download_log else {
+            return;
+        };
+        let inline_link = downloaded
+            .inline_media_type
+            .as_ref()
+            .map(|media_type| format!("data:{}", media_type));
+        let record = DownloadRecord {
+            name,
+            link: inline_link.as_deref().unwrap_or(link),
+            file_name: downloaded
+                .path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            disposition_name: downloaded.disposition_name.as_deref(),
+            bytes: downloaded.bytes,
+            found_on,
+        };
 
-        if let Err(e) = download_image(&image.link, destination, &client).await {
-            error!("Could not download image {}, error: {}", image.link, e);
+        let Ok(mut line) = serde_json::to_vec(&record) else {
+            return;
+        };
+        line.push(b'\n');
+        // Whole lines at a time, flushed so they survive the crawl being killed
+        let mut file = download_log.lock().await;
+        if let Err(e) = async {
+            file.write_all(&line).await?;
+            file.flush().await
+        }
+        .await
+        {
+            error!("could not log the download of {}: {}", name, e);
         }
     }
+}
 
+/// Written to `DOWNLOAD_LOG` once an image logged
+/// before is deleted, e.g. by the `--image-hook`
+#[derive(Serialize)]
+struct RemovedRecord<'a> {
+    file_name: &'a str,
+    /// Why it was deleted
+    removed: &'a str,
+}
+
+/// Records in the download log of `directory` that
+/// the image saved as `file_name` was deleted
+pub async fn log_removed(directory: &Path, file_name: &str, reason: &str) -> Result<()> {
+    let mut line = serde_json::to_vec(&RemovedRecord {
+        file_name,
+        removed: reason,
+    })?;
+    line.push(b'\n');
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(directory.join(DOWNLOAD_LOG))
+        .await?;
+    file.write_all(&line).await?;
+    file.flush().await?;
     Ok(())
 }
 
-// #[cfg(test)]
-// mod tests {
-//     // use crate::model::Image;
+/// The records of the images in the download log `log`,
+/// leaving out the ones deleted since they were downloaded
+pub fn read_download_log(log: &str) -> Vec<serde_json::Value> {
+    let mut records: Vec<serde_json::Value> = Vec::new();
+    for record in log
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+    {
+        if record.get("removed").is_some() {
+            records.retain(|kept| kept.get("file_name") != record.get("file_name"));
+        } else {
+            records.push(record);
+        }
+    }
+    records
+}
+
+fn create_download_log(directory: &str) -> Option<tokio::sync::Mutex<File>> {
+    let path = Path::new(directory).join(DOWNLOAD_LOG);
+    std::fs::create_dir_all(directory)
+        .and_then(|_| std::fs::File::create(&path))
+        .map(|file| tokio::sync::Mutex::new(File::from_std(file)))
+        .inspect_err(|e| error!("could not create {}: {}", path.display(), e))
+        .ok()
+}
+
+/// Records where each downloaded image was saved on its entry in
+/// `images`, returning where each image link was saved to
+pub fn record_downloads(
+    images: &mut HashMap<String, Image>,
+    downloads: ImageDownloads,
+) -> HashMap<String, PathBuf> {
+    for image in images.values_mut() {
+        image.url_name = Url::parse(&image.link).ok().and_then(|url| {
+            let last = url.path_segments()?.next_back()?;
+            (!last.is_empty()).then(|| last.to_string())
+        });
+    }
+
+    let mut saved_paths = HashMap::new();
+    for (name, link, result) in downloads {
+        match result {
+            Ok(downloaded) => {
+                if let Some(image) = images.get_mut(&name) {
+                    image.file_name = downloaded
+                        .path
+                        .file_name()
+                        .map(|f| f.to_string_lossy().into_owned());
+                    image.disposition_name = downloaded.disposition_name;
+                    if let Some(media_type) = downloaded.inline_media_type {
+                        // The whole image is in the link, keep the
+                        // database readable by only recording its type
+                        image.link = format!("data:{}", media_type);
+                        image.inline_bytes = Some(downloaded.bytes);
+                    }
+                }
+                saved_paths.insert(link, downloaded.path);
+            }
+            Err(e) => {
+                let link: String = link.chars().take(100).collect();
+                error!("Could not download image {}, error: {}", link, e)
+            }
+        }
+    }
+
+    saved_paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_images_list_every_page_they_are_on() {
+        let logo = Image {
+            link: String::from("https://example.com/logo.png"),
+            ..Default::default()
+        };
+        let mut links = LinkGraph::default();
+        links
+            .update("https://example.com/a", "", &[], std::slice::from_ref(&logo), &[])
+            .unwrap();
+        links
+            .update("https://example.com/b", "", &[], &[logo], &[])
+            .unwrap();
+
+        let images = convert_links_to_images(&links, ImageDedup::Once, &ImageDomains::default());
+        assert_eq!(images.len(), 1);
+
+        let image = images.values().next().unwrap();
+        let mut pages = image.pages.clone();
+        pages.sort();
+        assert_eq!(pages, vec!["https://example.com/a", "https://example.com/b"]);
+        assert_eq!(image.page_ids[0], links.get(&image.pages[0]).unwrap().id);
+    }
+
+    #[tokio::test]
+    async fn test_image_dedup() {
+        let image = |link: &str| Image {
+            link: link.to_string(),
+            ..Default::default()
+        };
+        let logo = image("data:image/gif;base64,R0lGODlhAQABAAAAACw=");
+        let photo = image("data:image/png;base64,iVBORw0KGgo=");
+        let page = |p: &str| Url::parse(&format!("https://example.com/{}", p)).unwrap();
+
+        let download = |dedup| {
+            let (logo, photo) = (logo.clone(), photo.clone());
+            async move {
+                let directory = std::env::temp_dir().join(format!("image_dedup_{:?}", dedup));
+                let downloader = ImageDownloader::start(
+                    directory.to_str().unwrap(),
+                    DownloadOptions {
+                        max_images: 10,
+                        max_bytes: None,
+                        politeness: Arc::new(Politeness::new(Duration::ZERO)),
+                        images_from: None,
+                        dedup,
+                        domains: ImageDomains::default(),
+                        referer: true,
+                        robots: Arc::default(),
+                    },
+                    Arc::new(AtomicUsize::new(0)),
+                );
+                downloader
+                    .queue(&[logo.clone(), photo.clone()], &page("a"))
+                    .await;
+                downloader
+                    .queue(std::slice::from_ref(&logo), &page("b"))
+                    .await;
+
+                let mut downloads: Vec<(String, String)> = downloader
+                    .finish()
+                    .await
+                    .into_iter()
+                    .map(|(name, link, result)| {
+                        assert!(result.is_ok());
+                        (name, link)
+                    })
+                    .collect();
+                downloads.sort();
+                let logged = std::fs::read_to_string(directory.join(DOWNLOAD_LOG)).unwrap();
+                assert_eq!(logged.lines().count(), downloads.len());
+                let _ = std::fs::remove_dir_all(directory);
+                downloads
+            }
+        };
+
+        let links = |downloads: Vec<(String, String)>| -> Vec<String> {
+            let mut links: Vec<String> = downloads.into_iter().map(|(_, link)| link).collect();
+            links.sort();
+            links
+        };
+        assert_eq!(
+            links(download(ImageDedup::Once).await),
+            [logo.link.as_str(), photo.link.as_str()]
+        );
+        assert_eq!(
+            links(download(ImageDedup::Skip).await),
+            [photo.link.as_str()]
+        );
+
+        let per_page = download(ImageDedup::PerPage).await;
+        assert_eq!(per_page.len(), 3);
+        assert!(per_page.contains(&(
+            ImageDedup::PerPage.image_name(&logo.link, page("b").as_str()),
+            logo.link.clone()
+        )));
+    }
+
+    #[test]
+    fn test_image_domains() {
+        let domains = ImageDomains {
+            allow: vec![String::from("example.com")],
+            block: vec![String::from("ads.example.com")],
+        };
+        assert!(domains.allows("https://example.com/logo.png"));
+        assert!(domains.allows("https://cdn.example.com/photo.jpg"));
+        assert!(!domains.allows("https://ads.example.com/banner.gif"));
+        assert!(!domains.allows("https://tracker.net/pixel.gif"));
+        assert!(domains.allows("data:image/gif;base64,R0lGODlhAQABAAAAACw="));
+        assert!(ImageDomains::default().allows("https://tracker.net/pixel.gif"));
+    }
+
+    #[test]
+    fn test_retain_images_from() {
+        let image = |link: &str, page: &str| Image {
+            link: link.to_string(),
+            pages: vec![page.to_string()],
+            ..Default::default()
+        };
+        let mut images = HashMap::from([
+            (
+                String::from("a"),
+                image("https://example.com/a.png", "https://example.com/gallery/1"),
+            ),
+            (
+                String::from("b"),
+                image(
+                    "https://example.com/b.png",
+                    "https://example.com/blog/gallery",
+                ),
+            ),
+        ]);
+
+        retain_images_from(&mut images, &Regex::new("^/gallery/.*").unwrap());
+        assert_eq!(images.keys().collect::<Vec<_>>(), vec!["a"]);
+    }
+
+    #[test]
+    fn test_sniff_extension() {
+        assert_eq!(sniff_extension(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some("png"));
+        assert_eq!(sniff_extension(b"\xff\xd8\xff\xe0\0\x10JFIF"), Some("jpg"));
+        assert_eq!(sniff_extension(b"GIF89a\x01\0"), Some("gif"));
+        assert_eq!(sniff_extension(b"RIFF\x24\0\0\0WEBPVP8 "), Some("webp"));
+        assert_eq!(sniff_extension(b"\0\0\0\x1cftypavif"), Some("avif"));
+        assert_eq!(
+            sniff_extension(b"<?xml version=\"1.0\"?>\n<svg xmlns="),
+            Some("svg")
+        );
+        assert_eq!(sniff_extension(b"<!DOCTYPE html><html>"), None);
+        assert_eq!(sniff_extension(b""), None);
+    }
+
+    #[test]
+    fn test_decode_data_uri() {
+        let png = decode_data_uri("data:image/png;base64,iVBORw0K\nGgo=").unwrap();
+        assert_eq!(png.media_type, "image/png");
+        assert_eq!(png.data, b"\x89PNG\r\n\x1a\n");
 
-//     #[test]
-//     fn a_unit_test() {
-//         assert!(true);
-//     }
-// }
+        let svg = decode_data_uri("data:image/svg+xml,%3Csvg%3E%3C/svg%3E").unwrap();
+        assert_eq!(svg.media_type, "image/svg+xml");
+        assert_eq!(svg.data, b"<svg></svg>");
+
+        assert!(decode_data_uri("data:image/png;base64").is_err());
+        assert!(decode_data_uri("data:image/png;base64,not base64!").is_err());
+    }
+
+    #[test]
+    fn test_disposition_file_name() {
+        assert_eq!(
+            disposition_file_name(r#"attachment; filename="cat photo.jpg""#).as_deref(),
+            Some("cat photo.jpg")
+        );
+        assert_eq!(
+            disposition_file_name(r#"inline; filename="naive.png"; filename*=UTF-8''na%C3%AFve.png"#)
+                .as_deref(),
+            Some("naïve.png")
+        );
+        assert_eq!(
+            disposition_file_name("attachment; filename=../../etc/passwd").as_deref(),
+            Some("passwd")
+        );
+        assert_eq!(disposition_file_name("inline"), None);
+        assert_eq!(disposition_file_name("attachment; filename=\"\""), None);
+
+        assert_eq!(disposition_save_name("cat.JPEG", "jpg").as_deref(), Some("cat.JPEG"));
+        assert_eq!(disposition_save_name("cat", "png").as_deref(), Some("cat.png"));
+        assert_eq!(disposition_save_name("report.pdf", "png"), None);
+    }
+
+    #[test]
+    fn test_read_download_log() {
+        let log = r#"{"name":"a","link":"https://example.com/a.png","file_name":"a.png","bytes":3}
+{"name":"b","link":"https://example.com/b.png","file_name":"b.png","bytes":3}
+{"file_name":"a.png","removed":"image_hook"}
+"#;
+        let records = read_download_log(log);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["file_name"], "b.png");
+    }
+
+    /// Answers each request with the next of `responses`, the
+    /// head of an HTTP response followed by its body
+    async fn image_server(responses: Vec<Vec<u8>>) -> String {
+        use tokio::io::AsyncReadExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/image", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let _ = stream.read(&mut [0; 4096]).await;
+                let _ = stream.write_all(&response).await;
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_hosts_take_turns() {
+        use tokio::io::AsyncReadExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let server_requests = requests.clone();
+        tokio::spawn(async move {
+            let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0; 4096];
+                let read = stream.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..read]).into_owned();
+                let path = request.split(' ').nth(1).unwrap_or_default().to_string();
+                server_requests.lock().unwrap().push(path);
+                let mut response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: image/png\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    png.len()
+                )
+                .into_bytes();
+                response.extend_from_slice(png);
+                let _ = stream.write_all(&response).await;
+            }
+        });
+
+        let image = |host: &str, name: &str| Image {
+            link: format!("http://{}:{}/{}.png", host, port, name),
+            ..Default::default()
+        };
+        let mut images: Vec<Image> = (0..5).map(|i| image("127.0.0.1", &i.to_string())).collect();
+        images.push(image("localhost", "other-host"));
+
+        let directory = std::env::temp_dir().join(format!("image_turns_{}", Uuid::new_v4()));
+        let downloader = ImageDownloader::start(
+            directory.to_str().unwrap(),
+            DownloadOptions {
+                max_images: 10,
+                max_bytes: None,
+                politeness: Arc::new(Politeness::new(Duration::from_millis(100))),
+                images_from: None,
+                dedup: ImageDedup::Once,
+                domains: ImageDomains::default(),
+                referer: false,
+                robots: Arc::default(),
+            },
+            Arc::new(AtomicUsize::new(0)),
+        );
+        downloader
+            .queue(&images, &Url::parse("https://example.com/").unwrap())
+            .await;
+        let downloads = downloader.finish().await;
+        assert!(downloads.iter().all(|(_, _, result)| result.is_ok()));
+
+        // The other host doesn't wait behind the images queued before it
+        let requests = requests.lock().unwrap().clone();
+        let other_host = requests
+            .iter()
+            .position(|path| path == "/other-host.png")
+            .unwrap();
+        assert!(other_host < 4, "requests: {:?}", requests);
+        let _ = std::fs::remove_dir_all(directory);
+    }
+
+    #[tokio::test]
+    async fn test_downloads_are_written_whole() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        let response = |disposition: &str, length: usize| {
+            let mut response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Disposition: attachment; filename=\"{}\"\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n",
+                disposition, length
+            )
+            .into_bytes();
+            response.extend_from_slice(png);
+            response
+        };
+        let url = image_server(vec![
+            response("logo.png", png.len()),
+            response("logo.png", png.len()),
+            response("logo.pdf", png.len()),
+            // The connection closes before the whole body is sent
+            response("cut.png", png.len() * 2),
+        ])
+        .await;
+
+        let directory = std::env::temp_dir().join(format!("image_download_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let client = Client::new();
+        let download = |name: &str| {
+            let destination = directory.join(name);
+            let (client, url) = (client.clone(), url.clone());
+            async move { try_download_image(&url, &destination, &client, None).await }
+        };
+
+        let first = download("a").await.unwrap();
+        assert_eq!(first.path, directory.join("logo.png"));
+        // The same image again replaces it instead of making a second copy
+        assert_eq!(download("b").await.unwrap().path, directory.join("logo.png"));
+        // The server's name is for some other type of file
+        assert_eq!(download("c").await.unwrap().path, directory.join("c.png"));
+        assert!(download("d").await.is_err());
+
+        let mut files: Vec<String> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["c.png", "logo.png"]);
+        let _ = std::fs::remove_dir_all(directory);
+    }
+
+    #[tokio::test]
+    async fn test_downloads_at_once_claim_different_names() {
+        let response = |pixel: u8| {
+            let mut body = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+            body.push(pixel);
+            let mut response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Disposition: attachment; filename=\"image.png\"\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .into_bytes();
+            response.extend_from_slice(&body);
+            response
+        };
+        let url = image_server(vec![response(1), response(2)]).await;
+
+        let directory = std::env::temp_dir().join(format!("image_download_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let client = Client::new();
+        let (a, b) = (directory.join("a"), directory.join("b"));
+        let (a, b) = tokio::join!(
+            try_download_image(&url, &a, &client, None),
+            try_download_image(&url, &b, &client, None),
+        );
+        let (a, b) = (a.unwrap(), b.unwrap());
+
+        assert_ne!(a.path, b.path);
+        let mut pixels = [a.path, b.path].map(|path| *std::fs::read(path).unwrap().last().unwrap());
+        pixels.sort();
+        assert_eq!(pixels, [1, 2]);
+        let _ = std::fs::remove_dir_all(directory);
+    }
+}
@@ -0,0 +1,192 @@
+use anyhow::{bail, Result};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use sqlx::Row;
+use std::str::FromStr;
+
+use crate::api::{JobState, JobStatus};
+use crate::model::LinkGraph;
+
+/// The schema, one step per version. A database is migrated by running
+/// the steps after the version in its `user_version`
+const MIGRATIONS: [&str; 2] = [
+    "CREATE TABLE jobs (
+        job_id TEXT PRIMARY KEY,
+        url TEXT NOT NULL,
+        status TEXT NOT NULL,
+        pages_crawled INTEGER NOT NULL,
+        pages_attempted INTEGER NOT NULL,
+        images_downloaded INTEGER NOT NULL,
+        started_at TEXT NOT NULL,
+        completed_at TEXT
+    )",
+    "CREATE TABLE job_results (
+        job_id TEXT PRIMARY KEY REFERENCES jobs (job_id),
+        link_graph TEXT NOT NULL,
+        images TEXT NOT NULL
+    )",
+];
+
+/// Where the API server keeps its jobs and their results,
+/// so they're still there after a restart
+#[derive(Clone)]
+pub struct JobStore {
+    pool: SqlitePool,
+}
+
+impl JobStore {
+    /// Opens the SQLite database at `path`, creating or migrating it
+    pub async fn open(path: &str) -> Result<Self> {
+        let options = SqliteConnectOptions::from_str(path)?.create_if_missing(true);
+        // A single connection keeps `sqlite::memory:` databases from splitting
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await?;
+
+        let (version,): (i64,) = sqlx::query_as("PRAGMA user_version")
+            .fetch_one(&pool)
+            .await?;
+        let Ok(applied) = usize::try_from(version) else {
+            bail!("invalid job database version {}", version);
+        };
+        if applied > MIGRATIONS.len() {
+            bail!("the job database was created by a newer version");
+        }
+        for (step, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+            let mut transaction = pool.begin().await?;
+            sqlx::query(migration).execute(&mut *transaction).await?;
+            sqlx::query(&format!("PRAGMA user_version = {}", step + 1))
+                .execute(&mut *transaction)
+                .await?;
+            transaction.commit().await?;
+        }
+
+        Ok(Self { pool })
+    }
+
+    /// Inserts `job`, or updates it if it's stored already
+    pub async fn save_job(&self, job: &JobStatus) -> Result<()> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO jobs (job_id, url, status, pages_crawled,
+                pages_attempted, images_downloaded, started_at, completed_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&job.job_id)
+        .bind(&job.url)
+        .bind(job.status.as_str())
+        .bind(job.pages_crawled as i64)
+        .bind(job.pages_attempted as i64)
+        .bind(job.images_downloaded as i64)
+        .bind(&job.started_at)
+        .bind(&job.completed_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn jobs(&self) -> Result<Vec<JobStatus>> {
+        let rows = sqlx::query("SELECT * FROM jobs ORDER BY started_at")
+            .fetch_all(&self.pool)
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                let status: String = row.try_get("status")?;
+                Ok(JobStatus {
+                    job_id: row.try_get("job_id")?,
+                    url: row.try_get("url")?,
+                    status: JobState::parse(&status)?,
+                    pages_crawled: row.try_get::<i64, _>("pages_crawled")? as usize,
+                    pages_attempted: row.try_get::<i64, _>("pages_attempted")? as usize,
+                    images_downloaded: row.try_get::<i64, _>("images_downloaded")? as usize,
+                    started_at: row.try_get("started_at")?,
+                    completed_at: row.try_get("completed_at")?,
+                })
+            })
+            .collect()
+    }
+
+    /// Stores what a finished job crawled, `images` being
+    /// the records of the images it downloaded
+    pub async fn save_result(
+        &self,
+        job_id: &str,
+        link_graph: &LinkGraph,
+        images: &[serde_json::Value],
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO job_results (job_id, link_graph, images) VALUES (?, ?, ?)",
+        )
+        .bind(job_id)
+        .bind(serde_json::to_string(link_graph)?)
+        .bind(serde_json::to_string(images)?)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The link graph of a finished job
+    pub async fn link_graph(&self, job_id: &str) -> Result<Option<LinkGraph>> {
+        let row: Option<(String,)> =
+            sqlx::query_as("SELECT link_graph FROM job_results WHERE job_id = ?")
+                .bind(job_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        row.map(|(json,)| Ok(serde_json::from_str(&json)?))
+            .transpose()
+    }
+
+    /// The images a finished job downloaded
+    pub async fn images(&self, job_id: &str) -> Result<Option<Vec<serde_json::Value>>> {
+        let row: Option<(String,)> =
+            sqlx::query_as("SELECT images FROM job_results WHERE job_id = ?")
+                .bind(job_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        row.map(|(json,)| Ok(serde_json::from_str(&json)?))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_job_store() {
+        let file = std::env::temp_dir().join(format!("jobs_{}.db", uuid::Uuid::new_v4()));
+        let path = format!("sqlite://{}", file.display());
+        let store = JobStore::open(&path).await.unwrap();
+
+        let mut job = JobStatus {
+            job_id: String::from("job"),
+            url: String::from("https://example.com/"),
+            status: JobState::Running,
+            pages_crawled: 0,
+            pages_attempted: 0,
+            images_downloaded: 0,
+            started_at: chrono::Utc::now().to_rfc3339(),
+            completed_at: None,
+        };
+        store.save_job(&job).await.unwrap();
+        job.status = JobState::Completed;
+        job.pages_crawled = 3;
+        store.save_job(&job).await.unwrap();
+        let images = vec![serde_json::json!({"link": "https://example.com/a.png"})];
+        store
+            .save_result("job", &LinkGraph::default(), &images)
+            .await
+            .unwrap();
+        drop(store);
+
+        // Opening it again doesn't migrate it again
+        let store = JobStore::open(&path).await.unwrap();
+        let jobs = store.jobs().await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert!(matches!(jobs[0].status, JobState::Completed));
+        assert_eq!(jobs[0].pages_crawled, 3);
+        assert!(store.link_graph("job").await.unwrap().is_some());
+        assert_eq!(store.images("job").await.unwrap(), Some(images));
+        assert!(store.link_graph("other").await.unwrap().is_none());
+        let _ = std::fs::remove_file(file);
+    }
+}
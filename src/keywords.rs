@@ -0,0 +1,312 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use tokio::fs;
+
+use crate::model::LinkGraph;
+
+/// The longest phrase kept as a single keyword
+const MAX_PHRASE_WORDS: usize = 4;
+
+/// Common English words that split text into candidate keyword phrases
+const STOPWORDS: &[&str] = &[
+    "a",
+    "about",
+    "above",
+    "after",
+    "again",
+    "against",
+    "all",
+    "also",
+    "am",
+    "an",
+    "and",
+    "any",
+    "are",
+    "as",
+    "at",
+    "be",
+    "because",
+    "been",
+    "before",
+    "being",
+    "below",
+    "between",
+    "both",
+    "but",
+    "by",
+    "can",
+    "could",
+    "did",
+    "do",
+    "does",
+    "doing",
+    "down",
+    "during",
+    "each",
+    "few",
+    "for",
+    "from",
+    "further",
+    "had",
+    "has",
+    "have",
+    "having",
+    "he",
+    "her",
+    "here",
+    "hers",
+    "herself",
+    "him",
+    "himself",
+    "his",
+    "how",
+    "i",
+    "if",
+    "in",
+    "into",
+    "is",
+    "it",
+    "its",
+    "itself",
+    "just",
+    "may",
+    "me",
+    "more",
+    "most",
+    "must",
+    "my",
+    "myself",
+    "no",
+    "nor",
+    "not",
+    "now",
+    "of",
+    "off",
+    "on",
+    "once",
+    "only",
+    "or",
+    "other",
+    "our",
+    "ours",
+    "ourselves",
+    "out",
+    "over",
+    "own",
+    "same",
+    "she",
+    "should",
+    "so",
+    "some",
+    "such",
+    "than",
+    "that",
+    "the",
+    "their",
+    "theirs",
+    "them",
+    "themselves",
+    "then",
+    "there",
+    "these",
+    "they",
+    "this",
+    "those",
+    "through",
+    "to",
+    "too",
+    "under",
+    "until",
+    "up",
+    "us",
+    "very",
+    "was",
+    "we",
+    "were",
+    "what",
+    "when",
+    "where",
+    "which",
+    "while",
+    "who",
+    "whom",
+    "why",
+    "will",
+    "with",
+    "would",
+    "you",
+    "your",
+    "yours",
+    "yourself",
+    "yourselves",
+];
+
+fn is_stopword(word: &str) -> bool {
+    STOPWORDS.contains(&word.to_lowercase().as_str())
+}
+
+/// Splits `text` into sentences, and each sentence into words
+fn sentences(text: &str) -> Vec<Vec<&str>> {
+    text.split(|c: char| {
+        matches!(
+            c,
+            '.' | '!' | '?' | ';' | ':' | ',' | '(' | ')' | '"' | '\n'
+        )
+    })
+    .map(|sentence| {
+        sentence
+            .split(|c: char| !(c.is_alphanumeric() || c == '\'' || c == '-'))
+            .map(|word| word.trim_matches(|c| c == '\'' || c == '-'))
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+    })
+    .filter(|words| !words.is_empty())
+    .collect()
+}
+
+/// The `max` best keyword phrases of `text`, scored with RAKE: each
+/// phrase is a run of words between stopwords, and scores the sum of
+/// its words' degree (how many words they appear next to) over frequency
+pub fn extract_keywords(text: &str, max: usize) -> Vec<String> {
+    let mut phrases: Vec<Vec<String>> = Vec::new();
+    for sentence in sentences(text) {
+        let mut phrase: Vec<String> = Vec::new();
+        for word in sentence {
+            let breaks_phrase = is_stopword(word) || word.chars().all(|c| c.is_numeric());
+            if breaks_phrase || phrase.len() == MAX_PHRASE_WORDS {
+                phrases.extend((!phrase.is_empty()).then(|| std::mem::take(&mut phrase)));
+            }
+            if !breaks_phrase && word.chars().count() > 1 {
+                phrase.push(word.to_lowercase());
+            }
+        }
+        phrases.extend((!phrase.is_empty()).then_some(phrase));
+    }
+
+    let mut frequency: HashMap<&str, f64> = HashMap::new();
+    let mut degree: HashMap<&str, f64> = HashMap::new();
+    for phrase in &phrases {
+        for word in phrase {
+            *frequency.entry(word).or_default() += 1.0;
+            *degree.entry(word).or_default() += phrase.len() as f64;
+        }
+    }
+
+    let mut scores: HashMap<String, f64> = HashMap::new();
+    for phrase in &phrases {
+        let score = phrase
+            .iter()
+            .map(|w| degree[w.as_str()] / frequency[w.as_str()])
+            .sum();
+        scores.insert(phrase.join(" "), score);
+    }
+
+    let mut keywords: Vec<(String, f64)> = scores.into_iter().collect();
+    keywords.sort_by(|(a, a_score), (b, b_score)| b_score.total_cmp(a_score).then(a.cmp(b)));
+    keywords
+        .into_iter()
+        .take(max)
+        .map(|(keyword, _)| keyword)
+        .collect()
+}
+
+/// The `max` most mentioned named entities of `text`, found as runs
+/// of two or more capitalized words, e.g. `New York` or `Rust Foundation`
+pub fn extract_entities(text: &str, max: usize) -> Vec<String> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for sentence in sentences(text) {
+        let mut run: Vec<&str> = Vec::new();
+        for word in sentence.into_iter().chain([""]) {
+            let capitalized = word.chars().next().is_some_and(char::is_uppercase);
+            if capitalized && !(run.is_empty() && is_stopword(word)) {
+                run.push(word);
+                continue;
+            }
+
+            if run.len() > 1 {
+                *counts.entry(run.join(" ")).or_default() += 1;
+            }
+            run.clear();
+        }
+    }
+
+    let mut entities: Vec<(String, usize)> = counts.into_iter().collect();
+    entities.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+    entities
+        .into_iter()
+        .take(max)
+        .map(|(entity, _)| entity)
+        .collect()
+}
+
+#[derive(Serialize)]
+struct TermFrequency {
+    term: String,
+    /// How many pages the term is one of the top terms of
+    pages: usize,
+}
+
+#[derive(Serialize)]
+struct KeywordReport {
+    keywords: Vec<TermFrequency>,
+    entities: Vec<TermFrequency>,
+}
+
+fn term_frequencies<'a>(terms: impl Iterator<Item = &'a String>) -> Vec<TermFrequency> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for term in terms {
+        *counts.entry(term).or_default() += 1;
+    }
+
+    let mut frequencies: Vec<TermFrequency> = counts
+        .into_iter()
+        .map(|(term, pages)| TermFrequency {
+            term: term.to_string(),
+            pages,
+        })
+        .collect();
+    frequencies.sort_by(|a, b| b.pages.cmp(&a.pages).then(a.term.cmp(&b.term)));
+    frequencies
+}
+
+/// Writes how many pages each keyword and entity was found on to `destination`
+pub async fn write_report(links: &LinkGraph, destination: &str) -> Result<()> {
+    let pages: Vec<_> = links.into_iter().map(|(_, link)| link).collect();
+
+    let report = KeywordReport {
+        keywords: term_frequencies(
+            pages
+                .iter()
+                .flat_map(|link| link.keywords.iter().collect::<HashSet<_>>()),
+        ),
+        entities: term_frequencies(
+            pages
+                .iter()
+                .flat_map(|link| link.entities.iter().collect::<HashSet<_>>()),
+        ),
+    };
+
+    fs::write(destination, serde_json::to_string_pretty(&report)?).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_keywords_and_entities() {
+        let text = "The Rust Foundation supports memory safe systems programming. \
+                    Memory safe systems programming is the future, said Jane Doe in New York. \
+                    The Rust Foundation was founded in 2021.";
+
+        let keywords = extract_keywords(text, 2);
+        assert_eq!(keywords[0], "memory safe systems programming");
+
+        assert_eq!(
+            extract_entities(text, 3),
+            vec!["Rust Foundation", "Jane Doe", "New York"]
+        );
+    }
+}
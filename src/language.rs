@@ -0,0 +1,147 @@
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use whatlang::Lang;
+
+use crate::model::LinkGraph;
+
+/// Parses an ISO 639-3 language code, e.g. `eng` or `fra`
+pub fn parse_language(code: &str) -> Result<String> {
+    let code = code.trim().to_lowercase();
+    Lang::from_code(&code)
+        .map(|lang| lang.code().to_string())
+        .ok_or_else(|| {
+            anyhow!(
+                "unknown language code '{}', expected ISO 639-3 (e.g. eng)",
+                code
+            )
+        })
+}
+
+/// ISO 639-1 codes, as used in `<html lang>`, of the languages that can be
+/// detected, with their ISO 639-3 codes
+const ISO_639_1_CODES: [(&str, &str); 70] = [
+    ("af", "afr"),
+    ("ak", "aka"),
+    ("am", "amh"),
+    ("ar", "ara"),
+    ("az", "aze"),
+    ("be", "bel"),
+    ("bg", "bul"),
+    ("bn", "ben"),
+    ("ca", "cat"),
+    ("cs", "ces"),
+    ("cy", "cym"),
+    ("da", "dan"),
+    ("de", "deu"),
+    ("el", "ell"),
+    ("en", "eng"),
+    ("eo", "epo"),
+    ("es", "spa"),
+    ("et", "est"),
+    ("fa", "pes"),
+    ("fi", "fin"),
+    ("fr", "fra"),
+    ("gu", "guj"),
+    ("he", "heb"),
+    ("hi", "hin"),
+    ("hr", "hrv"),
+    ("hu", "hun"),
+    ("hy", "hye"),
+    ("id", "ind"),
+    ("it", "ita"),
+    ("ja", "jpn"),
+    ("jv", "jav"),
+    ("ka", "kat"),
+    ("km", "khm"),
+    ("kn", "kan"),
+    ("ko", "kor"),
+    ("la", "lat"),
+    ("lt", "lit"),
+    ("lv", "lav"),
+    ("mk", "mkd"),
+    ("ml", "mal"),
+    ("mr", "mar"),
+    ("my", "mya"),
+    ("nb", "nob"),
+    ("ne", "nep"),
+    ("nl", "nld"),
+    ("no", "nob"),
+    ("or", "ori"),
+    ("pa", "pan"),
+    ("pl", "pol"),
+    ("pt", "por"),
+    ("ro", "ron"),
+    ("ru", "rus"),
+    ("si", "sin"),
+    ("sk", "slk"),
+    ("sl", "slv"),
+    ("sn", "sna"),
+    ("sr", "srp"),
+    ("sv", "swe"),
+    ("ta", "tam"),
+    ("te", "tel"),
+    ("th", "tha"),
+    ("tk", "tuk"),
+    ("tl", "tgl"),
+    ("tr", "tur"),
+    ("uk", "ukr"),
+    ("ur", "urd"),
+    ("uz", "uzb"),
+    ("vi", "vie"),
+    ("yi", "yid"),
+    ("zh", "cmn"),
+];
+
+/// The ISO 639-3 code of the language in an `<html lang>` attribute,
+/// e.g. `eng` for `en-GB`. `None` for languages that can't be detected
+/// either, so pages in them are treated the same whichever way they're found
+pub fn parse_html_lang(lang: &str) -> Option<String> {
+    let primary = lang.trim().split(['-', '_']).next()?.to_lowercase();
+    match primary.len() {
+        2 => ISO_639_1_CODES
+            .iter()
+            .find(|(code, _)| *code == primary)
+            .map(|(_, code)| code.to_string()),
+        _ => Lang::from_code(&primary).map(|lang| lang.code().to_string()),
+    }
+}
+
+/// The ISO 639-3 code of the language `text` is written in.
+/// `None` when there isn't enough text to tell reliably.
+pub fn detect_language(text: &str) -> Option<String> {
+    let info = whatlang::detect(text)?;
+    info.is_reliable().then(|| info.lang().code().to_string())
+}
+
+/// How many pages were found in each language
+pub fn language_counts(links: &LinkGraph) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for (_, link) in links {
+        if let Some(lang) = &link.lang {
+            *counts.entry(lang.clone()).or_default() += 1;
+        }
+    }
+
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_language() {
+        let text = "Le chat est assis sur le tapis et regarde les oiseaux qui chantent \
+                    dans le jardin pendant que le soleil se couche derrière les collines.";
+
+        assert_eq!(detect_language(text).as_deref(), Some("fra"));
+        assert_eq!(parse_language(" ENG ").unwrap(), "eng");
+        assert!(parse_language("english").is_err());
+
+        assert_eq!(parse_html_lang("en-GB").as_deref(), Some("eng"));
+        assert_eq!(parse_html_lang("zh_Hant").as_deref(), Some("cmn"));
+        assert_eq!(parse_html_lang("FRA").as_deref(), Some("fra"));
+        assert_eq!(parse_html_lang("gd"), None);
+        assert_eq!(parse_html_lang(""), None);
+    }
+}
@@ -0,0 +1,77 @@
+//! HyperCrawl crawls a site from a starting url, building a graph of the
+//! pages it finds and downloading their images. The `rust_crawler` binary
+//! is a command line interface over this library.
+//!
+//! ```no_run
+//! # async fn crawl() -> anyhow::Result<()> {
+//! use rust_crawler::Crawler;
+//!
+//! let link_graph = Crawler::builder()
+//!     .start_url("https://example.com/")
+//!     .max_links(50)
+//!     .run()
+//!     .await?;
+//! for (_, link) in &link_graph {
+//!     println!("{} {:?}", link.url, link.title);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A [`CrawlHandle`] runs the crawl in the background to follow its
+//! progress, pause or cancel it. Single pages can be scraped without
+//! crawling with [`crawler::scrape_page`].
+
+pub mod analysis;
+pub mod api;
+pub mod archive;
+pub mod auth;
+pub mod blocked;
+pub mod browser_session;
+pub mod caching;
+pub mod client_redirect;
+pub mod compression;
+pub mod content;
+pub mod corpus;
+pub mod crawler;
+pub mod dedup;
+#[cfg(feature = "embeddings")]
+pub mod embeddings;
+pub mod external_links;
+pub mod file_names;
+pub mod frontier;
+pub mod handle;
+pub mod har;
+pub mod host_report;
+pub mod html_stream;
+#[cfg(feature = "http3")]
+pub mod http3;
+pub mod image_hook;
+pub mod image_utils;
+pub mod job_store;
+pub mod keywords;
+pub mod language;
+pub mod link_scope;
+pub mod link_sink;
+pub mod memory;
+pub mod mirror;
+pub mod model;
+pub mod oauth2;
+pub mod output;
+pub mod politeness;
+pub mod profiles;
+pub mod robots;
+pub mod seed_check;
+pub mod session;
+pub mod sitemap;
+pub mod skipped;
+pub mod stats;
+pub mod stealth;
+pub mod technologies;
+pub mod titles;
+pub mod url_utils;
+pub mod worker;
+
+pub use crawler::{Crawler, CrawlerBuilder};
+pub use handle::CrawlHandle;
+pub use model::LinkGraph;
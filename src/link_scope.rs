@@ -0,0 +1,99 @@
+use anyhow::{anyhow, Result};
+use scraper::{ElementRef, Selector};
+use std::fmt;
+
+/// A CSS selector, kept with how it was written
+#[derive(Clone, Debug)]
+pub struct CssSelector {
+    source: String,
+    selector: Selector,
+}
+
+impl CssSelector {
+    pub fn parse(source: &str) -> Result<Self> {
+        let selector = Selector::parse(source)
+            .map_err(|e| anyhow!("invalid CSS selector '{}': {}", source, e))?;
+        Ok(Self {
+            source: source.trim().to_string(),
+            selector,
+        })
+    }
+
+    /// Whether `element` or anything it's inside of matches
+    fn encloses(&self, element: ElementRef) -> bool {
+        std::iter::once(element)
+            .chain(element.ancestors().filter_map(ElementRef::wrap))
+            .any(|element| self.selector.matches(&element))
+    }
+}
+
+impl fmt::Display for CssSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// Which parts of a page links are taken from, so the navigation
+/// and footer that are on every page don't add the same links to
+/// the graph again and again
+#[derive(Clone, Debug, Default)]
+pub struct LinkScope {
+    /// Only links inside these, e.g. `main, article`
+    pub links_from: Option<CssSelector>,
+    /// No links inside these, e.g. `nav, footer`
+    pub ignore: Option<CssSelector>,
+}
+
+impl LinkScope {
+    pub fn is_empty(&self) -> bool {
+        self.links_from.is_none() && self.ignore.is_none()
+    }
+
+    /// Whether the link `anchor` is in scope
+    pub fn contains(&self, anchor: ElementRef) -> bool {
+        self.links_from
+            .as_ref()
+            .is_none_or(|links_from| links_from.encloses(anchor))
+            && !self
+                .ignore
+                .as_ref()
+                .is_some_and(|ignore| ignore.encloses(anchor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scraper::Html;
+
+    #[test]
+    fn test_link_scope() {
+        let html = Html::parse_document(
+            r#"<nav><a href="/home">Home</a></nav>
+            <main><a href="/post">Post</a><nav><a href="/next">Next</a></nav></main>
+            <footer><a href="/about">About</a></footer>"#,
+        );
+        let in_scope = |scope: &LinkScope| -> Vec<String> {
+            let anchors = Selector::parse("a").unwrap();
+            html.select(&anchors)
+                .filter(|anchor| scope.contains(*anchor))
+                .filter_map(|anchor| anchor.value().attr("href"))
+                .map(str::to_string)
+                .collect()
+        };
+
+        assert_eq!(in_scope(&LinkScope::default()).len(), 4);
+        let scope = LinkScope {
+            links_from: Some(CssSelector::parse("main, article").unwrap()),
+            ignore: None,
+        };
+        assert_eq!(in_scope(&scope), vec!["/post", "/next"]);
+        let scope = LinkScope {
+            links_from: None,
+            ignore: Some(CssSelector::parse("nav, footer").unwrap()),
+        };
+        assert_eq!(in_scope(&scope), vec!["/post"]);
+
+        assert!(CssSelector::parse("main >").is_err());
+    }
+}
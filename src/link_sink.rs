@@ -0,0 +1,41 @@
+use anyhow::Result;
+use serde::Serialize;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+use crate::model::{LinkContent, LinkId};
+
+/// A visited page as written to the link stream. Children are
+/// stored as urls since most of them won't have an id yet.
+#[derive(Serialize)]
+pub struct StreamedLink<'a> {
+    pub id: LinkId,
+    pub url: &'a str,
+    pub parent: &'a str,
+    pub children: &'a [String],
+    #[serde(flatten)]
+    pub content: LinkContent,
+}
+
+/// Appends every visited page to a JSON lines file as soon as
+/// it's crawled, so the results survive the crawl crashing
+pub struct LinkSink {
+    file: File,
+}
+
+impl LinkSink {
+    pub async fn create(path: &str) -> Result<Self> {
+        Ok(Self {
+            file: File::create(path).await?,
+        })
+    }
+
+    pub async fn write(&mut self, link: &StreamedLink<'_>) -> Result<()> {
+        // Write whole lines at a time so a crash never leaves half a record
+        let mut line = serde_json::to_vec(link)?;
+        line.push(b'\n');
+        self.file.write_all(&line).await?;
+        self.file.flush().await?;
+        Ok(())
+    }
+}
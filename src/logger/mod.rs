@@ -1,3 +1,27 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 
 pub mod progress_bar;
+pub mod reporter;
 pub mod spinner;
+
+static EMOJI_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Applies `--no-emoji` and the `NO_COLOR` convention to everything printed
+pub fn configure_output(no_emoji: bool) {
+    if no_emoji {
+        EMOJI_ENABLED.store(false, Ordering::Relaxed);
+    }
+
+    if std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+        console::set_colors_enabled(false);
+        console::set_colors_enabled_stderr(false);
+    }
+}
+
+/// `emoji` where the terminal supports it, unless emoji are turned off
+pub fn emoji(emoji: &'static str, fallback: &'static str) -> console::Emoji<'static, 'static> {
+    match EMOJI_ENABLED.load(Ordering::Relaxed) {
+        true => console::Emoji(emoji, fallback),
+        false => console::Emoji(fallback, fallback),
+    }
+}
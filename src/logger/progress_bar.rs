@@ -1,5 +1,8 @@
 use std::borrow::Cow;
 
+/// How many characters wide the segmented bar is
+const BAR_WIDTH: usize = 40;
+
 pub struct ProgressBar {
     bar: indicatif::ProgressBar,
 }
@@ -10,19 +13,63 @@ impl ProgressBar {
 
         bar.set_style(
             indicatif::ProgressStyle::with_template(
-                "{msg}\n[{elapsed}] {bar:40.white} {pos:>7}/{len:7}",
+                "{msg}\n[{elapsed}] {prefix} {pos:>7}/{len:7}",
             )
             .unwrap(),
         );
+        bar.set_prefix(segmented_bar(0, 0, 0, total_steps));
 
         ProgressBar { bar }
     }
 
-    pub fn set_step(&self, step: u64) {
-        self.bar.set_position(step);
+    /// Shows how many pages are done, how many are being fetched
+    /// and how many more are queued, as segments of one bar
+    pub fn set_segments(&self, completed: u64, in_flight: u64, queued: u64) {
+        let total = self.bar.length().unwrap_or(0);
+        self.bar
+            .set_prefix(segmented_bar(completed, in_flight, queued, total));
+        self.bar.set_position(completed);
     }
 
     pub fn message(&self, msg: impl Into<Cow<'static, str>>) {
         self.bar.set_message(msg)
     }
 }
+
+/// `█` for completed steps, `▓` in flight and `░` queued, scaled to `total`
+fn segmented_bar(completed: u64, in_flight: u64, queued: u64, total: u64) -> String {
+    let scale = |steps: u64| match total {
+        0 => 0,
+        total => ((steps.min(total) as f64 / total as f64) * BAR_WIDTH as f64).round() as usize,
+    };
+
+    // Each segment ends where the running total does, so
+    // rounding never makes the bar wider than BAR_WIDTH
+    let completed_end = scale(completed);
+    let in_flight_end = scale(completed + in_flight).max(completed_end);
+    let queued_end = scale(completed + in_flight + queued).max(in_flight_end);
+
+    format!(
+        "{}{}{}{}",
+        "█".repeat(completed_end),
+        "▓".repeat(in_flight_end - completed_end),
+        "░".repeat(queued_end - in_flight_end),
+        " ".repeat(BAR_WIDTH - queued_end)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segmented_bar() {
+        let bar = segmented_bar(10, 5, 100, 40);
+        assert_eq!(bar.chars().count(), BAR_WIDTH);
+        assert_eq!(bar.chars().filter(|c| *c == '█').count(), 10);
+        assert_eq!(bar.chars().filter(|c| *c == '▓').count(), 5);
+        assert_eq!(bar.chars().filter(|c| *c == '░').count(), 25);
+
+        assert_eq!(segmented_bar(0, 0, 0, 0), " ".repeat(BAR_WIDTH));
+    }
+}
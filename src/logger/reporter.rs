@@ -0,0 +1,150 @@
+use clap::ValueEnum;
+use serde_json::json;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use super::progress_bar::ProgressBar;
+use super::spinner::{Colour, Spinner};
+use rust_crawler::stats::CrawlStats;
+
+/// How often the plain reporter prints crawl progress
+const PLAIN_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Where crawl progress and the steps after the crawl are reported
+pub trait ProgressReporter: Send + Sync {
+    /// Starts reporting crawl progress towards `total` pages
+    fn start_progress(&self, total: u64);
+    fn progress(&self, stats: &CrawlStats);
+    /// What is being worked on right now
+    fn status(&self, msg: &str);
+    /// A line that stays in the output, e.g. a finished step
+    fn print_above(&self, msg: &str, colour: Colour);
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ProgressMode {
+    /// Progress bars on a terminal, plain lines otherwise
+    #[default]
+    Auto,
+    /// Plain lines of text, safe for CI logs
+    Plain,
+    /// One JSON object per line
+    Json,
+    /// No progress output at all
+    None,
+}
+
+pub fn create_reporter(mode: ProgressMode) -> Arc<dyn ProgressReporter> {
+    match mode {
+        ProgressMode::Auto if console::Term::stdout().is_term() => Arc::new(TtyReporter::default()),
+        ProgressMode::Auto | ProgressMode::Plain => Arc::new(PlainReporter::default()),
+        ProgressMode::Json => Arc::new(JsonReporter),
+        ProgressMode::None => Arc::new(SilentReporter),
+    }
+}
+
+/// Animated progress bar and spinner
+#[derive(Default)]
+pub struct TtyReporter {
+    progress_bar: OnceLock<ProgressBar>,
+    // Created on first use, the spinner starts ticking as soon as it exists
+    spinner: OnceLock<Spinner>,
+}
+
+impl ProgressReporter for TtyReporter {
+    fn start_progress(&self, total: u64) {
+        let progress_bar = self.progress_bar.get_or_init(|| ProgressBar::new(total));
+        progress_bar.message("Finding links");
+    }
+
+    fn progress(&self, stats: &CrawlStats) {
+        let Some(progress_bar) = self.progress_bar.get() else {
+            return;
+        };
+
+        progress_bar.set_segments(
+            stats.crawled as u64,
+            stats.in_flight as u64,
+            stats.queued as u64,
+        );
+        progress_bar.message(format!(
+            "Finding links ({} discovered, {})",
+            stats.discovered, stats
+        ));
+    }
+
+    fn status(&self, msg: &str) {
+        self.spinner
+            .get_or_init(Spinner::new)
+            .status(msg.to_string());
+    }
+
+    fn print_above(&self, msg: &str, colour: Colour) {
+        self.spinner
+            .get_or_init(Spinner::new)
+            .print_above(msg, colour);
+    }
+}
+
+/// Lines of plain text, progress at most every few seconds
+#[derive(Default)]
+pub struct PlainReporter {
+    last_progress: Mutex<Option<Instant>>,
+}
+
+impl ProgressReporter for PlainReporter {
+    fn start_progress(&self, total: u64) {
+        println!("crawling up to {} pages", total);
+    }
+
+    fn progress(&self, stats: &CrawlStats) {
+        let mut last_progress = self.last_progress.lock().unwrap_or_else(|e| e.into_inner());
+        if last_progress.is_some_and(|last| last.elapsed() < PLAIN_PROGRESS_INTERVAL) {
+            return;
+        }
+
+        *last_progress = Some(Instant::now());
+        println!(
+            "crawled {} pages ({} discovered, {})",
+            stats.crawled, stats.discovered, stats
+        );
+    }
+
+    fn status(&self, msg: &str) {
+        println!("{}", msg.trim());
+    }
+
+    fn print_above(&self, msg: &str, _colour: Colour) {
+        println!("{}", msg.trim());
+    }
+}
+
+/// One JSON object per line, for other programs to read
+pub struct JsonReporter;
+
+impl ProgressReporter for JsonReporter {
+    fn start_progress(&self, total: u64) {
+        println!("{}", json!({ "event": "start", "max_links": total }));
+    }
+
+    fn progress(&self, stats: &CrawlStats) {
+        println!("{}", json!({ "event": "progress", "stats": stats }));
+    }
+
+    fn status(&self, msg: &str) {
+        println!("{}", json!({ "event": "status", "message": msg.trim() }));
+    }
+
+    fn print_above(&self, msg: &str, _colour: Colour) {
+        println!("{}", json!({ "event": "done", "message": msg.trim() }));
+    }
+}
+
+pub struct SilentReporter;
+
+impl ProgressReporter for SilentReporter {
+    fn start_progress(&self, _total: u64) {}
+    fn progress(&self, _stats: &CrawlStats) {}
+    fn status(&self, _msg: &str) {}
+    fn print_above(&self, _msg: &str, _colour: Colour) {}
+}
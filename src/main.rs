@@ -1,37 +1,107 @@
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{Context, Result};
+use clap::{CommandFactory, Parser, Subcommand};
 use log2::*;
 use logger::spinner::Colour;
-use model::LinkGraph;
-use std::{collections::VecDeque, process, sync::Arc, sync::atomic::{AtomicUsize, Ordering}, time::Duration};
-use tokio::{fs, sync::RwLock, task::JoinSet};
+use regex::Regex;
+use std::{collections::{BTreeMap, HashMap}, ops::RangeInclusive, path::{Path, PathBuf}, process, sync::Arc, sync::atomic::Ordering, time::Duration};
+use tokio::{fs, task::JoinSet};
 use url::Url;
 
-mod crawler;
-mod image_utils;
+mod commands;
+mod config;
 mod logger;
-mod model;
-use crawler::{scrape_page, CrawlerStateRef, LinkPath, ScrapeOption};
+use rust_crawler::{analysis, auth, crawler, keywords, language, memory, mirror, model, profiles, seed_check, sitemap, stealth, technologies, url_utils};
+#[cfg(feature = "embeddings")]
+use rust_crawler::embeddings;
+use crawler::{Crawler, CrawlerBuilder, CrawlerStateRef, LinkPath};
+use model::NodeKind;
+use url_utils::{in_path_prefixes, normalize_url, path_patterns_allow, HostNormalization, NormalizeOptions, PathPattern, PortPolicy};
+use rust_crawler::dedup::DedupKey;
+use rust_crawler::link_scope::{CssSelector, LinkScope};
+use rust_crawler::worker::{crawl, page_client, record_skipped};
+use logger::reporter::{ProgressMode, ProgressReporter};
 
-use crate::{
-    crawler::CrawlerState,
-    image_utils::{convert_links_to_images, download_images},
+use rust_crawler::{
+    archive::ArchiveFormat,
+    corpus::{CorpusFormat, CorpusWriter, PageExport},
+    frontier::{Schedule, SectionBudget},
+    output::{serialize_links, serialize_links_by_host, SplitOutput},
+    link_sink::LinkSink,
+    skipped::{SkipReason, SkippedLog},
+    host_report::SUMMARY_HOSTS,
+    image_hook::{run_image_hook, CommandHook},
+    image_utils::{convert_links_to_images, record_downloads, retain_images_from, ImageDedup, ImageDomains, RobotsDisallowed},
+    session::Session,
+    stealth::Stealth,
+    auth::{Auth, Credentials},
+    oauth2::{OAuth2, OAuth2Config},
+    browser_session::BrowserSession,
+    politeness::{parse_requests_per_second, Politeness},
+    profiles::{Profile, ProfileComparison},
+    stats::CrawlStats,
+    titles::TitleReport,
 };
 
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[derive(Parser, Clone, Debug)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 struct ProgramArgs {
-    #[arg(short, long)]
-    starting_url: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Read options from this TOML file, with a key for every long option
+    /// (e.g. `n-worker-threads = 8`). Options given here win over the file's.
+    /// hypercrawl.toml is read when it's in the current directory
+    #[arg(long)]
+    config: Option<String>,
+
+    #[arg(short, long, required = true)]
+    starting_url: Option<String>,
 
     /// Maximum links to find
     #[arg(long, default_value_t = 100)]
     max_links: u64,
 
+    /// Don't follow links further than this many clicks from the starting url,
+    /// 0 only crawls the starting url (and sitemap pages). A page's clicks are
+    /// counted along the shortest path found before it was crawled, which
+    /// can be longer than its `depth` in links.json, worked out after the crawl
+    #[arg(long)]
+    max_depth: Option<usize>,
+
     /// Max images
     #[arg(long, default_value_t = 100)]
     max_images: u64,
 
+    /// What to do with images found on more than one page
+    #[arg(long, value_enum, default_value_t = ImageDedup::Once)]
+    image_dedup: ImageDedup,
+
+    /// Only download images found on pages whose path matches
+    /// this regex (e.g. "/gallery/.*"). Every page is still crawled
+    #[arg(long)]
+    images_from: Option<Regex>,
+
+    /// A shell command to run on every downloaded image, e.g. to classify or upload it.
+    /// It gets the image's path as $1 and its metadata as JSON on stdin. Images it
+    /// exits unsuccessfully for are deleted, what it prints is saved as their label
+    #[arg(long)]
+    image_hook: Option<String>,
+
+    /// Don't send the page an image is on as the Referer when downloading it.
+    /// Some CDNs refuse images requested without one
+    #[arg(long, default_value_t = false)]
+    no_image_referer: bool,
+
+    /// Only download images on these domains and their subdomains,
+    /// e.g. example.com,examplecdn.net to leave out third-party images
+    #[arg(long, value_delimiter = ',')]
+    image_domains: Vec<String>,
+
+    /// Don't download images on these domains and their subdomains,
+    /// e.g. ad servers. Wins over --image-domains
+    #[arg(long, value_delimiter = ',')]
+    block_image_domains: Vec<String>,
+
     /// Number of worker threads
     #[arg(short, long, default_value_t = 4)]
     n_worker_threads: u64,
@@ -40,6 +110,18 @@ struct ProgramArgs {
     #[arg(short, long, default_value_t = false)]
     log_status: bool,
 
+    /// How progress is shown: bars on a terminal, plain lines, JSON lines or not at all
+    #[arg(long, value_enum, default_value_t = ProgressMode::Auto)]
+    progress: ProgressMode,
+
+    /// Print nothing but errors, e.g. when running from cron
+    #[arg(short, long, global = true, default_value_t = false)]
+    quiet: bool,
+
+    /// Print plain text instead of emoji. Colours are turned off by setting NO_COLOR.
+    #[arg(long, global = true, default_value_t = false)]
+    no_emoji: bool,
+
     /// The directory to save all the images scraped
     #[arg(short, long, default_value_t = String::from("images/"))]
     img_save_dir: String,
@@ -47,223 +129,1064 @@ struct ProgramArgs {
     /// The file to save the link information to
     #[arg(long, default_value_t = String::from("links.json"))]
     links_json: String,
+
+    /// The CSV file to save the pages, errors, bytes and latency of each host to
+    #[arg(long, default_value_t = String::from("hosts.csv"))]
+    hosts_csv: String,
+
+    /// The CSV file to save links to other sites to, with the pages linking to them
+    #[arg(long, default_value_t = String::from("external_links.csv"))]
+    external_links_csv: String,
+
+    /// The JSON lines file to save every url that wasn't crawled or downloaded to, with why
+    #[arg(long, default_value_t = String::from("skipped.jsonl"))]
+    skipped_jsonl: String,
+
+    /// The CSV file to save pages without a title, or with the same title as other pages, to
+    #[arg(long, default_value_t = String::from("title_issues.csv"))]
+    title_issues_csv: String,
+
+    /// Don't seed the crawl with the sitemaps listed in robots.txt
+    #[arg(long, default_value_t = false)]
+    no_sitemap_seeding: bool,
+
+    /// Also visit the site root and every directory above the starting url,
+    /// for starting urls deep in a site that link to few other pages
+    #[arg(long, default_value_t = false)]
+    seed_ancestors: bool,
+
+    /// Start crawling without first checking that the starting url
+    /// can be fetched, isn't disallowed by robots.txt and is HTML
+    #[arg(long, default_value_t = false)]
+    no_seed_check: bool,
+
+    /// Rewrite http links to https when the starting url uses https
+    #[arg(long, default_value_t = false)]
+    upgrade_http: bool,
+
+    /// How to treat `www.` hosts when deduplicating and filtering
+    /// links. Links are still fetched with the host they're written with
+    #[arg(long, value_enum, default_value_t = HostNormalization::Keep)]
+    normalize_host: HostNormalization,
+
+    /// Treat `/dir/` and `/dir/index.html` as the same url
+    #[arg(long, default_value_t = false)]
+    collapse_index_paths: bool,
+
+    /// Treat `/dir` and `/dir/` as the same url, for sites that
+    /// serve the same page for both. Paths with an extension are kept.
+    #[arg(long, default_value_t = false)]
+    add_trailing_slash: bool,
+
+    /// Whether other ports on the starting host are part of the site
+    #[arg(long, value_enum, default_value_t = PortPolicy::Auto)]
+    port_policy: PortPolicy,
+
+    /// Only follow links to urls whose path starts with this, e.g. /docs/.
+    /// Give it more than once to crawl several sections
+    #[arg(long = "path-prefix", value_parser = url_utils::parse_path_prefix)]
+    path_prefixes: Vec<String>,
+
+    /// Only follow links to urls whose path matches this, as a glob like /blog/**
+    /// or a regex after re:. Give it more than once to match any of several
+    #[arg(long = "include-pattern", value_parser = PathPattern::parse)]
+    include_patterns: Vec<PathPattern>,
+
+    /// Don't follow links to urls whose path matches this, e.g. /tag/*.
+    /// Wins over --include-pattern
+    #[arg(long = "exclude-pattern", value_parser = PathPattern::parse)]
+    exclude_patterns: Vec<PathPattern>,
+
+    /// Only take links from inside these parts of each page, as a CSS
+    /// selector like "main, article"
+    #[arg(long, value_parser = CssSelector::parse)]
+    links_from: Option<CssSelector>,
+
+    /// Leave out the links inside these parts of each page, as a CSS
+    /// selector like "nav, footer". Wins over --links-from
+    #[arg(long, value_parser = CssSelector::parse)]
+    ignore_links_in: Option<CssSelector>,
+
+    /// Which urls are the same page: url, path to ignore query strings, or
+    /// canonical to also treat pages as their rel="canonical" url
+    #[arg(long, default_value = "url", value_parser = DedupKey::parse)]
+    dedup_key: DedupKey,
+
+    /// The order queued links are crawled in, taking turns between hosts or
+    /// sections keeps a large one from using up --max-links on its own
+    #[arg(long, value_enum, default_value_t = Schedule::Queue)]
+    schedule: Schedule,
+
+    /// Split --max-links between sections of the site, e.g. "/blog=40%,/docs=40%,*=20%"
+    /// where * is every other page. The sections take turns, pages in none are skipped
+    #[arg(long, value_parser = SectionBudget::parse)]
+    budget: Option<SectionBudget>,
+
+    /// Don't follow links marked rel="nofollow", or any links on pages whose robots
+    /// meta tag says nofollow. They're still recorded on the page linking to them
+    #[arg(long, default_value_t = false)]
+    respect_nofollow: bool,
+
+    /// Don't crawl pages robots.txt disallows, they're logged as skipped.
+    /// Images are always checked against robots.txt
+    #[arg(long, default_value_t = false)]
+    respect_robots: bool,
+
+    /// Maximum number of links to take from a single page
+    #[arg(long)]
+    max_links_per_page: Option<usize>,
+
+    /// Approximate memory the crawl may use for queued and found
+    /// links (e.g. 512M, 2G). Queued links spill to disk past this
+    #[arg(long, value_parser = memory::parse_byte_size)]
+    max_memory: Option<usize>,
+
+    /// Stop downloading pages and images once this much has been
+    /// downloaded in total (e.g. 500M, 10G)
+    #[arg(long, value_parser = memory::parse_byte_size)]
+    max_download_bytes: Option<usize>,
+
+    /// Ask for pages uncompressed, e.g. to debug what a server sends
+    #[arg(long, default_value_t = false)]
+    no_compression: bool,
+
+    /// Fetch pages over HTTP/3 from hosts that advertise it,
+    /// falling back to HTTP/1.1 or HTTP/2 when it fails
+    #[cfg(feature = "http3")]
+    #[arg(long, default_value_t = false)]
+    http3: bool,
+
+    /// Make page requests look like they come from a browser: rotate
+    /// user agents, send browser headers and wait a random time first
+    #[arg(long, default_value_t = false)]
+    stealth: bool,
+
+    /// The user agents to rotate between with --stealth, one per line.
+    /// Common browsers are used without it
+    #[arg(long, requires = "stealth")]
+    user_agents_file: Option<String>,
+
+    /// How long to wait before each page request with --stealth, in
+    /// milliseconds, e.g. 500-2000
+    #[arg(long, requires = "stealth", value_parser = stealth::parse_delay_range, default_value = "250-1500")]
+    stealth_delay_ms: RangeInclusive<u64>,
+
+    /// Log in to the site with HTTP basic auth, as user:pass
+    #[arg(long, value_parser = auth::parse_basic_auth)]
+    basic_auth: Option<Credentials>,
+
+    /// Send this token as `Authorization: Bearer <token>` to the site
+    #[arg(long, conflicts_with = "basic_auth")]
+    bearer_token: Option<String>,
+
+    /// Credentials for specific hosts, one per line as `<host> basic <user>:<pass>`
+    /// or `<host> bearer <token>`. They're used instead of --basic-auth or --bearer-token
+    #[arg(long)]
+    credentials_file: Option<String>,
+
+    /// Get an OAuth2 access token from this url and send it with every page request.
+    /// It's refreshed before it expires
+    #[arg(long, requires = "oauth2_client_id", conflicts_with_all = ["basic_auth", "bearer_token", "credentials_file"])]
+    oauth2_token_url: Option<String>,
+
+    /// The client id to get OAuth2 tokens with
+    #[arg(long, requires = "oauth2_token_url")]
+    oauth2_client_id: Option<String>,
+
+    /// The client secret to get OAuth2 tokens with
+    #[arg(long, requires = "oauth2_token_url")]
+    oauth2_client_secret: Option<String>,
+
+    /// Get tokens with this refresh token instead of the client credentials
+    #[arg(long, requires = "oauth2_token_url")]
+    oauth2_refresh_token: Option<String>,
+
+    /// The scopes to ask OAuth2 tokens for, space separated
+    #[arg(long, requires = "oauth2_token_url")]
+    oauth2_scope: Option<String>,
+
+    /// Crawl as a logged in user with the cookies and headers from a browser,
+    /// saved as a HAR file from its dev tools or as a Netscape cookies.txt
+    #[arg(long)]
+    import_session: Option<String>,
+
+    /// Milliseconds to wait between page requests to the same host.
+    /// Requests to different hosts don't wait for each other
+    #[arg(long, default_value_t = 500)]
+    delay_ms: u64,
+
+    /// The most page requests to send to one host a second, e.g. 0.5
+    /// for one every two seconds. Waits longer than --delay-ms if needed
+    #[arg(long, value_parser = parse_requests_per_second)]
+    max_requests_per_second: Option<f64>,
+
+    /// A TOML file of delay_ms, concurrency and headers overrides by host pattern
+    /// (`example.com`, `*.example.com` or `*`), e.g. to crawl your own site fast
+    /// but third-party subdomains slowly. Concurrency can't go above --n-worker-threads
+    #[arg(long)]
+    politeness: Option<String>,
+
+    /// Crawl the site once as each of these users and compare the pages each of them
+    /// could see, written as `name` for an anonymous user or as `name=<session file>`
+    /// with a file like --import-session takes. Give it once for every user
+    #[arg(long = "profile", value_parser = profiles::parse_profile, conflicts_with_all = ["import_session", "record", "replay"])]
+    profiles: Vec<Profile>,
+
+    /// The directory the results of each profile are saved to, in a directory named after it
+    #[arg(long, default_value_t = String::from("profiles/"))]
+    profiles_dir: String,
+
+    /// The CSV file to save which pages each profile could see to
+    #[arg(long, default_value_t = String::from("access.csv"))]
+    access_csv: String,
+
+    /// Append every page to this JSON lines file as it's crawled. Page
+    /// headings, images and the rest of what's scraped from pages are
+    /// then only kept there, the final links file only has the links
+    #[arg(long)]
+    stream_links: Option<String>,
+
+    /// Split the links file up, the links file then holds an index of the parts
+    #[arg(long, value_enum)]
+    split_output: Option<SplitOutput>,
+
+    /// Save every page to this directory with links rewritten to
+    /// point at the saved pages and images, for offline browsing
+    #[arg(long)]
+    mirror: Option<String>,
+
+    /// Save a self contained archive of every page
+    #[arg(long, value_enum)]
+    archive: Option<ArchiveFormat>,
+
+    /// The directory to save page archives to
+    #[arg(long, default_value_t = String::from("archives/"))]
+    archive_dir: String,
+
+    /// Export the content of every page while crawling
+    #[arg(long, value_enum)]
+    export: Option<PageExport>,
+
+    /// The directory to save the corpus documents to
+    #[arg(long, default_value_t = String::from("corpus/"))]
+    corpus_dir: String,
+
+    /// Whether corpus documents are Markdown or plain text
+    #[arg(long, value_enum, default_value_t = CorpusFormat::Markdown)]
+    corpus_format: CorpusFormat,
+
+    /// The most characters in a single corpus document chunk
+    #[arg(long, default_value_t = 4000)]
+    corpus_chunk_size: usize,
+
+    /// Record the request and response of every page as a HAR file
+    #[arg(long, default_value_t = false)]
+    har: bool,
+
+    /// The directory to save HAR files to
+    #[arg(long, default_value_t = String::from("har/"))]
+    har_dir: String,
+
+    /// Schemes of links that aren't crawled but are counted on every page
+    #[arg(long, value_delimiter = ',', default_value = "mailto,tel,ftp,javascript")]
+    count_schemes: Vec<String>,
+
+    /// Response headers to store on every link, e.g. server,cache-control,x-powered-by
+    #[arg(long, value_delimiter = ',')]
+    capture_headers: Vec<String>,
+
+    /// Detect the frameworks, CMSes and servers every page uses
+    #[arg(long, default_value_t = false)]
+    detect_technologies: bool,
+
+    /// The file to save the detected technologies to
+    #[arg(long, default_value_t = String::from("technologies.json"))]
+    technologies_json: String,
+
+    /// Record every page's h1 to h6 headings, with their levels, in the links file
+    #[arg(long, default_value_t = false)]
+    outline: bool,
+
+    /// Record the AMP and mobile versions pages link to without crawling
+    /// them, they have the same content as the page itself
+    #[arg(long, default_value_t = false)]
+    skip_alternates: bool,
+
+    /// Only follow links on pages in these languages (ISO 639-3 codes), e.g. eng,fra.
+    /// A page's language is read from `<html lang>`, or detected from its text when
+    /// it doesn't say. Pages whose language can't be told are always followed.
+    #[arg(long, value_delimiter = ',', value_parser = language::parse_language)]
+    languages: Vec<String>,
+
+    /// Record every response and scheduling decision to this file.
+    /// The crawl runs on a single worker so the recording is reproducible.
+    #[arg(long, conflicts_with = "replay")]
+    record: Option<String>,
+
+    /// Re-run a crawl against a recording instead of the network,
+    /// reporting where the order of fetches differs from the recording
+    #[arg(long)]
+    replay: Option<String>,
+
+    /// Extract keywords and named entities from the text of every page
+    #[arg(long, default_value_t = false)]
+    extract_keywords: bool,
+
+    /// How many keywords and entities to keep for each page
+    #[arg(long, default_value_t = 10)]
+    keywords_per_page: usize,
+
+    /// The file to save the site-wide keyword frequencies to
+    #[arg(long, default_value_t = String::from("keywords.json"))]
+    keywords_json: String,
+
+    /// Embed the text of every page with this OpenAI-compatible API, e.g.
+    /// https://api.openai.com/v1. The key is read from OPENAI_API_KEY.
+    #[cfg(feature = "embeddings")]
+    #[arg(long)]
+    embeddings_endpoint: Option<String>,
+
+    /// The embedding model to ask the endpoint for
+    #[cfg(feature = "embeddings")]
+    #[arg(long, default_value_t = String::from("text-embedding-3-small"))]
+    embeddings_model: String,
+
+    /// The JSON lines file to save page embeddings to
+    #[cfg(feature = "embeddings")]
+    #[arg(long, default_value_t = String::from("embeddings.jsonl"))]
+    embeddings_file: String,
 }
 
-async fn output_status(crawler_state: CrawlerStateRef, total_links: u64) -> Result<()> {
-    let progress_bar = logger::progress_bar::ProgressBar::new(total_links);
-    progress_bar.message("Finding links");
+#[derive(Subcommand, Clone, Debug)]
+enum Command {
+    /// Analyze the links file of a finished crawl
+    Analyze(commands::analyze::AnalyzeArgs),
+    /// Measure crawl speed against a generated site served in-process
+    Bench(commands::bench::BenchArgs),
+    /// Export the links file of a finished crawl in another format
+    Export(commands::export::ExportArgs),
+    /// Fetch a single page and print what was scraped from it as JSON
+    Fetch(commands::fetch::FetchArgs),
+    /// Periodically re-check the status of a saved set of urls
+    Monitor(commands::monitor::MonitorArgs),
+    /// Explain whether robots.txt allows a url to be crawled
+    Robots(commands::robots::RobotsArgs),
+    /// Serve the REST API for starting crawls and following their progress
+    Serve(commands::serve::ServeArgs),
+}
+
+async fn output_status(
+    crawler_state: CrawlerStateRef,
+    total_links: u64,
+    reporter: Arc<dyn ProgressReporter>,
+) -> Result<()> {
+    reporter.start_progress(total_links);
     'output: loop {
         let link_queue = crawler_state.link_queue.read().await;
         let link_graph = crawler_state.link_graph.read().await;
 
-        if link_graph.len() > crawler_state.max_links {
+        if crawler_state.budget_reached()
+            || crawler_state.control.is_cancelled()
+            || (link_queue.is_empty() && crawler_state.is_idle())
+        {
             // Show the links
             info!("All links found: {:#?}", link_graph);
             break 'output;
         }
 
-        progress_bar.set_step(link_graph.len() as u64);
-
         drop(link_queue);
         drop(link_graph);
 
+        reporter.progress(&CrawlStats::snapshot(&crawler_state).await);
+
         tokio::time::sleep(Duration::from_millis(500)).await;
     }
 
     Ok(())
 }
 
-fn is_same_domain(url_domain: &str, base_domain: &str) -> bool {
-    url_domain == base_domain || url_domain.ends_with(&format!(".{}", base_domain))
-}
-
-async fn crawl(crawler_state: CrawlerStateRef) -> Result<()> {
-    let client = crawler::create_client();
+async fn new_crawler_state(args: &ProgramArgs) -> Result<CrawlerStateRef> {
+    let starting_url = args.starting_url.as_deref().context("missing starting url")?;
+    let starting_url = Url::parse(starting_url).context("invalid starting url")?;
 
-    'crawler: loop {
-        if crawler_state.visited_count.load(Ordering::Relaxed) >= crawler_state.max_links {
-            break 'crawler;
+    let politeness = match &args.politeness {
+        Some(path) => {
+            let politeness = Politeness::load(path, args.page_delay())?;
+            info!("loaded overrides for {} hosts from {}", politeness.host_count(), path);
+            politeness
         }
+        None => Politeness::new(args.page_delay()),
+    };
+    let auth = {
+        let default = args
+            .basic_auth
+            .clone()
+            .or_else(|| args.bearer_token.clone().map(Credentials::Bearer));
+        (default.is_some() || args.credentials_file.is_some())
+            .then(|| Auth::new(default, args.credentials_file.as_deref()))
+            .transpose()?
+    };
+    let oauth2 = args
+        .oauth2_token_url
+        .as_ref()
+        .zip(args.oauth2_client_id.as_ref())
+        .map(|(token_url, client_id)| {
+            OAuth2::new(OAuth2Config {
+                token_url: token_url.clone(),
+                client_id: client_id.clone(),
+                client_secret: args.oauth2_client_secret.clone(),
+                refresh_token: args.oauth2_refresh_token.clone(),
+                scope: args.oauth2_scope.clone(),
+            })
+        });
+    let browser_session = match &args.import_session {
+        Some(path) => {
+            let browser_session = BrowserSession::load(path)?;
+            info!(
+                "imported {} cookies and the headers for {} hosts from {}",
+                browser_session.cookie_count(),
+                browser_session.host_count(),
+                path
+            );
+            Some(browser_session)
+        }
+        None => None,
+    };
+    let stealth = if args.stealth {
+        Some(Stealth::new(
+            args.user_agents_file.as_deref(),
+            args.stealth_delay_ms.clone(),
+        )?)
+    } else {
+        None
+    };
+    let session = match (&args.record, &args.replay) {
+        (Some(path), _) => Some(Session::record(path)?),
+        (_, Some(path)) => Some(Session::replay(path)?),
+        (None, None) => None,
+    };
+    let link_sink = match &args.stream_links {
+        Some(path) => Some(
+            LinkSink::create(path)
+                .await
+                .context("could not create the link stream")?,
+        ),
+        None => None,
+    };
+    #[cfg(feature = "embeddings")]
+    let embedder = match &args.embeddings_endpoint {
+        Some(endpoint) => Some(
+            embeddings::Embedder::create(
+                endpoint,
+                &args.embeddings_model,
+                std::env::var("OPENAI_API_KEY").ok(),
+                &args.embeddings_file,
+            )
+            .await
+            .context("could not create the embeddings file")?,
+        ),
+        None => None,
+    };
+    let skipped_log = SkippedLog::create(&args.skipped_jsonl)
+        .await
+        .context("could not create the skipped urls log")?;
 
-        let link_to_visit = {
-            let mut link_queue = crawler_state.link_queue.write().await;
-            link_queue.pop_back()
-        };
-
-        let LinkPath { parent, child } = match link_to_visit {
-            Some(path) => path,
-            None => {
-                tokio::time::sleep(Duration::from_millis(200)).await;
-                let link_queue = crawler_state.link_queue.read().await;
-                if link_queue.is_empty() {
-                    break 'crawler;
-                }
-                continue;
+    let builder = Crawler::builder()
+        .start_url(starting_url.as_str())
+        .max_links(args.max_links as usize)
+        .max_links_per_page(args.max_links_per_page)
+        .max_depth(args.max_depth)
+        .max_memory(args.max_memory)
+        .max_download_bytes(args.max_download_bytes)
+        .workers(args.n_worker_threads as usize)
+        .politeness(politeness)
+        .compression(!args.no_compression)
+        .stealth(stealth)
+        .auth(auth)
+        .oauth2(oauth2)
+        .browser_session(browser_session)
+        .session(session)
+        .seed_ancestors(args.seed_ancestors)
+        .link_scope(LinkScope {
+            links_from: args.links_from.clone(),
+            ignore: args.ignore_links_in.clone(),
+        })
+        .normalize_options(NormalizeOptions {
+            upgrade_http: args.upgrade_http && starting_url.scheme() == "https",
+            collapse_index_paths: args.collapse_index_paths,
+            add_trailing_slash: args.add_trailing_slash,
+        })
+        .port_policy(args.port_policy)
+        .host_normalization(args.normalize_host)
+        .respect_nofollow(args.respect_nofollow)
+        .respect_robots(args.respect_robots)
+        .dedup_key(args.dedup_key.clone())
+        .schedule(args.schedule)
+        .budget(args.budget.clone())
+        .languages(args.languages.clone())
+        .counted_schemes(&args.count_schemes)
+        .capture_headers(&args.capture_headers)
+        .detect_technologies(args.detect_technologies)
+        .outline(args.outline)
+        .skip_alternates(args.skip_alternates)
+        .keywords_per_page(args.extract_keywords.then_some(args.keywords_per_page))
+        .link_sink(link_sink)
+        .skipped_log(skipped_log)
+        .mirror_dir(args.mirror.as_ref().map(PathBuf::from))
+        .archive_dir(args.archive.map(|_| PathBuf::from(&args.archive_dir)))
+        .har_dir(args.har.then(|| PathBuf::from(&args.har_dir)))
+        .corpus(args.export.map(|PageExport::Corpus| {
+            CorpusWriter::new(
+                Path::new(&args.corpus_dir),
+                args.corpus_format,
+                args.corpus_chunk_size,
+            )
+        }))
+        .images_from(args.images_from.clone())
+        .image_dedup(args.image_dedup)
+        .image_domains(args.image_domains())
+        .image_referer(!args.no_image_referer);
+    #[cfg(feature = "http3")]
+    let builder = builder.http3(args.http3);
+    #[cfg(feature = "embeddings")]
+    let builder = builder.embedder(embedder);
+    let builder = args
+        .include_patterns
+        .iter()
+        .cloned()
+        .fold(builder, CrawlerBuilder::include_pattern);
+    let builder = args
+        .exclude_patterns
+        .iter()
+        .cloned()
+        .fold(builder, CrawlerBuilder::exclude_pattern);
+    let builder = args
+        .path_prefixes
+        .iter()
+        .fold(builder, |builder, prefix| builder.path_prefix(prefix));
+    // Nothing is downloaded while replaying, the recording only has pages
+    let builder = match args.replay {
+        None => builder.download_images(&args.img_save_dir, args.max_images as usize),
+        Some(_) => builder,
+    };
+
+    Ok(builder.build()?.state().clone())
+}
+
+/// Adds the pages listed in the site's sitemaps to the front of
+/// the queue, so the starting url is still visited first
+async fn seed_from_sitemaps(crawler_state: &CrawlerStateRef, starting_url: &str) {
+    let Ok(url) = Url::parse(starting_url) else {
+        return;
+    };
+
+    let session = crawler_state.session.as_ref();
+    let pages = match session.and_then(Session::replayed_seeds) {
+        Some(pages) => pages,
+        None => {
+            let client = crawler::create_client();
+            let pages = sitemap::discover_seed_pages(&url, &client, crawler_state.max_links).await;
+            if let Some(session) = session {
+                session.record_seeds(&pages);
             }
-        };
+            pages
+        }
+    };
+    info!("seeding {} links from sitemaps", pages.len());
 
-        if child.is_empty() {
-            continue;
+    let mut link_queue = crawler_state.link_queue.write().await;
+    for page in pages {
+        let page = normalize_url(&page, &crawler_state.normalize_options)
+            .filter(|page| in_path_prefixes(page, &crawler_state.path_prefixes))
+            .filter(|page| {
+                path_patterns_allow(page, &crawler_state.include_patterns, &crawler_state.exclude_patterns)
+            });
+        if let Some(page) = page {
+            link_queue.push_front(LinkPath {
+                child: page.to_string(),
+                ..Default::default()
+            });
         }
+    }
+}
 
-        let parsed_url = match Url::parse(&child) {
-            Ok(mut url) => {
-                if !matches!(url.scheme(), "http" | "https") {
-                    continue 'crawler;
-                }
-                url.set_fragment(None);
-                url
+async fn try_main(args: ProgramArgs) -> Result<CrawlerStateRef> {
+    let crawler_state = new_crawler_state(&args).await?;
+
+    // Ctrl-C stops the crawl like the budget running out, so what was
+    // crawled so far is still written. A second one quits right away
+    let interrupt = {
+        let crawler_state = crawler_state.clone();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_err() {
+                return;
+            }
+            eprintln!(
+                "{} {}",
+                logger::emoji("🛑", ""),
+                console::style("Interrupted, saving what was crawled. Press Ctrl-C again to quit").yellow()
+            );
+            crawler_state.control.cancel();
+            if tokio::signal::ctrl_c().await.is_ok() {
+                process::exit(130);
             }
-            Err(_) => continue 'crawler,
-        };
+        })
+    };
+
+    // There's nothing to fetch while replaying
+    if let (false, None, Some(starting_url)) = (args.no_seed_check, &args.replay, &args.starting_url) {
+        let starting_url = Url::parse(starting_url).context("invalid starting url")?;
+        seed_check::check_seed(&starting_url, &page_client(&crawler_state)?, &crawler::create_client())
+            .await
+            .context("the crawl can't start from the starting url")?;
+    }
+
+    if let (false, Some(starting_url)) = (args.no_sitemap_seeding, &args.starting_url) {
+        seed_from_sitemaps(&crawler_state, starting_url).await;
+    }
+
+    // The actual crawling goes here
+    let mut tasks = JoinSet::new();
+
+    // Recordings are only reproducible if pages are fetched in order
+    let n_workers = match crawler_state.session {
+        Some(_) => 1,
+        None => args.n_worker_threads,
+    };
+
+    for _ in 0..n_workers {
+        let crawler_state = crawler_state.clone();
+        let task = tokio::spawn(async move { crawl(crawler_state.clone()).await });
+
+        tasks.spawn(task);
+    }
+
+    let reporter = logger::reporter::create_reporter(args.progress);
+    if args.log_status {
+        let crawler_state = crawler_state.clone();
+        let reporter = reporter.clone();
+        tasks.spawn(tokio::spawn(async move {
+            output_status(crawler_state.clone(), args.max_links, reporter).await
+        }));
+    }
 
-        let normalized_url = parsed_url.to_string();
+    while let Some(result) = tasks.join_next().await {
+        if let Err(e) = result {
+            error!("Error: {:?}", e);
+        }
+    }
 
-        if let Some(domain) = parsed_url.domain() {
-            if !is_same_domain(domain, &crawler_state.base_domain) {
-                continue 'crawler;
+    // Whatever is still queued was cut off by the budget or Ctrl-C
+    let reason = match crawler_state.control.is_cancelled() {
+        true => SkipReason::Interrupted,
+        false => SkipReason::Budget,
+    };
+    let mut unvisited = Vec::new();
+    {
+        let mut link_queue = crawler_state.link_queue.write().await;
+        let link_graph = crawler_state.link_graph.read().await;
+        while let Some(LinkPath { parent, child, .. }) = link_queue.pop_back() {
+            if !link_graph.link_visited(&child) {
+                unvisited.push((parent, child, reason));
             }
         }
+        for LinkPath { parent, child, .. } in link_queue.take_over_budget() {
+            unvisited.push((parent, child, SkipReason::Budget));
+        }
+    }
+    for (parent, child, reason) in unvisited {
+        record_skipped(&crawler_state, &[(child, reason)], &parent).await;
+    }
 
-        let is_new = {
-            let link_graph = crawler_state.link_graph.read().await;
-            !link_graph.link_visited(&normalized_url)
-        };
+    let mut link_graph = crawler_state.link_graph.write().await;
 
-        if !is_new {
-            continue 'crawler;
-        }
+    let stats = CrawlStats::snapshot(&crawler_state).await;
+    reporter.print_above(
+        &format!(
+            "  crawled {} pages ({} attempted) in {:.1}s: {:.1} pages/sec, {:.1}% errors",
+            stats.crawled,
+            stats.attempted,
+            stats.elapsed_secs,
+            stats.pages_per_sec,
+            stats.error_rate * 100.0
+        ),
+        Colour::Green,
+    );
+
+    if stats.blocked > 0 {
+        reporter.print_above(
+            &format!(
+                "  {} pages were challenge or block pages, they're marked as blocked in {}",
+                stats.blocked, args.links_json
+            ),
+            Colour::Green,
+        );
+    }
 
-        if crawler_state.visited_count.load(Ordering::Relaxed) >= crawler_state.max_links {
-            break 'crawler;
+    let mut scheme_counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for (_, link) in &*link_graph {
+        for (scheme, count) in &link.scheme_counts {
+            *scheme_counts.entry(scheme).or_default() += count;
         }
+    }
+    if !scheme_counts.is_empty() {
+        let counts: Vec<String> = scheme_counts
+            .iter()
+            .map(|(scheme, count)| format!("{} {}", scheme, count))
+            .collect();
+        reporter.print_above(
+            &format!("  uncrawled links by scheme: {}", counts.join(", ")),
+            Colour::Green,
+        );
+    }
 
-        crawler_state.visited_count.fetch_add(1, Ordering::Relaxed);
+    let mut kind_counts: BTreeMap<NodeKind, usize> = BTreeMap::new();
+    for (_, link) in &*link_graph {
+        *kind_counts.entry(link.kind).or_default() += 1;
+    }
+    if kind_counts.keys().any(|kind| *kind != NodeKind::Page) {
+        let counts: Vec<String> = kind_counts
+            .iter()
+            .map(|(kind, count)| format!("{:?} {}", kind, count).to_lowercase())
+            .collect();
+        reporter.print_above(
+            &format!("  links by kind: {}", counts.join(", ")),
+            Colour::Green,
+        );
+    }
 
-        let scrape_options = vec![ScrapeOption::Images, ScrapeOption::Titles];
-        let scrape_output = scrape_page(parsed_url.clone(), &client, &scrape_options).await;
+    if crawler_state.bytes_budget_reached() {
+        reporter.print_above(
+            &format!(
+                "  stopped crawling after downloading {} bytes",
+                crawler_state.downloaded_bytes.load(Ordering::Relaxed)
+            ),
+            Colour::Green,
+        );
+    }
 
-        tokio::time::sleep(Duration::from_millis(500)).await;
+    let language_counts = language::language_counts(&link_graph);
+    if !language_counts.is_empty() {
+        let counts: Vec<String> = language_counts
+            .iter()
+            .map(|(lang, count)| format!("{} {}", lang, count))
+            .collect();
+        reporter.print_above(
+            &format!("  pages by language: {}", counts.join(", ")),
+            Colour::Green,
+        );
+    }
 
-        let mut link_queue = crawler_state.link_queue.write().await;
-        let mut link_graph = crawler_state.link_graph.write().await;
-        
-        for link in scrape_output.links.iter() {
-            if crawler_state.visited_count.load(Ordering::Relaxed) >= crawler_state.max_links {
-                break;
+    let host_report = crawler_state.host_report.lock().await;
+    for (host, host_stats) in host_report.by_pages().into_iter().take(SUMMARY_HOSTS) {
+        let latency = host_stats
+            .average_latency()
+            .map(|latency| format!(", {} ms average latency", latency.as_millis()))
+            .unwrap_or_default();
+        reporter.print_above(
+            &format!(
+                "  {}: {} pages, {} errors, {} bytes{}",
+                host, host_stats.pages, host_stats.errors, host_stats.bytes, latency
+            ),
+            Colour::Green,
+        );
+    }
+    drop(host_report);
+
+    let starting_url = args
+        .starting_url
+        .as_deref()
+        .and_then(|url| normalize_url(url, &crawler_state.normalize_options));
+    if let Some(seed) = starting_url.and_then(|url| link_graph.get(url.as_str()).map(|link| link.id)) {
+        let depths = analysis::click_depths(&link_graph, seed);
+        link_graph.set_click_depths(&depths);
+
+        let (histogram, unreachable) = analysis::depth_histogram(&link_graph);
+        let mut counts: Vec<String> = histogram
+            .iter()
+            .map(|(depth, count)| format!("{}: {}", depth, count))
+            .collect();
+        if unreachable > 0 {
+            counts.push(format!("unreachable: {}", unreachable));
+        }
+        reporter.print_above(
+            &format!("  pages by click depth: {}", counts.join(", ")),
+            Colour::Green,
+        );
+    }
+
+    reporter.status("[1/4] converting image links");
+    let image_domains = args.image_domains();
+    let mut image_metadata = convert_links_to_images(&link_graph, args.image_dedup, &image_domains);
+    if !image_domains.allow.is_empty() || !image_domains.block.is_empty() {
+        let mut skipped_log = crawler_state.skipped_log.lock().await;
+        for (_, page) in &*link_graph {
+            for image in page.images.iter().filter(|image| !image_domains.allows(&image.link)) {
+                skipped_log.record(&image.link, Some(&page.url), SkipReason::ImageDomain).await?;
             }
+        }
+    }
+    if let Some(pattern) = &args.images_from {
+        let mut skipped_log = crawler_state.skipped_log.lock().await;
+        for image in retain_images_from(&mut image_metadata, pattern) {
+            let found_on = image.pages.first().map(String::as_str);
+            skipped_log.record(&image.link, found_on, SkipReason::Pattern).await?;
+        }
+    }
+    reporter.print_above("  [1/4] converted image links", Colour::Green);
 
-            let should_add = if let Ok(mut link_url) = Url::parse(link) {
-                if !matches!(link_url.scheme(), "http" | "https") {
-                    false
-                } else {
-                    link_url.set_fragment(None);
-                    let normalized = link_url.to_string();
-                    link_url.domain().map_or(false, |d| is_same_domain(d, &crawler_state.base_domain))
-                        && !link_graph.link_visited(&normalized)
+    let mut image_paths = match &crawler_state.image_downloader {
+        Some(image_downloader) => {
+            reporter.status("[2/4] finishing image downloads");
+            let downloads = image_downloader.finish().await;
+            let mut host_report = crawler_state.host_report.lock().await;
+            let mut skipped_log = crawler_state.skipped_log.lock().await;
+            if args.image_dedup == ImageDedup::Skip {
+                for image in image_metadata.values().filter(|image| image.pages.len() > 1) {
+                    let found_on = image.pages.first().map(String::as_str);
+                    skipped_log.record(&image.link, found_on, SkipReason::SharedImage).await?;
                 }
-            } else {
-                false
-            };
-
-            if should_add {
-                if let Ok(mut link_url) = Url::parse(link) {
-                    link_url.set_fragment(None);
-                    link_queue.push_back(LinkPath {
-                        parent: normalized_url.clone(),
-                        child: link_url.to_string(),
-                    });
+            }
+            for (_, link, result) in &downloads {
+                if result.as_ref().is_err_and(|e| e.is::<RobotsDisallowed>()) {
+                    host_report.record_robots_block(link);
+                    skipped_log.record(link, None, SkipReason::Robots).await?;
                 }
             }
+            drop(skipped_log);
+            reporter.print_above("  [2/4] downloaded images", Colour::Green);
+            record_downloads(&mut image_metadata, downloads)
+        }
+        None => {
+            reporter.print_above("  [2/4] skipped downloading images while replaying", Colour::Green);
+            HashMap::new()
         }
+    };
 
-        if let Err(e) = link_graph.update(
-            &normalized_url,
-            &parent,
-            &scrape_output.links,
-            &scrape_output.images,
-            &scrape_output.titles,
-        ) {
-            error!("could not update the link graph: {}", e);
+    if let Some(command) = &args.image_hook {
+        reporter.status("running the image hook");
+        let hook = CommandHook::new(command);
+        let rejected = run_image_hook(&hook, &mut image_metadata, Path::new(&args.img_save_dir)).await;
+        let mut skipped_log = crawler_state.skipped_log.lock().await;
+        for image in &rejected {
+            image_paths.remove(&image.link);
+            let found_on = image.pages.first().map(String::as_str);
+            skipped_log.record(&image.link, found_on, SkipReason::ImageHook).await?;
         }
+        reporter.print_above(
+            &format!("  the image hook rejected {} images", rejected.len()),
+            Colour::Green,
+        );
     }
 
-    Ok(())
-}
+    link_graph.set_image_file_names(|page, image_link| {
+        let name = args.image_dedup.image_name(image_link, page);
+        image_metadata.get(&name)?.file_name.clone()
+    });
 
-async fn serialize_links(links: &LinkGraph, destination: &str) -> Result<()> {
-    let json = serde_json::to_string(links)?;
-    fs::write(destination, json).await?;
-    Ok(())
-}
+    crawler_state
+        .host_report
+        .lock()
+        .await
+        .write_csv(&args.hosts_csv)
+        .await?;
+    reporter.print_above(
+        &format!("  saved per-host stats to {}", args.hosts_csv),
+        Colour::Green,
+    );
 
-fn new_crawler_state(starting_url: String, max_links: u64) -> CrawlerStateRef {
-    let base_domain = Url::parse(&starting_url)
-        .ok()
-        .and_then(|url| url.domain().map(|d| d.to_string()))
-        .unwrap_or_else(|| String::from("localhost"));
-
-    let crawler_state = CrawlerState {
-        link_queue: RwLock::new(VecDeque::from([LinkPath {
-            child: starting_url,
-            ..Default::default()
-        }])),
-        link_graph: RwLock::new(Default::default()),
-        max_links: max_links as usize,
-        base_domain,
-        visited_count: Arc::new(AtomicUsize::new(0)),
-    };
+    let external_links = crawler_state.external_links.lock().await;
+    external_links.write_csv(&args.external_links_csv).await?;
+    reporter.print_above(
+        &format!(
+            "  saved {} external links to {} hosts to {}",
+            external_links.len(),
+            external_links.host_count(),
+            args.external_links_csv
+        ),
+        Colour::Green,
+    );
+    drop(external_links);
 
-    Arc::new(crawler_state)
-}
+    let title_report = TitleReport::new(&link_graph);
+    title_report.write_csv(&args.title_issues_csv).await?;
+    reporter.print_above(
+        &format!(
+            "  {} titles are shared by {} pages and {} pages have no title, saved them to {}",
+            title_report.duplicate_titles(),
+            title_report.duplicate_pages(),
+            title_report.missing(),
+            args.title_issues_csv
+        ),
+        Colour::Green,
+    );
 
-async fn try_main(args: ProgramArgs) -> Result<()> {
-    let crawler_state = new_crawler_state(args.starting_url, args.max_links);
+    let mut skipped_log = crawler_state.skipped_log.lock().await;
+    skipped_log.flush().await?;
+    let skipped_counts: Vec<String> = skipped_log
+        .counts()
+        .iter()
+        .map(|(reason, count)| format!("{} {}", reason, count))
+        .collect();
+    if !skipped_counts.is_empty() {
+        reporter.print_above(
+            &format!(
+                "  skipped urls by reason: {}, saved them to {}",
+                skipped_counts.join(", "),
+                args.skipped_jsonl
+            ),
+            Colour::Green,
+        );
+    }
+    drop(skipped_log);
 
-    // The actual crawling goes here
-    let mut tasks = JoinSet::new();
+    if args.detect_technologies {
+        technologies::write_report(&link_graph, &args.technologies_json).await?;
+        reporter.print_above(
+            &format!("  saved detected technologies to {}", args.technologies_json),
+            Colour::Green,
+        );
+    }
 
-    for _ in 0..args.n_worker_threads {
-        let crawler_state = crawler_state.clone();
-        let task = tokio::spawn(async move { crawl(crawler_state.clone()).await });
+    if args.extract_keywords {
+        keywords::write_report(&link_graph, &args.keywords_json).await?;
+        reporter.print_above(
+            &format!("  saved keyword frequencies to {}", args.keywords_json),
+            Colour::Green,
+        );
+    }
 
-        tasks.spawn(task);
+    if let Some(mirror_dir) = &crawler_state.mirror_dir {
+        reporter.status("rewriting mirrored links");
+        mirror::rewrite_mirror_links(
+            mirror_dir,
+            &link_graph,
+            &image_paths,
+            &crawler_state.normalize_options,
+        )
+        .await?;
+        reporter.print_above(
+            &format!("  mirrored pages to {}", mirror_dir.display()),
+            Colour::Green,
+        );
     }
 
-    if args.log_status {
-        let crawler_state = crawler_state.clone();
-        tasks.spawn(tokio::spawn(async move {
-            output_status(crawler_state.clone(), args.max_links).await
-        }));
+    // Save this to image dir
+    reporter.status("[3/4] creating image database");
+    let image_database = serde_json::to_string(&image_metadata)?;
+    fs::create_dir_all(&args.img_save_dir).await?;
+    fs::write(Path::new(&args.img_save_dir).join("database.json"), image_database).await?;
+    reporter.print_above("  [3/4] created image database", Colour::Green);
+
+    reporter.status(&format!("[4/4] serializing links to {}", args.links_json));
+    match args.split_output {
+        Some(SplitOutput::ByHost) => serialize_links_by_host(&link_graph, &args.links_json).await?,
+        None => serialize_links(&link_graph, &args.links_json).await?,
     }
+    reporter.print_above(
+        &format!("  [4/4] serializing links to {}", args.links_json),
+        Colour::Green,
+    );
+    drop(link_graph);
 
-    while let Some(result) = tasks.join_next().await {
-        if let Err(e) = result {
-            error!("Error: {:?}", e);
+    interrupt.abort();
+    if crawler_state.control.is_cancelled() {
+        reporter.print_above("  stopped early, saved what was crawled until then", Colour::Green);
+    }
+    Ok(crawler_state)
+}
+
+impl ProgramArgs {
+    /// How long to wait between page requests to a host
+    fn page_delay(&self) -> Duration {
+        let delay = Duration::from_millis(self.delay_ms);
+        match self.max_requests_per_second {
+            Some(rate) => delay.max(Duration::from_secs_f64(1.0 / rate)),
+            None => delay,
         }
     }
 
-    let link_graph = crawler_state.link_graph.read().await;
+    fn image_domains(&self) -> ImageDomains {
+        ImageDomains {
+            allow: self.image_domains.clone(),
+            block: self.block_image_domains.clone(),
+        }
+    }
 
-    let spinner = logger::spinner::Spinner::new();
-    spinner.status("[1/4] converting image links");
-    let image_metadata = convert_links_to_images(&link_graph);
-    spinner.print_above("  [1/4] converted image links", Colour::Green);
+    /// The arguments to crawl as `profile`, with the output files
+    /// moved into a directory of its own
+    fn for_profile(&self, profile: &Profile) -> Self {
+        let profile_dir = Path::new(&self.profiles_dir).join(&profile.name);
+        let in_profile_dir = |path: &str| profile_dir.join(path).to_string_lossy().into_owned();
 
-    spinner.status("[2/4] downloading image metadata");
-    download_images(&image_metadata, &args.img_save_dir, args.max_images).await?;
-    spinner.print_above("  [2/4] downloaded image metadata", Colour::Green);
+        let mut args = self.clone();
+        args.profiles = Vec::new();
+        args.import_session = profile.session.clone();
+        // A profile being refused the starting url is a result to compare, not an error
+        args.no_seed_check = true;
+        args.img_save_dir = in_profile_dir(&self.img_save_dir);
+        args.links_json = in_profile_dir(&self.links_json);
+        args.hosts_csv = in_profile_dir(&self.hosts_csv);
+        args.external_links_csv = in_profile_dir(&self.external_links_csv);
+        args.skipped_jsonl = in_profile_dir(&self.skipped_jsonl);
+        args.title_issues_csv = in_profile_dir(&self.title_issues_csv);
+        args.stream_links = self.stream_links.as_deref().map(in_profile_dir);
+        args.mirror = self.mirror.as_deref().map(in_profile_dir);
+        args.archive_dir = in_profile_dir(&self.archive_dir);
+        args.corpus_dir = in_profile_dir(&self.corpus_dir);
+        args.har_dir = in_profile_dir(&self.har_dir);
+        args.technologies_json = in_profile_dir(&self.technologies_json);
+        args.keywords_json = in_profile_dir(&self.keywords_json);
+        #[cfg(feature = "embeddings")]
+        {
+            args.embeddings_file = in_profile_dir(&self.embeddings_file);
+        }
+        args
+    }
+}
 
-    // Save this to image dir
-    spinner.status("[3/4] creating image database");
-    let image_database = serde_json::to_string(&image_metadata)?;
-    fs::write(args.img_save_dir + "database.json", image_database).await?;
-    spinner.print_above("  [3/4] created image database", Colour::Green);
+/// Crawls the site as every profile one after the other,
+/// then compares the pages each of them could see
+async fn crawl_profiles(args: ProgramArgs) -> Result<()> {
+    let names = args.profiles.iter().map(|profile| profile.name.clone()).collect();
+    let mut comparison = ProfileComparison::new(names);
 
-    spinner.status(format!("[4/4] serializing links to {}", args.links_json));
-    serialize_links(&link_graph, &args.links_json).await?;
-    spinner.print_above(
-        format!("  [4/4] serializing links to {}", args.links_json),
+    for (i, profile) in args.profiles.iter().enumerate() {
+        if !args.quiet {
+            println!(
+                "{}  Crawling as {}",
+                logger::emoji("👤", ""),
+                console::style(&profile.name).bold().cyan()
+            );
+        }
+        fs::create_dir_all(Path::new(&args.profiles_dir).join(&profile.name)).await?;
+        let crawler_state = try_main(args.for_profile(profile))
+            .await
+            .with_context(|| format!("could not crawl as {}", profile.name))?;
+        comparison.add(i, &*crawler_state.link_graph.read().await);
+        // The profiles crawled so far are still compared
+        if crawler_state.control.is_cancelled() {
+            break;
+        }
+    }
+
+    let reporter = logger::reporter::create_reporter(args.progress);
+    comparison.write_csv(&args.access_csv).await?;
+    let visible_to_some: Vec<String> = comparison
+        .visible_to_some()
+        .into_iter()
+        .map(|(name, count)| format!("{} {}", name, count))
+        .collect();
+    reporter.print_above(
+        &format!(
+            "  {} pages were visible to every profile, pages only some could see: {}",
+            comparison.visible_to_all(),
+            visible_to_some.join(", ")
+        ),
+        Colour::Green,
+    );
+    reporter.print_above(
+        &format!("  saved what each profile could see to {}", args.access_csv),
         Colour::Green,
     );
 
@@ -275,41 +1198,437 @@ fn pretty_print_args(args: &ProgramArgs) {
         "{}",
         console::style("CRAWLER INPUT ARGUMENTS").white().on_black()
     );
+    if let Some(config) = &args.config {
+        println!(
+            "{}  Config file: {}",
+            logger::emoji("⚙️", ""),
+            console::style(config).bold().cyan()
+        );
+    }
     println!(
         "{}  Starting URL: {}",
-        console::Emoji("🌐", ""),
-        console::style(&args.starting_url).bold().cyan()
+        logger::emoji("🌐", ""),
+        console::style(args.starting_url.as_deref().unwrap_or_default()).bold().cyan()
     );
     println!(
         "{}  Maximum visited links: {}",
-        console::Emoji("🔗", ""),
+        logger::emoji("🔗", ""),
         console::style(&args.max_links).bold().cyan()
     );
+    if let Some(max_depth) = args.max_depth {
+        println!(
+            "{}  Maximum click depth: {}",
+            logger::emoji("🪜", ""),
+            console::style(max_depth).bold().cyan()
+        );
+    }
     println!(
         "{}  Maximum number of images: {}",
-        console::Emoji("🖼️", ""),
+        logger::emoji("🖼️", ""),
         console::style(&args.max_images).bold().cyan()
     );
+    println!(
+        "{}  Delay between page requests to a host: {}ms",
+        logger::emoji("⏱️", ""),
+        console::style(args.page_delay().as_millis()).bold().cyan()
+    );
+    if let Some(images_from) = &args.images_from {
+        println!(
+            "{}  Images from pages matching: {}",
+            logger::emoji("🖼️", ""),
+            console::style(images_from).bold().cyan()
+        );
+    }
+    if let Some(image_hook) = &args.image_hook {
+        println!(
+            "{}  Running on every image: {}",
+            logger::emoji("🪝", ""),
+            console::style(image_hook).bold().cyan()
+        );
+    }
+    if args.no_image_referer {
+        println!(
+            "{}  Downloading images without a Referer",
+            logger::emoji("🕶️", "")
+        );
+    }
+    if !args.image_domains.is_empty() {
+        println!(
+            "{}  Images only from: {}",
+            logger::emoji("🖼️", ""),
+            console::style(args.image_domains.join(", ")).bold().cyan()
+        );
+    }
+    if !args.block_image_domains.is_empty() {
+        println!(
+            "{}  Images never from: {}",
+            logger::emoji("🚫", ""),
+            console::style(args.block_image_domains.join(", ")).bold().cyan()
+        );
+    }
+    println!(
+        "{}  Images on several pages: {}",
+        logger::emoji("🗂️", ""),
+        console::style(match args.image_dedup {
+            ImageDedup::Once => "downloaded once",
+            ImageDedup::PerPage => "downloaded for every page",
+            ImageDedup::Skip => "skipped",
+        })
+        .bold()
+        .cyan()
+    );
     println!(
         "{}  Number of workers: {}",
-        console::Emoji("⚒️", ""),
+        logger::emoji("⚒️", ""),
         console::style(&args.n_worker_threads).bold().cyan()
     );
     println!(
         "{}  Should log progress? {}",
-        console::Emoji("❔", ""),
+        logger::emoji("❔", ""),
         console::style(args.log_status).bold().cyan()
     );
     println!(
         "{}  Image directory: {}",
-        console::Emoji("📁", ""),
+        logger::emoji("📁", ""),
         console::style(&args.img_save_dir).bold().cyan()
     );
     println!(
         "{}  Output json path: {}",
-        console::Emoji("📁", ""),
+        logger::emoji("📁", ""),
         console::style(&args.links_json).bold().cyan()
     );
+    println!(
+        "{}  Seed from sitemaps? {}",
+        logger::emoji("🗺️", ""),
+        console::style(!args.no_sitemap_seeding).bold().cyan()
+    );
+    println!(
+        "{}  Check the starting url first? {}",
+        logger::emoji("🩺", ""),
+        console::style(!args.no_seed_check).bold().cyan()
+    );
+    if args.seed_ancestors {
+        println!(
+            "{}  Also visiting the directories above the starting url",
+            logger::emoji("🪜", "")
+        );
+    }
+    println!(
+        "{}  Upgrade http links? {}",
+        logger::emoji("🔒", ""),
+        console::style(args.upgrade_http).bold().cyan()
+    );
+    println!(
+        "{}  Host normalization: {:?}",
+        logger::emoji("🏷️", ""),
+        console::style(args.normalize_host).bold().cyan()
+    );
+    println!(
+        "{}  Collapse index paths? {}",
+        logger::emoji("📂", ""),
+        console::style(args.collapse_index_paths).bold().cyan()
+    );
+    println!(
+        "{}  Add trailing slashes? {}",
+        logger::emoji("➕", ""),
+        console::style(args.add_trailing_slash).bold().cyan()
+    );
+    println!(
+        "{}  Port policy: {:?}",
+        logger::emoji("🔌", ""),
+        console::style(args.port_policy).bold().cyan()
+    );
+    if args.respect_nofollow {
+        println!(
+            "{}  Not following nofollow links",
+            logger::emoji("🚫", "")
+        );
+    }
+    if args.respect_robots {
+        println!(
+            "{}  Not crawling pages robots.txt disallows",
+            logger::emoji("🤖", "")
+        );
+    }
+    if !args.path_prefixes.is_empty() {
+        println!(
+            "{}  Only following paths starting with: {}",
+            logger::emoji("📂", ""),
+            console::style(args.path_prefixes.join(", ")).bold().cyan()
+        );
+    }
+    let join = |patterns: &[PathPattern]| {
+        patterns.iter().map(PathPattern::to_string).collect::<Vec<_>>().join(", ")
+    };
+    if !args.include_patterns.is_empty() {
+        println!(
+            "{}  Only following paths matching: {}",
+            logger::emoji("🎯", ""),
+            console::style(join(&args.include_patterns)).bold().cyan()
+        );
+    }
+    if !args.exclude_patterns.is_empty() {
+        println!(
+            "{}  Not following paths matching: {}",
+            logger::emoji("🚫", ""),
+            console::style(join(&args.exclude_patterns)).bold().cyan()
+        );
+    }
+    if let Some(links_from) = &args.links_from {
+        println!(
+            "{}  Only taking links from: {}",
+            logger::emoji("🔍", ""),
+            console::style(links_from).bold().cyan()
+        );
+    }
+    if let Some(ignore_links_in) = &args.ignore_links_in {
+        println!(
+            "{}  Ignoring links in: {}",
+            logger::emoji("🙈", ""),
+            console::style(ignore_links_in).bold().cyan()
+        );
+    }
+    if !matches!(args.dedup_key, DedupKey::Url) {
+        println!(
+            "{}  Deduplicating pages by: {}",
+            logger::emoji("👯", ""),
+            console::style(&args.dedup_key).bold().cyan()
+        );
+    }
+    if args.schedule != Schedule::Queue {
+        println!(
+            "{}  Taking turns between: {}",
+            logger::emoji("🔄", ""),
+            console::style(match args.schedule {
+                Schedule::Hosts => "hosts",
+                _ => "sections",
+            })
+            .bold()
+            .cyan()
+        );
+    }
+    if let Some(budget) = &args.budget {
+        println!(
+            "{}  Budget by section: {}",
+            logger::emoji("🥧", ""),
+            console::style(budget).bold().cyan()
+        );
+    }
+    #[cfg(feature = "embeddings")]
+    if let Some(endpoint) = &args.embeddings_endpoint {
+        println!(
+            "{}  Embedding pages with {} from {} to: {}",
+            logger::emoji("🧮", ""),
+            console::style(&args.embeddings_model).bold().cyan(),
+            console::style(endpoint).bold().cyan(),
+            console::style(&args.embeddings_file).bold().cyan()
+        );
+    }
+    if args.extract_keywords {
+        println!(
+            "{}  Keywords per page: {} (report: {})",
+            logger::emoji("🔑", ""),
+            console::style(args.keywords_per_page).bold().cyan(),
+            console::style(&args.keywords_json).bold().cyan()
+        );
+    }
+    if let Some(record) = &args.record {
+        println!(
+            "{}  Recording the session to: {}",
+            logger::emoji("⏺️", ""),
+            console::style(record).bold().cyan()
+        );
+    }
+    if let Some(replay) = &args.replay {
+        println!(
+            "{}  Replaying the session from: {}",
+            logger::emoji("🔁", ""),
+            console::style(replay).bold().cyan()
+        );
+    }
+    if !args.languages.is_empty() {
+        println!(
+            "{}  Languages: {}",
+            logger::emoji("🗣️", ""),
+            console::style(args.languages.join(", ")).bold().cyan()
+        );
+    }
+    if args.detect_technologies {
+        println!(
+            "{}  Technologies report: {}",
+            logger::emoji("🔍", ""),
+            console::style(&args.technologies_json).bold().cyan()
+        );
+    }
+    if args.outline {
+        println!("{}  Recording heading outlines", logger::emoji("📑", ""));
+    }
+    if args.skip_alternates {
+        println!(
+            "{}  Not crawling AMP and alternate versions of pages",
+            logger::emoji("⚡", "")
+        );
+    }
+    if !args.count_schemes.is_empty() {
+        println!(
+            "{}  Counting links with schemes: {}",
+            logger::emoji("📧", ""),
+            console::style(args.count_schemes.join(", ")).bold().cyan()
+        );
+    }
+    if !args.capture_headers.is_empty() {
+        println!(
+            "{}  Capturing headers: {}",
+            logger::emoji("📋", ""),
+            console::style(args.capture_headers.join(", ")).bold().cyan()
+        );
+    }
+    if args.har {
+        println!(
+            "{}  Saving HAR files to: {}",
+            logger::emoji("⏱️", ""),
+            console::style(&args.har_dir).bold().cyan()
+        );
+    }
+    if let Some(PageExport::Corpus) = args.export {
+        println!(
+            "{}  Exporting a {:?} corpus to: {}",
+            logger::emoji("📚", ""),
+            console::style(args.corpus_format).bold().cyan(),
+            console::style(&args.corpus_dir).bold().cyan()
+        );
+    }
+    if let Some(archive) = args.archive {
+        println!(
+            "{}  Archiving pages as {:?} to: {}",
+            logger::emoji("🗄️", ""),
+            console::style(archive).bold().cyan(),
+            console::style(&args.archive_dir).bold().cyan()
+        );
+    }
+    if let Some(mirror) = &args.mirror {
+        println!(
+            "{}  Mirror directory: {}",
+            logger::emoji("📁", ""),
+            console::style(mirror).bold().cyan()
+        );
+    }
+    if let Some(split_output) = args.split_output {
+        println!(
+            "{}  Split output: {:?}",
+            logger::emoji("✂️", ""),
+            console::style(split_output).bold().cyan()
+        );
+    }
+    if let Some(stream_links) = &args.stream_links {
+        println!(
+            "{}  Streaming links to: {}",
+            logger::emoji("📁", ""),
+            console::style(stream_links).bold().cyan()
+        );
+    }
+    if let Some(max_memory) = args.max_memory {
+        println!(
+            "{}  Memory limit: {} bytes",
+            logger::emoji("🧠", ""),
+            console::style(max_memory).bold().cyan()
+        );
+    }
+    if let Some(max_download_bytes) = args.max_download_bytes {
+        println!(
+            "{}  Download limit: {} bytes",
+            logger::emoji("📦", ""),
+            console::style(max_download_bytes).bold().cyan()
+        );
+    }
+    if args.no_compression {
+        println!(
+            "{}  Compression: {}",
+            logger::emoji("🗜️", ""),
+            console::style("off").bold().cyan()
+        );
+    }
+    if args.stealth {
+        println!(
+            "{}  Stealth: {} ms between requests, {}",
+            logger::emoji("🥷", ""),
+            console::style(format!(
+                "{}-{}",
+                args.stealth_delay_ms.start(),
+                args.stealth_delay_ms.end()
+            ))
+            .bold()
+            .cyan(),
+            console::style(args.user_agents_file.as_deref().unwrap_or("browser user agents"))
+                .bold()
+                .cyan()
+        );
+    }
+    if let Some(Credentials::Basic { username, .. }) = &args.basic_auth {
+        println!(
+            "{}  Logging in as: {}",
+            logger::emoji("🔑", ""),
+            console::style(username).bold().cyan()
+        );
+    }
+    if args.bearer_token.is_some() {
+        println!(
+            "{}  Logging in with: {}",
+            logger::emoji("🔑", ""),
+            console::style("a bearer token").bold().cyan()
+        );
+    }
+    if let Some(politeness) = &args.politeness {
+        println!(
+            "{}  Politeness overrides: {}",
+            logger::emoji("🐢", ""),
+            console::style(politeness).bold().cyan()
+        );
+    }
+    if let Some(import_session) = &args.import_session {
+        println!(
+            "{}  Browser session: {}",
+            logger::emoji("🍪", ""),
+            console::style(import_session).bold().cyan()
+        );
+    }
+    if !args.profiles.is_empty() {
+        let profiles: Vec<&str> = args.profiles.iter().map(|profile| profile.name.as_str()).collect();
+        println!(
+            "{}  Crawling as profiles: {}",
+            logger::emoji("👤", ""),
+            console::style(profiles.join(", ")).bold().cyan()
+        );
+    }
+    if let Some(token_url) = &args.oauth2_token_url {
+        println!(
+            "{}  OAuth2 tokens from: {}",
+            logger::emoji("🔑", ""),
+            console::style(token_url).bold().cyan()
+        );
+    }
+    if let Some(credentials_file) = &args.credentials_file {
+        println!(
+            "{}  Credentials by host: {}",
+            logger::emoji("🔑", ""),
+            console::style(credentials_file).bold().cyan()
+        );
+    }
+    #[cfg(feature = "http3")]
+    if args.http3 {
+        println!(
+            "{}  HTTP/3: {}",
+            logger::emoji("⚡", ""),
+            console::style("when advertised").bold().cyan()
+        );
+    }
+    if let Some(max_links_per_page) = args.max_links_per_page {
+        println!(
+            "{}  Maximum links per page: {}",
+            logger::emoji("📄", ""),
+            console::style(max_links_per_page).bold().cyan()
+        );
+    }
     println!()
 }
 
@@ -317,20 +1636,61 @@ fn pretty_print_args(args: &ProgramArgs) {
 async fn main() {
     let _log2 = log2::open("log.txt");
 
-    // Print the arguments passed in nicely
-    let args = ProgramArgs::parse();
-    pretty_print_args(&args);
+    let cli = config::args_with_config(std::env::args_os().collect(), &ProgramArgs::command())
+        .unwrap_or_else(|e| {
+            eprintln!(
+                "{} {}",
+                logger::emoji("❌", ""),
+                console::style(format!("Error: {:#}", e)).red()
+            );
+            process::exit(-1);
+        });
+    let mut args = ProgramArgs::parse_from(cli);
+    logger::configure_output(args.no_emoji);
+    if args.quiet {
+        args.progress = ProgressMode::None;
+    }
 
-    match try_main(args).await {
-        Ok(_) => {
+    // Fetch prints JSON, anything else on stdout would break piping it
+    let print_finished = !args.quiet && !matches!(args.command, Some(Command::Fetch(_)));
+
+    let result = match args.command.take() {
+        Some(Command::Analyze(analyze_args)) => commands::analyze::run(analyze_args).await,
+        Some(Command::Bench(bench_args)) => commands::bench::run(bench_args).await,
+        Some(Command::Export(export_args)) => commands::export::run(export_args).await,
+        Some(Command::Fetch(fetch_args)) => commands::fetch::run(fetch_args).await,
+        Some(Command::Monitor(monitor_args)) => commands::monitor::run(monitor_args).await,
+        Some(Command::Robots(robots_args)) => commands::robots::run(robots_args).await,
+        Some(Command::Serve(serve_args)) => commands::serve::run(serve_args).await,
+        None => {
+            // Print the arguments passed in nicely
+            if !args.quiet {
+                pretty_print_args(&args);
+            }
+            if args.profiles.is_empty() {
+                try_main(args).await.map(drop)
+            } else {
+                crawl_profiles(args).await
+            }
+        }
+    };
+
+    match result {
+        Ok(_) if print_finished => {
             println!(
                 "{} {}",
-                console::Emoji("✅", ""),
+                logger::emoji("✅", ""),
                 console::style("Finished!").green()
             );
         }
+        Ok(_) => {}
         Err(e) => {
             error!("Error: {:?}", e);
+            eprintln!(
+                "{} {}",
+                logger::emoji("❌", ""),
+                console::style(format!("Error: {:#}", e)).red()
+            );
             process::exit(-1);
         }
     }
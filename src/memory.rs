@@ -0,0 +1,50 @@
+use anyhow::{anyhow, Result};
+
+/// Rough per-allocation overhead used when estimating
+/// how much memory strings and map entries take up
+pub const ENTRY_OVERHEAD_BYTES: usize = 48;
+
+/// Approximate heap usage of a string stored in a collection
+pub fn string_bytes(s: &str) -> usize {
+    s.len() + ENTRY_OVERHEAD_BYTES
+}
+
+/// Parses sizes like `512`, `64K`, `100M` or `2G` (binary units) into bytes
+pub fn parse_byte_size(size: &str) -> Result<usize> {
+    let size = size.trim();
+    let split_at = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split_at);
+
+    let number: usize = number
+        .parse()
+        .map_err(|_| anyhow!("invalid size '{}'", size))?;
+
+    let multiplier: usize = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => return Err(anyhow!("invalid size unit '{}'", unit)),
+    };
+
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow!("size '{}' is too large", size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("512").unwrap(), 512);
+        assert_eq!(parse_byte_size("64K").unwrap(), 64 * 1024);
+        assert_eq!(parse_byte_size("100mb").unwrap(), 100 * 1024 * 1024);
+        assert_eq!(parse_byte_size("2G").unwrap(), 2 * 1024 * 1024 * 1024);
+        assert!(parse_byte_size("G").is_err());
+        assert!(parse_byte_size("2T").is_err());
+    }
+}
@@ -0,0 +1,268 @@
+use anyhow::Result;
+use log2::*;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+use url::Url;
+
+use crate::file_names::sanitize_file_name;
+use crate::model::{link_key, LinkGraph};
+use crate::url_utils::{normalize_url, NormalizeOptions};
+
+/// The attributes whose urls get rewritten to point at the mirror
+const URL_ATTRIBUTES: [&str; 2] = ["href", "src"];
+
+/// Where the page at `url` is saved inside `mirror_dir`. Directory
+/// urls become `index.html`, extensionless pages get `.html` added,
+/// and query strings are hashed into the file name.
+pub fn mirror_path(mirror_dir: &Path, url: &Url) -> PathBuf {
+    let mut host = url.host_str().unwrap_or("unknown").to_string();
+    if let Some(port) = url.port() {
+        host = format!("{}_{}", host, port);
+    }
+
+    let mut path = mirror_dir.join(sanitize_file_name(&host));
+    let segments: Vec<&str> = url
+        .path_segments()
+        .map(|s| s.filter(|s| !s.is_empty() && *s != "..").collect())
+        .unwrap_or_default();
+
+    let directory_url = url.path().ends_with('/') || segments.is_empty();
+    for segment in &segments {
+        path.push(sanitize_file_name(segment));
+    }
+    if directory_url {
+        path.push("index.html");
+    }
+
+    let file_name = path
+        .file_name()
+        .and_then(|f| f.to_str())
+        .unwrap_or("index.html")
+        .to_string();
+    let (stem, extension) = match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem.to_string(), extension.to_string()),
+        _ => (file_name.clone(), String::from("html")),
+    };
+
+    let stem = match url.query() {
+        Some(query) => {
+            let mut hasher = DefaultHasher::new();
+            query.hash(&mut hasher);
+            format!("{}_{:x}", stem, hasher.finish())
+        }
+        None => stem,
+    };
+
+    path.set_file_name(format!("{}.{}", stem, extension));
+    path
+}
+
+/// Saves the HTML of the page at `url` into the mirror
+pub async fn save_page(mirror_dir: &Path, url: &Url, html: &str) -> Result<()> {
+    let path = mirror_path(mirror_dir, url);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    fs::write(path, html).await?;
+    Ok(())
+}
+
+/// The path to `to` relative to the directory `from_dir`.
+/// Both paths must be absolute.
+fn relative_path(from_dir: &Path, to: &Path) -> String {
+    let from: Vec<Component> = from_dir.components().collect();
+    let to_components: Vec<Component> = to.components().collect();
+
+    let common = from
+        .iter()
+        .zip(to_components.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..from.len() {
+        relative.push("..");
+    }
+    for component in &to_components[common..] {
+        relative.push(component.as_os_str());
+    }
+
+    // Links in HTML always use forward slashes
+    relative
+        .to_string_lossy()
+        .replace(std::path::MAIN_SEPARATOR, "/")
+}
+
+/// Calls `rewrite` with the value of every `href` and `src`
+/// attribute in `html`, replacing the value with what it returns
+fn rewrite_attributes(html: &str, rewrite: impl Fn(&str) -> Option<String>) -> String {
+    let mut output = String::with_capacity(html.len());
+    let lowercase = html.to_ascii_lowercase();
+    let mut position = 0;
+
+    while position < html.len() {
+        let next = URL_ATTRIBUTES
+            .iter()
+            .filter_map(|attr| {
+                lowercase[position..]
+                    .find(&format!("{}=", attr))
+                    .map(|i| (position + i, attr.len() + 1))
+            })
+            .min();
+
+        let Some((attr_start, attr_len)) = next else {
+            break;
+        };
+
+        let value_start = attr_start + attr_len;
+
+        // Skip matches that are only the end of another name, e.g. `data-href=`
+        let is_attribute = html[..attr_start]
+            .chars()
+            .next_back()
+            .is_none_or(|c| c.is_whitespace());
+        if !is_attribute {
+            output.push_str(&html[position..value_start]);
+            position = value_start;
+            continue;
+        }
+
+        let quote = html[value_start..].chars().next();
+        let (value_start, value_end) = match quote {
+            Some(q @ ('"' | '\'')) => {
+                let start = value_start + 1;
+                match html[start..].find(q) {
+                    Some(len) => (start, start + len),
+                    None => break,
+                }
+            }
+            _ => {
+                let len = html[value_start..]
+                    .find(|c: char| c.is_whitespace() || c == '>')
+                    .unwrap_or(html.len() - value_start);
+                (value_start, value_start + len)
+            }
+        };
+
+        output.push_str(&html[position..value_start]);
+        let value = &html[value_start..value_end];
+        match rewrite(value) {
+            Some(new_value) => output.push_str(&new_value),
+            None => output.push_str(value),
+        }
+        position = value_end;
+    }
+
+    output.push_str(&html[position..]);
+    output
+}
+
+/// Rewrites the links in every mirrored page so the mirror can be
+/// browsed offline. Links to mirrored pages and downloaded images
+/// become relative paths, every other link becomes absolute.
+pub async fn rewrite_mirror_links(
+    mirror_dir: &Path,
+    links: &LinkGraph,
+    image_paths: &HashMap<String, PathBuf>,
+    normalize_options: &NormalizeOptions,
+) -> Result<()> {
+    let mirror_dir = std::path::absolute(mirror_dir)?;
+
+    let mut page_paths: HashMap<String, PathBuf> = HashMap::new();
+    for (_, link) in links {
+        let Ok(url) = Url::parse(&link.url) else {
+            continue;
+        };
+
+        let path = mirror_path(&mirror_dir, &url);
+        if path.is_file() {
+            page_paths.insert(link_key(url.as_str()).to_string(), path);
+        }
+    }
+
+    let image_paths: HashMap<&str, PathBuf> = image_paths
+        .iter()
+        .filter_map(|(link, path)| Some((link.as_str(), std::path::absolute(path).ok()?)))
+        .collect();
+
+    for (_, link) in links {
+        let Ok(page_url) = Url::parse(&link.url) else {
+            continue;
+        };
+        let Some(page_path) = page_paths.get(link_key(page_url.as_str())) else {
+            continue;
+        };
+        let page_dir = page_path.parent().unwrap_or(&mirror_dir);
+
+        let html = match fs::read_to_string(page_path).await {
+            Ok(html) => html,
+            Err(e) => {
+                error!("could not read mirrored page {:?}: {}", page_path, e);
+                continue;
+            }
+        };
+
+        let rewritten = rewrite_attributes(&html, |value| {
+            if value.starts_with('#') || value.is_empty() {
+                return None;
+            }
+
+            let target = page_url.join(value).ok()?;
+            if !matches!(target.scheme(), "http" | "https") {
+                return None;
+            }
+
+            if let Some(image_path) = image_paths.get(target.as_str()) {
+                return Some(relative_path(page_dir, image_path));
+            }
+
+            let page = normalize_url(target.as_str(), normalize_options)?;
+            match page_paths.get(link_key(page.as_str())) {
+                Some(path) => {
+                    let fragment = target.fragment().map(|f| format!("#{}", f));
+                    Some(relative_path(page_dir, path) + fragment.as_deref().unwrap_or(""))
+                }
+                None => Some(target.to_string()),
+            }
+        });
+
+        fs::write(page_path, rewritten).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mirror_path() {
+        let dir = Path::new("/mirror");
+        let path = |url: &str| mirror_path(dir, &Url::parse(url).unwrap());
+
+        assert_eq!(path("https://example.com"), dir.join("example.com/index.html"));
+        assert_eq!(path("https://example.com/docs/"), dir.join("example.com/docs/index.html"));
+        assert_eq!(path("https://example.com/docs/intro"), dir.join("example.com/docs/intro.html"));
+        assert_eq!(path("http://localhost:3000/a.htm"), dir.join("localhost_3000/a.htm"));
+        assert_ne!(path("https://example.com/a?page=1"), path("https://example.com/a?page=2"));
+    }
+
+    #[test]
+    fn test_rewrite_attributes() {
+        let html = r#"<a HREF="/a">A</a><img src='b.png' data-src="d"><a href=c.html>C</a>"#;
+        let rewritten = rewrite_attributes(html, |v| Some(format!("x{}", v)));
+
+        assert_eq!(
+            rewritten,
+            r#"<a HREF="x/a">A</a><img src='xb.png' data-src="d"><a href=xc.html>C</a>"#
+        );
+        assert_eq!(
+            relative_path(Path::new("/m/site/docs"), Path::new("/m/site/img/a.png")),
+            "../img/a.png"
+        );
+    }
+}
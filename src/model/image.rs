@@ -1,9 +1,33 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Serialize)]
+use super::LinkId;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Image {
     /// the link for this image
     pub link: String,
     /// the alternative text found within the image
     pub alt: String,
+    /// the name the image was saved under, once downloaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+    /// the file name the server suggested in `Content-Disposition`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disposition_name: Option<String>,
+    /// the last segment of the image link's path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url_name: Option<String>,
+    /// the size of images inlined as `data:` urls, whose link
+    /// is shortened to the media type once they're saved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inline_bytes: Option<usize>,
+    /// the urls of the pages the image was found on
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pages: Vec<String>,
+    /// the ids of those pages in the link graph, in the same order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub page_ids: Vec<LinkId>,
+    /// what `--image-hook` printed about the image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
 }
@@ -1,22 +1,214 @@
 use anyhow::{anyhow, Context, Result};
-use serde::Serialize;
-use std::collections::{HashMap, HashSet};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use url::Url;
 use uuid::Uuid;
 
 use super::Image;
+use crate::caching::Caching;
+use crate::content::ContentMetrics;
+use crate::memory::string_bytes;
+use crate::url_utils::{display_url, looks_like_image, looks_like_page, SiteScope};
 
 pub type LinkId = Uuid;
 
-#[derive(Clone, Debug, Serialize)]
+/// Approximate memory used by one side of an edge in the graph
+const EDGE_BYTES: usize = std::mem::size_of::<LinkId>() * 2;
+
+/// What a link in the graph points to
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeKind {
+    /// An HTML page
+    #[default]
+    Page,
+    /// An image that was linked to rather than embedded
+    Image,
+    /// A PDF, archive or any other file that isn't a page or image
+    Document,
+    /// A url outside the crawled site
+    External,
+    /// A url that redirected to another one
+    Redirect,
+}
+
+impl NodeKind {
+    /// The kind of a link served as `media_type`, or of one that
+    /// wasn't fetched going by its url
+    pub fn classify(url: &str, media_type: Option<&str>) -> Self {
+        match media_type {
+            Some(media_type) if is_html_media_type(media_type) => NodeKind::Page,
+            Some(media_type) if media_type.starts_with("image/") => NodeKind::Image,
+            Some(_) => NodeKind::Document,
+            None if looks_like_page(url) => NodeKind::Page,
+            None if looks_like_image(url) => NodeKind::Image,
+            None => NodeKind::Document,
+        }
+    }
+}
+
+/// A heading in a page's outline
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Heading {
+    /// 1 for h1 through 6 for h6
+    pub level: u8,
+    pub text: String,
+}
+
+/// A link on a page with the text it's shown as
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Anchor {
+    pub url: String,
+    /// Empty for links around an image or icon
+    pub text: String,
+}
+
+/// How a page redirected to another url
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RedirectKind {
+    /// A 3xx response with a `Location` header
+    Http,
+    /// A `<meta http-equiv="refresh">` tag with a url
+    MetaRefresh,
+    /// An inline script setting `location`
+    Javascript,
+}
+
+/// What another version of a page is for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlternateKind {
+    /// The page's AMP version, linked with `rel="amphtml"`
+    Amp,
+    /// A version for some screens, e.g. a mobile site, linked
+    /// with `rel="alternate"` and a `media` query
+    Media,
+}
+
+/// Another version of a page with the same content
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Alternate {
+    pub url: String,
+    pub kind: AlternateKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Link {
     pub id: LinkId,
     pub url: String,
+    /// `url` with an internationalized host shown in Unicode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_url: Option<String>,
     #[serde(serialize_with = "serialize_hashset")]
     pub parents: HashSet<LinkId>,
     #[serde(serialize_with = "serialize_hashset")]
     pub children: HashSet<LinkId>,
     pub images: Vec<Image>,
-    pub titles: Vec<String>,
+    /// The page's `<title>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// The page's h1 and h2 headings. Links files from before the
+    /// title was kept apart have them with the title as `titles`
+    #[serde(default, alias = "titles")]
+    pub headings: Vec<String>,
+    /// Every h1 to h6 heading in the order they're on the page,
+    /// only recorded with --outline
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outline: Vec<Heading>,
+    /// When the page was last fetched successfully
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fetched_at: Option<DateTime<Utc>>,
+    /// Where the page's archive was saved, if it was archived
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_path: Option<String>,
+    /// The response headers captured for the page, by lowercase name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    /// Frameworks, CMSes and servers detected on the page
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub technologies: Vec<String>,
+    /// ISO 639-3 code of the language the page is written in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+    /// How many words the page has and how long it takes to read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<ContentMetrics>,
+    /// The page's best keyword phrases, best first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    /// Named entities mentioned on the page, most mentioned first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entities: Vec<String>,
+    /// Size of the page's body once decompressed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_bytes: Option<usize>,
+    /// Size of the page's body as sent, compressed if the server compressed it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transfer_bytes: Option<usize>,
+    /// Why a challenge or block page was served instead of the page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blocked: Option<String>,
+    /// The fewest clicks it takes to reach the page from the starting url,
+    /// `None` if it can't be reached by following links
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depth: Option<usize>,
+    /// The media type the page was served as, e.g. `text/html`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(default)]
+    pub kind: NodeKind,
+    /// Where the url redirected to, when it's a `NodeKind::Redirect`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirected_to: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect_kind: Option<RedirectKind>,
+    /// The page's `rel="canonical"` url, when it's another page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canonical: Option<String>,
+    /// The page's AMP and other alternate versions
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternates: Vec<Alternate>,
+    /// How many mailto:, tel: and other links that can't be crawled
+    /// are on the page, by scheme
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub scheme_counts: BTreeMap<String, usize>,
+    /// The links on the page marked nofollow, with `--respect-nofollow`
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub nofollow: BTreeSet<String>,
+    /// The page's caching headers, used to tell when it's due to be checked again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caching: Option<Caching>,
+}
+
+/// Everything kept about a page besides how it's linked. Moved
+/// out of the graph with `LinkGraph::take_content` when pages
+/// are streamed, so only what's needed for dedup stays in memory.
+#[derive(Default, Serialize)]
+pub struct LinkContent {
+    pub images: Vec<Image>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub headings: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub outline: Vec<Heading>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub technologies: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<ContentMetrics>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub entities: Vec<String>,
 }
 
 fn serialize_hashset<S>(set: &HashSet<LinkId>, serializer: S) -> Result<S::Ok, S::Error>
@@ -32,10 +224,34 @@ impl Default for Link {
         Self {
             id: Uuid::new_v4(),
             url: String::new(),
+            display_url: None,
             parents: HashSet::new(),
             children: HashSet::new(),
             images: Vec::new(),
-            titles: Vec::new(),
+            title: None,
+            headings: Vec::new(),
+            outline: Vec::new(),
+            fetched_at: None,
+            archive_path: None,
+            headers: HashMap::new(),
+            technologies: Vec::new(),
+            lang: None,
+            content: None,
+            keywords: Vec::new(),
+            entities: Vec::new(),
+            body_bytes: None,
+            transfer_bytes: None,
+            blocked: None,
+            depth: None,
+            content_type: None,
+            kind: NodeKind::default(),
+            redirected_to: None,
+            redirect_kind: None,
+            canonical: None,
+            alternates: Vec::new(),
+            scheme_counts: BTreeMap::new(),
+            nofollow: BTreeSet::new(),
+            caching: None,
         }
     }
 }
@@ -43,23 +259,100 @@ impl Default for Link {
 impl Link {
     pub fn new(url: String) -> Self {
         Self {
+            kind: NodeKind::classify(&url, None),
             id: Uuid::new_v4(),
+            display_url: display_url(&url),
             url,
             parents: HashSet::new(),
             children: HashSet::new(),
             images: Vec::new(),
-            titles: Vec::new(),
+            title: None,
+            headings: Vec::new(),
+            outline: Vec::new(),
+            fetched_at: None,
+            archive_path: None,
+            headers: HashMap::new(),
+            technologies: Vec::new(),
+            lang: None,
+            content: None,
+            keywords: Vec::new(),
+            entities: Vec::new(),
+            body_bytes: None,
+            transfer_bytes: None,
+            blocked: None,
+            depth: None,
+            content_type: None,
+            redirected_to: None,
+            redirect_kind: None,
+            canonical: None,
+            alternates: Vec::new(),
+            scheme_counts: BTreeMap::new(),
+            nofollow: BTreeSet::new(),
+            caching: None,
         }
     }
+
+    /// Whether the link is an HTML page, or redirected to one, rather
+    /// than an image, document or other file
+    pub fn is_page(&self) -> bool {
+        match self.kind {
+            NodeKind::Page => true,
+            NodeKind::Redirect => self
+                .content_type
+                .as_deref()
+                .is_none_or(is_html_media_type),
+            NodeKind::Image | NodeKind::Document | NodeKind::External => false,
+        }
+    }
+}
+
+pub fn is_html_media_type(media_type: &str) -> bool {
+    matches!(media_type, "text/html" | "application/xhtml+xml")
 }
 
-#[derive(Default, Debug, Serialize)]
+/// The key used to deduplicate links. The scheme is ignored so
+/// the http and https versions of a page end up as one node.
+pub fn link_key(url: &str) -> &str {
+    url.strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .unwrap_or(url)
+}
+
+#[derive(Default, Debug, Serialize, Deserialize)]
 pub struct LinkGraph {
     links: HashMap<LinkId, Link>,
     link_ids: HashMap<String, LinkId>,
+    /// Pages that link to urls which haven't been visited yet,
+    /// so the edges can be added once those urls are visited
+    #[serde(skip)]
+    pending_parents: HashMap<String, HashSet<LinkId>>,
+    /// Approximate memory used by the graph
+    #[serde(skip)]
+    memory_bytes: usize,
+    /// The crawled site, links outside it are `NodeKind::External`
+    #[serde(skip)]
+    site: Option<SiteScope>,
 }
 
 impl LinkGraph {
+    /// The key `url` is stored under in `link_ids`
+    fn key<'a>(&self, url: &'a str) -> &'a str {
+        match &self.site {
+            Some(site) => site.key(url),
+            None => link_key(url),
+        }
+    }
+
+    /// A graph that adds the links its pages have outside `site`
+    /// as external links, since they're never visited. Urls are
+    /// stored under keys with their hosts normalized like `site`'s.
+    pub fn with_site(site: SiteScope) -> Self {
+        Self {
+            site: Some(site),
+            ..Default::default()
+        }
+    }
+
     // Update a link
     pub fn update(
         &mut self,
@@ -67,49 +360,105 @@ impl LinkGraph {
         parent: &str,
         children: &[String],
         images: &[Image],
-        titles: &[String],
+        headings: &[String],
     ) -> Result<()> {
-        let maybe_parent = self.link_ids.get(parent).cloned();
-
-        // for each child, add their id (if it exists) to this
-        // links children
-        let valid_children: Vec<LinkId> = children
-            .iter()
-            .filter_map(|c| self.link_ids.get(c).cloned())
-            .collect();
+        let maybe_parent = self.link_ids.get(self.key(parent)).cloned();
 
         let link = self.force_get_link_id(url)?;
 
-        if let Some(parent_id) = maybe_parent {
-            link.parents.insert(parent_id);
-        }
-
-        link.children.extend(valid_children);
+        let mut added_bytes = 0;
 
         let mut seen_images = HashSet::new();
         for img in images {
             if !seen_images.contains(&img.link) {
                 seen_images.insert(img.link.clone());
+                added_bytes += string_bytes(&img.link) + string_bytes(&img.alt);
                 link.images.push(img.clone());
             }
         }
 
-        let mut seen_titles = HashSet::new();
-        for title in titles {
-            if !seen_titles.contains(title) {
-                seen_titles.insert(title.clone());
-                link.titles.push(title.clone());
+        let mut seen_headings = HashSet::new();
+        for heading in headings {
+            if !seen_headings.contains(heading) {
+                seen_headings.insert(heading.clone());
+                added_bytes += string_bytes(heading);
+                link.headings.push(heading.clone());
             }
         }
 
         let this_link_id = link.id;
+        self.memory_bytes += added_bytes;
+
+        // Every page that linked here before we visited it is a parent,
+        // not just the one we happened to queue this link from
+        let mut parents = self
+            .pending_parents
+            .remove(self.key(url))
+            .unwrap_or_default();
+        self.memory_bytes = self
+            .memory_bytes
+            .saturating_sub(string_bytes(url) + parents.len() * EDGE_BYTES);
+        parents.extend(maybe_parent);
+
+        for parent_id in parents {
+            self.add_edge(parent_id, this_link_id)?;
+        }
+
+        for child in children {
+            match self.link_ids.get(self.key(child)).cloned() {
+                Some(child_id) => self.add_edge(this_link_id, child_id)?,
+                None if self.is_external(child) => {
+                    let external = self.force_get_link_id(child)?;
+                    external.kind = NodeKind::External;
+                    let external_id = external.id;
+                    self.add_edge(this_link_id, external_id)?;
+                }
+                None => {
+                    let pending = self
+                        .pending_parents
+                        .entry(self.key(child).to_string())
+                        .or_default();
+
+                    if pending.is_empty() {
+                        self.memory_bytes += string_bytes(child);
+                    }
+                    if pending.insert(this_link_id) {
+                        self.memory_bytes += EDGE_BYTES;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_external(&self, url: &str) -> bool {
+        let Some(site) = &self.site else {
+            return false;
+        };
+        Url::parse(url)
+            .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && !site.contains(&url))
+    }
 
-        if let Some(parent_id) = maybe_parent {
-            let parent_link = self
-                .links
-                .get_mut(&parent_id)
-                .context("could not find parent link")?;
-            parent_link.children.insert(this_link_id);
+    fn add_edge(&mut self, parent_id: LinkId, child_id: LinkId) -> Result<()> {
+        if parent_id == child_id {
+            return Ok(());
+        }
+
+        let is_new = self
+            .links
+            .get_mut(&parent_id)
+            .context("could not find parent link")?
+            .children
+            .insert(child_id);
+        self.links
+            .get_mut(&child_id)
+            .context("could not find child link")?
+            .parents
+            .insert(parent_id);
+
+        if is_new {
+            self.memory_bytes += 2 * EDGE_BYTES;
         }
 
         Ok(())
@@ -119,23 +468,171 @@ impl LinkGraph {
         self.links.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    /// Approximate memory used by the graph
+    pub fn memory_bytes(&self) -> usize {
+        self.memory_bytes
+    }
+
+    pub fn get(&self, url: &str) -> Option<&Link> {
+        self.link_ids
+            .get(self.key(url))
+            .and_then(|id| self.links.get(id))
+    }
+
+    pub fn get_by_id(&self, id: &LinkId) -> Option<&Link> {
+        self.links.get(id)
+    }
+
+    pub fn get_mut(&mut self, url: &str) -> Option<&mut Link> {
+        self.link_ids
+            .get(self.key(url))
+            .and_then(|id| self.links.get_mut(id))
+    }
+
+    /// Records that `url` was fetched successfully at `fetched_at`
+    pub fn mark_fetched(&mut self, url: &str, fetched_at: DateTime<Utc>) {
+        if let Some(link) = self.get_mut(url) {
+            link.fetched_at = Some(fetched_at);
+        }
+    }
+
+    pub fn set_archive_path(&mut self, url: &str, archive_path: String) {
+        if let Some(link) = self.get_mut(url) {
+            link.archive_path = Some(archive_path);
+        }
+    }
+
+    pub fn set_headers(&mut self, url: &str, headers: HashMap<String, String>) {
+        let added_bytes: usize = headers
+            .iter()
+            .map(|(name, value)| string_bytes(name) + string_bytes(value))
+            .sum();
+
+        if let Some(link) = self.get_mut(url) {
+            link.headers = headers;
+            self.memory_bytes += added_bytes;
+        }
+    }
+
+    pub fn set_title(&mut self, url: &str, title: String) {
+        let added_bytes = string_bytes(&title);
+
+        if let Some(link) = self.get_mut(url) {
+            link.title = Some(title);
+            self.memory_bytes += added_bytes;
+        }
+    }
+
+    /// Removes everything but the links stored for `url`, returning
+    /// it. Used once it's been written somewhere else to save memory.
+    pub fn take_content(&mut self, url: &str) -> LinkContent {
+        let Some(link) = self.get_mut(url) else {
+            return LinkContent::default();
+        };
+
+        let content = LinkContent {
+            images: std::mem::take(&mut link.images),
+            title: link.title.take(),
+            headings: std::mem::take(&mut link.headings),
+            outline: std::mem::take(&mut link.outline),
+            headers: std::mem::take(&mut link.headers),
+            technologies: std::mem::take(&mut link.technologies),
+            lang: link.lang.take(),
+            content: link.content.take(),
+            keywords: std::mem::take(&mut link.keywords),
+            entities: std::mem::take(&mut link.entities),
+        };
+
+        let freed_bytes: usize = content
+            .images
+            .iter()
+            .map(|img| string_bytes(&img.link) + string_bytes(&img.alt))
+            .chain(content.title.iter().map(|title| string_bytes(title)))
+            .chain(content.headings.iter().map(|heading| string_bytes(heading)))
+            .chain(
+                content
+                    .headers
+                    .iter()
+                    .map(|(name, value)| string_bytes(name) + string_bytes(value)),
+            )
+            .sum();
+        self.memory_bytes = self.memory_bytes.saturating_sub(freed_bytes);
+        content
+    }
+
+    /// Records the file each image was saved to on the pages it was found
+    /// on. `file_name` gives the file the image link on the page url was
+    /// saved to, pages can have their own copy of an image.
+    pub fn set_image_file_names(&mut self, file_name: impl Fn(&str, &str) -> Option<String>) {
+        for link in self.links.values_mut() {
+            for image in &mut link.images {
+                if let Some(file_name) = file_name(&link.url, &image.link) {
+                    self.memory_bytes += string_bytes(&file_name);
+                    image.file_name = Some(file_name);
+                }
+            }
+        }
+    }
+
+    /// Records the click depth of every page, see `analysis::click_depths`
+    pub fn set_click_depths(&mut self, depths: &HashMap<LinkId, usize>) {
+        for (id, link) in self.links.iter_mut() {
+            link.depth = depths.get(id).copied();
+        }
+    }
+
+    /// A copy of the graph with only its pages, see `Link::is_page`,
+    /// and the links between them
+    pub fn pages_only(&self) -> LinkGraph {
+        let mut links: HashMap<LinkId, Link> = self
+            .links
+            .iter()
+            .filter(|(_, link)| link.is_page())
+            .map(|(id, link)| (*id, link.clone()))
+            .collect();
+        let page_ids: HashSet<LinkId> = links.keys().copied().collect();
+        for link in links.values_mut() {
+            link.parents.retain(|id| page_ids.contains(id));
+            link.children.retain(|id| page_ids.contains(id));
+        }
+
+        let link_ids = self
+            .link_ids
+            .iter()
+            .filter(|(_, id)| page_ids.contains(id))
+            .map(|(key, id)| (key.clone(), *id))
+            .collect();
+
+        LinkGraph {
+            links,
+            link_ids,
+            ..Default::default()
+        }
+    }
+
     pub fn link_visited(&self, url: &str) -> bool {
-        self.link_ids.get(url).is_some()
+        self.link_ids.contains_key(self.key(url))
     }
 
     /// This function will retrieve a valid link ID if the
     /// `url` is already contained within the links map.
     /// Otherwise, it will create a new Link with the
     /// given `url` and add it to the map, returning the
-    /// new link ID.
+    /// new link ID. An existing http link is upgraded to
+    /// https when seen again through `url`.
     fn force_get_link_id(&mut self, url: &str) -> Result<&mut Link> {
-        let this_link_id = if let Some(link_id) = self.link_ids.get(url) {
+        let this_link_id = if let Some(link_id) = self.link_ids.get(self.key(url)) {
             *link_id
         } else {
             let new_link = Link::new(url.to_string());
             let new_link_id = new_link.id;
 
             // add new link to the map, return its id
+            self.memory_bytes += string_bytes(url) * 2 + std::mem::size_of::<Link>();
             self.links
                 .insert(new_link_id, new_link)
                 .map_or(Ok(()), |_| Err(anyhow!("link already exists")))?;
@@ -143,10 +640,18 @@ impl LinkGraph {
             new_link_id
         };
 
-        self.link_ids.insert(url.to_string(), this_link_id);
-        self.links
+        self.link_ids.insert(self.key(url).to_string(), this_link_id);
+        let link = self
+            .links
             .get_mut(&this_link_id)
-            .ok_or_else(|| anyhow!("failed to get link"))
+            .ok_or_else(|| anyhow!("failed to get link"))?;
+
+        if url.starts_with("https://") && link.url.starts_with("http://") {
+            link.url = url.to_string();
+            link.display_url = display_url(url);
+        }
+
+        Ok(link)
     }
 
     // Get the ID for a link
@@ -160,3 +665,60 @@ impl<'a> IntoIterator for &'a LinkGraph {
         self.links.iter()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::url_utils::PortPolicy;
+
+    #[test]
+    fn test_off_site_links_are_external() {
+        let start = Url::parse("https://example.com/").unwrap();
+        let site = SiteScope::from_url(&start, PortPolicy::default()).unwrap();
+        let mut links = LinkGraph::with_site(site);
+        let children = [
+            String::from("https://example.com/about"),
+            String::from("https://other.org/page"),
+            String::from("https://other.org/logo.png"),
+        ];
+        links.update(start.as_str(), "", &children, &[], &[]).unwrap();
+
+        // Only visited pages of the site are in the graph, off-site links right away
+        assert!(links.get("https://example.com/about").is_none());
+        for url in &children[1..] {
+            let external = links.get(url).unwrap();
+            assert_eq!(external.kind, NodeKind::External);
+            assert!(links.get(start.as_str()).unwrap().children.contains(&external.id));
+        }
+        assert_eq!(links.pages_only().len(), 1);
+    }
+
+    #[test]
+    fn test_take_content_leaves_the_links() {
+        let mut links = LinkGraph::default();
+        let url = "https://example.com/";
+        let image = Image {
+            link: String::from("https://example.com/logo.png"),
+            alt: String::from("Logo"),
+            ..Default::default()
+        };
+        links
+            .update(url, "", &[], &[image], &[String::from("Welcome")])
+            .unwrap();
+        links.set_title(url, String::from("Home"));
+        let links_only_bytes = links.memory_bytes()
+            - string_bytes("Welcome")
+            - string_bytes("Home")
+            - string_bytes("https://example.com/logo.png")
+            - string_bytes("Logo");
+
+        let content = links.take_content(url);
+        assert_eq!(content.title.as_deref(), Some("Home"));
+        assert_eq!(content.headings, ["Welcome"]);
+        assert_eq!(content.images.len(), 1);
+        assert_eq!(links.memory_bytes(), links_only_bytes);
+
+        let link = links.get(url).unwrap();
+        assert!(link.title.is_none() && link.headings.is_empty() && link.images.is_empty());
+    }
+}
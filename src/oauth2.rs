@@ -0,0 +1,210 @@
+use anyhow::{bail, Context, Result};
+use reqwest::header::HeaderValue;
+use reqwest::Client;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// Tokens are refreshed this long before they expire, so one
+/// doesn't run out while a request is in flight
+const EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
+/// Where and how to get access tokens
+#[derive(Clone, Debug)]
+pub struct OAuth2Config {
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    /// Uses the refresh token flow when set, client credentials otherwise
+    pub refresh_token: Option<String>,
+    pub scope: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+    /// Some servers rotate the refresh token on every refresh
+    refresh_token: Option<String>,
+}
+
+#[derive(Default)]
+struct TokenState {
+    /// The `Authorization` header and when it stops being valid
+    access: Option<(HeaderValue, Option<Instant>)>,
+    refresh_token: Option<String>,
+}
+
+/// Gets an access token when it's first needed and a new one before
+/// it expires. Clones share the same token.
+#[derive(Clone)]
+pub struct OAuth2 {
+    config: Arc<OAuth2Config>,
+    client: Client,
+    state: Arc<Mutex<TokenState>>,
+}
+
+impl OAuth2 {
+    pub fn new(config: OAuth2Config) -> Self {
+        let state = TokenState {
+            access: None,
+            refresh_token: config.refresh_token.clone(),
+        };
+
+        Self {
+            config: Arc::new(config),
+            client: crate::crawler::create_client(),
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// The `Authorization` header to send, getting a new token if needed
+    pub async fn authorization(&self) -> Result<HeaderValue> {
+        let mut state = self.state.lock().await;
+        if let Some((authorization, expires_at)) = &state.access {
+            if expires_at.is_none_or(|expires_at| Instant::now() < expires_at) {
+                return Ok(authorization.clone());
+            }
+        }
+
+        let token = self.fetch_token(state.refresh_token.as_deref()).await?;
+        let mut authorization = HeaderValue::from_str(&format!("Bearer {}", token.access_token))
+            .context("the token server sent an invalid access token")?;
+        authorization.set_sensitive(true);
+
+        let expires_at = token.expires_in.map(|expires_in| {
+            Instant::now() + Duration::from_secs(expires_in).saturating_sub(EXPIRY_MARGIN)
+        });
+        state.access = Some((authorization.clone(), expires_at));
+        if token.refresh_token.is_some() {
+            state.refresh_token = token.refresh_token;
+        }
+
+        Ok(authorization)
+    }
+
+    /// Drops the current token after a server rejected it, so
+    /// the next request gets a new one
+    pub async fn invalidate(&self) {
+        self.state.lock().await.access = None;
+    }
+
+    async fn fetch_token(&self, refresh_token: Option<&str>) -> Result<TokenResponse> {
+        let config = &self.config;
+        let mut form = vec![("client_id", config.client_id.as_str())];
+        match refresh_token {
+            Some(refresh_token) => {
+                form.push(("grant_type", "refresh_token"));
+                form.push(("refresh_token", refresh_token));
+            }
+            None => form.push(("grant_type", "client_credentials")),
+        }
+        if let Some(client_secret) = &config.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+        if let Some(scope) = &config.scope {
+            form.push(("scope", scope));
+        }
+
+        let response = self
+            .client
+            .post(&config.token_url)
+            .form(&form)
+            .send()
+            .await
+            .context("could not reach the token server")?;
+        if !response.status().is_success() {
+            bail!(
+                "the token server refused to issue a token: {}",
+                response.status()
+            );
+        }
+
+        response
+            .json()
+            .await
+            .context("the token server sent an invalid response")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers every request with the next of `bodies`, sending back
+    /// the request bodies it got
+    async fn token_server(
+        bodies: Vec<&'static str>,
+    ) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for response_body in bodies {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                // Read until the whole form body has arrived
+                let mut request = String::new();
+                let mut buffer = [0; 4096];
+                let request_body = loop {
+                    let read = stream.read(&mut buffer).await.unwrap();
+                    request.push_str(&String::from_utf8_lossy(&buffer[..read]));
+                    let Some((head, body)) = request.split_once("\r\n\r\n") else {
+                        continue;
+                    };
+                    let content_length = head
+                        .lines()
+                        .find_map(|line| {
+                            line.to_lowercase()
+                                .strip_prefix("content-length: ")?
+                                .parse()
+                                .ok()
+                        })
+                        .unwrap_or(0);
+                    if body.len() >= content_length || read == 0 {
+                        break body.to_string();
+                    }
+                };
+                requests.push(request_body);
+
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    response_body.len(),
+                    response_body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+            requests
+        });
+
+        (url, server)
+    }
+
+    #[tokio::test]
+    async fn test_refreshes_expired_tokens() {
+        let (token_url, server) = token_server(vec![
+            r#"{"access_token":"first","expires_in":10,"refresh_token":"rotated"}"#,
+            r#"{"access_token":"second","expires_in":3600}"#,
+        ])
+        .await;
+        let oauth2 = OAuth2::new(OAuth2Config {
+            token_url,
+            client_id: String::from("crawler"),
+            client_secret: None,
+            refresh_token: Some(String::from("original")),
+            scope: None,
+        });
+
+        // Expires within the margin, so it's refreshed straight away
+        assert_eq!(oauth2.authorization().await.unwrap(), "Bearer first");
+        assert_eq!(oauth2.authorization().await.unwrap(), "Bearer second");
+        assert_eq!(oauth2.authorization().await.unwrap(), "Bearer second");
+
+        let requests = server.await.unwrap();
+        assert!(requests[0].contains("refresh_token=original"));
+        assert!(requests[1].contains("refresh_token=rotated"));
+    }
+}
@@ -0,0 +1,86 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use tokio::fs;
+use url::Url;
+
+use crate::file_names::sanitize_file_name;
+use crate::model::{Link, LinkGraph, LinkId};
+
+/// How the links file is split up
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum SplitOutput {
+    /// One links file per host, plus an index of them
+    ByHost,
+}
+
+/// The links of a single host, shaped like the full links file
+#[derive(Serialize)]
+struct HostLinks<'a> {
+    links: HashMap<&'a LinkId, &'a Link>,
+}
+
+#[derive(Serialize)]
+struct HostIndexEntry {
+    host: String,
+    file: String,
+    links: usize,
+}
+
+#[derive(Serialize)]
+struct HostIndex {
+    hosts: Vec<HostIndexEntry>,
+}
+
+pub async fn serialize_links(links: &LinkGraph, destination: &str) -> Result<()> {
+    let json = serde_json::to_string(links)?;
+    fs::write(destination, json).await?;
+    Ok(())
+}
+
+/// Writes the links of every host to `<destination stem>.<host>.json`
+/// and an index of those files to `destination`
+pub async fn serialize_links_by_host(links: &LinkGraph, destination: &str) -> Result<()> {
+    let mut by_host: BTreeMap<String, HostLinks> = BTreeMap::new();
+    for (id, link) in links {
+        let host = Url::parse(&link.url)
+            .ok()
+            .and_then(|url| url.host_str().map(|h| h.to_string()))
+            .unwrap_or_else(|| String::from("unknown"));
+
+        by_host
+            .entry(host)
+            .or_insert_with(|| HostLinks {
+                links: HashMap::new(),
+            })
+            .links
+            .insert(id, link);
+    }
+
+    let destination = Path::new(destination);
+    let stem = destination
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("links");
+
+    let mut index = HostIndex { hosts: Vec::new() };
+    for (host, host_links) in by_host {
+        let file_name = sanitize_file_name(&format!("{}.{}.json", stem, host));
+        fs::write(
+            destination.with_file_name(&file_name),
+            serde_json::to_string(&host_links)?,
+        )
+        .await?;
+
+        index.hosts.push(HostIndexEntry {
+            host,
+            file: file_name,
+            links: host_links.links.len(),
+        });
+    }
+
+    fs::write(destination, serde_json::to_string(&index)?).await?;
+    Ok(())
+}
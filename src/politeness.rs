@@ -0,0 +1,385 @@
+use anyhow::{bail, Context, Result};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, OwnedSemaphorePermit, Semaphore};
+use tokio::time::{sleep_until, Instant};
+use url::Url;
+
+/// Spaces out requests to the same host. Requests to
+/// different hosts don't hold each other up.
+pub struct HostRateLimiter {
+    delay: Duration,
+    /// When the next request to each host may be sent
+    next_request: Mutex<HashMap<String, Instant>>,
+}
+
+impl HostRateLimiter {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            next_request: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Waits until a request may be sent to `host`
+    pub async fn wait(&self, host: &str) {
+        self.wait_for(host, self.delay).await;
+    }
+
+    /// Reserves the next request to `host` if it may be sent right
+    /// away, otherwise returns when it may be sent without waiting
+    pub async fn try_wait(&self, host: &str) -> Result<(), Instant> {
+        self.try_wait_for(host, self.delay).await
+    }
+
+    /// Like `try_wait`, spacing the next request
+    /// `delay` after it instead of the usual delay
+    pub async fn try_wait_for(&self, host: &str, delay: Duration) -> Result<(), Instant> {
+        let mut next_request = self.next_request.lock().await;
+        let now = Instant::now();
+        if let Some(next) = next_request.get(host).filter(|next| **next > now) {
+            return Err(*next);
+        }
+        next_request.insert(host.to_string(), now + delay);
+        Ok(())
+    }
+
+    /// Waits until a request may be sent to `host`, spacing
+    /// the next one `delay` after it instead of the usual delay
+    pub async fn wait_for(&self, host: &str, delay: Duration) {
+        let send_at = {
+            let mut next_request = self.next_request.lock().await;
+            let now = Instant::now();
+            let send_at = next_request.get(host).map_or(now, |next| (*next).max(now));
+            next_request.insert(host.to_string(), send_at + delay);
+            send_at
+        };
+
+        sleep_until(send_at).await;
+    }
+}
+
+/// How long `Politeness::try_wait` says to wait when a host already has
+/// as many requests in flight as its concurrency allows, since there's
+/// no telling when one of them will be done
+const BUSY_HOST_RETRY: Duration = Duration::from_millis(50);
+
+/// How one host pattern in a politeness file is crawled
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HostConfig {
+    delay_ms: Option<u64>,
+    concurrency: Option<usize>,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+}
+
+/// Which hosts a rule is for: `example.com` is only that host,
+/// `*.example.com` its subdomains and `*` every host
+#[derive(Debug, PartialEq)]
+enum HostPattern {
+    Host(String),
+    Subdomains(String),
+    Any,
+}
+
+impl HostPattern {
+    fn parse(pattern: &str) -> Self {
+        let pattern = pattern.trim().to_lowercase();
+        if pattern == "*" {
+            return HostPattern::Any;
+        }
+        match pattern.strip_prefix("*.") {
+            Some(domain) => HostPattern::Subdomains(domain.to_string()),
+            None => HostPattern::Host(pattern),
+        }
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        match self {
+            HostPattern::Host(pattern) => host == pattern,
+            HostPattern::Subdomains(domain) => host
+                .strip_suffix(domain.as_str())
+                .is_some_and(|subdomain| subdomain.ends_with('.')),
+            HostPattern::Any => true,
+        }
+    }
+
+    /// Rules for fewer hosts win over rules for more
+    fn specificity(&self) -> usize {
+        match self {
+            HostPattern::Host(_) => usize::MAX,
+            HostPattern::Subdomains(domain) => domain.len(),
+            HostPattern::Any => 0,
+        }
+    }
+}
+
+struct HostRule {
+    pattern: HostPattern,
+    delay: Option<Duration>,
+    concurrency: Option<usize>,
+    headers: HeaderMap,
+}
+
+/// How pages are requested from each host: spaced out by a delay, with
+/// overrides from a politeness file so one crawl can go fast on the
+/// user's own site but slow on others. Images are downloaded through
+/// the same `Politeness`, so a host serving both isn't sent more.
+pub struct Politeness {
+    /// Most specific first, only the first matching rule is used
+    rules: Vec<HostRule>,
+    /// Between requests to hosts without a delay override
+    default_delay: Duration,
+    limiter: HostRateLimiter,
+    /// Limits requests in flight to each host with a concurrency
+    slots: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl Politeness {
+    /// Spaces out requests to every host by `default_delay`
+    pub fn new(default_delay: Duration) -> Self {
+        Self {
+            rules: Vec::new(),
+            default_delay,
+            limiter: HostRateLimiter::new(default_delay),
+            slots: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Reads a politeness file, a TOML table for every host pattern:
+    ///
+    /// ```toml
+    /// ["*.cdn.example.com"]
+    /// delay_ms = 2000
+    /// concurrency = 1
+    /// headers = { "X-Crawler-Contact" = "seo@example.com" }
+    /// ```
+    ///
+    /// Hosts without a `delay_ms` are spaced out by `default_delay`
+    pub fn load(path: &str, default_delay: Duration) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read the politeness overrides in {}", path))?;
+        Self::parse(&contents, default_delay)
+            .with_context(|| format!("invalid politeness file {}", path))
+    }
+
+    fn parse(contents: &str, default_delay: Duration) -> Result<Self> {
+        let hosts: BTreeMap<String, HostConfig> = toml::from_str(contents)?;
+
+        let mut rules = Vec::new();
+        for (pattern, config) in hosts {
+            if config.concurrency == Some(0) {
+                bail!("the concurrency for {} has to be at least 1", pattern);
+            }
+            let mut headers = HeaderMap::new();
+            for (name, value) in &config.headers {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .with_context(|| format!("invalid header name {} for {}", name, pattern))?;
+                let value = HeaderValue::from_str(value).with_context(|| {
+                    format!("invalid value for header {} for {}", name, pattern)
+                })?;
+                headers.insert(name, value);
+            }
+
+            rules.push(HostRule {
+                pattern: HostPattern::parse(&pattern),
+                delay: config.delay_ms.map(Duration::from_millis),
+                concurrency: config.concurrency,
+                headers,
+            });
+        }
+        rules.sort_by_key(|rule| std::cmp::Reverse(rule.pattern.specificity()));
+
+        Ok(Self {
+            rules,
+            ..Self::new(default_delay)
+        })
+    }
+
+    /// How many host patterns have overrides
+    pub fn host_count(&self) -> usize {
+        self.rules.len()
+    }
+
+    fn rule(&self, host: &str) -> Option<&HostRule> {
+        self.rules.iter().find(|rule| rule.pattern.matches(host))
+    }
+
+    /// The headers to add to requests to `url`
+    pub fn headers(&self, url: &Url) -> Option<&HeaderMap> {
+        self.rule(&url.host_str()?.to_lowercase())
+            .map(|rule| &rule.headers)
+    }
+
+    /// The requests that may be in flight to `host` at once
+    async fn slots(&self, host: &str, concurrency: usize) -> Arc<Semaphore> {
+        self.slots
+            .lock()
+            .await
+            .entry(host.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(concurrency)))
+            .clone()
+    }
+
+    fn delay(&self, rule: Option<&HostRule>) -> Duration {
+        rule.and_then(|rule| rule.delay)
+            .unwrap_or(self.default_delay)
+    }
+
+    /// Waits until a request to `url` may be sent. The permit that's
+    /// returned has to be held until the response has arrived
+    pub async fn wait(&self, url: &Url) -> Option<OwnedSemaphorePermit> {
+        let host = url.host_str()?.to_lowercase();
+        let rule = self.rule(&host);
+
+        let permit = match rule.and_then(|rule| rule.concurrency) {
+            Some(concurrency) => {
+                let slots = self.slots(&host, concurrency).await;
+                slots.acquire_owned().await.ok()
+            }
+            None => None,
+        };
+        let delay = self.delay(rule);
+        if !delay.is_zero() {
+            self.limiter.wait_for(&host, delay).await;
+        }
+
+        permit
+    }
+
+    /// Like `wait`, but instead of waiting returns when to try
+    /// again if a request to `url` can't be sent right away
+    pub async fn try_wait(&self, url: &Url) -> Result<Option<OwnedSemaphorePermit>, Instant> {
+        let Some(host) = url.host_str().map(str::to_lowercase) else {
+            return Ok(None);
+        };
+        let rule = self.rule(&host);
+
+        let permit = match rule.and_then(|rule| rule.concurrency) {
+            Some(concurrency) => {
+                let slots = self.slots(&host, concurrency).await;
+                match slots.try_acquire_owned() {
+                    Ok(permit) => Some(permit),
+                    Err(_) => return Err(Instant::now() + BUSY_HOST_RETRY),
+                }
+            }
+            None => None,
+        };
+        let delay = self.delay(rule);
+        if !delay.is_zero() {
+            self.limiter.try_wait_for(&host, delay).await?;
+        }
+
+        Ok(permit)
+    }
+}
+
+/// Parses `--max-requests-per-second`, which has to be above zero
+pub fn parse_requests_per_second(rate: &str) -> Result<f64> {
+    match rate.trim().parse::<f64>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(rate),
+        _ => bail!("invalid request rate '{}', it has to be above 0", rate),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_requests_to_a_host_are_spaced_out() {
+        let limiter = HostRateLimiter::new(Duration::from_millis(50));
+        let start = Instant::now();
+
+        limiter.wait("example.com").await;
+        limiter.wait("example.org").await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        limiter.wait("example.com").await;
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // Only reserves the request if it can be sent right away
+        let ready_at = limiter.try_wait("example.com").await.unwrap_err();
+        assert!(ready_at >= start + Duration::from_millis(100));
+        assert!(limiter.try_wait("example.net").await.is_ok());
+        assert!(limiter.try_wait("example.net").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_politeness_overrides() {
+        let politeness = Politeness::parse(
+            r#"
+            ["*"]
+            delay_ms = 1000
+
+            ["www.example.com"]
+            concurrency = 2
+
+            ["*.example.com"]
+            delay_ms = 50
+            concurrency = 1
+            headers = { "X-Crawler-Contact" = "seo@example.com" }
+            "#,
+            Duration::ZERO,
+        )
+        .unwrap();
+        let url = |url: &str| Url::parse(url).unwrap();
+
+        let rule = |host: &str| politeness.rule(host).map(|rule| &rule.pattern);
+        assert_eq!(
+            rule("www.example.com"),
+            Some(&HostPattern::Host(String::from("www.example.com")))
+        );
+        assert_eq!(
+            rule("cdn.example.com"),
+            Some(&HostPattern::Subdomains(String::from("example.com")))
+        );
+        assert_eq!(rule("example.com"), Some(&HostPattern::Any));
+        assert!(politeness
+            .headers(&url("https://WWW.example.com/"))
+            .is_some());
+        assert_eq!(
+            politeness
+                .headers(&url("https://cdn.example.com/"))
+                .and_then(|headers| headers.get("x-crawler-contact")),
+            Some(&HeaderValue::from_static("seo@example.com"))
+        );
+
+        // The second request waits for the first one's permit and delay
+        let start = Instant::now();
+        let cdn = url("https://cdn.example.com/");
+        let permit = politeness.wait(&cdn).await;
+        assert!(permit.is_some());
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), politeness.wait(&cdn))
+                .await
+                .is_err()
+        );
+        drop(permit);
+        politeness.wait(&cdn).await;
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // Says when to try again instead of waiting
+        let permit = politeness.try_wait(&url("https://img.example.com/")).await;
+        assert!(permit.unwrap().is_some());
+        assert!(politeness.try_wait(&cdn).await.is_err());
+
+        let parse = |contents| Politeness::parse(contents, Duration::ZERO);
+        assert!(parse("[\"example.com\"]\nconcurrency = 0").is_err());
+        assert!(parse("[\"example.com\"]\ndelay = 5").is_err());
+
+        // Hosts without an override are spaced out by the default delay
+        let politeness = Politeness::new(Duration::from_millis(50));
+        let start = Instant::now();
+        politeness.wait(&url("https://example.org/")).await;
+        politeness.wait(&url("https://example.org/about")).await;
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        assert_eq!(parse_requests_per_second("2.5").unwrap(), 2.5);
+        assert!(parse_requests_per_second("0").is_err());
+    }
+}
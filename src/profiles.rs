@@ -0,0 +1,200 @@
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use tokio::fs;
+
+use crate::external_links::csv_field;
+use crate::model::{Link, LinkGraph};
+
+/// A user to crawl the site as, anonymous without a session
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    /// A HAR or cookies.txt file with the user's session, as with --import-session
+    pub session: Option<String>,
+}
+
+/// Parses a profile written as `name` or `name=<session file>`
+pub fn parse_profile(profile: &str) -> Result<Profile> {
+    let (name, session) = match profile.split_once('=') {
+        Some((name, session)) => (name, Some(session.to_string())),
+        None => (profile, None),
+    };
+
+    // Each profile's results are saved to a directory named after it
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        bail!("profile names may only have letters, digits, '-' and '_'");
+    }
+    if session.as_deref() == Some("") {
+        bail!("the session file of profile '{}' is empty", name);
+    }
+
+    Ok(Profile {
+        name: name.to_string(),
+        session,
+    })
+}
+
+/// What a profile got when it asked for a page
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Visible,
+    /// A challenge, login wall or other block page was served instead
+    Blocked,
+    /// Linked to but not fetched, e.g. the server refused or errored
+    NotFetched,
+}
+
+impl Access {
+    fn of(link: &Link) -> Self {
+        if link.blocked.is_some() {
+            Access::Blocked
+        } else if link.fetched_at.is_some() {
+            Access::Visible
+        } else {
+            Access::NotFetched
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Access::Visible => "visible",
+            Access::Blocked => "blocked",
+            Access::NotFetched => "not_fetched",
+        }
+    }
+}
+
+/// Which pages each profile could see, to find pages only some users can get to
+pub struct ProfileComparison {
+    profiles: Vec<String>,
+    /// By url, one entry per profile, `None` if the profile never found the page
+    pages: BTreeMap<String, Vec<Option<Access>>>,
+}
+
+impl ProfileComparison {
+    pub fn new(profiles: Vec<String>) -> Self {
+        Self {
+            profiles,
+            pages: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the pages the profile at `index` found
+    pub fn add(&mut self, index: usize, link_graph: &LinkGraph) {
+        for (_, link) in link_graph.into_iter().filter(|(_, link)| link.is_page()) {
+            let access = self
+                .pages
+                .entry(link.url.clone())
+                .or_insert_with(|| vec![None; self.profiles.len()]);
+            access[index] = Some(Access::of(link));
+        }
+    }
+
+    /// How many pages every profile could see
+    pub fn visible_to_all(&self) -> usize {
+        self.pages
+            .values()
+            .filter(|access| access.iter().all(|access| *access == Some(Access::Visible)))
+            .count()
+    }
+
+    /// How many pages each profile could see that some other profile couldn't
+    pub fn visible_to_some(&self) -> Vec<(&str, usize)> {
+        let partly_visible: Vec<_> = self
+            .pages
+            .values()
+            .filter(|access| access.iter().any(|access| *access != Some(Access::Visible)))
+            .collect();
+
+        self.profiles
+            .iter()
+            .enumerate()
+            .map(|(i, profile)| {
+                let count = partly_visible
+                    .iter()
+                    .filter(|access| access[i] == Some(Access::Visible))
+                    .count();
+                (profile.as_str(), count)
+            })
+            .collect()
+    }
+
+    /// One row per page with what every profile got, empty where it never found the page
+    fn to_csv(&self) -> String {
+        let mut csv = format!("url,{}\n", self.profiles.join(","));
+        for (url, access) in &self.pages {
+            let access: Vec<_> = access
+                .iter()
+                .map(|access| access.map(Access::as_str).unwrap_or_default())
+                .collect();
+            let _ = writeln!(csv, "{},{}", csv_field(url), access.join(","));
+        }
+        csv
+    }
+
+    pub async fn write_csv(&self, destination: &str) -> Result<()> {
+        fs::write(destination, self.to_csv()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_profiles() {
+        assert_eq!(
+            parse_profile("admin=admin.har").unwrap(),
+            Profile {
+                name: String::from("admin"),
+                session: Some(String::from("admin.har")),
+            }
+        );
+        assert!(parse_profile("../etc=a.har").is_err());
+
+        // Pages are in a graph once they've been visited, fetched or not
+        let crawl = |fetched: &[&str], not_fetched: &[&str]| {
+            let mut link_graph = LinkGraph::default();
+            for url in fetched.iter().chain(not_fetched) {
+                link_graph.update(url, "", &[], &[], &[]).unwrap();
+            }
+            for url in fetched {
+                link_graph.mark_fetched(url, chrono::Utc::now());
+            }
+            link_graph
+        };
+        let anonymous = crawl(&["https://example.com/"], &["https://example.com/admin"]);
+        let admin = crawl(
+            &[
+                "https://example.com/",
+                "https://example.com/admin",
+                "https://example.com/users",
+            ],
+            &[],
+        );
+
+        let mut comparison =
+            ProfileComparison::new(vec![String::from("anonymous"), String::from("admin")]);
+        comparison.add(0, &anonymous);
+        comparison.add(1, &admin);
+
+        assert_eq!(comparison.visible_to_all(), 1);
+        assert_eq!(
+            comparison.visible_to_some(),
+            [("anonymous", 0), ("admin", 2)]
+        );
+        assert_eq!(
+            comparison.to_csv(),
+            "url,anonymous,admin\n\
+             https://example.com/,visible,visible\n\
+             https://example.com/admin,not_fetched,visible\n\
+             https://example.com/users,,visible\n"
+        );
+    }
+}
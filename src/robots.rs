@@ -0,0 +1,302 @@
+use anyhow::{bail, Result};
+use reqwest::{Client, StatusCode};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+use url::{Position, Url};
+
+use crate::crawler::USER_AGENT;
+
+/// A single `Allow:` or `Disallow:` line
+#[derive(Debug, PartialEq)]
+pub struct RobotsRule {
+    pub allow: bool,
+    /// The path pattern, may use `*` and a trailing `$`
+    pub pattern: String,
+    /// The 1-based line the rule is on
+    pub line: usize,
+}
+
+/// The rules for one or more `User-agent:` lines
+#[derive(Debug, Default)]
+pub struct RobotsGroup {
+    pub user_agents: Vec<String>,
+    pub rules: Vec<RobotsRule>,
+    /// The 1-based line the group starts on
+    pub line: usize,
+}
+
+/// The parts of a robots.txt file the crawler cares about
+#[derive(Debug, Default)]
+pub struct RobotsTxt {
+    /// Every `Sitemap:` directive found in the file, in order
+    pub sitemaps: Vec<String>,
+    pub groups: Vec<RobotsGroup>,
+}
+
+impl RobotsTxt {
+    /// The groups that apply to `user_agent`: the ones naming the longest
+    /// part of it, or the `*` groups if none name it at all
+    pub fn groups_for(&self, user_agent: &str) -> Vec<&RobotsGroup> {
+        let user_agent = user_agent.to_lowercase();
+        let specificity = |group: &RobotsGroup| {
+            group
+                .user_agents
+                .iter()
+                .map(|agent| agent.to_lowercase())
+                .filter(|agent| agent != "*" && user_agent.contains(agent.as_str()))
+                .map(|agent| agent.len())
+                .max()
+        };
+
+        let best = self.groups.iter().filter_map(specificity).max();
+        match best {
+            Some(best) => self
+                .groups
+                .iter()
+                .filter(|group| specificity(group) == Some(best))
+                .collect(),
+            None => self
+                .groups
+                .iter()
+                .filter(|group| group.user_agents.iter().any(|agent| agent == "*"))
+                .collect(),
+        }
+    }
+
+    /// The rule deciding whether `user_agent` may fetch `url`. The longest
+    /// matching pattern wins, and `Allow` wins over `Disallow` on a tie.
+    pub fn matching_rule(&self, user_agent: &str, url: &Url) -> Option<&RobotsRule> {
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+
+        self.groups_for(user_agent)
+            .into_iter()
+            .flat_map(|group| &group.rules)
+            .filter(|rule| pattern_matches(&rule.pattern, &path))
+            .max_by_key(|rule| (rule.pattern.len(), rule.allow))
+    }
+
+    pub fn is_allowed(&self, user_agent: &str, url: &Url) -> bool {
+        url.path() == "/robots.txt"
+            || self
+                .matching_rule(user_agent, url)
+                .is_none_or(|rule| rule.allow)
+    }
+}
+
+/// The robots.txt of each host, fetched the first time one of its urls is
+/// checked. Shared by the crawl and the image downloader
+#[derive(Default)]
+pub struct RobotsCache {
+    /// `None` if it couldn't be fetched, which allows everything
+    hosts: Mutex<HashMap<String, Arc<OnceCell<Option<RobotsTxt>>>>>,
+}
+
+impl RobotsCache {
+    /// Whether robots.txt lets us fetch `url`, `fetch`
+    /// is run to get it if its host hasn't been checked yet
+    pub async fn allows(&self, url: &Url, fetch: impl Future<Output = Option<RobotsTxt>>) -> bool {
+        let origin = &url[..Position::AfterPort];
+        let cell = self
+            .hosts
+            .lock()
+            .unwrap()
+            .entry(origin.to_string())
+            .or_default()
+            .clone();
+
+        cell.get_or_init(|| fetch)
+            .await
+            .as_ref()
+            .is_none_or(|robots| robots.is_allowed(USER_AGENT, url))
+    }
+}
+
+/// Whether a robots.txt path pattern matches `path`. `*` matches any
+/// characters and a trailing `$` anchors the pattern to the end.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+
+    let mut pieces = pattern.split('*');
+    let first = pieces.next().unwrap_or_default();
+    let Some(mut rest) = path.strip_prefix(first) else {
+        return false;
+    };
+
+    let pieces: Vec<&str> = pieces.collect();
+    for (i, piece) in pieces.iter().enumerate() {
+        let is_last = i == pieces.len() - 1;
+        if is_last && anchored {
+            return rest.ends_with(piece);
+        }
+
+        match rest.find(piece) {
+            Some(index) => rest = &rest[index + piece.len()..],
+            None => return false,
+        }
+    }
+
+    !anchored || rest.is_empty()
+}
+
+/// Parses the contents of a robots.txt file. Unknown
+/// directives and malformed lines are ignored.
+pub fn parse_robots(contents: &str) -> RobotsTxt {
+    let mut robots = RobotsTxt::default();
+    // Consecutive user-agent lines share a group, until a rule ends it
+    let mut group_has_rules = true;
+
+    for (index, line) in contents.lines().enumerate() {
+        // Anything after a '#' is a comment
+        let line = line.split('#').next().unwrap_or("").trim();
+
+        let Some((directive, value)) = line.split_once(':') else {
+            continue;
+        };
+
+        let directive = directive.trim().to_ascii_lowercase();
+        let value = value.trim();
+
+        match directive.as_str() {
+            "user-agent" if !value.is_empty() => {
+                if group_has_rules {
+                    robots.groups.push(RobotsGroup {
+                        line: index + 1,
+                        ..Default::default()
+                    });
+                    group_has_rules = false;
+                }
+                if let Some(group) = robots.groups.last_mut() {
+                    group.user_agents.push(value.to_string());
+                }
+            }
+            "allow" | "disallow" => {
+                group_has_rules = true;
+                // An empty disallow allows everything, same as no rule
+                if value.is_empty() {
+                    continue;
+                }
+
+                if let Some(group) = robots.groups.last_mut() {
+                    group.rules.push(RobotsRule {
+                        allow: directive == "allow",
+                        pattern: value.to_string(),
+                        line: index + 1,
+                    });
+                }
+            }
+            "sitemap" if !value.is_empty() => robots.sitemaps.push(value.to_string()),
+            _ => {}
+        }
+    }
+
+    robots
+}
+
+/// The robots.txt url for the host of `url`
+pub fn robots_url(url: &Url) -> Result<Url> {
+    Ok(url.join("/robots.txt")?)
+}
+
+/// Fetches and parses the robots.txt for the host of `url`
+pub async fn fetch_robots(url: &Url, client: &Client) -> Result<RobotsTxt> {
+    let response = client.get(robots_url(url)?).send().await?;
+
+    if response.status() != StatusCode::OK {
+        bail!("robots.txt returned status {}", response.status());
+    }
+
+    Ok(parse_robots(&response.text().await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sitemap_directives() {
+        let robots = parse_robots(
+            "User-agent: *\n\
+             Disallow: /private\n\
+             Sitemap: https://example.com/sitemap.xml # main\n\
+             sitemap:https://example.com/news.xml\n\
+             Sitemap:\n",
+        );
+
+        assert_eq!(
+            robots.sitemaps,
+            vec![
+                "https://example.com/sitemap.xml",
+                "https://example.com/news.xml"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_allow_and_disallow_rules() {
+        let robots = parse_robots(
+            "User-agent: *\n\
+             Disallow: /private\n\
+             Allow: /private/public\n\
+             \n\
+             User-agent: BadBot\n\
+             User-agent: HyperCrawler\n\
+             Disallow: /*.pdf$\n\
+             Disallow:\n",
+        );
+        let url = |path: &str| Url::parse(&format!("https://example.com{}", path)).unwrap();
+
+        assert!(!robots.is_allowed("SomeBot/2.0", &url("/private/a")));
+        assert!(robots.is_allowed("SomeBot/2.0", &url("/private/public/a")));
+
+        // The HyperCrawler group replaces the `*` group entirely
+        assert!(robots.is_allowed("HyperCrawler/1.0", &url("/private/a")));
+        assert!(!robots.is_allowed("HyperCrawler/1.0", &url("/docs/a.pdf")));
+        assert!(robots.is_allowed("HyperCrawler/1.0", &url("/docs/a.pdf?dl=1")));
+        assert_eq!(
+            robots
+                .matching_rule("hypercrawler", &url("/a.pdf"))
+                .map(|rule| rule.line),
+            Some(7)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_robots_cache() {
+        let cache = RobotsCache::default();
+        let fetches = std::sync::atomic::AtomicUsize::new(0);
+        let fetch = || async {
+            fetches.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            Some(parse_robots("User-agent: *\nDisallow: /private\n"))
+        };
+        let url = |url: &str| Url::parse(url).unwrap();
+
+        assert!(
+            !cache
+                .allows(&url("https://example.com/private/a"), fetch())
+                .await
+        );
+        assert!(cache.allows(&url("https://example.com/a"), fetch()).await);
+        assert_eq!(fetches.load(std::sync::atomic::Ordering::Relaxed), 1);
+
+        // Each host has its own, one that can't be fetched allows everything
+        assert!(
+            cache
+                .allows(&url("https://other.org/private"), async { None })
+                .await
+        );
+        assert!(
+            !cache
+                .allows(&url("https://example.com:8080/private"), fetch())
+                .await
+        );
+        assert_eq!(fetches.load(std::sync::atomic::Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,144 @@
+use anyhow::{anyhow, bail, Result};
+use reqwest::{Client, StatusCode};
+use std::error::Error;
+use url::Url;
+
+use crate::crawler::{media_type, PageClient, LINK_REQUEST_TIMEOUT_S, USER_AGENT};
+use crate::model::is_html_media_type;
+use crate::robots;
+
+/// Fetches the starting url before the crawl starts, so a seed that can't
+/// be crawled fails with why instead of ending in an empty link graph
+pub async fn check_seed(url: &Url, client: &PageClient, robots_client: &Client) -> Result<()> {
+    // A missing or broken robots.txt allows everything
+    if let Ok(robots) = robots::fetch_robots(url, robots_client).await {
+        if !robots.is_allowed(USER_AGENT, url) {
+            let line = robots
+                .matching_rule(USER_AGENT, url)
+                .map(|rule| format!(" (`Disallow: {}` on line {})", rule.pattern, rule.line))
+                .unwrap_or_default();
+            bail!("robots.txt disallows crawling {}{}", url, line);
+        }
+    }
+
+    let response = client.get(url).await.map_err(|e| describe_error(url, e))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let hint = match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                ", if it needs a login use --basic-auth, --bearer-token or --import-session"
+            }
+            _ => "",
+        };
+        bail!("{} returned {}{}", url, status, hint);
+    }
+
+    if let Some(media_type) = media_type(response.headers()) {
+        if !is_html_media_type(&media_type) {
+            bail!(
+                "{} is {}, not an HTML page with links to follow",
+                url,
+                media_type
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Says what went wrong in words, reqwest's errors bury it in their sources
+fn describe_error(url: &Url, e: anyhow::Error) -> anyhow::Error {
+    let Some(reqwest_error) = e.downcast_ref::<reqwest::Error>() else {
+        return e;
+    };
+    let host = url.host_str().unwrap_or_default();
+
+    if reqwest_error.is_timeout() {
+        return anyhow!(
+            "{} didn't respond within {} seconds",
+            url,
+            LINK_REQUEST_TIMEOUT_S
+        );
+    }
+    if reqwest_error.is_connect() {
+        // The innermost source is the one saying why, e.g. the connection was refused
+        let mut cause = e.to_string();
+        let mut source = reqwest_error.source();
+        while let Some(error) = source {
+            cause = error.to_string();
+            if cause.contains("dns error") {
+                return anyhow!("could not look up {}, check the host name", host);
+            }
+            source = error.source();
+        }
+        let port = url.port_or_known_default().unwrap_or_default();
+        return anyhow!("could not connect to {}:{}: {}", host, port, cause);
+    }
+    e
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serves robots.txt and a PDF at /report.pdf, 404 for anything else
+    async fn site() -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![0; 4096];
+                let read = stream.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..read]);
+                let path = request.split(' ').nth(1).unwrap_or_default();
+
+                let (status, content_type, body) = match path {
+                    "/robots.txt" => ("200 OK", "text/plain", "User-agent: *\nDisallow: /admin\n"),
+                    "/report.pdf" => ("200 OK", "application/pdf", "%PDF-1.4"),
+                    _ => ("404 Not Found", "text/html", "<p>Not found</p>"),
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    content_type,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        url
+    }
+
+    #[tokio::test]
+    async fn test_check_seed() {
+        let site = site().await;
+        let client = PageClient::new(false);
+        let robots_client = crate::crawler::create_client();
+        let check = |path: &str| {
+            let url = site.join(path).unwrap();
+            let client = &client;
+            let robots_client = &robots_client;
+            async move {
+                check_seed(&url, client, robots_client)
+                    .await
+                    .unwrap_err()
+                    .to_string()
+            }
+        };
+
+        assert!(check("/admin/users")
+            .await
+            .starts_with("robots.txt disallows crawling"));
+        assert!(check("/missing").await.ends_with("returned 404 Not Found"));
+        assert!(check("/report.pdf")
+            .await
+            .ends_with("is application/pdf, not an HTML page with links to follow"));
+    }
+}
@@ -0,0 +1,264 @@
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log2::*;
+use reqwest::{Client, Request, Response, ResponseBuilderExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::sync::Mutex;
+use url::Url;
+
+/// One line of a session recording
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum SessionEvent {
+    /// Pages seeded from the site's sitemaps
+    Seeds { pages: Vec<String> },
+    /// A worker decided to fetch `url`
+    Scheduled { url: String },
+    /// The response to fetching `url`, body base64 encoded
+    Response {
+        url: String,
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: String,
+    },
+    /// Fetching `url` failed before a response arrived
+    Error { url: String, error: String },
+}
+
+/// A recorded fetch, as replayed
+enum RecordedFetch {
+    Response {
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    },
+    Error(String),
+}
+
+/// A loaded recording and how far the crawl is into it
+pub struct Replay {
+    seeds: Vec<String>,
+    schedule: Vec<String>,
+    fetches: HashMap<String, RecordedFetch>,
+    /// How far into `schedule` the replay is
+    position: usize,
+    diverged: bool,
+}
+
+/// Records every response and scheduling decision of a crawl to a
+/// JSON lines file, or replays a recording instead of the network so
+/// the same crawl can be run again exactly
+pub enum Session {
+    Record(Mutex<BufWriter<File>>),
+    Replay(Mutex<Replay>),
+}
+
+impl Session {
+    pub fn record(path: &str) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("could not create {}", path))?;
+        Ok(Session::Record(Mutex::new(BufWriter::new(file))))
+    }
+
+    pub fn replay(path: &str) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("could not open {}", path))?;
+
+        let mut replay = Replay {
+            seeds: Vec::new(),
+            schedule: Vec::new(),
+            fetches: HashMap::new(),
+            position: 0,
+            diverged: false,
+        };
+        for line in BufReader::new(file).lines() {
+            match serde_json::from_str(&line?)? {
+                SessionEvent::Seeds { pages } => replay.seeds = pages,
+                SessionEvent::Scheduled { url } => replay.schedule.push(url),
+                SessionEvent::Response {
+                    url,
+                    status,
+                    headers,
+                    body,
+                } => {
+                    let body = STANDARD.decode(body)?;
+                    let fetch = RecordedFetch::Response {
+                        status,
+                        headers,
+                        body,
+                    };
+                    replay.fetches.insert(url, fetch);
+                }
+                SessionEvent::Error { url, error } => {
+                    replay.fetches.insert(url, RecordedFetch::Error(error));
+                }
+            }
+        }
+
+        Ok(Session::Replay(Mutex::new(replay)))
+    }
+
+    fn write(&self, event: &SessionEvent) {
+        let Session::Record(writer) = self else {
+            return;
+        };
+
+        let mut writer = writer.lock().unwrap_or_else(|e| e.into_inner());
+        let written = serde_json::to_writer(&mut *writer, event)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| Ok(writer.write_all(b"\n")?))
+            .and_then(|_| Ok(writer.flush()?));
+        if let Err(e) = written {
+            error!("could not write to the session recording: {}", e);
+        }
+    }
+
+    pub fn record_seeds(&self, pages: &[String]) {
+        self.write(&SessionEvent::Seeds {
+            pages: pages.to_vec(),
+        });
+    }
+
+    /// The recorded sitemap seeds, `None` when recording
+    pub fn replayed_seeds(&self) -> Option<Vec<String>> {
+        match self {
+            Session::Record(_) => None,
+            Session::Replay(replay) => Some(
+                replay
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .seeds
+                    .clone(),
+            ),
+        }
+    }
+
+    /// Notes that `url` is about to be fetched. When replaying, warns
+    /// the first time the crawl fetches a different url than recorded.
+    pub fn scheduled(&self, url: &str) {
+        let Session::Replay(replay) = self else {
+            self.write(&SessionEvent::Scheduled {
+                url: url.to_string(),
+            });
+            return;
+        };
+
+        let mut replay = replay.lock().unwrap_or_else(|e| e.into_inner());
+        let step = replay.position;
+        let recorded = replay.schedule.get(step).cloned();
+        replay.position += 1;
+
+        if recorded.as_deref() != Some(url) && !replay.diverged {
+            replay.diverged = true;
+            warn!(
+                "replay diverged at step {}: recorded {:?}, crawled {}",
+                step, recorded, url
+            );
+        }
+    }
+
+    /// Sends `request` (or replays its recorded response), recording the response
+    pub async fn execute(&self, client: &Client, request: Request) -> Result<Response> {
+        let request_url = request.url().clone();
+        let url = request_url.to_string();
+
+        if let Session::Replay(replay) = self {
+            let replay = replay.lock().unwrap_or_else(|e| e.into_inner());
+            return match replay.fetches.get(&url) {
+                Some(RecordedFetch::Response {
+                    status,
+                    headers,
+                    body,
+                }) => build_response(request_url, *status, headers, body.clone()),
+                Some(RecordedFetch::Error(error)) => Err(anyhow!("{}", error)),
+                None => Err(anyhow!("{} is not in the session recording", url)),
+            };
+        }
+
+        let response = match client.execute(request).await {
+            Ok(response) => response,
+            Err(e) => {
+                self.write(&SessionEvent::Error {
+                    url,
+                    error: e.to_string(),
+                });
+                return Err(e.into());
+            }
+        };
+
+        let final_url = response.url().clone();
+        let status = response.status().as_u16();
+        let headers: Vec<(String, String)> = response
+            .headers()
+            .iter()
+            .map(|(name, value)| {
+                let value = String::from_utf8_lossy(value.as_bytes()).to_string();
+                (name.to_string(), value)
+            })
+            .collect();
+        let body = response.bytes().await?.to_vec();
+
+        self.write(&SessionEvent::Response {
+            url,
+            status,
+            headers: headers.clone(),
+            body: STANDARD.encode(&body),
+        });
+        build_response(final_url, status, &headers, body)
+    }
+}
+
+fn build_response(
+    url: Url,
+    status: u16,
+    headers: &[(String, String)],
+    body: Vec<u8>,
+) -> Result<Response> {
+    let mut builder = http::Response::builder().status(status).url(url);
+    for (name, value) in headers {
+        builder = builder.header(name, value);
+    }
+
+    Ok(Response::from(builder.body(body)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_replay_serves_recorded_responses() {
+        let path = std::env::temp_dir().join(format!("session-{}.jsonl", uuid::Uuid::new_v4()));
+        let events = [
+            SessionEvent::Scheduled {
+                url: String::from("https://example.com/"),
+            },
+            SessionEvent::Response {
+                url: String::from("https://example.com/"),
+                status: 200,
+                headers: vec![(String::from("content-type"), String::from("text/html"))],
+                body: STANDARD.encode("<a href='/a'>a</a>"),
+            },
+        ];
+        let lines: Vec<String> = events
+            .iter()
+            .map(|event| serde_json::to_string(event).unwrap())
+            .collect();
+        std::fs::write(&path, lines.join("\n")).unwrap();
+
+        let session = Session::replay(path.to_str().unwrap()).unwrap();
+        let client = Client::new();
+        let request = client.get("https://example.com/").build().unwrap();
+        let response = session.execute(&client, request).await.unwrap();
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "text/html");
+        assert_eq!(response.text().await.unwrap(), "<a href='/a'>a</a>");
+
+        let missing = client.get("https://example.com/b").build().unwrap();
+        assert!(session.execute(&client, missing).await.is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
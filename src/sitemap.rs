@@ -0,0 +1,145 @@
+use anyhow::{bail, Result};
+use log2::*;
+use reqwest::{Client, StatusCode};
+use url::Url;
+
+use crate::robots;
+
+/// How many nested sitemap indexes we follow before giving up
+const MAX_SITEMAP_DEPTH: usize = 3;
+
+/// The contents of a single sitemap file
+#[derive(Debug, Default, PartialEq)]
+pub struct Sitemap {
+    /// Page urls found in a `<urlset>`
+    pub pages: Vec<String>,
+    /// Nested sitemap urls found in a `<sitemapindex>`
+    pub sitemaps: Vec<String>,
+}
+
+/// Extracts the `<loc>` entries of a sitemap, splitting them into
+/// pages and nested sitemaps depending on the document type.
+pub fn parse_sitemap(xml: &str) -> Sitemap {
+    let mut locs = Vec::new();
+    let mut rest = xml;
+
+    while let Some(start) = rest.find("<loc>") {
+        rest = &rest[start + "<loc>".len()..];
+        let Some(end) = rest.find("</loc>") else {
+            break;
+        };
+
+        let loc = unescape_xml(rest[..end].trim());
+        if !loc.is_empty() {
+            locs.push(loc);
+        }
+        rest = &rest[end + "</loc>".len()..];
+    }
+
+    if xml.contains("<sitemapindex") {
+        Sitemap {
+            sitemaps: locs,
+            ..Default::default()
+        }
+    } else {
+        Sitemap {
+            pages: locs,
+            ..Default::default()
+        }
+    }
+}
+
+fn unescape_xml(text: &str) -> String {
+    let text = text
+        .strip_prefix("<![CDATA[")
+        .and_then(|t| t.strip_suffix("]]>"))
+        .unwrap_or(text);
+
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+async fn fetch_sitemap(url: &str, client: &Client) -> Result<Sitemap> {
+    let response = client.get(url).send().await?;
+
+    if response.status() != StatusCode::OK {
+        bail!("sitemap returned status {}", response.status());
+    }
+
+    Ok(parse_sitemap(&response.text().await?))
+}
+
+/// Fetches every sitemap in `sitemap_urls` (following sitemap indexes)
+/// and returns up to `max_pages` page urls found in them.
+pub async fn collect_sitemap_pages(
+    sitemap_urls: Vec<String>,
+    client: &Client,
+    max_pages: usize,
+) -> Vec<String> {
+    let mut pages = Vec::new();
+    let mut to_fetch: Vec<(String, usize)> = sitemap_urls.into_iter().map(|u| (u, 0)).collect();
+
+    while let Some((sitemap_url, depth)) = to_fetch.pop() {
+        if pages.len() >= max_pages {
+            break;
+        }
+
+        let sitemap = match fetch_sitemap(&sitemap_url, client).await {
+            Ok(sitemap) => sitemap,
+            Err(e) => {
+                warn!("could not fetch sitemap {}: {}", sitemap_url, e);
+                continue;
+            }
+        };
+
+        if depth < MAX_SITEMAP_DEPTH {
+            to_fetch.extend(sitemap.sitemaps.into_iter().map(|u| (u, depth + 1)));
+        }
+
+        let remaining = max_pages - pages.len();
+        pages.extend(sitemap.pages.into_iter().take(remaining));
+    }
+
+    pages
+}
+
+/// Discovers the sitemaps advertised in the robots.txt of
+/// `starting_url` and returns the page urls they list.
+pub async fn discover_seed_pages(starting_url: &Url, client: &Client, max_pages: usize) -> Vec<String> {
+    let robots = match robots::fetch_robots(starting_url, client).await {
+        Ok(robots) => robots,
+        Err(e) => {
+            warn!("could not fetch robots.txt: {}", e);
+            return Vec::new();
+        }
+    };
+
+    collect_sitemap_pages(robots.sitemaps, client, max_pages).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_urlset_and_index() {
+        let urlset = parse_sitemap(
+            "<urlset><url><loc> https://example.com/a?x=1&amp;y=2 </loc></url>\
+             <url><loc>https://example.com/b</loc></url></urlset>",
+        );
+        assert_eq!(
+            urlset.pages,
+            vec!["https://example.com/a?x=1&y=2", "https://example.com/b"]
+        );
+        assert!(urlset.sitemaps.is_empty());
+
+        let index = parse_sitemap(
+            "<sitemapindex><sitemap><loc>https://example.com/s1.xml</loc></sitemap></sitemapindex>",
+        );
+        assert_eq!(index.sitemaps, vec!["https://example.com/s1.xml"]);
+        assert!(index.pages.is_empty());
+    }
+}
@@ -0,0 +1,185 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+/// Why a url wasn't crawled or downloaded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// Not an http(s) url
+    Scheme,
+    /// Outside the crawled site
+    OffSite,
+    /// Outside the sections given with `--path-prefix`
+    PathPrefix,
+    /// Past `--max-links-per-page` on the page it was found on
+    PageLinkLimit,
+    /// Further from the starting url than `--max-depth`
+    Depth,
+    /// Marked nofollow with `--respect-nofollow`
+    Nofollow,
+    /// An AMP or other alternate version of a page, with `--skip-alternates`
+    Alternate,
+    /// Found on a page in a language that isn't being crawled
+    Language,
+    /// Not reached before the page or byte budget, or its
+    /// section's share of the `--budget`, ran out
+    Budget,
+    /// Found while the link graph was over `--max-memory`
+    MemoryLimit,
+    /// A page robots.txt disallows crawling, with `--respect-robots`,
+    /// or an image it disallows downloading
+    Robots,
+    /// Filtered out by `--include-pattern` or `--exclude-pattern`,
+    /// or an image on pages that don't match `--images-from`
+    Pattern,
+    /// An image on more than one page, with `--image-dedup skip`
+    SharedImage,
+    /// An image on a domain `--image-domains` or `--block-image-domains` leaves out
+    ImageDomain,
+    /// A downloaded image `--image-hook` rejected
+    ImageHook,
+    /// Still queued when the crawl was stopped with Ctrl-C
+    Interrupted,
+    /// Found on a page whose canonical url was already queued,
+    /// with `--dedup-key canonical`
+    Duplicate,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SkipReason::Scheme => "scheme",
+            SkipReason::OffSite => "off_site",
+            SkipReason::PathPrefix => "path_prefix",
+            SkipReason::PageLinkLimit => "page_link_limit",
+            SkipReason::Depth => "depth",
+            SkipReason::Nofollow => "nofollow",
+            SkipReason::Alternate => "alternate",
+            SkipReason::Language => "language",
+            SkipReason::Budget => "budget",
+            SkipReason::MemoryLimit => "memory_limit",
+            SkipReason::Robots => "robots",
+            SkipReason::Pattern => "pattern",
+            SkipReason::SharedImage => "shared_image",
+            SkipReason::ImageDomain => "image_domain",
+            SkipReason::ImageHook => "image_hook",
+            SkipReason::Interrupted => "interrupted",
+            SkipReason::Duplicate => "duplicate",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A skipped url as written to the skipped log
+#[derive(Serialize)]
+struct SkippedUrl<'a> {
+    url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    found_on: Option<&'a str>,
+    reason: SkipReason,
+}
+
+/// Appends every url the crawl skips to a JSON lines file with the
+/// reason it was skipped, so filters can be checked after a crawl
+pub struct SkippedLog {
+    /// `None` when skipped urls are only counted
+    file: Option<File>,
+    /// Urls already written, a url is only logged for the first reason it was skipped
+    seen: HashSet<String>,
+    counts: BTreeMap<SkipReason, usize>,
+}
+
+impl SkippedLog {
+    pub async fn create(path: &str) -> Result<Self> {
+        Ok(Self {
+            file: Some(File::create(path).await?),
+            seen: HashSet::new(),
+            counts: BTreeMap::new(),
+        })
+    }
+
+    /// Counts skipped urls without writing them anywhere
+    pub fn counting() -> Self {
+        Self {
+            file: None,
+            seen: HashSet::new(),
+            counts: BTreeMap::new(),
+        }
+    }
+
+    /// Records that `url`, found on the page `found_on`, was skipped
+    pub async fn record(
+        &mut self,
+        url: &str,
+        found_on: Option<&str>,
+        reason: SkipReason,
+    ) -> Result<()> {
+        if !self.seen.insert(url.to_string()) {
+            return Ok(());
+        }
+        *self.counts.entry(reason).or_default() += 1;
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+
+        let mut line = serde_json::to_vec(&SkippedUrl {
+            url,
+            found_on,
+            reason,
+        })?;
+        line.push(b'\n');
+        file.write_all(&line).await?;
+        Ok(())
+    }
+
+    /// How many urls were skipped for each reason
+    pub fn counts(&self) -> &BTreeMap<SkipReason, usize> {
+        &self.counts
+    }
+
+    pub async fn flush(&mut self) -> Result<()> {
+        if let Some(file) = &mut self.file {
+            file.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_skipped_log() {
+        let path = std::env::temp_dir().join(format!("skipped-{}.jsonl", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+
+        let mut log = SkippedLog::create(path).await.unwrap();
+        let page = Some("https://example.com/");
+        log.record("mailto:me@example.com", page, SkipReason::Scheme)
+            .await
+            .unwrap();
+        log.record("https://github.com/", page, SkipReason::OffSite)
+            .await
+            .unwrap();
+        log.record("https://github.com/", None, SkipReason::Budget)
+            .await
+            .unwrap();
+        log.flush().await.unwrap();
+
+        assert_eq!(
+            log.counts(),
+            &BTreeMap::from([(SkipReason::Scheme, 1), (SkipReason::OffSite, 1)])
+        );
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "{\"url\":\"mailto:me@example.com\",\"found_on\":\"https://example.com/\",\"reason\":\"scheme\"}\n\
+             {\"url\":\"https://github.com/\",\"found_on\":\"https://example.com/\",\"reason\":\"off_site\"}\n"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+use serde::Serialize;
+use std::fmt;
+use std::sync::atomic::Ordering;
+
+use crate::crawler::CrawlerState;
+
+/// A snapshot of how a crawl is progressing
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CrawlStats {
+    /// Pages fetched successfully
+    pub crawled: usize,
+    /// Fetches that finished, successful or not
+    pub attempted: usize,
+    /// Links waiting in the queue
+    pub queued: usize,
+    /// Pages being fetched right now
+    pub in_flight: usize,
+    /// Different links ever queued, visited or not
+    pub discovered: usize,
+    /// Fetches that got a challenge or block page
+    pub blocked: usize,
+    pub images_downloaded: usize,
+    pub elapsed_secs: f64,
+    /// Successfully fetched pages per second
+    pub pages_per_sec: f64,
+    /// The fraction of attempted fetches that failed
+    pub error_rate: f64,
+    /// Seconds until `max_links` pages are crawled at the current rate
+    pub eta_secs: Option<f64>,
+}
+
+impl CrawlStats {
+    pub fn new(
+        crawled: usize,
+        attempted: usize,
+        queued: usize,
+        max_links: usize,
+        elapsed_secs: f64,
+    ) -> Self {
+        let pages_per_sec = match elapsed_secs > 0.0 {
+            true => crawled as f64 / elapsed_secs,
+            false => 0.0,
+        };
+        let error_rate = match attempted {
+            0 => 0.0,
+            attempted => attempted.saturating_sub(crawled) as f64 / attempted as f64,
+        };
+        let eta_secs =
+            (pages_per_sec > 0.0).then(|| max_links.saturating_sub(crawled) as f64 / pages_per_sec);
+
+        Self {
+            crawled,
+            attempted,
+            queued,
+            in_flight: 0,
+            discovered: 0,
+            blocked: 0,
+            images_downloaded: 0,
+            elapsed_secs,
+            pages_per_sec,
+            error_rate,
+            eta_secs,
+        }
+    }
+
+    pub async fn snapshot(crawler_state: &CrawlerState) -> Self {
+        let (queued, discovered) = {
+            let link_queue = crawler_state.link_queue.read().await;
+            (link_queue.len(), link_queue.discovered())
+        };
+
+        Self {
+            in_flight: crawler_state.in_flight_count.load(Ordering::Relaxed),
+            discovered,
+            blocked: crawler_state.blocked_count.load(Ordering::Relaxed),
+            images_downloaded: crawler_state
+                .image_downloader
+                .as_ref()
+                .map_or(0, |image_downloader| image_downloader.downloaded_count()),
+            ..Self::new(
+                crawler_state.crawled_count.load(Ordering::Relaxed),
+                crawler_state.attempted_count.load(Ordering::Relaxed),
+                queued,
+                crawler_state.max_links,
+                crawler_state.started_at.elapsed().as_secs_f64(),
+            )
+        }
+    }
+}
+
+fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+impl fmt::Display for CrawlStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.1} pages/sec, {:.1}% errors, {} queued",
+            self.pages_per_sec,
+            self.error_rate * 100.0,
+            self.queued
+        )?;
+        if self.blocked > 0 {
+            write!(f, ", {} blocked", self.blocked)?;
+        }
+        if let Some(eta_secs) = self.eta_secs {
+            write!(f, ", ETA {}", format_duration(eta_secs))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crawl_stats() {
+        let stats = CrawlStats::new(40, 50, 120, 100, 20.0);
+        assert_eq!(stats.pages_per_sec, 2.0);
+        assert_eq!(stats.error_rate, 0.2);
+        assert_eq!(stats.eta_secs, Some(30.0));
+        assert_eq!(
+            stats.to_string(),
+            "2.0 pages/sec, 20.0% errors, 120 queued, ETA 30s"
+        );
+
+        let starting = CrawlStats::new(0, 0, 1, 100, 0.0);
+        assert_eq!(starting.eta_secs, None);
+        assert_eq!(format_duration(3725.0), "1h02m");
+    }
+}
@@ -0,0 +1,112 @@
+use anyhow::{anyhow, bail, Context, Result};
+use rand::{seq::SliceRandom, Rng};
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, ACCEPT_LANGUAGE, USER_AGENT};
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+/// Common desktop browsers, rotated between when no list is given
+const DEFAULT_USER_AGENTS: [&str; 5] = [
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Safari/605.1.15",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:125.0) Gecko/20100101 Firefox/125.0",
+    "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+];
+
+/// What browsers send when navigating to a page
+const BROWSER_ACCEPT: &str =
+    "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8";
+const BROWSER_ACCEPT_LANGUAGE: &str = "en-US,en;q=0.9";
+
+/// Makes page requests look like they come from a browser: each one
+/// gets a user agent from a list, browser headers and a random delay
+#[derive(Clone, Debug)]
+pub struct Stealth {
+    user_agents: Vec<String>,
+    delay_ms: RangeInclusive<u64>,
+}
+
+impl Stealth {
+    /// Rotates between the user agents in `user_agents_file`, one per
+    /// line, or between common browsers without one
+    pub fn new(user_agents_file: Option<&str>, delay_ms: RangeInclusive<u64>) -> Result<Self> {
+        let user_agents = match user_agents_file {
+            Some(path) => read_user_agents(path)?,
+            None => DEFAULT_USER_AGENTS
+                .iter()
+                .map(|ua| ua.to_string())
+                .collect(),
+        };
+
+        Ok(Self {
+            user_agents,
+            delay_ms,
+        })
+    }
+
+    /// The headers to send a request with, with a randomly picked user agent
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let user_agent = self.user_agents.choose(&mut rand::thread_rng());
+        if let Some(user_agent) = user_agent.and_then(|ua| HeaderValue::from_str(ua).ok()) {
+            headers.insert(USER_AGENT, user_agent);
+        }
+        headers.insert(ACCEPT, HeaderValue::from_static(BROWSER_ACCEPT));
+        headers.insert(
+            ACCEPT_LANGUAGE,
+            HeaderValue::from_static(BROWSER_ACCEPT_LANGUAGE),
+        );
+        headers
+    }
+
+    /// Waits a random time within the delay range
+    pub async fn wait(&self) {
+        let delay_ms = rand::thread_rng().gen_range(self.delay_ms.clone());
+        tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+    }
+}
+
+fn read_user_agents(path: &str) -> Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("could not read the user agents in {}", path))?;
+    let user_agents: Vec<String> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect();
+
+    if user_agents.is_empty() {
+        bail!("{} has no user agents", path);
+    }
+    Ok(user_agents)
+}
+
+/// Parses a range of milliseconds like `500-2000`, or `800` for a fixed delay
+pub fn parse_delay_range(range: &str) -> Result<RangeInclusive<u64>> {
+    let (min, max) = range.split_once('-').unwrap_or((range, range));
+    let parse = |ms: &str| {
+        ms.trim()
+            .parse::<u64>()
+            .map_err(|_| anyhow!("invalid delay '{}'", range))
+    };
+    let (min, max) = (parse(min)?, parse(max)?);
+
+    if min > max {
+        bail!("the delay range '{}' ends before it starts", range);
+    }
+    Ok(min..=max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_delay_range() {
+        assert_eq!(parse_delay_range("500-2000").unwrap(), 500..=2000);
+        assert_eq!(parse_delay_range("800").unwrap(), 800..=800);
+        assert!(parse_delay_range("2000-500").is_err());
+        assert!(parse_delay_range("fast").is_err());
+    }
+}
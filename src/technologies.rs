@@ -0,0 +1,275 @@
+use anyhow::Result;
+use regex::Regex;
+use reqwest::header::HeaderMap;
+use scraper::{Html, Selector};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::LazyLock;
+use tokio::fs;
+use url::Url;
+
+use crate::model::{LinkGraph, NodeKind};
+
+/// Where a rule looks for its pattern
+enum Evidence {
+    /// A response header with this name, the pattern
+    /// is matched against its value (empty matches any)
+    Header(&'static str),
+    /// The `src` of any `<script>` tag
+    ScriptSrc,
+    /// The file name in the `src` of any `<script>` tag,
+    /// the pattern is a regex it has to match whole
+    ScriptFile,
+    /// The name of an attribute on any element, or the start
+    /// of the name if the pattern ends in `-`
+    Attribute,
+    /// The content of `<meta name="generator">`
+    MetaGenerator,
+}
+
+/// Detects a technology when `pattern` is found (case
+/// insensitively) in the given evidence. Libraries with common
+/// words for names are only matched by their file names and the
+/// attributes they add, e.g. `react` is in a lot of urls
+struct Rule {
+    technology: &'static str,
+    evidence: Evidence,
+    pattern: &'static str,
+}
+
+const fn rule(technology: &'static str, evidence: Evidence, pattern: &'static str) -> Rule {
+    Rule {
+        technology,
+        evidence,
+        pattern,
+    }
+}
+
+const RULES: &[Rule] = &[
+    // CMSes and site generators
+    rule("WordPress", Evidence::MetaGenerator, "wordpress"),
+    rule("WordPress", Evidence::ScriptSrc, "/wp-content/"),
+    rule("WordPress", Evidence::ScriptSrc, "/wp-includes/"),
+    rule("Drupal", Evidence::MetaGenerator, "drupal"),
+    rule("Drupal", Evidence::Header("x-drupal-cache"), ""),
+    rule("Drupal", Evidence::Header("x-generator"), "drupal"),
+    rule("Joomla", Evidence::MetaGenerator, "joomla"),
+    rule("Ghost", Evidence::MetaGenerator, "ghost"),
+    rule("Hugo", Evidence::MetaGenerator, "hugo"),
+    rule("Jekyll", Evidence::MetaGenerator, "jekyll"),
+    rule("Gatsby", Evidence::MetaGenerator, "gatsby"),
+    rule("Wix", Evidence::MetaGenerator, "wix.com"),
+    rule("Squarespace", Evidence::MetaGenerator, "squarespace"),
+    rule("Shopify", Evidence::Header("x-shopid"), ""),
+    rule("Shopify", Evidence::ScriptSrc, "cdn.shopify.com"),
+    // Frontend frameworks and libraries
+    rule("Next.js", Evidence::Header("x-powered-by"), "next.js"),
+    rule("Next.js", Evidence::ScriptSrc, "/_next/"),
+    rule("Nuxt", Evidence::ScriptSrc, "/_nuxt/"),
+    rule(
+        "React",
+        Evidence::ScriptFile,
+        r"react(-dom)?([.-][\d.]+)?(\.production|\.development)?(\.min)?\.js",
+    ),
+    rule("React", Evidence::Attribute, "data-reactroot"),
+    rule(
+        "Vue.js",
+        Evidence::ScriptFile,
+        r"vue([.-][\d.]+)?(\.runtime)?(\.global)?(\.prod|\.min)?\.js",
+    ),
+    rule("Vue.js", Evidence::Attribute, "data-v-"),
+    rule(
+        "Angular",
+        Evidence::ScriptFile,
+        r"angular([.-][\d.]+)?(\.min)?\.js",
+    ),
+    rule("Angular", Evidence::Attribute, "ng-version"),
+    rule(
+        "jQuery",
+        Evidence::ScriptFile,
+        r"jquery([.-][\d.]+)?(\.slim)?(\.min)?\.js",
+    ),
+    rule(
+        "Bootstrap",
+        Evidence::ScriptFile,
+        r"bootstrap([.-][\d.]+)?(\.bundle)?(\.min)?\.js",
+    ),
+    // Analytics
+    rule(
+        "Google Analytics",
+        Evidence::ScriptSrc,
+        "google-analytics.com",
+    ),
+    rule(
+        "Google Tag Manager",
+        Evidence::ScriptSrc,
+        "googletagmanager.com",
+    ),
+    // Servers and backends
+    rule("Nginx", Evidence::Header("server"), "nginx"),
+    rule("Apache", Evidence::Header("server"), "apache"),
+    rule("IIS", Evidence::Header("server"), "microsoft-iis"),
+    rule("PHP", Evidence::Header("x-powered-by"), "php"),
+    rule("ASP.NET", Evidence::Header("x-powered-by"), "asp.net"),
+    rule("ASP.NET", Evidence::Header("x-aspnet-version"), ""),
+    rule("Express", Evidence::Header("x-powered-by"), "express"),
+    // CDNs and hosting
+    rule("Cloudflare", Evidence::Header("cf-ray"), ""),
+    rule("Cloudflare", Evidence::Header("server"), "cloudflare"),
+    rule("Amazon CloudFront", Evidence::Header("x-amz-cf-id"), ""),
+    rule("Fastly", Evidence::Header("x-served-by"), "cache-"),
+    rule("Varnish", Evidence::Header("x-varnish"), ""),
+    rule("Vercel", Evidence::Header("x-vercel-id"), ""),
+    rule("Netlify", Evidence::Header("server"), "netlify"),
+];
+
+/// The `Evidence::ScriptFile` patterns, compiled
+static SCRIPT_FILE_PATTERNS: LazyLock<HashMap<&'static str, Regex>> = LazyLock::new(|| {
+    RULES
+        .iter()
+        .filter(|rule| matches!(rule.evidence, Evidence::ScriptFile))
+        .map(|rule| {
+            let regex = Regex::new(&format!("^(?i:{})$", rule.pattern)).unwrap();
+            (rule.pattern, regex)
+        })
+        .collect()
+});
+
+/// Every technology the rules detect in a page's headers and HTML
+pub fn detect(headers: &HeaderMap, html_dom: &Html) -> Vec<String> {
+    let script_selector = Selector::parse("script[src]").unwrap();
+    let script_srcs: Vec<String> = html_dom
+        .select(&script_selector)
+        .filter_map(|e| e.value().attr("src"))
+        .map(|src| src.to_lowercase())
+        .collect();
+    let script_files: Vec<&str> = script_srcs
+        .iter()
+        .map(|src| {
+            let path = src.split(['?', '#']).next().unwrap_or_default();
+            path.rsplit('/').next().unwrap_or_default()
+        })
+        .collect();
+
+    let attributes: BTreeSet<String> = html_dom
+        .tree
+        .values()
+        .filter_map(|node| node.as_element())
+        .flat_map(|element| element.attrs().map(|(name, _)| name.to_lowercase()))
+        .collect();
+
+    let generator_selector = Selector::parse("meta[name=generator][content]").unwrap();
+    let generators: Vec<String> = html_dom
+        .select(&generator_selector)
+        .filter_map(|e| e.value().attr("content"))
+        .map(|content| content.to_lowercase())
+        .collect();
+
+    let mut technologies = BTreeSet::new();
+    for rule in RULES {
+        let found = match rule.evidence {
+            Evidence::Header(name) => headers
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .any(|value| value.to_lowercase().contains(rule.pattern)),
+            Evidence::ScriptSrc => script_srcs.iter().any(|src| src.contains(rule.pattern)),
+            Evidence::ScriptFile => {
+                let pattern = &SCRIPT_FILE_PATTERNS[rule.pattern];
+                script_files.iter().any(|file| pattern.is_match(file))
+            }
+            Evidence::Attribute if rule.pattern.ends_with('-') => {
+                attributes.iter().any(|name| name.starts_with(rule.pattern))
+            }
+            Evidence::Attribute => attributes.contains(rule.pattern),
+            Evidence::MetaGenerator => generators.iter().any(|g| g.contains(rule.pattern)),
+        };
+
+        if found {
+            technologies.insert(rule.technology.to_string());
+        }
+    }
+
+    technologies.into_iter().collect()
+}
+
+#[derive(Serialize)]
+struct TechnologyReport {
+    /// Technologies found on any page of each host
+    hosts: BTreeMap<String, BTreeSet<String>>,
+    /// How many pages each technology was found on
+    pages: BTreeMap<String, usize>,
+}
+
+/// Writes the technologies found on every host and how
+/// many pages each one was found on to `destination`
+pub async fn write_report(links: &LinkGraph, destination: &str) -> Result<()> {
+    let mut report = TechnologyReport {
+        hosts: BTreeMap::new(),
+        pages: BTreeMap::new(),
+    };
+
+    // External links aren't fetched, their hosts would only be empty entries
+    for (_, link) in links
+        .into_iter()
+        .filter(|(_, link)| link.kind != NodeKind::External)
+    {
+        let host = Url::parse(&link.url)
+            .ok()
+            .and_then(|url| url.host_str().map(|h| h.to_string()))
+            .unwrap_or_else(|| String::from("unknown"));
+        let host_technologies = report.hosts.entry(host).or_default();
+
+        for technology in &link.technologies {
+            host_technologies.insert(technology.clone());
+            *report.pages.entry(technology.clone()).or_default() += 1;
+        }
+    }
+
+    fs::write(destination, serde_json::to_string_pretty(&report)?).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_detect() {
+        let mut headers = HeaderMap::new();
+        headers.insert("server", HeaderValue::from_static("nginx/1.25"));
+        headers.insert("x-powered-by", HeaderValue::from_static("PHP/8.2"));
+
+        let html = Html::parse_document(
+            r#"<html><head><meta name="generator" content="WordPress 6.4">
+            <script src="/wp-includes/js/jquery/jquery.min.js"></script></head></html>"#,
+        );
+
+        assert_eq!(
+            detect(&headers, &html),
+            vec!["Nginx", "PHP", "WordPress", "jQuery"]
+        );
+    }
+
+    #[test]
+    fn test_library_names_in_urls_are_not_libraries() {
+        let html = Html::parse_document(
+            r#"<html><head><script src="/js/reactions.js"></script>
+            <script src="/blog/angular-vs-vue/embed.js"></script>
+            <script src="/bootstrap-theme-loader.js"></script></head>
+            <body><p>We love React and Vue.</p></body></html>"#,
+        );
+        assert!(detect(&HeaderMap::new(), &html).is_empty());
+
+        let html = Html::parse_document(
+            r#"<html><head><script src="https://unpkg.com/react-dom@18/umd/react-dom.production.min.js"></script>
+            <script src="/static/bootstrap.bundle.min.js?v=5"></script></head>
+            <body><app-root ng-version="17.0.0"></app-root><div data-v-7ba5bd90>Hi</div></body></html>"#,
+        );
+        assert_eq!(
+            detect(&HeaderMap::new(), &html),
+            vec!["Angular", "Bootstrap", "React", "Vue.js"]
+        );
+    }
+}
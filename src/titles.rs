@@ -0,0 +1,119 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use tokio::fs;
+
+use crate::external_links::csv_field;
+use crate::model::{LinkGraph, NodeKind};
+
+/// Pages without a title, and titles used by more than one
+/// page, which readers and search results can't tell apart
+#[derive(Debug, Default)]
+pub struct TitleReport {
+    /// The pages using each title that's on more than one page
+    duplicates: BTreeMap<String, BTreeSet<String>>,
+    missing: BTreeSet<String>,
+}
+
+impl TitleReport {
+    /// Looks at the pages that were fetched, redirects and block pages aren't counted
+    pub fn new(link_graph: &LinkGraph) -> Self {
+        let mut by_title: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        let mut missing = BTreeSet::new();
+
+        let pages = link_graph.into_iter().map(|(_, link)| link).filter(|link| {
+            link.kind == NodeKind::Page && link.fetched_at.is_some() && link.blocked.is_none()
+        });
+        for page in pages {
+            match &page.title {
+                Some(title) => {
+                    by_title
+                        .entry(title.clone())
+                        .or_default()
+                        .insert(page.url.clone());
+                }
+                None => {
+                    missing.insert(page.url.clone());
+                }
+            }
+        }
+        by_title.retain(|_, urls| urls.len() > 1);
+
+        Self {
+            duplicates: by_title,
+            missing,
+        }
+    }
+
+    /// How many titles are used by more than one page
+    pub fn duplicate_titles(&self) -> usize {
+        self.duplicates.len()
+    }
+
+    /// How many pages share their title with another page
+    pub fn duplicate_pages(&self) -> usize {
+        self.duplicates.values().map(BTreeSet::len).sum()
+    }
+
+    pub fn missing(&self) -> usize {
+        self.missing.len()
+    }
+
+    /// One row per page with a duplicate or missing title
+    fn to_csv(&self) -> String {
+        let mut csv = String::from("issue,title,url\n");
+        for (title, urls) in &self.duplicates {
+            for url in urls {
+                let _ = writeln!(csv, "duplicate,{},{}", csv_field(title), csv_field(url));
+            }
+        }
+        for url in &self.missing {
+            let _ = writeln!(csv, "missing,,{}", csv_field(url));
+        }
+        csv
+    }
+
+    pub async fn write_csv(&self, destination: &str) -> Result<()> {
+        fs::write(destination, self.to_csv()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_title_report() {
+        let mut link_graph = LinkGraph::default();
+        for (url, title) in [
+            ("https://example.com/", Some("Home")),
+            ("https://example.com/a", Some("Shop, Example")),
+            ("https://example.com/b", Some("Shop, Example")),
+            ("https://example.com/c", None),
+            ("https://example.com/d", Some("Contact")),
+        ] {
+            link_graph.update(url, "", &[], &[], &[]).unwrap();
+            link_graph.mark_fetched(url, chrono::Utc::now());
+            if let Some(title) = title {
+                link_graph.set_title(url, title.to_string());
+            }
+        }
+        // Never fetched, so it can't have a title
+        link_graph
+            .update("https://example.com/e", "", &[], &[], &[])
+            .unwrap();
+
+        let report = TitleReport::new(&link_graph);
+        assert_eq!(report.duplicate_titles(), 1);
+        assert_eq!(report.duplicate_pages(), 2);
+        assert_eq!(report.missing(), 1);
+        assert_eq!(
+            report.to_csv(),
+            "issue,title,url\n\
+             duplicate,\"Shop, Example\",https://example.com/a\n\
+             duplicate,\"Shop, Example\",https://example.com/b\n\
+             missing,,https://example.com/c\n"
+        );
+    }
+}
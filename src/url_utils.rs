@@ -0,0 +1,556 @@
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use regex::Regex;
+use std::fmt;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use url::{Host, Url};
+
+use crate::model::link_key;
+
+/// File names servers commonly serve for a bare directory path
+const INDEX_FILES: [&str; 5] = [
+    "index.html",
+    "index.htm",
+    "index.php",
+    "default.htm",
+    "default.html",
+];
+
+/// Extensions of urls that are served as HTML pages
+const PAGE_EXTENSIONS: [&str; 9] = [
+    "html", "htm", "xhtml", "shtml", "php", "asp", "aspx", "jsp", "cfm",
+];
+
+/// Extensions of urls that are images
+const IMAGE_EXTENSIONS: [&str; 9] = [
+    "jpg", "jpeg", "png", "gif", "webp", "avif", "svg", "bmp", "ico",
+];
+
+/// How hosts are compared when deduplicating urls and telling whether
+/// they're part of the site. Urls are still fetched as they're written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum HostNormalization {
+    /// `www.example.com` is the same host as `example.com`
+    StripWww,
+    /// Hosts are left as they are
+    #[default]
+    Keep,
+}
+
+impl HostNormalization {
+    pub fn apply<'a>(&self, host: &'a str) -> &'a str {
+        match self {
+            HostNormalization::StripWww => host.strip_prefix("www.").unwrap_or(host),
+            HostNormalization::Keep => host,
+        }
+    }
+
+    /// `link_key(url)` with the host normalized, so the
+    /// same page on `www.` and the bare host has one key
+    pub fn key<'a>(&self, url: &'a str) -> &'a str {
+        self.apply(link_key(url))
+    }
+}
+
+/// Options controlling how discovered links are
+/// normalized before being queued or stored
+#[derive(Clone, Debug, Default)]
+pub struct NormalizeOptions {
+    /// Rewrite `http://` links to `https://`
+    pub upgrade_http: bool,
+    /// Treat `/dir/index.html` as `/dir/`
+    pub collapse_index_paths: bool,
+    /// Treat `/dir` as `/dir/`. Only paths without an extension
+    /// are changed, and only when asked, since plenty of sites
+    /// serve different pages (or a 404) for the two.
+    pub add_trailing_slash: bool,
+}
+
+/// The form domains are compared in: lowercase punycode
+/// without a trailing dot. E.g. `Bücher.DE.` -> `xn--bcher-kva.de`
+pub fn domain_key(domain: &str) -> String {
+    let domain = domain.trim_end_matches('.');
+    match Host::parse(domain) {
+        Ok(Host::Domain(ascii)) => ascii,
+        _ => domain.to_lowercase(),
+    }
+}
+
+pub fn is_same_domain(url_domain: &str, base_domain: &str) -> bool {
+    let url_domain = domain_key(url_domain);
+    let base_domain = domain_key(base_domain);
+    url_domain == base_domain || url_domain.ends_with(&format!(".{}", base_domain))
+}
+
+/// Whether urls on a different port of the site's host are part of the site
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum PortPolicy {
+    /// Domains match on any port, IP addresses and `localhost` only
+    /// on the starting port (different ports are usually different apps)
+    #[default]
+    Auto,
+    /// Only the starting port is part of the site
+    Same,
+    /// Every port is part of the site
+    Any,
+}
+
+/// The set of urls that count as part of the site being crawled
+#[derive(Clone, Debug)]
+pub struct SiteScope {
+    host: Host<String>,
+    port: Option<u16>,
+    port_policy: PortPolicy,
+    host_normalization: HostNormalization,
+}
+
+impl SiteScope {
+    pub fn from_url(url: &Url, port_policy: PortPolicy) -> Option<Self> {
+        Some(Self {
+            host: url.host()?.to_owned(),
+            port: url.port_or_known_default(),
+            port_policy,
+            host_normalization: HostNormalization::default(),
+        })
+    }
+
+    /// Compares hosts after normalizing them, e.g. so
+    /// `www.` links are part of a site started without it
+    pub fn with_host_normalization(mut self, host_normalization: HostNormalization) -> Self {
+        self.host_normalization = host_normalization;
+        self
+    }
+
+    /// The key urls of the site are stored under, see `HostNormalization::key`
+    pub fn key<'a>(&self, url: &'a str) -> &'a str {
+        self.host_normalization.key(url)
+    }
+
+    /// Domains match themselves and their subdomains, anything
+    /// else (IP addresses, `localhost`) only matches exactly
+    pub fn contains(&self, url: &Url) -> bool {
+        let (same_host, is_domain) = match (&self.host, url.host()) {
+            (Host::Domain(base), Some(Host::Domain(domain))) if base != "localhost" => {
+                let normalize = |host| self.host_normalization.apply(host);
+                (is_same_domain(normalize(domain), normalize(base)), true)
+            }
+            (base, Some(host)) => (*base == host.to_owned(), false),
+            (_, None) => return false,
+        };
+
+        let same_port = self.port == url.port_or_known_default();
+        let port_matches = match self.port_policy {
+            PortPolicy::Auto => is_domain || same_port,
+            PortPolicy::Same => same_port,
+            PortPolicy::Any => true,
+        };
+
+        same_host && port_matches
+    }
+}
+
+/// A readable, filesystem safe name for files holding data about
+/// `url`. A hash of the full url keeps the names unique.
+pub fn url_file_stem(url: &Url) -> String {
+    let readable: String = url
+        .as_str()
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .take(100)
+        .collect();
+
+    let mut hasher = DefaultHasher::new();
+    url.as_str().hash(&mut hasher);
+
+    format!("{}_{:x}", readable, hasher.finish())
+}
+
+/// Returns `url` with its host decoded from punycode, for showing
+/// to people. Returns `None` when the host has nothing to decode.
+pub fn display_url(url: &str) -> Option<String> {
+    let parsed = Url::parse(url).ok()?;
+    let host = parsed.host_str()?;
+    if !host.split('.').any(|label| label.starts_with("xn--")) {
+        return None;
+    }
+
+    let (unicode_host, result) = idna::domain_to_unicode(host);
+    result.ok()?;
+
+    Some(url.replacen(host, &unicode_host, 1))
+}
+
+/// Whether `url` looks like it's an HTML page rather than an image,
+/// document or other file, going by the extension of its path
+pub fn looks_like_page(url: &str) -> bool {
+    url_extension(url).is_none_or(|extension| PAGE_EXTENSIONS.contains(&extension.as_str()))
+}
+
+/// Whether `url` looks like an image, going by the extension of its path
+pub fn looks_like_image(url: &str) -> bool {
+    url_extension(url).is_some_and(|extension| IMAGE_EXTENSIONS.contains(&extension.as_str()))
+}
+
+/// The lowercase extension of the last segment of `url`'s path
+fn url_extension(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let last_segment = url.path().rsplit('/').next()?;
+    let (_, extension) = last_segment.rsplit_once('.')?;
+    Some(extension.to_lowercase())
+}
+
+/// Parses a `--path-prefix`, which always starts with a slash
+pub fn parse_path_prefix(prefix: &str) -> Result<String> {
+    let prefix = prefix.trim();
+    if prefix.contains(['?', '#']) {
+        bail!("a path prefix can't have a query or fragment");
+    }
+    if prefix.starts_with('/') {
+        Ok(prefix.to_string())
+    } else {
+        Ok(format!("/{}", prefix))
+    }
+}
+
+/// Whether the path of `url` starts with one of `prefixes`, or every url
+/// when there are none. `/docs/` takes in `/docs` too.
+pub fn in_path_prefixes(url: &Url, prefixes: &[String]) -> bool {
+    let path = url.path();
+    prefixes.is_empty()
+        || prefixes.iter().any(|prefix| {
+            path.starts_with(prefix.as_str())
+                || prefix.strip_suffix('/').is_some_and(|dir| path == dir)
+        })
+}
+
+/// A pattern urls' paths are matched against, written as a glob like
+/// `/blog/**` or as a regex after `re:`, like `re:^/20\d\d/`
+#[derive(Clone, Debug)]
+pub struct PathPattern {
+    pattern: String,
+    regex: Regex,
+}
+
+impl PathPattern {
+    /// Parses an `--include-pattern` or `--exclude-pattern`. In globs `*`
+    /// matches within a path segment and `**` across them, `/blog/**`
+    /// takes in `/blog` too. Regexes match anywhere in the path
+    pub fn parse(pattern: &str) -> Result<Self> {
+        let pattern = pattern.trim();
+        let regex = match pattern.strip_prefix("re:") {
+            Some(regex) => Regex::new(regex)?,
+            None => Regex::new(&glob_to_regex(pattern))?,
+        };
+        Ok(Self {
+            pattern: pattern.to_string(),
+            regex,
+        })
+    }
+
+    pub fn matches(&self, url: &Url) -> bool {
+        self.regex.is_match(url.path())
+    }
+}
+
+impl fmt::Display for PathPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut rest = glob;
+    while let Some(c) = rest.chars().next() {
+        let (part, len) = if rest.starts_with("/**") {
+            ("(/.*)?", 3)
+        } else if rest.starts_with("**") {
+            (".*", 2)
+        } else if c == '*' {
+            ("[^/]*", 1)
+        } else if c == '?' {
+            ("[^/]", 1)
+        } else {
+            regex.push_str(&regex::escape(&rest[..c.len_utf8()]));
+            rest = &rest[c.len_utf8()..];
+            continue;
+        };
+        regex.push_str(part);
+        rest = &rest[len..];
+    }
+    regex.push('$');
+    regex
+}
+
+/// Whether `url` matches one of `include`, or there are none,
+/// and doesn't match any of `exclude`
+pub fn path_patterns_allow(url: &Url, include: &[PathPattern], exclude: &[PathPattern]) -> bool {
+    (include.is_empty() || include.iter().any(|pattern| pattern.matches(url)))
+        && !exclude.iter().any(|pattern| pattern.matches(url))
+}
+
+/// The directories above `url` up to the root of its site,
+/// nearest first, e.g. `/a/b/`, `/a/` and `/` for `/a/b/c.html`
+pub fn ancestor_urls(url: &Url) -> Vec<Url> {
+    let mut ancestors = Vec::new();
+    let mut path = url.path().trim_end_matches('/');
+
+    while let Some((parent, _)) = path.rsplit_once('/') {
+        let mut ancestor = url.clone();
+        ancestor.set_path(&format!("{}/", parent));
+        ancestor.set_query(None);
+        ancestor.set_fragment(None);
+        ancestors.push(ancestor);
+        path = parent;
+    }
+    ancestors
+}
+
+/// Parses `link` into the form the crawler stores and visits.
+/// Returns `None` for links the crawler can't fetch (i.e. non http(s)).
+pub fn normalize_url(link: &str, options: &NormalizeOptions) -> Option<Url> {
+    let mut url = Url::parse(link).ok()?;
+
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+
+    url.set_fragment(None);
+
+    if options.upgrade_http && url.scheme() == "http" {
+        // Changing between special schemes can't fail
+        let _ = url.set_scheme("https");
+    }
+
+    collapse_index_path(&mut url, options);
+
+    Some(url)
+}
+
+/// Drops index file names from the end of the path and, with
+/// `add_trailing_slash`, makes directory-like paths end in a slash
+fn collapse_index_path(url: &mut Url, options: &NormalizeOptions) {
+    let path = url.path();
+    let (dir, last_segment) = path.rsplit_once('/').unwrap_or(("", path));
+
+    let new_path = if options.collapse_index_paths
+        && INDEX_FILES.contains(&last_segment.to_lowercase().as_str())
+    {
+        format!("{}/", dir)
+    } else if options.add_trailing_slash
+        && !last_segment.is_empty()
+        && !last_segment.contains('.')
+    {
+        format!("{}/", path)
+    } else {
+        return;
+    };
+
+    url.set_path(&new_path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_url() {
+        let options = NormalizeOptions::default();
+        assert_eq!(
+            normalize_url("http://example.com/a#top", &options).unwrap().as_str(),
+            "http://example.com/a"
+        );
+        assert!(normalize_url("mailto:me@example.com", &options).is_none());
+
+        let upgrade = NormalizeOptions {
+            upgrade_http: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            normalize_url("http://example.com/a", &upgrade).unwrap().as_str(),
+            "https://example.com/a"
+        );
+
+        // Hosts are only normalized to compare them, urls are fetched as written
+        assert_eq!(
+            normalize_url("https://www.example.com/a", &options).unwrap().as_str(),
+            "https://www.example.com/a"
+        );
+    }
+
+    #[test]
+    fn test_strip_www() {
+        let start = Url::parse("https://www.example.com/").unwrap();
+        let bare = Url::parse("http://example.com/a").unwrap();
+
+        let site = SiteScope::from_url(&start, PortPolicy::default()).unwrap();
+        assert!(!site.contains(&bare));
+        assert_ne!(site.key(start.as_str()), site.key("https://example.com/"));
+
+        let site = site.with_host_normalization(HostNormalization::StripWww);
+        assert!(site.contains(&bare));
+        assert_eq!(site.key(start.as_str()), site.key("https://example.com/"));
+        assert_eq!(site.key(bare.as_str()), "example.com/a");
+    }
+
+    #[test]
+    fn test_looks_like_page() {
+        assert!(looks_like_page("https://example.com/"));
+        assert!(looks_like_page("https://example.com/docs/intro"));
+        assert!(looks_like_page("https://example.com/about.HTML"));
+        assert!(looks_like_page("https://example.com/search.php?q=a.pdf"));
+        assert!(!looks_like_page("https://example.com/report.pdf"));
+        assert!(!looks_like_page("https://example.com/files/release.tar.gz"));
+        assert!(looks_like_image("https://example.com/logo.PNG"));
+        assert!(!looks_like_image("https://example.com/report.pdf"));
+    }
+
+    #[test]
+    fn test_path_prefixes() {
+        let prefixes = [
+            parse_path_prefix("docs/").unwrap(),
+            parse_path_prefix("/blog/2024").unwrap(),
+        ];
+        let in_prefixes = |url: &str| in_path_prefixes(&Url::parse(url).unwrap(), &prefixes);
+
+        assert!(in_prefixes("https://example.com/docs/install"));
+        assert!(in_prefixes("https://example.com/docs"));
+        assert!(in_prefixes("https://example.com/blog/2024-recap"));
+        assert!(!in_prefixes("https://example.com/documents/a"));
+        assert!(!in_prefixes("https://example.com/"));
+        assert!(in_path_prefixes(&Url::parse("https://example.com/").unwrap(), &[]));
+        assert!(parse_path_prefix("/docs?page=2").is_err());
+    }
+
+    #[test]
+    fn test_path_patterns() {
+        let include = [PathPattern::parse("/blog/**").unwrap()];
+        let exclude = [
+            PathPattern::parse("/blog/tag/*").unwrap(),
+            PathPattern::parse(r"re:/page/\d+$").unwrap(),
+        ];
+        let allowed =
+            |url: &str| path_patterns_allow(&Url::parse(url).unwrap(), &include, &exclude);
+
+        assert!(allowed("https://example.com/blog"));
+        assert!(allowed("https://example.com/blog/2024/hello.html"));
+        assert!(allowed("https://example.com/blog/tag/rust/feed"));
+        assert!(!allowed("https://example.com/blog/tag/rust"));
+        assert!(!allowed("https://example.com/blog/page/2"));
+        assert!(!allowed("https://example.com/blogroll"));
+        assert!(!allowed("https://example.com/about"));
+        assert!(PathPattern::parse("re:(").is_err());
+    }
+
+    #[test]
+    fn test_ancestor_urls() {
+        let ancestors = |url: &str| -> Vec<String> {
+            ancestor_urls(&Url::parse(url).unwrap())
+                .into_iter()
+                .map(String::from)
+                .collect()
+        };
+
+        assert_eq!(
+            ancestors("https://example.com/docs/guide/install.html?v=2"),
+            [
+                "https://example.com/docs/guide/",
+                "https://example.com/docs/",
+                "https://example.com/"
+            ]
+        );
+        assert_eq!(ancestors("https://example.com/docs/"), ["https://example.com/"]);
+        assert!(ancestors("https://example.com/").is_empty());
+    }
+
+    #[test]
+    fn test_idn_domains() {
+        assert!(is_same_domain("xn--bcher-kva.de", "Bücher.de"));
+        assert!(is_same_domain("shop.bücher.de.", "xn--bcher-kva.de"));
+        assert!(!is_same_domain("example.de", "bücher.de"));
+
+        assert_eq!(
+            display_url("https://xn--bcher-kva.de/a").as_deref(),
+            Some("https://bücher.de/a")
+        );
+        assert_eq!(display_url("https://example.com/a"), None);
+    }
+
+    #[test]
+    fn test_site_scope() {
+        let scope = |url: &str| {
+            SiteScope::from_url(&Url::parse(url).unwrap(), PortPolicy::Auto).unwrap()
+        };
+        let url = |url: &str| Url::parse(url).unwrap();
+
+        let domain = scope("https://example.com");
+        assert!(domain.contains(&url("https://blog.example.com:8443/a")));
+        assert!(!domain.contains(&url("https://example.org/a")));
+
+        let ip = scope("http://192.168.1.10:8080/");
+        assert!(ip.contains(&url("http://192.168.1.10:8080/docs")));
+        assert!(!ip.contains(&url("http://192.168.1.10:9090/docs")));
+        assert!(!ip.contains(&url("http://192.168.1.11:8080/docs")));
+
+        let localhost = scope("http://localhost:3000");
+        assert!(localhost.contains(&url("http://localhost:3000/a")));
+        assert!(!localhost.contains(&url("http://localhost:4000/a")));
+
+        let url_in = |policy, url: &str| {
+            SiteScope::from_url(&Url::parse("http://staging.test:8000").unwrap(), policy)
+                .unwrap()
+                .contains(&Url::parse(url).unwrap())
+        };
+        assert!(url_in(PortPolicy::Auto, "http://staging.test:9000/"));
+        assert!(!url_in(PortPolicy::Same, "http://staging.test:9000/"));
+        assert!(url_in(PortPolicy::Any, "http://staging.test:9000/"));
+        assert!(!url_in(PortPolicy::Any, "http://other.test:8000/"));
+    }
+
+    #[test]
+    fn test_collapse_index_paths() {
+        let options = NormalizeOptions {
+            collapse_index_paths: true,
+            ..Default::default()
+        };
+
+        for link in [
+            "https://example.com/dir/",
+            "https://example.com/dir/index.html",
+            "https://example.com/dir/INDEX.HTM",
+        ] {
+            assert_eq!(
+                normalize_url(link, &options).unwrap().as_str(),
+                "https://example.com/dir/"
+            );
+        }
+        assert_eq!(
+            normalize_url("https://example.com/about", &options).unwrap().as_str(),
+            "https://example.com/about"
+        );
+
+        assert_eq!(
+            normalize_url("https://example.com/dir/page.html?q=1", &options).unwrap().as_str(),
+            "https://example.com/dir/page.html?q=1"
+        );
+        assert_eq!(
+            normalize_url("https://example.com", &options).unwrap().as_str(),
+            "https://example.com/"
+        );
+
+        let options = NormalizeOptions {
+            add_trailing_slash: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            normalize_url("https://example.com/about", &options).unwrap().as_str(),
+            "https://example.com/about/"
+        );
+        assert_eq!(
+            normalize_url("https://example.com/about/index.html", &options).unwrap().as_str(),
+            "https://example.com/about/index.html"
+        );
+    }
+}
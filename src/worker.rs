@@ -0,0 +1,680 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use log2::*;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use url::Url;
+
+use crate::crawler::{
+    self, CrawlerState, CrawlerStateRef, FetchSlot, FetchedPage, LinkPath, PageEvent, ScrapeOption,
+};
+use crate::dedup::DedupKey;
+use crate::link_sink::StreamedLink;
+use crate::model::{link_key, NodeKind};
+use crate::skipped::SkipReason;
+use crate::url_utils::{in_path_prefixes, normalize_url, path_patterns_allow};
+use crate::{archive, har, mirror};
+
+/// How many fetched pages a worker's fetch stage can get ahead of its parse stage
+const FETCHED_PAGES_QUEUE: usize = 2;
+
+/// A page the fetch stage fetched, or tried to, on its way to the parse stage
+struct FetchedLink {
+    fetch_slot: FetchSlot,
+    parent: String,
+    depth: usize,
+    url: Url,
+    scrape_options: Vec<ScrapeOption>,
+    fetched_at: DateTime<Utc>,
+    page: Result<FetchedPage>,
+}
+
+/// A client that fetches pages with the crawl's credentials and headers
+pub fn page_client(crawler_state: &CrawlerState) -> Result<crawler::PageClient> {
+    let client = crawler::PageClient::new(crawler_state.compression)
+        .with_stealth(crawler_state.stealth.clone())
+        .with_auth(crawler_state.auth.clone())
+        .with_oauth2(crawler_state.oauth2.clone())
+        .with_browser_session(crawler_state.browser_session.clone())
+        .with_politeness(Some(crawler_state.politeness.clone()));
+    #[cfg(feature = "http3")]
+    let client = if crawler_state.http3 {
+        client.with_http3(crawler_state.compression)?
+    } else {
+        client
+    };
+    Ok(client)
+}
+
+/// What every crawl worker runs: takes links off the queue, scrapes them
+/// and queues the links found on them, until the queue is empty and no
+/// other worker is fetching, or the budget is used up. A worker is two
+/// stages connected by a channel, one fetching pages and one parsing them
+/// and queueing their links, so the next page is fetched while the last
+/// one is parsed.
+pub async fn crawl(crawler_state: CrawlerStateRef) -> Result<()> {
+    let (sender, receiver) = mpsc::channel(FETCHED_PAGES_QUEUE);
+    let parse_stage = tokio::spawn(parse_pages(crawler_state.clone(), receiver));
+    let fetched = fetch_pages(crawler_state, sender).await;
+    parse_stage.await?;
+    fetched
+}
+
+/// The fetch stage: takes links off the queue, fetches
+/// them and hands them over to the parse stage
+async fn fetch_pages(
+    crawler_state: CrawlerStateRef,
+    fetched_pages: mpsc::Sender<FetchedLink>,
+) -> Result<()> {
+    let client = page_client(&crawler_state)?;
+
+    'crawler: loop {
+        if crawler_state.budget_reached() || crawler_state.control.is_cancelled() {
+            break 'crawler;
+        }
+        if crawler_state.control.is_paused() {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            continue;
+        }
+
+        // Wait for the fetches in flight to finish if
+        // they could use up the rest of the budget
+        let Some(fetch_slot) = crawler_state.reserve_fetch() else {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            continue;
+        };
+
+        let (link_to_visit, over_budget) = {
+            let mut link_queue = crawler_state.link_queue.write().await;
+            let link_to_visit = link_queue.pop_back();
+            (link_to_visit, link_queue.take_over_budget())
+        };
+        for path in over_budget {
+            let skipped = [(path.child, SkipReason::Budget)];
+            record_skipped(&crawler_state, &skipped, &path.parent).await;
+        }
+
+        let LinkPath {
+            parent,
+            child,
+            depth,
+            ..
+        } = match link_to_visit {
+            Some(path) => path,
+            None => {
+                drop(fetch_slot);
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                let link_queue = crawler_state.link_queue.read().await;
+                // Other workers may still find more links
+                if link_queue.is_empty() && crawler_state.is_idle() {
+                    break 'crawler;
+                }
+                continue;
+            }
+        };
+
+        if child.is_empty() {
+            continue;
+        }
+
+        let parsed_url = match normalize_url(&child, &crawler_state.normalize_options) {
+            Some(url) => url,
+            None => {
+                record_skipped(&crawler_state, &[(child, SkipReason::Scheme)], &parent).await;
+                continue 'crawler;
+            }
+        };
+
+        let normalized_url = parsed_url.to_string();
+
+        if !crawler_state.site.contains(&parsed_url) {
+            record_skipped(
+                &crawler_state,
+                &[(normalized_url, SkipReason::OffSite)],
+                &parent,
+            )
+            .await;
+            continue 'crawler;
+        }
+
+        let is_new = {
+            let link_graph = crawler_state.link_graph.read().await;
+            !link_graph.link_visited(&normalized_url)
+        };
+
+        if !is_new {
+            continue 'crawler;
+        }
+
+        if crawler_state.respect_robots
+            && !crawler_state
+                .robots
+                .allows(&parsed_url, client.robots(&parsed_url))
+                .await
+        {
+            crawler_state
+                .host_report
+                .lock()
+                .await
+                .record_robots_block(&normalized_url);
+            record_skipped(
+                &crawler_state,
+                &[(normalized_url, SkipReason::Robots)],
+                &parent,
+            )
+            .await;
+            continue 'crawler;
+        }
+
+        if let Some(session) = &crawler_state.session {
+            session.scheduled(&normalized_url);
+        }
+
+        let mut scrape_options = vec![
+            ScrapeOption::Images,
+            ScrapeOption::Titles,
+            ScrapeOption::Language,
+            ScrapeOption::ContentMetrics,
+        ];
+        if crawler_state.mirror_dir.is_some()
+            || crawler_state.archive_dir.is_some()
+            || crawler_state.corpus.is_some()
+        {
+            scrape_options.push(ScrapeOption::Html);
+        }
+        if crawler_state.har_dir.is_some() {
+            scrape_options.push(ScrapeOption::Har);
+        }
+        if !crawler_state.capture_headers.is_empty() {
+            scrape_options.push(ScrapeOption::Headers(crawler_state.capture_headers.clone()));
+        }
+        if crawler_state.detect_technologies {
+            scrape_options.push(ScrapeOption::Technologies);
+        }
+        if crawler_state.outline {
+            scrape_options.push(ScrapeOption::Headings);
+        }
+        if let Some(max) = crawler_state.keywords_per_page {
+            scrape_options.push(ScrapeOption::Keywords(max));
+        }
+        if !crawler_state.link_scope.is_empty() {
+            scrape_options.push(ScrapeOption::LinkScope(crawler_state.link_scope.clone()));
+        }
+        #[cfg(feature = "embeddings")]
+        if crawler_state.embedder.is_some() {
+            scrape_options.push(ScrapeOption::Text);
+        }
+
+        let fetched_at = chrono::Utc::now();
+        let page = crawler::fetch_page(
+            &parsed_url,
+            &client,
+            &scrape_options,
+            crawler_state.session.as_ref(),
+        )
+        .await;
+        let fetched = FetchedLink {
+            fetch_slot,
+            parent,
+            depth,
+            url: parsed_url,
+            scrape_options,
+            fetched_at,
+            page,
+        };
+        if fetched_pages.send(fetched).await.is_err() {
+            break 'crawler;
+        }
+    }
+
+    Ok(())
+}
+
+/// The parse stage: parses the pages the fetch stage fetched,
+/// saves what's asked for and queues the links found on them
+async fn parse_pages(
+    crawler_state: CrawlerStateRef,
+    mut fetched_pages: mpsc::Receiver<FetchedLink>,
+) {
+    // Page resources are saved as they are, so they're decompressed by reqwest
+    let archive_client = crawler::create_client();
+
+    while let Some(FetchedLink {
+        fetch_slot,
+        parent,
+        depth,
+        url: parsed_url,
+        scrape_options,
+        fetched_at,
+        page,
+    }) = fetched_pages.recv().await
+    {
+        let normalized_url = parsed_url.to_string();
+        let mut scrape_output =
+            crawler::parse_fetched_page(parsed_url.clone(), page, &scrape_options).await;
+        crawler_state
+            .downloaded_bytes
+            .fetch_add(scrape_output.transfer_bytes, Ordering::Relaxed);
+        crawler_state
+            .host_report
+            .lock()
+            .await
+            .record_fetch(&parsed_url, &scrape_output);
+
+        if let Some(image_downloader) = &crawler_state.image_downloader {
+            image_downloader
+                .queue(&scrape_output.images, &parsed_url)
+                .await;
+        }
+
+        let html = scrape_output.html.take();
+        if let (Some(mirror_dir), Some(html)) = (&crawler_state.mirror_dir, &html) {
+            if let Err(e) = mirror::save_page(mirror_dir, &parsed_url, html).await {
+                error!("could not mirror {}: {}", normalized_url, e);
+            }
+        }
+
+        if let (Some(har_dir), Some(har_entry)) = (&crawler_state.har_dir, &scrape_output.har_entry)
+        {
+            let title = scrape_output
+                .title
+                .as_ref()
+                .or(scrape_output.headings.first())
+                .map(String::as_str)
+                .unwrap_or_default();
+            if let Err(e) = har::write_har(har_dir, &parsed_url, title, har_entry).await {
+                error!("could not write HAR for {}: {}", normalized_url, e);
+            }
+        }
+
+        let mut archive_path = None;
+        if let (Some(archive_dir), Some(html)) = (&crawler_state.archive_dir, &html) {
+            match archive::archive_page(archive_dir, &parsed_url, html, &archive_client).await {
+                Ok(path) => archive_path = Some(path.to_string_lossy().to_string()),
+                Err(e) => error!("could not archive {}: {}", normalized_url, e),
+            }
+        }
+
+        if let (Some(corpus), Some(html)) = (&crawler_state.corpus, &html) {
+            if let Err(e) = corpus.write_page(&parsed_url, html).await {
+                error!("could not export {} to the corpus: {}", normalized_url, e);
+            }
+        }
+
+        // Store the children the same way we store the pages
+        // themselves so they line up in the link graph
+        let mut seen_links = HashSet::new();
+        let mut skipped_links = Vec::new();
+        let mut links = Vec::new();
+        let mut scheme_counts: BTreeMap<String, usize> = BTreeMap::new();
+        for link in &scrape_output.links {
+            match normalize_url(link, &crawler_state.normalize_options) {
+                Some(url) if seen_links.insert(url.to_string()) => links.push(url.to_string()),
+                Some(_) => {}
+                None => {
+                    let counted_url = Url::parse(link).ok().filter(|url| {
+                        crawler_state
+                            .counted_schemes
+                            .iter()
+                            .any(|scheme| scheme == url.scheme())
+                    });
+                    if let Some(url) = counted_url {
+                        *scheme_counts.entry(url.scheme().to_string()).or_default() += 1;
+                    }
+                    skipped_links.push((link.clone(), SkipReason::Scheme));
+                }
+            }
+        }
+        if let Some(max_links_per_page) = crawler_state.max_links_per_page {
+            if links.len() > max_links_per_page {
+                let over_limit = links.split_off(max_links_per_page);
+                skipped_links.extend(
+                    over_limit
+                        .into_iter()
+                        .map(|link| (link, SkipReason::PageLinkLimit)),
+                );
+            }
+        }
+        scrape_output.links = links;
+
+        let mut alternates = std::mem::take(&mut scrape_output.alternates);
+        alternates.retain_mut(|alternate| {
+            let Some(url) = normalize_url(&alternate.url, &crawler_state.normalize_options) else {
+                return false;
+            };
+            alternate.url = url.to_string();
+            true
+        });
+        let canonical = scrape_output
+            .canonical
+            .take()
+            .and_then(|canonical| normalize_url(&canonical, &crawler_state.normalize_options))
+            .map(|canonical| canonical.to_string())
+            .filter(|canonical| link_key(canonical) != link_key(&normalized_url));
+
+        // Links to other sites aren't followed, but they're kept for the report
+        let mut external_links = crawler_state.external_links.lock().await;
+        for link in &scrape_output.links {
+            if Url::parse(link).is_ok_and(|link_url| !crawler_state.site.contains(&link_url)) {
+                external_links.record(link, &normalized_url);
+            }
+        }
+        drop(external_links);
+
+        let lock_start = Instant::now();
+        let mut link_queue = crawler_state.link_queue.write().await;
+        let mut link_graph = crawler_state.link_graph.write().await;
+        crawler_state
+            .lock_wait_nanos
+            .fetch_add(lock_start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+
+        // The frontier gets whatever memory the graph isn't using,
+        // if the graph is using all of it stop adding links
+        let mut enqueue_paused = false;
+        if let Some(max_memory) = crawler_state.max_memory {
+            let graph_bytes = link_graph.memory_bytes();
+            enqueue_paused = graph_bytes > max_memory;
+            link_queue.set_memory_limit(Some(max_memory.saturating_sub(graph_bytes)));
+
+            if enqueue_paused {
+                warn!("link graph is over the memory limit, not queueing new links");
+            }
+        }
+
+        // A page standing in for one that's already queued is a duplicate and
+        // its links aren't followed, otherwise the page it stands in for is
+        let duplicate = match (&canonical, link_queue.dedup_key()) {
+            (Some(canonical), DedupKey::Canonical) => !link_queue.mark_seen(canonical),
+            _ => false,
+        };
+
+        // Pages in other languages are kept in the graph, but
+        // their links aren't followed
+        let language_allowed = crawler_state.languages.is_empty()
+            || scrape_output
+                .lang
+                .as_ref()
+                .is_none_or(|lang| crawler_state.languages.contains(lang));
+
+        let nofollow: BTreeSet<String> = if crawler_state.respect_nofollow {
+            std::mem::take(&mut scrape_output.nofollow_links)
+                .into_iter()
+                .collect()
+        } else {
+            BTreeSet::new()
+        };
+        for link in scrape_output.links.iter() {
+            let Ok(link_url) = Url::parse(link) else {
+                continue;
+            };
+            if link_graph.link_visited(link) {
+                continue;
+            }
+
+            let skip_reason = if !crawler_state.site.contains(&link_url) {
+                Some(SkipReason::OffSite)
+            } else if !in_path_prefixes(&link_url, &crawler_state.path_prefixes) {
+                Some(SkipReason::PathPrefix)
+            } else if !path_patterns_allow(
+                &link_url,
+                &crawler_state.include_patterns,
+                &crawler_state.exclude_patterns,
+            ) {
+                Some(SkipReason::Pattern)
+            } else if nofollow.contains(link) {
+                Some(SkipReason::Nofollow)
+            } else if crawler_state
+                .max_depth
+                .is_some_and(|max_depth| depth >= max_depth)
+                && *link != normalized_url
+            {
+                Some(SkipReason::Depth)
+            } else if crawler_state.skip_alternates
+                && alternates.iter().any(|alternate| alternate.url == *link)
+            {
+                Some(SkipReason::Alternate)
+            } else if crawler_state.budget_reached() {
+                Some(SkipReason::Budget)
+            } else if enqueue_paused {
+                Some(SkipReason::MemoryLimit)
+            } else if !language_allowed {
+                Some(SkipReason::Language)
+            } else if duplicate {
+                Some(SkipReason::Duplicate)
+            } else {
+                None
+            };
+
+            // Links queued from another page are skipped here, the
+            // graph still records this page as one of their parents
+            match skip_reason {
+                Some(reason) => skipped_links.push((link.clone(), reason)),
+                None => {
+                    link_queue.push_back(LinkPath {
+                        parent: normalized_url.clone(),
+                        child: link.clone(),
+                        depth: depth + 1,
+                        seed: false,
+                    });
+                }
+            }
+        }
+
+        if let Err(e) = link_graph.update(
+            &normalized_url,
+            &parent,
+            &scrape_output.links,
+            &scrape_output.images,
+            &scrape_output.headings,
+        ) {
+            error!("could not update the link graph: {}", e);
+        }
+
+        if scrape_output.fetched {
+            link_graph.mark_fetched(&normalized_url, fetched_at);
+        }
+
+        if let Some(archive_path) = archive_path {
+            link_graph.set_archive_path(&normalized_url, archive_path);
+        }
+
+        if let Some(title) = scrape_output.title.clone() {
+            link_graph.set_title(&normalized_url, title);
+        }
+
+        if !scrape_output.headers.is_empty() {
+            link_graph.set_headers(&normalized_url, std::mem::take(&mut scrape_output.headers));
+        }
+
+        if let Some(link) = link_graph.get_mut(&normalized_url) {
+            link.technologies = std::mem::take(&mut scrape_output.technologies);
+            link.outline = std::mem::take(&mut scrape_output.outline);
+            link.lang = scrape_output.lang.take();
+            link.content = scrape_output.content.take();
+            link.keywords = std::mem::take(&mut scrape_output.keywords);
+            link.entities = std::mem::take(&mut scrape_output.entities);
+            link.scheme_counts = scheme_counts;
+            link.nofollow = nofollow;
+            link.canonical = canonical;
+            link.alternates = alternates;
+            if scrape_output.fetched {
+                link.body_bytes = Some(scrape_output.bytes);
+                link.transfer_bytes = Some(scrape_output.transfer_bytes);
+                link.content_type = scrape_output.content_type.take();
+                link.kind = if scrape_output.redirected_to.is_some() {
+                    NodeKind::Redirect
+                } else {
+                    NodeKind::classify(&normalized_url, link.content_type.as_deref())
+                };
+                link.redirected_to = scrape_output.redirected_to.take();
+                link.redirect_kind = scrape_output.redirect_kind.take();
+                link.caching = scrape_output.caching.take();
+            }
+            if scrape_output.blocked.is_some() {
+                crawler_state.blocked_count.fetch_add(1, Ordering::Relaxed);
+                link.blocked = scrape_output.blocked.clone();
+            }
+        }
+
+        let link_id = link_graph.get(&normalized_url).map(|link| link.id);
+        let streamed_content = crawler_state
+            .link_sink
+            .is_some()
+            .then(|| link_graph.take_content(&normalized_url));
+        drop(link_queue);
+        drop(link_graph);
+
+        record_skipped(&crawler_state, &skipped_links, &normalized_url).await;
+
+        if let (Some(link_sink), Some(id), Some(content)) =
+            (&crawler_state.link_sink, link_id, streamed_content)
+        {
+            let streamed_link = StreamedLink {
+                id,
+                url: &normalized_url,
+                parent: &parent,
+                children: &scrape_output.links,
+                content,
+            };
+
+            if let Err(e) = link_sink.lock().await.write(&streamed_link).await {
+                error!("could not stream link {}: {}", normalized_url, e);
+            }
+        }
+
+        #[cfg(feature = "embeddings")]
+        if let (Some(embedder), Some(id), Some(text)) =
+            (&crawler_state.embedder, link_id, &scrape_output.text)
+        {
+            if let Err(e) = embedder.embed_page(id, &normalized_url, text).await {
+                error!("could not embed {}: {}", normalized_url, e);
+            }
+        }
+
+        fetch_slot.finish(scrape_output.fetched);
+        // Fails when nothing is subscribed
+        let _ = crawler_state.page_events.send(PageEvent {
+            url: normalized_url,
+            links: scrape_output.links.len(),
+            error: scrape_output.error.take(),
+        });
+    }
+}
+
+/// Adds the `skipped` urls found on `found_on` to the skipped log
+pub async fn record_skipped(
+    crawler_state: &CrawlerStateRef,
+    skipped: &[(String, SkipReason)],
+    found_on: &str,
+) {
+    if skipped.is_empty() {
+        return;
+    }
+
+    let found_on = (!found_on.is_empty()).then_some(found_on);
+    let mut skipped_log = crawler_state.skipped_log.lock().await;
+    for (url, reason) in skipped {
+        if let Err(e) = skipped_log.record(url, found_on, *reason).await {
+            error!("could not log skipped url {}: {}", url, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crawler::Crawler;
+    use axum::extract::State;
+    use axum::http::{StatusCode, Uri};
+    use axum::response::{Html, IntoResponse, Response};
+    use axum::Router;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+
+    type Hits = Arc<Mutex<HashMap<String, usize>>>;
+
+    /// A small site where /shared is linked from both /a and /b, /deeper
+    /// is three clicks from the home page and /missing fails
+    async fn test_page(State(hits): State<Hits>, uri: Uri) -> Response {
+        *hits
+            .lock()
+            .unwrap()
+            .entry(uri.path().to_string())
+            .or_default() += 1;
+        let links: &[&str] = match uri.path() {
+            "/" => &["/a", "/b", "/missing"],
+            "/a" => &["/shared", "/deep"],
+            "/b" => &["/shared"],
+            "/deep" => &["/deeper"],
+            "/shared" | "/deeper" => &[],
+            _ => return StatusCode::NOT_FOUND.into_response(),
+        };
+        let links: String = links
+            .iter()
+            .map(|link| format!("<a href=\"{}\">{}</a>\n", link, link))
+            .collect();
+        Html(format!("<html><body>{}</body></html>", links)).into_response()
+    }
+
+    /// Serves the test site, returning its url and the
+    /// number of times each of its paths was requested
+    async fn test_site() -> (String, Hits) {
+        let hits = Hits::default();
+        let app = Router::new().fallback(test_page).with_state(hits.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{}/", address), hits)
+    }
+
+    #[tokio::test]
+    async fn test_crawl_follows_links_to_max_depth() {
+        let (url, hits) = test_site().await;
+        let crawler = Crawler::builder()
+            .start_url(&url)
+            .max_depth(2)
+            .workers(4)
+            .delay(Duration::ZERO)
+            .build()
+            .unwrap();
+        let state = crawler.state().clone();
+        let link_graph = crawler.run().await.unwrap();
+
+        assert_eq!(state.crawled_count.load(Ordering::Relaxed), 5);
+        assert_eq!(state.attempted_count.load(Ordering::Relaxed), 6);
+
+        let hits = hits.lock().unwrap();
+        assert_eq!(hits.get("/missing"), Some(&1));
+        assert_eq!(hits.get("/deeper"), None);
+
+        // Found on two pages, it's fetched once but has both as parents
+        assert_eq!(hits.get("/shared"), Some(&1));
+        let link = |path: &str| link_graph.get(&format!("{}{}", url, path)).unwrap();
+        let shared = link("shared");
+        assert_eq!(shared.parents, HashSet::from([link("a").id, link("b").id]));
+        assert!(shared.fetched_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_failed_pages_do_not_count_against_max_links() {
+        let (url, hits) = test_site().await;
+        // One worker takes the last link found first, /missing comes
+        // right after the home page
+        let crawler = Crawler::builder()
+            .start_url(&url)
+            .max_links(3)
+            .workers(1)
+            .delay(Duration::ZERO)
+            .build()
+            .unwrap();
+        let state = crawler.state().clone();
+        crawler.run().await.unwrap();
+
+        assert_eq!(state.crawled_count.load(Ordering::Relaxed), 3);
+        assert_eq!(state.attempted_count.load(Ordering::Relaxed), 4);
+        assert_eq!(hits.lock().unwrap().get("/missing"), Some(&1));
+    }
+}